@@ -82,6 +82,7 @@ pub fn execute(command: Commands, config: &Config) -> anyhow::Result<()> {
 mod tests {
     use vsmtp_common::{
         addr,
+        dsn::{DsnMail, DsnRcpt},
         envelop::Envelop,
         mail::{BodyType, Mail},
         mail_context::{Body, ConnectionContext, MailContext, MessageMetadata},
@@ -128,6 +129,10 @@ mod tests {
                 is_authenticated: false,
                 is_secured: false,
                 server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
             },
             client_addr: "0.0.0.0:25".parse().unwrap(),
             envelop: Envelop {
@@ -137,7 +142,11 @@ mod tests {
                     address: addr!("foo+1@domain.com"),
                     transfer_method: Transfer::Mbox,
                     email_status: EmailTransferStatus::Waiting,
+                    dsn: DsnRcpt::default(),
                 }],
+                dsn: DsnMail::default(),
+                smtputf8: false,
+                null_reverse_path: false,
             },
             body: Body::Parsed(Box::new(Mail {
                 headers: [
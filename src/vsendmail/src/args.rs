@@ -0,0 +1,136 @@
+/// Submit a message read on the standard input to vSMTP, the flags of sendmail
+/// commonly used by the scripts are supported.
+#[derive(clap::Parser)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+#[clap(about, version, author)]
+pub struct Args {
+    /// Path of the vSMTP configuration file (toml format)
+    #[clap(short = 'C', long, default_value = "/etc/vsmtp/vsmtp.toml")]
+    pub config: String,
+
+    /// Read the recipients from the To, Cc and Bcc headers of the message
+    #[clap(short = 't')]
+    pub extract_recipients: bool,
+
+    /// Do not treat a line with a single dot as the end of the message
+    #[clap(short = 'i')]
+    pub ignore_dots: bool,
+
+    /// Envelope sender of the message, defaults to the current user
+    #[clap(short = 'f', short_alias = 'r')]
+    pub sender: Option<String>,
+
+    /// Full name of the sender, used if the message has no From header
+    #[clap(short = 'F')]
+    pub full_name: Option<String>,
+
+    /// Sendmail options, `-oi` is the same as `-i` and the others are ignored
+    #[clap(short = 'o')]
+    pub options: Vec<String>,
+
+    /// Mode of operation, only `-bm` (read the message on the standard input) is supported
+    #[clap(short = 'b', default_value = "m")]
+    pub mode: String,
+
+    /// Recipients of the message
+    pub recipients: Vec<String>,
+}
+
+impl Args {
+    /// Should a line with a single dot be part of the message ?
+    #[must_use]
+    pub fn ignore_dots(&self) -> bool {
+        self.ignore_dots || self.options.iter().any(|option| option == "i")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args() -> Args {
+        Args {
+            config: "/etc/vsmtp/vsmtp.toml".to_string(),
+            extract_recipients: false,
+            ignore_dots: false,
+            sender: None,
+            full_name: None,
+            options: vec![],
+            mode: "m".to_string(),
+            recipients: vec![],
+        }
+    }
+
+    #[test]
+    fn recipients() {
+        assert_eq!(
+            Args {
+                recipients: vec!["john@doe.com".to_string(), "jane".to_string()],
+                ..args()
+            },
+            <Args as clap::StructOpt>::try_parse_from(&["", "john@doe.com", "jane"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn cron() {
+        let parsed = <Args as clap::StructOpt>::try_parse_from(&[
+            "",
+            "-t",
+            "-oi",
+            "-f",
+            "root@localhost",
+            "-FCronDaemon",
+        ])
+        .unwrap();
+
+        assert!(parsed.ignore_dots());
+        assert_eq!(
+            Args {
+                extract_recipients: true,
+                sender: Some("root@localhost".to_string()),
+                full_name: Some("CronDaemon".to_string()),
+                options: vec!["i".to_string()],
+                ..args()
+            },
+            parsed
+        );
+    }
+
+    #[test]
+    fn aliases() {
+        assert_eq!(
+            Args {
+                ignore_dots: true,
+                sender: Some("john@doe.com".to_string()),
+                mode: "m".to_string(),
+                options: vec!["em".to_string(), "di".to_string()],
+                recipients: vec!["jane@doe.com".to_string()],
+                ..args()
+            },
+            <Args as clap::StructOpt>::try_parse_from(&[
+                "",
+                "-i",
+                "-r",
+                "john@doe.com",
+                "-bm",
+                "-oem",
+                "-odi",
+                "jane@doe.com"
+            ])
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn config() {
+        assert_eq!(
+            Args {
+                config: "./vsmtp.toml".to_string(),
+                ..args()
+            },
+            <Args as clap::StructOpt>::try_parse_from(&["", "-C", "./vsmtp.toml"]).unwrap()
+        );
+    }
+}
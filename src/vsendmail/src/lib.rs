@@ -0,0 +1,52 @@
+//! vSendmail: submit messages to vSMTP with the command line of sendmail
+//!
+//! The message is read on the standard input, and dropped in the pickup directory
+//! of the server (`{server.queues.dirpath}/pickup`), which is scanned by the working
+//! process every `server.queues.working.pickup_period`. The message then goes
+//! through the `postq` rules and the delivery like any message received with SMTP.
+
+#![doc(html_no_source)]
+#![deny(missing_docs)]
+#![deny(unsafe_code)]
+//
+#![warn(clippy::all)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::nursery)]
+#![warn(clippy::cargo)]
+//
+#![allow(clippy::multiple_crate_versions)]
+
+mod args;
+mod message;
+
+pub use args::Args;
+use vsmtp_common::re::anyhow;
+use vsmtp_config::Config;
+
+/// Read a message on `input` and submit it to the server, return the id of the message
+///
+/// # Errors
+///
+/// * the mode of operation is not supported
+/// * the sender or a recipient is not a valid address, or there is no recipient
+/// * the message cannot be written in the pickup directory
+pub fn execute(
+    args: &Args,
+    config: &Config,
+    input: impl std::io::BufRead,
+) -> anyhow::Result<String> {
+    anyhow::ensure!(
+        args.mode == "m",
+        "the mode '-b{}' is not supported, only '-bm' is",
+        args.mode
+    );
+
+    let lines = message::read_message(input, args.ignore_dots())?;
+    let ctx = message::build_context(args, config, &lines)?;
+    message::submit(&config.server.queues.dirpath, &ctx)?;
+
+    Ok(ctx
+        .metadata
+        .map(|metadata| metadata.message_id)
+        .unwrap_or_default())
+}
@@ -0,0 +1,15 @@
+use vsendmail::{execute, Args};
+use vsmtp_common::re::anyhow::{self, Context};
+use vsmtp_config::Config;
+
+fn main() -> anyhow::Result<()> {
+    let args = <Args as clap::StructOpt>::parse();
+
+    let config = std::fs::read_to_string(&args.config)
+        .context(format!("Cannot read file '{}'", args.config))
+        .and_then(|f| Config::from_toml(&f).context("File contains format error"))
+        .context("Cannot parse the configuration")?;
+
+    let stdin = std::io::stdin();
+    execute(&args, &config, stdin.lock()).map(|_| ())
+}
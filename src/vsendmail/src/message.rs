@@ -0,0 +1,479 @@
+use crate::Args;
+use time::format_description::well_known::Rfc2822;
+use vsmtp_common::{
+    dsn::DsnMail,
+    envelop::Envelop,
+    mail_context::{Body, ConnectionContext, MailContext, MessageMetadata},
+    queue_path,
+    rcpt::Rcpt,
+    re::{
+        anyhow::{self, Context},
+        serde_json,
+    },
+    Address,
+};
+use vsmtp_config::{re::users, Config};
+
+/// Read the message, the line endings are removed.
+///
+/// Unless `ignore_dots` is set, a line with a single dot ends the message.
+pub(crate) fn read_message(
+    mut input: impl std::io::BufRead,
+    ignore_dots: bool,
+) -> std::io::Result<Vec<String>> {
+    let mut lines = vec![];
+    let mut buffer = vec![];
+
+    while input.read_until(b'\n', &mut buffer)? != 0 {
+        let line = String::from_utf8_lossy(&buffer);
+        let line = line.trim_end_matches('\n').trim_end_matches('\r');
+
+        if !ignore_dots && line == "." {
+            break;
+        }
+        lines.push(line.to_string());
+        buffer.clear();
+    }
+
+    Ok(lines)
+}
+
+/// Split the header section in fields (with their continuation lines),
+/// and return the remaining lines of the message.
+fn split_header(lines: &[String]) -> (Vec<Vec<String>>, &[String]) {
+    let mut fields: Vec<Vec<String>> = vec![];
+
+    for (index, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            return (fields, &lines[index + 1..]);
+        }
+        match fields.last_mut() {
+            Some(field) if line.starts_with(' ') || line.starts_with('\t') => {
+                field.push(line.clone());
+            }
+            _ if line.contains(':') => fields.push(vec![line.clone()]),
+            // not a header, the message has no separator between the header and the body.
+            _ => return (fields, &lines[index..]),
+        }
+    }
+
+    (fields, &[])
+}
+
+fn field_name(field: &[String]) -> &str {
+    field[0].split(':').next().unwrap_or_default().trim()
+}
+
+/// unfolded value of the field
+fn field_value(field: &[String]) -> String {
+    let mut value = field[0]
+        .split_once(':')
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_default();
+    for line in &field[1..] {
+        value.push_str(line);
+    }
+    value
+}
+
+/// Extract the addresses of an address list (rfc5322 section 3.4),
+/// or of the recipients given on the command line.
+pub(crate) fn split_addresses(list: &str) -> Vec<String> {
+    let mut addresses = vec![];
+    let mut current = String::new();
+    let (mut quoted, mut comment, mut angle) = (false, 0_usize, false);
+
+    for c in list.chars().chain(std::iter::once(',')) {
+        match c {
+            '"' if comment == 0 => quoted = !quoted,
+            '(' if !quoted => comment += 1,
+            ')' if !quoted && comment != 0 => comment -= 1,
+            '<' if !quoted && comment == 0 => {
+                angle = true;
+                current.clear();
+            }
+            '>' if angle => angle = false,
+            // the display name of a group is dropped.
+            ':' if !quoted && comment == 0 && !angle => current.clear(),
+            ',' | ';' if !quoted && comment == 0 && !angle => {
+                let address = current.trim();
+                if !address.is_empty() {
+                    addresses.push(address.to_string());
+                }
+                current.clear();
+            }
+            _ if quoted || comment != 0 => {}
+            c if angle || !c.is_whitespace() => current.push(c),
+            _ => {}
+        }
+    }
+
+    addresses
+}
+
+/// Add the domain of the server to the local user names.
+fn qualify(address: &str, domain: &str) -> String {
+    if address.contains('@') {
+        address.to_string()
+    } else {
+        format!("{address}@{domain}")
+    }
+}
+
+/// Create the context of the message, as if it was received with SMTP.
+pub(crate) fn build_context(
+    args: &Args,
+    config: &Config,
+    lines: &[String],
+) -> anyhow::Result<MailContext> {
+    let now = std::time::SystemTime::now();
+    let domain = &config.server.domain;
+    let message_id = format!(
+        "{}{}{}",
+        now.duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap_or(std::time::Duration::ZERO)
+            .as_micros(),
+        std::iter::repeat_with(fastrand::alphanumeric)
+            .take(36)
+            .collect::<String>(),
+        std::process::id()
+    );
+
+    let null_reverse_path =
+        matches!(&args.sender, Some(sender) if sender.is_empty() || sender == "<>");
+    let mail_from = match &args.sender {
+        // the message is sent by the server itself, as a delivery status notification.
+        Some(_) if null_reverse_path => format!("mailer-daemon@{domain}"),
+        Some(sender) => qualify(sender.trim_start_matches('<').trim_end_matches('>'), domain),
+        None => qualify(
+            &users::get_current_username()
+                .and_then(|name| name.into_string().ok())
+                .ok_or_else(|| anyhow::anyhow!("cannot get the name of the current user"))?,
+            domain,
+        ),
+    };
+    let mail_from = Address::try_from(mail_from).context("invalid sender")?;
+
+    let (mut fields, body) = split_header(lines);
+
+    let mut recipients = args
+        .recipients
+        .iter()
+        .flat_map(|list| split_addresses(list))
+        .collect::<Vec<_>>();
+    if args.extract_recipients {
+        recipients.extend(
+            fields
+                .iter()
+                .filter(|field| {
+                    ["to", "cc", "bcc"]
+                        .iter()
+                        .any(|name| field_name(field).eq_ignore_ascii_case(name))
+                })
+                .flat_map(|field| split_addresses(&field_value(field))),
+        );
+    }
+
+    let mut rcpt: Vec<Rcpt> = vec![];
+    for address in recipients {
+        let address = Address::try_from(qualify(&address, domain)).context("invalid recipient")?;
+        if !rcpt.iter().any(|rcpt| rcpt.address == address) {
+            rcpt.push(Rcpt::new(address));
+        }
+    }
+    anyhow::ensure!(!rcpt.is_empty(), "no recipient for the message");
+
+    // the blind copies must not be visible to the recipients.
+    fields.retain(|field| !field_name(field).eq_ignore_ascii_case("bcc"));
+
+    let has_field = |fields: &[Vec<String>], name: &str| {
+        fields
+            .iter()
+            .any(|field| field_name(field).eq_ignore_ascii_case(name))
+    };
+    if !has_field(&fields, "from") {
+        fields.push(vec![match &args.full_name {
+            Some(full_name) => format!("From: {full_name} <{mail_from}>"),
+            None => format!("From: {mail_from}"),
+        }]);
+    }
+    if !has_field(&fields, "date") {
+        fields.push(vec![format!(
+            "Date: {}",
+            time::OffsetDateTime::from(now).format(&Rfc2822)?
+        )]);
+    }
+    if !has_field(&fields, "message-id") {
+        fields.push(vec![format!("Message-ID: <{message_id}@{domain}>")]);
+    }
+
+    let mut raw = String::new();
+    for line in fields
+        .iter()
+        .flatten()
+        .chain(std::iter::once(&String::new()))
+    {
+        raw.push_str(line);
+        raw.push_str("\r\n");
+    }
+    for line in body {
+        raw.push_str(line);
+        raw.push_str("\r\n");
+    }
+
+    let smtputf8 =
+        !mail_from.full().is_ascii() || rcpt.iter().any(|rcpt| !rcpt.address.full().is_ascii());
+
+    Ok(MailContext {
+        connection: ConnectionContext {
+            timestamp: now,
+            credentials: None,
+            server_name: domain.clone(),
+            is_authenticated: false,
+            is_secured: false,
+            query: None,
+            peer_credentials: None,
+            early_talker: false,
+            client_certificate: None,
+        },
+        client_addr: std::net::SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, 0)),
+        envelop: Envelop {
+            helo: "localhost".to_string(),
+            mail_from,
+            rcpt,
+            dsn: DsnMail::default(),
+            smtputf8,
+            null_reverse_path,
+        },
+        body: Body::Raw(raw),
+        metadata: Some(MessageMetadata {
+            timestamp: now,
+            message_id,
+            skipped: None,
+        }),
+    })
+}
+
+/// Drop the message in the pickup directory of the server.
+///
+/// The file is written under a hidden name and renamed once complete,
+/// so the server never reads a partial message.
+pub(crate) fn submit(
+    queues_dirpath: &std::path::Path,
+    ctx: &MailContext,
+) -> anyhow::Result<std::path::PathBuf> {
+    let message_id = &ctx
+        .metadata
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("message metadata not found"))?
+        .message_id;
+
+    let pickup = queue_path!(queues_dirpath, "pickup");
+    let partial = pickup.join(format!(".{message_id}"));
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial)
+        .with_context(|| format!("cannot create '{}'", partial.display()))?;
+    // the server reads the file with its own user, whatever the umask.
+    std::fs::set_permissions(
+        &partial,
+        std::os::unix::fs::PermissionsExt::from_mode(0o644),
+    )?;
+    serde_json::to_writer(&mut file, ctx)?;
+    std::io::Write::flush(&mut file)?;
+
+    let path = pickup.join(message_id);
+    std::fs::rename(&partial, &path).with_context(|| {
+        format!(
+            "cannot move '{}' to '{}'",
+            partial.display(),
+            path.display()
+        )
+    })?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::{addr, re::anyhow};
+
+    fn lines(message: &str) -> Vec<String> {
+        read_message(message.as_bytes(), false).unwrap()
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.server.domain = "testserver.com".to_string();
+        config
+    }
+
+    fn args() -> Args {
+        <Args as clap::StructOpt>::try_parse_from(&[""]).unwrap()
+    }
+
+    #[test]
+    fn read() {
+        assert_eq!(
+            read_message("a\r\nb\n.\nc\n".as_bytes(), false).unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            read_message("a\r\nb\n.\nc".as_bytes(), true).unwrap(),
+            vec!["a", "b", ".", "c"]
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            split_addresses(r#""Doe, John" <john@doe.com>, jane@doe.com (Jane, Doe),root"#),
+            vec!["john@doe.com", "jane@doe.com", "root"]
+        );
+        assert_eq!(
+            split_addresses("friends: john@doe.com, <jane@doe.com>;"),
+            vec!["john@doe.com", "jane@doe.com"]
+        );
+        assert!(split_addresses("undisclosed-recipients:;").is_empty());
+    }
+
+    #[test]
+    fn extract_recipients() {
+        let ctx = build_context(
+            &Args {
+                extract_recipients: true,
+                sender: Some("cron@testserver.com".to_string()),
+                full_name: Some("Cron Daemon".to_string()),
+                recipients: vec!["admin".to_string()],
+                ..args()
+            },
+            &config(),
+            &lines(concat!(
+                "To: john@doe.com,\r\n",
+                " Jane <jane@doe.com>\r\n",
+                "Bcc: admin@testserver.com, hidden@doe.com\r\n",
+                "Subject: report\r\n",
+                "\r\n",
+                "everything is fine\r\n",
+            )),
+        )
+        .unwrap();
+
+        assert_eq!(ctx.envelop.mail_from, addr!("cron@testserver.com"));
+        assert_eq!(
+            ctx.envelop
+                .rcpt
+                .iter()
+                .map(|rcpt| rcpt.address.full())
+                .collect::<Vec<_>>(),
+            vec![
+                "admin@testserver.com",
+                "john@doe.com",
+                "jane@doe.com",
+                "hidden@doe.com"
+            ]
+        );
+
+        let message_id = &ctx.metadata.as_ref().unwrap().message_id;
+        let raw = match &ctx.body {
+            Body::Raw(raw) => raw,
+            _ => panic!("the body must be inlined"),
+        };
+        let date = raw.lines().find(|line| line.starts_with("Date: ")).unwrap();
+
+        pretty_assertions::assert_eq!(
+            raw,
+            &[
+                "To: john@doe.com,\r\n",
+                " Jane <jane@doe.com>\r\n",
+                "Subject: report\r\n",
+                "From: Cron Daemon <cron@testserver.com>\r\n",
+                &format!("{date}\r\n"),
+                &format!("Message-ID: <{message_id}@testserver.com>\r\n"),
+                "\r\n",
+                "everything is fine\r\n",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn no_recipient() {
+        assert!(build_context(
+            &Args {
+                sender: Some("john@doe.com".to_string()),
+                ..args()
+            },
+            &config(),
+            &lines("Subject: hello\r\n\r\nworld\r\n"),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn null_sender() {
+        let ctx = build_context(
+            &Args {
+                sender: Some("<>".to_string()),
+                recipients: vec!["john@doe.com".to_string()],
+                ..args()
+            },
+            &config(),
+            &lines("Subject: hello\r\n\r\nworld\r\n"),
+        )
+        .unwrap();
+
+        assert!(ctx.envelop.null_reverse_path);
+        assert_eq!(ctx.envelop.reverse_path(), None);
+        assert_eq!(ctx.envelop.mail_from, addr!("mailer-daemon@testserver.com"));
+    }
+
+    #[test]
+    fn without_header() -> anyhow::Result<()> {
+        let ctx = build_context(
+            &Args {
+                sender: Some("john@doe.com".to_string()),
+                recipients: vec!["jane@doe.com".to_string()],
+                ..args()
+            },
+            &config(),
+            &lines("hello world\r\n"),
+        )?;
+
+        match &ctx.body {
+            Body::Raw(raw) => assert!(raw.ends_with("\r\n\r\nhello world\r\n")),
+            _ => panic!("the body must be inlined"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pickup() -> anyhow::Result<()> {
+        let queues_dirpath = std::path::PathBuf::from("./tmp/pickup");
+        queue_path!(create_if_missing => &queues_dirpath, "pickup")?;
+
+        let ctx = build_context(
+            &Args {
+                sender: Some("john@doe.com".to_string()),
+                recipients: vec!["jane@doe.com".to_string()],
+                ..args()
+            },
+            &config(),
+            &lines("Subject: hello\r\n\r\nworld\r\n"),
+        )?;
+
+        let path = submit(&queues_dirpath, &ctx)?;
+
+        assert_eq!(
+            path.file_name().unwrap().to_str().unwrap(),
+            ctx.metadata.as_ref().unwrap().message_id
+        );
+        assert_eq!(MailContext::from_file(&path)?, ctx);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -60,6 +60,9 @@ pub enum Commands {
     ConfigShow,
     /// Show the difference between the loaded config and the default one
     ConfigDiff,
+    /// Make the running server read its config and compile its rules again
+    /// (sends SIGHUP to the pid of `server.system.pid_file`)
+    Reload,
 }
 
 #[cfg(test)]
@@ -101,6 +104,16 @@ mod tests {
             <Args as clap::StructOpt>::try_parse_from(&["", "-c", "path", "config-diff"]).unwrap()
         );
 
+        assert_eq!(
+            Args {
+                command: Some(Commands::Reload),
+                config: Some("path".to_string()),
+                no_daemon: false,
+                timeout: None
+            },
+            <Args as clap::StructOpt>::try_parse_from(&["", "-c", "path", "reload"]).unwrap()
+        );
+
         assert_eq!(
             Args {
                 command: None,
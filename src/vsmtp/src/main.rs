@@ -17,11 +17,15 @@
 use anyhow::Context;
 use vsmtp::{Args, Commands};
 use vsmtp_common::{
-    libc_abstraction::{daemon, initgroups, setgid, setuid},
-    re::{anyhow, log, serde_json},
+    libc_abstraction::{chown, daemon, initgroups, kill, setgid, setuid},
+    re::{anyhow, libc, log, serde_json},
 };
-use vsmtp_config::{get_log4rs_config, re::log4rs, Config};
-use vsmtp_server::start_runtime;
+use vsmtp_config::{
+    get_log4rs_config,
+    re::{log4rs, users},
+    Config, ConfigServerListenerUnix,
+};
+use vsmtp_server::{start_runtime, systemd, Socket};
 
 fn socket_bind_anyhow<A: std::net::ToSocketAddrs + std::fmt::Debug>(
     addr: A,
@@ -36,6 +40,59 @@ fn socket_bind_anyhow<A: std::net::ToSocketAddrs + std::fmt::Debug>(
     Ok(socket)
 }
 
+fn unix_socket_bind_anyhow(
+    unix: &ConfigServerListenerUnix,
+) -> anyhow::Result<std::os::unix::net::UnixListener> {
+    // a stale socket from a previous instance prevents the bind,
+    // but any other file at this path is left untouched.
+    match std::fs::symlink_metadata(&unix.path) {
+        Ok(metadata) => {
+            anyhow::ensure!(
+                std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type()),
+                "Refusing to replace '{}': not a socket",
+                unix.path.display()
+            );
+            std::fs::remove_file(&unix.path).with_context(|| {
+                format!("Failed to remove stale socket: '{}'", unix.path.display())
+            })?;
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to stat: '{}'", unix.path.display()));
+        }
+    }
+
+    let socket = std::os::unix::net::UnixListener::bind(&unix.path)
+        .with_context(|| format!("Failed to bind socket on path: '{}'", unix.path.display()))?;
+
+    if unix.user.is_some() || unix.group.is_some() {
+        chown(
+            &unix.path,
+            unix.user.as_ref().map(users::User::uid),
+            unix.group.as_ref().map(users::Group::gid),
+        )
+        .with_context(|| format!("Failed to change owner of: '{}'", unix.path.display()))?;
+    }
+
+    if let Some(mode) = unix.mode {
+        std::fs::set_permissions(
+            &unix.path,
+            std::os::unix::fs::PermissionsExt::from_mode(mode),
+        )
+        .with_context(|| format!("Failed to set mode of: '{}'", unix.path.display()))?;
+    }
+
+    socket.set_nonblocking(true).with_context(|| {
+        format!(
+            "Failed to set non-blocking socket on path: '{}'",
+            unix.path.display()
+        )
+    })?;
+
+    Ok(socket)
+}
+
 fn main() {
     if let Err(err) = try_main() {
         eprintln!("ERROR: {}", err);
@@ -80,17 +137,65 @@ fn try_main() -> anyhow::Result<()> {
                 }
                 return Ok(());
             }
+            Commands::Reload => {
+                let pid_file = config.server.system.pid_file.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!("'server.system.pid_file' is required to find the server")
+                })?;
+                let pid = std::fs::read_to_string(pid_file)
+                    .with_context(|| format!("Cannot read file '{}'", pid_file.display()))?
+                    .trim()
+                    .parse::<libc::pid_t>()
+                    .with_context(|| format!("Invalid pid in '{}'", pid_file.display()))?;
+                kill(pid, libc::SIGHUP)?;
+                println!("Reload requested to the server {pid}");
+                return Ok(());
+            }
         }
     }
 
-    let sockets = (
-        socket_bind_anyhow(&config.server.interfaces.addr[..])?,
-        socket_bind_anyhow(&config.server.interfaces.addr_submission[..])?,
-        socket_bind_anyhow(&config.server.interfaces.addr_submissions[..])?,
-    );
+    // the daemon changes its working directory, the configuration is reloaded from this path.
+    let config_path = args
+        .config
+        .as_ref()
+        .map(std::fs::canonicalize)
+        .transpose()
+        .context("Cannot resolve the path of the configuration")?;
+
+    let mut sockets = vec![];
+    if let Some(fds) = systemd::listen_fds()? {
+        // the service manager has bound the sockets, the configured addresses are not bound.
+        for (name, fd) in fds {
+            sockets.push(
+                systemd::socket_of(&config.server.interfaces, &name, fd)
+                    .with_context(|| format!("Cannot use the socket '{name}' passed by systemd"))?,
+            );
+        }
+    } else {
+        // every address of every listener is bound before dropping the privileges.
+        for listener in config.server.interfaces.listeners() {
+            for addr in &listener.addr {
+                sockets.push((listener.clone(), Socket::Tcp(socket_bind_anyhow(addr)?)));
+            }
+            if let Some(unix) = &listener.unix {
+                sockets.push((
+                    listener.clone(),
+                    Socket::Unix(unix_socket_bind_anyhow(unix)?),
+                ));
+            }
+        }
+    }
 
     if !args.no_daemon {
         daemon(false, false)?;
+    }
+
+    // the pid is known once daemonized, and written before dropping the privileges.
+    if let Some(pid_file) = &config.server.system.pid_file {
+        std::fs::write(pid_file, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Cannot write the pid file '{}'", pid_file.display()))?;
+    }
+
+    if !args.no_daemon {
         initgroups(
             config.server.system.user.name().to_str().ok_or_else(|| {
                 anyhow::anyhow!(
@@ -111,7 +216,7 @@ fn try_main() -> anyhow::Result<()> {
         .map(log4rs::init_config)
         .context("Cannot initialize logs")??;
 
-    start_runtime(config, sockets, args.timeout.map(|t| t.0)).map_err(|e| {
+    start_runtime(config, config_path, sockets, args.timeout.map(|t| t.0)).map_err(|e| {
         log::error!("vSMTP terminating error: '{e}'");
         e
     })
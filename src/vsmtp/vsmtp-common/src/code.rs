@@ -69,7 +69,7 @@ pub enum SMTPReplyCode {
     /// user not local; will forward
     // Code251,
     /// cannot verify the user, but it will try to deliver the message anyway
-    // Code252,
+    Code252,
     ///
     /// start mail input
     Code354,
@@ -116,12 +116,18 @@ pub enum SMTPReplyCode {
     // Code551,
     /// requested mail action aborted: exceeded storage allocation
     // Code552,
+    /// 552 5.3.4 Message size exceeds fixed maximum message size
+    Code552MessageSizeExceeded,
     /// requested action not taken: mailbox name not allowed
     // Code553,
+    /// 553 5.6.7 Non-ASCII addresses not permitted without the SMTPUTF8 extension
+    Code553NonAsciiAddress,
     /// connection has been denied.
     Code554,
     /// transaction has failed
     Code554tls,
+    /// 554 5.6.0 the message content cannot be handled by the server
+    Code554InvalidMessageContent,
     /// 554 5.5.1 Error: TLS already active
     TlsAlreadyUnderTls,
     // Code555,
@@ -129,6 +135,22 @@ pub enum SMTPReplyCode {
     // Code556,
     /// 554
     ConnectionMaxReached,
+    /// 421 4.7.0 too many connections from the client's network
+    ConnectionRateExceeded,
+    /// 451 4.7.1 too many messages from the client's network
+    MessageRateExceeded,
+    /// 451 4.7.1 too many recipients from the client's network
+    RcptRateExceeded,
+    /// 421 4.3.2 the server is shutting down
+    ShuttingDown,
+    /// 550 5.7.0 the client is not allowed to send XCLIENT or XFORWARD
+    ForwardNotAllowed,
+    /// 250 the deferred messages of the node given to ETRN are being delivered
+    EtrnStarted,
+    /// 458 the deferred messages of the node given to ETRN cannot be delivered now
+    EtrnUnavailable,
+    /// 459 the client is not allowed to start the delivery of this node
+    EtrnNotAllowed,
 
     /// 504 5.5.4
     AuthMechanismNotSupported,
@@ -161,6 +183,8 @@ impl SMTPReplyCode {
             | Self::Code250
             | Self::Code250PlainEsmtp
             | Self::Code250SecuredEsmtp
+            | Self::Code252
+            | Self::EtrnStarted
             | Self::Code354
             | Self::AuthSucceeded
             | Self::Custom(..) => false,
@@ -176,9 +200,19 @@ impl SMTPReplyCode {
             | Self::Code530
             | Self::Code554
             | Self::Code554tls
+            | Self::Code554InvalidMessageContent
             | Self::ConnectionMaxReached
+            | Self::ConnectionRateExceeded
+            | Self::MessageRateExceeded
+            | Self::RcptRateExceeded
+            | Self::ShuttingDown
+            | Self::ForwardNotAllowed
+            | Self::EtrnUnavailable
+            | Self::EtrnNotAllowed
             | Self::Code451TooManyError
             | Self::Code504
+            | Self::Code552MessageSizeExceeded
+            | Self::Code553NonAsciiAddress
             | Self::AuthMechanismNotSupported
             | Self::AuthMechanismMustBeEncrypted
             | Self::AuthClientMustNotStart
@@ -200,6 +234,7 @@ impl std::fmt::Display for SMTPReplyCode {
             Self::Code250 => "Code250",
             Self::Code250PlainEsmtp => "Code250PlainEsmtp",
             Self::Code250SecuredEsmtp => "Code250SecuredEsmtp",
+            Self::Code252 => "Code252",
             Self::Code354 => "Code354",
             Self::Code451 => "Code451",
             Self::Code451Timeout => "Code451Timeout",
@@ -213,9 +248,20 @@ impl std::fmt::Display for SMTPReplyCode {
             Self::BadSequence => "BadSequence",
             Self::Code504 => "Code504",
             Self::Code530 => "Code530",
+            Self::Code552MessageSizeExceeded => "Code552MessageSizeExceeded",
+            Self::Code553NonAsciiAddress => "Code553NonAsciiAddress",
             Self::Code554 => "Code554",
             Self::Code554tls => "Code554tls",
+            Self::Code554InvalidMessageContent => "Code554InvalidMessageContent",
             Self::ConnectionMaxReached => "ConnectionMaxReached",
+            Self::ConnectionRateExceeded => "ConnectionRateExceeded",
+            Self::MessageRateExceeded => "MessageRateExceeded",
+            Self::RcptRateExceeded => "RcptRateExceeded",
+            Self::ShuttingDown => "ShuttingDown",
+            Self::ForwardNotAllowed => "ForwardNotAllowed",
+            Self::EtrnStarted => "EtrnStarted",
+            Self::EtrnUnavailable => "EtrnUnavailable",
+            Self::EtrnNotAllowed => "EtrnNotAllowed",
             Self::AuthMechanismNotSupported => "AuthMechanismNotSupported",
             Self::AuthSucceeded => "AuthSucceeded",
             Self::AuthMechanismMustBeEncrypted => "AuthMechanismMustBeEncrypted",
@@ -247,6 +293,7 @@ impl std::str::FromStr for SMTPReplyCode {
             "Code250" => Ok(Self::Code250),
             "Code250PlainEsmtp" => Ok(Self::Code250PlainEsmtp),
             "Code250SecuredEsmtp" => Ok(Self::Code250SecuredEsmtp),
+            "Code252" => Ok(Self::Code252),
             "Code354" => Ok(Self::Code354),
             "Code451" => Ok(Self::Code451),
             "Code451Timeout" => Ok(Self::Code451Timeout),
@@ -260,9 +307,20 @@ impl std::str::FromStr for SMTPReplyCode {
             "BadSequence" => Ok(Self::BadSequence),
             "Code504" => Ok(Self::Code504),
             "Code530" => Ok(Self::Code530),
+            "Code552MessageSizeExceeded" => Ok(Self::Code552MessageSizeExceeded),
+            "Code553NonAsciiAddress" => Ok(Self::Code553NonAsciiAddress),
             "Code554" => Ok(Self::Code554),
             "Code554tls" => Ok(Self::Code554tls),
+            "Code554InvalidMessageContent" => Ok(Self::Code554InvalidMessageContent),
             "ConnectionMaxReached" => Ok(Self::ConnectionMaxReached),
+            "ConnectionRateExceeded" => Ok(Self::ConnectionRateExceeded),
+            "MessageRateExceeded" => Ok(Self::MessageRateExceeded),
+            "RcptRateExceeded" => Ok(Self::RcptRateExceeded),
+            "ShuttingDown" => Ok(Self::ShuttingDown),
+            "ForwardNotAllowed" => Ok(Self::ForwardNotAllowed),
+            "EtrnStarted" => Ok(Self::EtrnStarted),
+            "EtrnUnavailable" => Ok(Self::EtrnUnavailable),
+            "EtrnNotAllowed" => Ok(Self::EtrnNotAllowed),
             "AuthMechanismNotSupported" => Ok(Self::AuthMechanismNotSupported),
             "AuthSucceeded" => Ok(Self::AuthSucceeded),
             "AuthMechanismMustBeEncrypted" => Ok(Self::AuthMechanismMustBeEncrypted),
@@ -0,0 +1,299 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{code::SMTPReplyCode, transfer::EmailTransferStatus};
+
+/// Content of the message returned in a failure notification. (RET= parameter)
+/// See https://datatracker.ietf.org/doc/html/rfc3461#section-4.3
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ReturnContent {
+    /// the full message.
+    Full,
+    /// only the headers of the message.
+    Headers,
+}
+
+impl std::str::FromStr for ReturnContent {
+    type Err = SMTPReplyCode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "FULL" => Ok(Self::Full),
+            "HDRS" => Ok(Self::Headers),
+            _ => Err(SMTPReplyCode::Code501),
+        }
+    }
+}
+
+/// Conditions on which the sender must be notified. (NOTIFY= parameter)
+/// All fields set to false means "NEVER".
+/// See https://datatracker.ietf.org/doc/html/rfc3461#section-4.1
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NotifyOn {
+    /// notify on successful delivery.
+    pub success: bool,
+    /// notify on failed delivery.
+    pub failure: bool,
+    /// notify if the delivery is delayed.
+    pub delay: bool,
+}
+
+impl NotifyOn {
+    /// the client does not want any notification.
+    #[must_use]
+    pub const fn never() -> Self {
+        Self {
+            success: false,
+            failure: false,
+            delay: false,
+        }
+    }
+
+    /// behavior used when no NOTIFY parameter was given.
+    /// (the server "MAY" choose between "FAILURE" and "FAILURE,DELAY")
+    #[must_use]
+    pub const fn by_default() -> Self {
+        Self {
+            success: false,
+            failure: true,
+            delay: true,
+        }
+    }
+}
+
+impl std::str::FromStr for NotifyOn {
+    type Err = SMTPReplyCode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("NEVER") {
+            return Ok(Self::never());
+        }
+
+        let mut out = Self::never();
+        for keyword in s.split(',') {
+            let flag = match keyword.to_ascii_uppercase().as_str() {
+                "SUCCESS" => &mut out.success,
+                "FAILURE" => &mut out.failure,
+                "DELAY" => &mut out.delay,
+                // NOTE: "NEVER" must appear by itself.
+                _ => return Err(SMTPReplyCode::Code501),
+            };
+            if *flag {
+                return Err(SMTPReplyCode::Code501);
+            }
+            *flag = true;
+        }
+        Ok(out)
+    }
+}
+
+impl std::fmt::Display for NotifyOn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keywords = [
+            (self.success, "SUCCESS"),
+            (self.failure, "FAILURE"),
+            (self.delay, "DELAY"),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, keyword)| keyword)
+        .collect::<Vec<_>>();
+
+        if keywords.is_empty() {
+            write!(f, "NEVER")
+        } else {
+            write!(f, "{}", keywords.join(","))
+        }
+    }
+}
+
+/// Address of the recipient given by the client. (ORCPT= parameter)
+/// See https://datatracker.ietf.org/doc/html/rfc3461#section-4.2
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct OriginalRecipient {
+    /// type of the address, "rfc822" in most cases.
+    pub addr_type: String,
+    /// the address, decoded from xtext.
+    pub mailbox: String,
+}
+
+impl std::str::FromStr for OriginalRecipient {
+    type Err = SMTPReplyCode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(';') {
+            Some((addr_type, mailbox)) if !addr_type.is_empty() && !mailbox.is_empty() => {
+                Ok(Self {
+                    addr_type: addr_type.to_string(),
+                    mailbox: decode_xtext(mailbox)?,
+                })
+            }
+            _ => Err(SMTPReplyCode::Code501),
+        }
+    }
+}
+
+impl std::fmt::Display for OriginalRecipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{};{}", self.addr_type, self.mailbox)
+    }
+}
+
+/// DSN parameters received with the MAIL FROM command.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DsnMail {
+    /// what should be returned in a failure notification.
+    pub ret: Option<ReturnContent>,
+    /// identifier of the transaction given by the client, decoded from xtext.
+    pub envid: Option<String>,
+}
+
+/// DSN parameters received with the RCPT TO command.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DsnRcpt {
+    /// conditions on which the sender must be notified.
+    pub notify: Option<NotifyOn>,
+    /// original address of the recipient.
+    pub orcpt: Option<OriginalRecipient>,
+}
+
+impl DsnRcpt {
+    /// empty parameters, same as [`DsnRcpt::default`] but usable in const context.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            notify: None,
+            orcpt: None,
+        }
+    }
+
+    /// should the sender be notified of the given delivery status.
+    #[must_use]
+    pub fn must_notify(&self, status: &EmailTransferStatus) -> bool {
+        let notify = self.notify.unwrap_or_else(NotifyOn::by_default);
+        match status {
+            EmailTransferStatus::Waiting => false,
+            EmailTransferStatus::Sent => notify.success,
+            // only the first delay is notified.
+            EmailTransferStatus::HeldBack(count) => notify.delay && *count == 0,
+            EmailTransferStatus::Failed(_) => notify.failure,
+        }
+    }
+}
+
+/// decode a string encoded with xtext.
+/// See https://datatracker.ietf.org/doc/html/rfc3461#section-4
+///
+/// # Errors
+///
+/// * the input contains a character outside of the xtext charset.
+/// * an hexchar is malformed.
+pub fn decode_xtext(input: &str) -> Result<String, SMTPReplyCode> {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '+' => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                if hex.len() != 2 || hex.chars().any(|c| c.is_ascii_lowercase()) {
+                    return Err(SMTPReplyCode::Code501);
+                }
+                out.push(char::from(
+                    u8::from_str_radix(&hex, 16).map_err(|_| SMTPReplyCode::Code501)?,
+                ));
+            }
+            '!'..='~' if c != '=' => out.push(c),
+            _ => return Err(SMTPReplyCode::Code501),
+        }
+    }
+
+    Ok(out)
+}
+
+/// encode a string with xtext.
+/// See https://datatracker.ietf.org/doc/html/rfc3461#section-4
+#[must_use]
+pub fn encode_xtext(input: &str) -> String {
+    input
+        .bytes()
+        .map(|c| match c {
+            b'!'..=b'~' if c != b'+' && c != b'=' => char::from(c).to_string(),
+            _ => format!("+{:02X}", c),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xtext() {
+        assert_eq!(decode_xtext("foo+2Bbar").unwrap(), "foo+bar");
+        assert_eq!(decode_xtext("a+20b").unwrap(), "a b");
+        assert_eq!(decode_xtext("foo+2").unwrap_err(), SMTPReplyCode::Code501);
+        assert_eq!(decode_xtext("foo+2b").unwrap_err(), SMTPReplyCode::Code501);
+        assert_eq!(decode_xtext("foo=bar").unwrap_err(), SMTPReplyCode::Code501);
+        assert_eq!(encode_xtext("foo+bar=baz qux"), "foo+2Bbar+3Dbaz+20qux");
+        assert_eq!(
+            decode_xtext(&encode_xtext("john+doe@foo.com")).unwrap(),
+            "john+doe@foo.com"
+        );
+    }
+
+    #[test]
+    fn notify() {
+        assert_eq!("NEVER".parse::<NotifyOn>().unwrap(), NotifyOn::never());
+        assert_eq!(
+            "success,DELAY".parse::<NotifyOn>().unwrap(),
+            NotifyOn {
+                success: true,
+                failure: false,
+                delay: true
+            }
+        );
+        assert!("NEVER,SUCCESS".parse::<NotifyOn>().is_err());
+        assert!("FAILURE,FAILURE".parse::<NotifyOn>().is_err());
+        assert!("".parse::<NotifyOn>().is_err());
+        assert_eq!(
+            "FAILURE,SUCCESS".parse::<NotifyOn>().unwrap().to_string(),
+            "SUCCESS,FAILURE"
+        );
+    }
+
+    #[test]
+    fn must_notify() {
+        let default = DsnRcpt::new();
+        assert!(!default.must_notify(&EmailTransferStatus::Sent));
+        assert!(default.must_notify(&EmailTransferStatus::HeldBack(0)));
+        assert!(!default.must_notify(&EmailTransferStatus::HeldBack(1)));
+        assert!(default.must_notify(&EmailTransferStatus::Failed(String::default())));
+
+        let never = DsnRcpt {
+            notify: Some(NotifyOn::never()),
+            orcpt: None,
+        };
+        assert!(!never.must_notify(&EmailTransferStatus::Failed(String::default())));
+
+        let success = DsnRcpt {
+            notify: Some("SUCCESS".parse().unwrap()),
+            orcpt: None,
+        };
+        assert!(success.must_notify(&EmailTransferStatus::Sent));
+        assert!(!success.must_notify(&EmailTransferStatus::HeldBack(0)));
+    }
+}
@@ -14,6 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
+use crate::dsn::DsnMail;
 use crate::rcpt::Rcpt;
 use crate::Address;
 
@@ -26,6 +27,29 @@ pub struct Envelop {
     pub mail_from: Address,
     /// a list of recipients received using the RCPT TO command.
     pub rcpt: Vec<Rcpt>,
+    /// delivery status notification parameters received using the MAIL FROM command.
+    #[serde(default)]
+    pub dsn: DsnMail,
+    /// the client requested the `SMTPUTF8` extension using the MAIL FROM command,
+    /// the message must be relayed to a server supporting it.
+    #[serde(default)]
+    pub smtputf8: bool,
+    /// the message is sent with the null reverse-path `<>` (delivery status notifications),
+    /// `mail_from` only identifies the server sending it.
+    #[serde(default)]
+    pub null_reverse_path: bool,
+}
+
+impl Envelop {
+    /// the reverse-path used to relay the message, `None` for the null reverse-path.
+    #[must_use]
+    pub const fn reverse_path(&self) -> Option<&Address> {
+        if self.null_reverse_path {
+            None
+        } else {
+            Some(&self.mail_from)
+        }
+    }
 }
 
 impl Default for Envelop {
@@ -35,6 +59,9 @@ impl Default for Envelop {
             // FIXME:
             mail_from: addr!("default@domain.com"),
             rcpt: vec![],
+            dsn: DsnMail::default(),
+            smtputf8: false,
+            null_reverse_path: false,
         }
     }
 }
@@ -15,7 +15,11 @@
  *
 */
 use super::code::SMTPReplyCode;
-use crate::{mechanism::Mechanism, Address};
+use crate::{
+    dsn::{DsnMail, DsnRcpt},
+    mechanism::Mechanism,
+    Address,
+};
 
 /// See "SMTP Service Extension for 8-bit MIME Transport"
 /// https://datatracker.ietf.org/doc/html/rfc6152
@@ -25,7 +29,9 @@ pub enum MimeBodyType {
     SevenBit,
     ///
     EightBitMime,
-    // Binary, // TODO: https://datatracker.ietf.org/doc/html/rfc3030
+    /// See "SMTP Service Extensions for Transmission of Large and Binary MIME Messages"
+    /// https://datatracker.ietf.org/doc/html/rfc3030
+    Binary,
 }
 
 impl std::str::FromStr for MimeBodyType {
@@ -35,11 +41,34 @@ impl std::str::FromStr for MimeBodyType {
         match s {
             "7BIT" => Ok(Self::SevenBit),
             "8BITMIME" => Ok(Self::EightBitMime),
+            "BINARYMIME" => Ok(Self::Binary),
             _ => Err(SMTPReplyCode::Code501),
         }
     }
 }
 
+/// Information of the client of a trusted proxy, see [`Event::XClientCmd`] and [`Event::XForwardCmd`].
+///
+/// The attributes not given, or given as `[UNAVAILABLE]` / `[TEMPUNAVAIL]`, are `None`.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ForwardedClient {
+    /// address of the client (ADDR)
+    pub addr: Option<std::net::IpAddr>,
+    /// port of the client (PORT)
+    pub port: Option<u16>,
+    /// domain sent by the client with the HELO or EHLO command (HELO)
+    pub helo: Option<String>,
+    /// identity of the authenticated client (LOGIN, XCLIENT only)
+    pub login: Option<String>,
+}
+
+/// Attributes accepted with XCLIENT, the ones not stored in [`ForwardedClient`] are ignored.
+pub const XCLIENT_ATTRIBUTES: [&str; 6] = ["NAME", "ADDR", "PORT", "PROTO", "HELO", "LOGIN"];
+
+/// Attributes accepted with XFORWARD, the ones not stored in [`ForwardedClient`] are ignored.
+pub const XFORWARD_ATTRIBUTES: [&str; 7] =
+    ["NAME", "ADDR", "PORT", "PROTO", "HELO", "IDENT", "SOURCE"];
+
 /// Command SMTPs sent and received by servers and clients
 /// See "Simple Mail Transfer Protocol"
 /// https://datatracker.ietf.org/doc/html/rfc5321
@@ -51,6 +80,11 @@ pub enum Event {
     /// Used to identify the SMTP client to the SMTP server and request smtp extensions.
     /// Syntax = `"EHLO" SP ( Domain / address-literal ) CRLF`
     EhloCmd(String),
+    /// Used instead of EHLO by the LMTP clients.
+    /// See "Local Mail Transfer Protocol"
+    /// https://datatracker.ietf.org/doc/html/rfc2033
+    /// Syntax = `"LHLO" SP ( Domain / address-literal ) CRLF`
+    LhloCmd(String),
     /// This command is used to initiate a mail transaction in which the mail
     /// data is delivered to an SMTP server that may, in turn, deliver it to
     /// one or more mailboxes or pass it on to another system (possibly using
@@ -59,13 +93,34 @@ pub enum Event {
     ///
     /// 3rd argument is an xtext of the identity of the submitter,
     /// "<>" meaning not enough unknown or insufficiently authenticated
-    MailCmd(Option<Address>, Option<MimeBodyType>, Option<String>),
+    ///
+    /// 4th argument is the size of the message declared by the client,
+    /// See "SMTP Service Extension for Message Size Declaration"
+    /// https://datatracker.ietf.org/doc/html/rfc1870
+    ///
+    /// 5th argument is the delivery status notification parameters (RET and ENVID),
+    /// See "SMTP Service Extension for Delivery Status Notifications (DSNs)"
+    /// https://datatracker.ietf.org/doc/html/rfc3461
+    ///
+    /// 6th argument is true if the client requested the `SMTPUTF8` extension,
+    /// See "SMTP Extension for Internationalized Email"
+    /// https://datatracker.ietf.org/doc/html/rfc6531
+    MailCmd(
+        Option<Address>,
+        Option<MimeBodyType>,
+        Option<String>,
+        Option<usize>,
+        DsnMail,
+        bool,
+    ),
     /// This command is used to identify an individual recipient of the mail
     /// data; multiple recipients are specified by multiple uses of this
     /// command.
     /// Syntax = `"RCPT TO:" ( "<Postmaster@" Domain ">" / "<Postmaster>" /
     /// Forward-path ) [SP Rcpt-parameters] CRLF`
-    RcptCmd(Address),
+    ///
+    /// 2nd argument is the delivery status notification parameters (NOTIFY and ORCPT)
+    RcptCmd(Address, DsnRcpt),
     /// This command causes the mail data to be appended to the mail data
     /// buffer.
     /// Syntax = `"DATA" CRLF`
@@ -76,6 +131,13 @@ pub enum Event {
     /// is the end of mail data indication.
     /// Syntax = `"." CRLF`
     DataEnd,
+    /// Alternative to the DATA command, the next `size` octets sent by the client
+    /// are appended to the mail data, without dot-stuffing. The 2nd argument
+    /// is true if this is the last chunk of the message.
+    /// See "SMTP Service Extensions for Transmission of Large and Binary MIME Messages"
+    /// https://datatracker.ietf.org/doc/html/rfc3030
+    /// Syntax = `"BDAT" SP chunk-size [ SP end-marker ] CRLF`
+    BdatCmd(usize, bool),
     /// "RSET\r\n"
     /// This command specifies that the current mail transaction will be
     /// aborted. Any stored sender, recipients, and mail data MUST be
@@ -117,15 +179,25 @@ pub enum Event {
     /// https://datatracker.ietf.org/doc/html/rfc4954
     /// Syntax = `"AUTH" mechanism [initial-response] CRLF`
     Auth(Mechanism, Option<Vec<u8>>),
+    /// Sent by a trusted proxy to replace the information of the client,
+    /// the session starts again as if the original client was connected.
+    /// See http://www.postfix.org/XCLIENT_README.html
+    /// Syntax = `"XCLIENT" 1*( SP attribute-name "=" attribute-value ) CRLF`
+    XClientCmd(ForwardedClient),
+    /// Sent by a trusted proxy to give the information of the client
+    /// of the messages it re-injects, outside of a mail transaction.
+    /// See http://www.postfix.org/XFORWARD_README.html
+    /// Syntax = `"XFORWARD" 1*( SP attribute-name "=" attribute-value ) CRLF`
+    XForwardCmd(ForwardedClient),
+    /// Ask the server to start the delivery of the messages queued for a node,
+    /// a domain, or a domain and its subdomains if prefixed by '@'.
+    /// See "SMTP Service Extension for Remote Message Queue Starting"
+    /// https://datatracker.ietf.org/doc/html/rfc1985
+    /// Syntax = `"ETRN" SP [ "@" ] domain CRLF`
+    EtrnCmd(String),
     //
     // Authenticated TURN for On-Demand Mail Relay // https://datatracker.ietf.org/doc/html/rfc2645
-    // Chunking // https://datatracker.ietf.org/doc/html/rfc3030
-    // Delivery status notification // https://datatracker.ietf.org/doc/html/rfc3461
     // https://en.wikipedia.org/wiki/Variable_envelope_return_path
-    // Extended version of remote message queue starting command TURN
-    // https://datatracker.ietf.org/doc/html/rfc1985
-    // Command pipelining // https://datatracker.ietf.org/doc/html/rfc2920
-    // Message size declaration // https://datatracker.ietf.org/doc/html/rfc1870
 }
 
 impl Event {
@@ -134,8 +206,7 @@ impl Event {
     ///
     /// # Errors
     pub fn parse_cmd(input: &str) -> Result<Self, SMTPReplyCode> {
-        // 88 = 80 - "\r\n".len() + (SMTPUTF8 ? 10 : 0)
-        if input.len() > 88 || input.is_empty() {
+        if input.is_empty() {
             return Err(SMTPReplyCode::Code500);
         }
 
@@ -155,12 +226,32 @@ impl Event {
             None => return Err(SMTPReplyCode::Code500),
         };
 
+        // 88 = 80 - "\r\n".len() + (SMTPUTF8 ? 10 : 0)
+        // the limit is increased by 26 for the MAIL command (SIZE, rfc1870 section 3)
+        // and by 110 for MAIL / 530 for RCPT (DSN, rfc3461)
+        // XCLIENT and XFORWARD are limited to the size of a command line (rfc5321 section 4.5.3.1.4)
+        let length_max = if smtp_verb.eq_ignore_ascii_case("MAIL") {
+            88 + 26 + 110
+        } else if smtp_verb.eq_ignore_ascii_case("RCPT") {
+            88 + 530
+        } else if smtp_verb.eq_ignore_ascii_case("XCLIENT")
+            || smtp_verb.eq_ignore_ascii_case("XFORWARD")
+        {
+            510
+        } else {
+            88
+        };
+        if input.len() > length_max {
+            return Err(SMTPReplyCode::Code500);
+        }
+
         match (
             smtp_verb.to_ascii_uppercase().as_str(),
             smtp_args.as_slice(),
         ) {
             ("HELO", args) => Self::parse_arg_helo(args),
             ("EHLO", args) => Self::parse_arg_ehlo(args),
+            ("LHLO", args) => Self::parse_arg_lhlo(args),
             ("MAIL", args) => Self::parse_arg_mail_from(args),
             ("RCPT", args) => Self::parse_arg_rcpt_to(args),
 
@@ -175,6 +266,10 @@ impl Event {
             ("HELP", [help_value]) => Ok(Self::HelpCmd(Some((*help_value).to_string()))),
 
             ("DATA", []) => Ok(Self::DataCmd),
+            ("BDAT", [size]) => Self::parse_arg_bdat(size, false),
+            ("BDAT", [size, end_marker]) if end_marker.eq_ignore_ascii_case("LAST") => {
+                Self::parse_arg_bdat(size, true)
+            }
             ("QUIT", []) => Ok(Self::QuitCmd),
             ("RSET", []) => Ok(Self::RsetCmd),
             ("NOOP", [..]) => Ok(Self::NoopCmd),
@@ -185,10 +280,62 @@ impl Event {
                 Self::parse_arg_auth(mechanism, Some(initial_response))
             }
 
+            ("XCLIENT", args) => {
+                Self::parse_arg_forward(args, &XCLIENT_ATTRIBUTES).map(Self::XClientCmd)
+            }
+            ("XFORWARD", args) => {
+                Self::parse_arg_forward(args, &XFORWARD_ATTRIBUTES).map(Self::XForwardCmd)
+            }
+
+            // the named queues ("#" prefix) are not supported.
+            ("ETRN", [node]) => addr::parse_domain_name(node.strip_prefix('@').unwrap_or(node))
+                .map(|_| Self::EtrnCmd((*node).to_string()))
+                .map_err(|_| SMTPReplyCode::Code501),
+
             _ => Err(SMTPReplyCode::Code501),
         }
     }
 
+    fn parse_arg_forward(
+        args: &[&str],
+        attributes: &[&str],
+    ) -> Result<ForwardedClient, SMTPReplyCode> {
+        if args.is_empty() {
+            return Err(SMTPReplyCode::Code501);
+        }
+
+        let mut client = ForwardedClient::default();
+        for arg in args {
+            let (name, value) = arg.split_once('=').ok_or(SMTPReplyCode::Code501)?;
+            let name = name.to_ascii_uppercase();
+            if !attributes.contains(&name.as_str()) {
+                return Err(SMTPReplyCode::Code501);
+            }
+
+            let value = crate::dsn::decode_xtext(value)?;
+            if value == "[UNAVAILABLE]" || value == "[TEMPUNAVAIL]" {
+                continue;
+            }
+
+            match name.as_str() {
+                "ADDR" => {
+                    // the IPv6 addresses are prefixed with "IPV6:"
+                    let addr = match value.get(..5) {
+                        Some(prefix) if prefix.eq_ignore_ascii_case("IPV6:") => &value[5..],
+                        _ => value.as_str(),
+                    };
+                    client.addr = Some(addr.parse().map_err(|_| SMTPReplyCode::Code501)?);
+                }
+                "PORT" => client.port = Some(value.parse().map_err(|_| SMTPReplyCode::Code501)?),
+                "HELO" => client.helo = Some(value),
+                "LOGIN" => client.login = Some(value),
+                _ => {}
+            }
+        }
+
+        Ok(client)
+    }
+
     fn parse_domain_or_address_literal(args: &[&str]) -> anyhow::Result<String> {
         match args {
             [ip] if ip.starts_with('[') && ip.ends_with(']') => Ok(ip[1..ip.len() - 1]
@@ -216,6 +363,13 @@ impl Event {
         }
     }
 
+    fn parse_arg_lhlo(args: &[&str]) -> Result<Self, SMTPReplyCode> {
+        match Self::parse_domain_or_address_literal(args) {
+            Ok(out) => Ok(Self::LhloCmd(out)),
+            Err(_) => Err(SMTPReplyCode::Code501),
+        }
+    }
+
     pub(super) fn from_path(input: &str, may_be_empty: bool) -> Result<String, SMTPReplyCode> {
         if input.starts_with('<') && input.ends_with('>') {
             match &input[1..input.len() - 1] {
@@ -236,6 +390,9 @@ impl Event {
         fn parse_esmtp_args(path: String, args: &[&str]) -> Result<Event, SMTPReplyCode> {
             let mut bitmime = None;
             let mut auth_mailbox = None;
+            let mut size = None;
+            let mut dsn = DsnMail::default();
+            let mut smtputf8 = false;
 
             for arg in args {
                 if let Some(raw) = arg.strip_prefix("BODY=") {
@@ -245,14 +402,32 @@ impl Event {
                         return Err(SMTPReplyCode::Code501);
                     }
                 } else if *arg == "SMTPUTF8" {
-                    // TODO: ?
-                    // do we want to set a flag in the envelope to force utf8 in the deliver/relay ?
+                    smtputf8 = true;
                 } else if let Some(mailbox) = arg.strip_prefix("AUTH=") {
                     if auth_mailbox.is_none() {
                         auth_mailbox = Some(mailbox.to_string());
                     } else {
                         return Err(SMTPReplyCode::Code501);
                     }
+                } else if let Some(raw) = arg.strip_prefix("SIZE=") {
+                    if size.is_none() {
+                        size = Some(raw.parse::<usize>().map_err(|_| SMTPReplyCode::Code501)?);
+                    } else {
+                        return Err(SMTPReplyCode::Code501);
+                    }
+                } else if let Some(raw) = arg.strip_prefix("RET=") {
+                    if dsn.ret.is_none() {
+                        dsn.ret = Some(raw.parse()?);
+                    } else {
+                        return Err(SMTPReplyCode::Code501);
+                    }
+                } else if let Some(raw) = arg.strip_prefix("ENVID=") {
+                    // NOTE: the envid is limited to 100 characters (rfc3461 section 4.4)
+                    if dsn.envid.is_none() && !raw.is_empty() && raw.len() <= 100 {
+                        dsn.envid = Some(crate::dsn::decode_xtext(raw)?);
+                    } else {
+                        return Err(SMTPReplyCode::Code501);
+                    }
                 } else {
                     return Err(SMTPReplyCode::Code504);
                 }
@@ -266,6 +441,9 @@ impl Event {
                 },
                 bitmime,
                 auth_mailbox,
+                size,
+                dsn,
+                smtputf8,
             ))
         }
 
@@ -298,15 +476,32 @@ impl Event {
 
         // TODO: parse "<Postmaster@" Domain ">" / "<Postmaster>"
 
-        #[allow(clippy::missing_const_for_fn)]
         fn parse_esmtp_args(path: String, args: &[&str]) -> Result<Event, SMTPReplyCode> {
-            if args.is_empty() {
-                Ok(Event::RcptCmd(
-                    Address::try_from(path).map_err(|_| SMTPReplyCode::Code501)?,
-                ))
-            } else {
-                Err(SMTPReplyCode::Code504)
+            let mut dsn = DsnRcpt::default();
+
+            for arg in args {
+                if let Some(raw) = arg.strip_prefix("NOTIFY=") {
+                    if dsn.notify.is_none() {
+                        dsn.notify = Some(raw.parse()?);
+                    } else {
+                        return Err(SMTPReplyCode::Code501);
+                    }
+                } else if let Some(raw) = arg.strip_prefix("ORCPT=") {
+                    // NOTE: the orcpt is limited to 500 characters (rfc3461 section 4.2)
+                    if dsn.orcpt.is_none() && raw.len() <= 500 {
+                        dsn.orcpt = Some(raw.parse()?);
+                    } else {
+                        return Err(SMTPReplyCode::Code501);
+                    }
+                } else {
+                    return Err(SMTPReplyCode::Code504);
+                }
             }
+
+            Ok(Event::RcptCmd(
+                Address::try_from(path).map_err(|_| SMTPReplyCode::Code501)?,
+                dsn,
+            ))
         }
 
         match args {
@@ -327,6 +522,12 @@ impl Event {
         }
     }
 
+    fn parse_arg_bdat(size: &str, last: bool) -> Result<Self, SMTPReplyCode> {
+        size.parse::<usize>()
+            .map(|size| Self::BdatCmd(size, last))
+            .map_err(|_| SMTPReplyCode::Code501)
+    }
+
     fn parse_arg_auth(
         mechanism: &str,
         initial_response: Option<&str>,
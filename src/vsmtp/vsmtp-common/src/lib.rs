@@ -38,6 +38,9 @@ pub use r#type::address::Address;
 /// smtp reply code to client's command
 pub mod code;
 
+/// delivery status notification parameters
+pub mod dsn;
+
 /// envelop of a transaction
 pub mod envelop;
 
@@ -112,6 +112,61 @@ pub fn setuid(uid: libc::uid_t) -> anyhow::Result<i32> {
     }
 }
 
+/// Send a signal to a process
+///
+/// # Errors
+///
+/// see kill(2) ERRORS
+#[inline]
+pub fn kill(pid: libc::pid_t, signal: libc::c_int) -> anyhow::Result<()> {
+    match unsafe { libc::kill(pid, signal) } {
+        -1 => Err(anyhow::anyhow!(
+            "kill: '{}'",
+            std::io::Error::last_os_error()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the address family (`AF_INET`, `AF_INET6`, `AF_UNIX`, ...) of the socket `@fd`
+///
+/// # Errors
+///
+/// see getsockname(2) ERRORS
+pub fn socket_family(fd: std::os::unix::io::RawFd) -> anyhow::Result<libc::c_int> {
+    let mut addr = unsafe { std::mem::zeroed::<libc::sockaddr_storage>() };
+    let mut len = libc::socklen_t::try_from(std::mem::size_of::<libc::sockaddr_storage>())?;
+
+    match unsafe {
+        libc::getsockname(
+            fd,
+            std::ptr::addr_of_mut!(addr).cast::<libc::sockaddr>(),
+            &mut len,
+        )
+    } {
+        0 => Ok(libc::c_int::from(addr.ss_family)),
+        _ => Err(anyhow::anyhow!(
+            "getsockname: '{}'",
+            std::io::Error::last_os_error()
+        )),
+    }
+}
+
+/// Close the file descriptor `@fd` when executing another program
+///
+/// # Errors
+///
+/// see fcntl(2) ERRORS
+pub fn set_cloexec(fd: std::os::unix::io::RawFd) -> anyhow::Result<()> {
+    match unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } {
+        -1 => Err(anyhow::anyhow!(
+            "fcntl: '{}'",
+            std::io::Error::last_os_error()
+        )),
+        _ => Ok(()),
+    }
+}
+
 /// Set group identity
 ///
 /// # Errors
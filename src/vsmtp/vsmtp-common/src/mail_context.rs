@@ -17,9 +17,6 @@
 use crate::{envelop::Envelop, mail::Mail, status::Status, MailParser};
 use anyhow::Context;
 
-/// average size of a mail
-pub const MAIL_CAPACITY: usize = 10_000_000; // 10MB
-
 /// metadata
 /// TODO: remove retry & resolver fields.
 #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
@@ -52,6 +49,11 @@ pub enum Body {
     Raw(String),
     /// The message parsed using [MailMimeParser]
     Parsed(Box<Mail>),
+    /// The raw message is stored in a spool file, and read only when needed
+    Spooled(std::path::PathBuf),
+    /// The message has been received with `BODY=BINARYMIME` (rfc3030), it is stored
+    /// as received in a spool file and cannot be read or modified as text
+    Binary(std::path::PathBuf),
 }
 
 impl std::fmt::Display for Body {
@@ -60,28 +62,102 @@ impl std::fmt::Display for Body {
             Body::Empty => "".to_string(),
             Body::Raw(data) => data.clone(),
             Body::Parsed(mail) => mail.to_raw(),
+            Body::Spooled(path) => std::fs::read_to_string(path).map_err(|_| std::fmt::Error)?,
+            Body::Binary(path) => {
+                String::from_utf8_lossy(&std::fs::read(path).map_err(|_| std::fmt::Error)?)
+                    .into_owned()
+            }
         })
     }
 }
 
 impl Body {
+    /// path of the spool file of the message `message_id`, next to the queues.
+    #[must_use]
+    pub fn spool_path(queues_dirpath: &std::path::Path, message_id: &str) -> std::path::PathBuf {
+        crate::queue_path!(queues_dirpath, "mails", message_id)
+    }
+
+    /// Convert a [`Body::Spooled`] into a [`Body::Raw`] by reading the spool file,
+    /// a [`Body::Binary`] is left untouched
+    ///
+    /// # Errors
+    ///
+    /// * Fail to read the spool file
+    pub fn load(self) -> anyhow::Result<Self> {
+        Ok(match self {
+            Body::Spooled(path) => Self::Raw(
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read spool file '{}'", path.display()))?,
+            ),
+            otherwise => otherwise,
+        })
+    }
+
+    /// Write the message in the spool file `path`, the instance becomes a [`Body::Spooled`].
+    /// A body already spooled, or binary, is left untouched.
+    ///
+    /// # Errors
+    ///
+    /// * Fail to write the spool file, the instance is unchanged
+    pub fn spool(&mut self, path: std::path::PathBuf) -> anyhow::Result<()> {
+        if !matches!(self, Body::Spooled(_) | Body::Binary(_)) {
+            std::fs::write(&path, self.to_string())
+                .with_context(|| format!("failed to write spool file '{}'", path.display()))?;
+            *self = Body::Spooled(path);
+        }
+        Ok(())
+    }
+
+    /// Get the raw representation of the message, reading the spool file if needed
+    ///
+    /// # Errors
+    ///
+    /// * Fail to read the spool file
+    /// * The body is a [`Body::Binary`]
+    pub fn to_raw(&self) -> anyhow::Result<String> {
+        Ok(match self {
+            Body::Empty => String::default(),
+            Body::Raw(raw) => raw.clone(),
+            Body::Parsed(parsed) => parsed.to_raw(),
+            Body::Spooled(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read spool file '{}'", path.display()))?,
+            Body::Binary(_) => anyhow::bail!("a message received with BODY=BINARYMIME is not text"),
+        })
+    }
+
+    /// Get the bytes of the message as sent to the next hop, reading the spool file if needed
+    ///
+    /// # Errors
+    ///
+    /// * Fail to read the spool file
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Body::Binary(path) => std::fs::read(path)
+                .with_context(|| format!("failed to read spool file '{}'", path.display())),
+            otherwise => otherwise.to_raw().map(String::into_bytes),
+        }
+    }
+
     /// Convert a the instance into a [`Body::Parsed`] or [`Body::Empty`]
     ///
     /// # Errors
     ///
+    /// * Fail to read the spool file
     /// * Fail to parse using the provided [`MailParser`]
     pub fn to_parsed<P: MailParser>(self) -> anyhow::Result<Self> {
-        Ok(match self {
+        Ok(match self.load()? {
             Body::Raw(raw) => Self::Parsed(Box::new(P::default().parse(raw.as_bytes())?)),
             otherwise => otherwise,
         })
     }
 
     /// get the value of an header, return None if it does not exists or when the body is empty.
+    /// a spooled body must be loaded first, see [`Body::load`].
     #[must_use]
     pub fn get_header(&self, name: &str) -> Option<&str> {
         match self {
-            Body::Empty => None,
+            Body::Empty | Body::Spooled(_) | Body::Binary(_) => None,
             Body::Raw(raw) => {
                 for line in raw.lines() {
                     let mut split = line.splitn(2, ": ");
@@ -101,9 +177,10 @@ impl Body {
     }
 
     /// rewrite a header with a new value or add it to the header section.
+    /// a spooled body must be loaded first, see [`Body::load`].
     pub fn set_header(&mut self, name: &str, value: &str) {
         match self {
-            Body::Empty => {}
+            Body::Empty | Body::Spooled(_) | Body::Binary(_) => {}
             Body::Raw(raw) => {
                 let mut header_start = 0;
                 let mut header_end = None;
@@ -135,9 +212,10 @@ impl Body {
     }
 
     /// prepend a header to the header section.
+    /// a spooled body must be loaded first, see [`Body::load`].
     pub fn add_header(&mut self, name: &str, value: &str) {
         match self {
-            Body::Empty => {}
+            Body::Empty | Body::Spooled(_) | Body::Binary(_) => {}
             Body::Raw(raw) => *raw = format!("{name}: {value}\n{raw}"),
             Body::Parsed(parsed) => {
                 parsed.prepend_headers(vec![(name.to_string(), value.to_string())]);
@@ -176,6 +254,66 @@ pub struct ConnectionContext {
     pub is_authenticated: bool,
     /// is the connection under tls ?
     pub is_secured: bool,
+    /// argument of the last VRFY / EXPN / ETRN command, never written to the queues.
+    #[serde(skip)]
+    pub query: Option<String>,
+    /// credentials of the local process connected with a unix domain socket.
+    #[serde(default)]
+    pub peer_credentials: Option<PeerCredentials>,
+    /// has the client sent data before the greetings ? (see `greet_pause`)
+    #[serde(default)]
+    pub early_talker: bool,
+    /// certificate of the client, verified against `server.tls.client_auth`
+    #[serde(default)]
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+/// Identity of the certificate presented by the client during the tls handshake
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct ClientCertificate {
+    /// distinguished name of the subject, e.g. "CN=relay.example.com, O=Example"
+    pub subject: String,
+    /// common name of the subject, e.g. "relay.example.com"
+    pub common_name: Option<String>,
+    /// dns names of the subject alternative name extension
+    pub dns_names: Vec<String>,
+    /// email addresses of the subject alternative name extension
+    pub email_addresses: Vec<String>,
+    /// uris of the subject alternative name extension
+    pub uris: Vec<String>,
+}
+
+impl ClientCertificate {
+    /// dns names, email addresses and uris of the subject alternative name extension
+    pub fn subject_alt_names(&self) -> impl Iterator<Item = &String> {
+        self.dns_names
+            .iter()
+            .chain(&self.email_addresses)
+            .chain(&self.uris)
+    }
+
+    /// the identity given to the client authenticated with `AUTH EXTERNAL`:
+    /// its first dns name, else its first email address, else the common name
+    /// or the distinguished name of the subject.
+    #[must_use]
+    pub fn identity(&self) -> &str {
+        self.dns_names
+            .first()
+            .or_else(|| self.email_addresses.first())
+            .or(self.common_name.as_ref())
+            .map_or(self.subject.as_str(), String::as_str)
+    }
+}
+
+/// Credentials of the peer process of a unix domain socket, read with `SO_PEERCRED`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct PeerCredentials {
+    /// user id of the peer process
+    pub uid: u32,
+    /// group id of the peer process
+    pub gid: u32,
+    /// id of the peer process, if the platform provides it
+    pub pid: Option<i32>,
 }
 
 /// Representation of one mail obtained by a transaction SMTP
@@ -211,3 +349,79 @@ impl MailContext {
             })?
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Body, ClientCertificate};
+
+    #[test]
+    fn spooled() {
+        let path = Body::spool_path(std::path::Path::new("./tmp/spool"), "spooled");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "subject: hello\n\nworld\n").unwrap();
+
+        let body = Body::Spooled(path.clone());
+        assert_eq!(body.get_header("subject"), None);
+        assert_eq!(body.to_raw().unwrap(), "subject: hello\n\nworld\n");
+        assert_eq!(body.to_string(), "subject: hello\n\nworld\n");
+
+        let body = body.load().unwrap();
+        assert_eq!(body, Body::Raw("subject: hello\n\nworld\n".to_string()));
+        assert_eq!(body.get_header("subject"), Some("hello"));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(Body::Spooled(path).load().is_err());
+    }
+
+    #[test]
+    fn binary() {
+        let path = Body::spool_path(std::path::Path::new("./tmp/spool"), "binary");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"subject: hello\r\n\r\n\xff\x00\r\n").unwrap();
+
+        let body = Body::Binary(path.clone()).load().unwrap();
+        assert_eq!(body, Body::Binary(path.clone()));
+        assert_eq!(body.get_header("subject"), None);
+        assert!(body.to_raw().is_err());
+        assert_eq!(
+            body.to_bytes().unwrap(),
+            b"subject: hello\r\n\r\n\xff\x00\r\n"
+        );
+
+        let mut spooled = body.clone();
+        spooled.spool(path.clone()).unwrap();
+        assert_eq!(spooled, body);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn client_certificate_identity() {
+        let mut certificate = ClientCertificate {
+            subject: "O=Example".to_string(),
+            common_name: None,
+            dns_names: vec![],
+            email_addresses: vec![],
+            uris: vec!["https://relay.example.com".to_string()],
+        };
+        assert_eq!(certificate.identity(), "O=Example");
+
+        certificate.common_name = Some("Relay".to_string());
+        assert_eq!(certificate.identity(), "Relay");
+
+        certificate.email_addresses = vec!["postmaster@example.com".to_string()];
+        assert_eq!(certificate.identity(), "postmaster@example.com");
+
+        certificate.dns_names = vec!["relay.example.com".to_string()];
+        assert_eq!(certificate.identity(), "relay.example.com");
+
+        assert_eq!(
+            certificate.subject_alt_names().collect::<Vec<_>>(),
+            [
+                "relay.example.com",
+                "postmaster@example.com",
+                "https://relay.example.com"
+            ]
+        );
+    }
+}
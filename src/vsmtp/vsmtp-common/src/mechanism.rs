@@ -38,9 +38,10 @@ pub enum Mechanism {
     Login,
     /// Limited
     CramMd5,
+    /// The identity of the client is its tls certificate
+    External,
     /*
       ANONYMOUS
-    - EXTERNAL
     - SECURID
     - DIGEST-MD5
     - SCRAM-SHA-1
@@ -67,7 +68,7 @@ impl Mechanism {
     #[must_use]
     pub const fn client_first(self) -> bool {
         match self {
-            Mechanism::Plain => true,
+            Mechanism::Plain | Mechanism::External => true,
             Mechanism::Login | Mechanism::CramMd5 => false,
         }
     }
@@ -76,7 +77,7 @@ impl Mechanism {
     #[must_use]
     pub const fn must_be_under_tls(self) -> bool {
         match self {
-            Mechanism::Plain | Mechanism::Login | Mechanism::CramMd5 => true,
+            Mechanism::Plain | Mechanism::Login | Mechanism::CramMd5 | Mechanism::External => true,
         }
     }
 }
@@ -87,6 +88,7 @@ impl std::fmt::Display for Mechanism {
             Mechanism::Plain => "PLAIN",
             Mechanism::Login => "LOGIN",
             Mechanism::CramMd5 => "CRAM-MD5",
+            Mechanism::External => "EXTERNAL",
         })
     }
 }
@@ -105,6 +107,7 @@ impl std::str::FromStr for Mechanism {
             "PLAIN" => Ok(Self::Plain),
             "LOGIN" => Ok(Self::Login),
             "CRAM-MD5" => Ok(Self::CramMd5),
+            "EXTERNAL" => Ok(Self::External),
             _ => anyhow::bail!("not a valid AUTH Mechanism: '{}'", s),
         }
     }
@@ -15,6 +15,7 @@
  *
 */
 use crate::{
+    dsn::DsnRcpt,
     transfer::{EmailTransferStatus, Transfer},
     Address,
 };
@@ -28,6 +29,9 @@ pub struct Rcpt {
     pub transfer_method: Transfer,
     /// delivery status of the email bound to this recipient.
     pub email_status: EmailTransferStatus,
+    /// delivery status notification parameters received using the RCPT TO command.
+    #[serde(default)]
+    pub dsn: DsnRcpt,
 }
 
 impl std::fmt::Debug for Rcpt {
@@ -45,6 +49,7 @@ impl Rcpt {
             address,
             transfer_method: Transfer::Deliver,
             email_status: EmailTransferStatus::Waiting,
+            dsn: DsnRcpt::new(),
         }
     }
 
@@ -55,6 +60,7 @@ impl Rcpt {
             address,
             transfer_method: method,
             email_status: EmailTransferStatus::Waiting,
+            dsn: DsnRcpt::new(),
         }
     }
 }
@@ -93,16 +99,20 @@ pub fn filter_by_transfer_method(rcpt: &[Rcpt]) -> std::collections::HashMap<Tra
 }
 
 /// filter recipients by domain name using mutable reference on the recipients.
+///
+/// internationalized domain names are grouped by their ascii form (punycode),
+/// which is the form used for the dns lookups.
 #[must_use]
 pub fn filter_by_domain_mut(
     rcpt: &mut [Rcpt],
 ) -> std::collections::HashMap<String, Vec<&mut Rcpt>> {
     let mut acc = std::collections::HashMap::<String, Vec<&mut Rcpt>>::new();
     for rcpt in rcpt.iter_mut() {
-        if let Some(domain) = acc.get_mut(rcpt.address.domain()) {
+        let domain = rcpt.address.domain_ascii();
+        if let Some(domain) = acc.get_mut(&domain) {
             domain.push(rcpt);
         } else {
-            acc.insert(rcpt.address.domain().to_string(), vec![rcpt]);
+            acc.insert(domain, vec![rcpt]);
         }
     }
     acc
@@ -185,4 +195,18 @@ mod test {
             .all(|rcpt| rcpt.address.domain() == "localhost"));
         assert_eq!(filtered.get("localhost").unwrap().len(), 4);
     }
+
+    #[test]
+    fn test_filter_by_domain_mut_idn() {
+        let mut rcpt = vec![
+            Rcpt::from(addr!("green@bücher.example")),
+            Rcpt::from(addr!("john@xn--bcher-kva.example")),
+            Rcpt::from(addr!("用户@例子.广告")),
+        ];
+        let filtered = super::filter_by_domain_mut(&mut rcpt);
+
+        assert_eq!(filtered.len(), 2);
+        assert_eq!(filtered.get("xn--bcher-kva.example").unwrap().len(), 2);
+        assert_eq!(filtered.get("xn--fsqu00a.xn--4rr70v").unwrap().len(), 1);
+    }
 }
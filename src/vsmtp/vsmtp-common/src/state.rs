@@ -48,6 +48,14 @@ pub enum StateSMTP {
     RcptTo,
     /// After receiving DATA command
     Data,
+    /// After receiving a BDAT command which is not the last chunk
+    Chunking,
+    /// After receiving VRFY command
+    Verify,
+    /// After receiving EXPN command
+    Expand,
+    /// After receiving ETRN command
+    Etrn,
     /// Before write on disk
     PreQ,
     /// After receiving QUIT command
@@ -77,9 +85,13 @@ impl std::fmt::Display for StateSMTP {
             Self::Delivery => "delivery",
             Self::Authentication(_, _) => "authenticate",
             Self::Data => "data",
+            Self::Verify => "vrfy",
+            Self::Expand => "expn",
+            Self::Etrn => "etrn",
             // others
             Self::Stop => "Stop",
             Self::NegotiationTLS => "NegotiationTLS",
+            Self::Chunking => "Chunking",
         })
     }
 }
@@ -108,9 +120,13 @@ impl std::str::FromStr for StateSMTP {
                 Option::<Vec<u8>>::default(),
             )),
             "data" => Ok(Self::Data),
+            "vrfy" => Ok(Self::Verify),
+            "expn" => Ok(Self::Expand),
+            "etrn" => Ok(Self::Etrn),
             // others
             "Stop" => Ok(Self::Stop),
             "NegotiationTLS" => Ok(Self::NegotiationTLS),
+            "Chunking" => Ok(Self::Chunking),
             _ => anyhow::bail!("not a valid SMTP state: '{}'", s),
         }
     }
@@ -16,7 +16,8 @@
 */
 use crate::{
     code::SMTPReplyCode,
-    event::{Event, MimeBodyType},
+    dsn::{DsnMail, DsnRcpt, NotifyOn, OriginalRecipient, ReturnContent},
+    event::{Event, ForwardedClient, MimeBodyType},
     mechanism::Mechanism,
 };
 
@@ -157,6 +158,23 @@ fn ehlo_command() {
     );
 }
 
+#[test]
+fn lhlo_command() {
+    assert_eq!(
+        Event::parse_cmd("LHLO foobar"),
+        Ok(Event::LhloCmd("foobar".to_string()))
+    );
+    assert_eq!(
+        Event::parse_cmd("lhlo [127.0.0.1]"),
+        Ok(Event::LhloCmd("127.0.0.1".to_string()))
+    );
+    assert_eq!(Event::parse_cmd("LHLO  "), Err(SMTPReplyCode::Code501));
+    assert_eq!(
+        Event::parse_cmd("LHLO one two"),
+        Err(SMTPReplyCode::Code501)
+    );
+}
+
 #[test]
 fn command_mail_from() {
     assert_eq!(
@@ -164,7 +182,10 @@ fn command_mail_from() {
         Ok(Event::MailCmd(
             Some(addr!("valid@reverse.path.com")),
             None,
-            None
+            None,
+            None,
+            DsnMail::default(),
+            false
         ))
     );
     assert_eq!(
@@ -172,12 +193,22 @@ fn command_mail_from() {
         Ok(Event::MailCmd(
             Some(addr!("valid2@reverse.path.com")),
             None,
-            None
+            None,
+            None,
+            DsnMail::default(),
+            false
         ))
     );
     assert_eq!(
         Event::parse_cmd("MaIl From:   <>  "),
-        Ok(Event::MailCmd(None, None, None))
+        Ok(Event::MailCmd(
+            None,
+            None,
+            None,
+            None,
+            DsnMail::default(),
+            false
+        ))
     );
     // assert_eq!(
     //     Event::parse_cmd("MaIl From:   <local.part@[127.0.0.1]>  "),
@@ -188,7 +219,10 @@ fn command_mail_from() {
         Ok(Event::MailCmd(
             Some(addr!("\"john..doe\"@example.org")),
             None,
-            None
+            None,
+            None,
+            DsnMail::default(),
+            false
         ))
     );
     assert_eq!(
@@ -210,7 +244,10 @@ fn command_mail_from_8bitmime() {
         Ok(Event::MailCmd(
             Some(addr!("ned@ymir.claremont.edu")),
             Some(MimeBodyType::EightBitMime),
-            None
+            None,
+            None,
+            DsnMail::default(),
+            false
         ))
     );
 
@@ -219,7 +256,10 @@ fn command_mail_from_8bitmime() {
         Ok(Event::MailCmd(
             Some(addr!("ned@ymir.claremont.edu")),
             Some(MimeBodyType::SevenBit),
-            None
+            None,
+            None,
+            DsnMail::default(),
+            false
         ))
     );
 
@@ -252,7 +292,10 @@ fn command_mail_from_international() {
         Ok(Event::MailCmd(
             Some(addr!("ned@ymir.claremont.edu")),
             None,
-            None
+            None,
+            None,
+            DsnMail::default(),
+            true
         ))
     );
     assert_eq!(
@@ -261,7 +304,25 @@ fn command_mail_from_international() {
     );
     assert_eq!(
         Event::parse_cmd("MAIL FROM:<用户@例子.广告> SMTPUTF8"),
-        Ok(Event::MailCmd(Some(addr!("用户@例子.广告")), None, None))
+        Ok(Event::MailCmd(
+            Some(addr!("用户@例子.广告")),
+            None,
+            None,
+            None,
+            DsnMail::default(),
+            true
+        ))
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<用户@例子.广告>"),
+        Ok(Event::MailCmd(
+            Some(addr!("用户@例子.广告")),
+            None,
+            None,
+            None,
+            DsnMail::default(),
+            false
+        ))
     );
 }
 
@@ -272,7 +333,10 @@ fn command_mail_from_auth() {
         Ok(Event::MailCmd(
             Some(addr!("e=mc2@example.com")),
             None,
-            Some("e+3Dmc2@example.com".to_string())
+            Some("e+3Dmc2@example.com".to_string()),
+            None,
+            DsnMail::default(),
+            false
         ))
     );
     assert_eq!(
@@ -280,7 +344,10 @@ fn command_mail_from_auth() {
         Ok(Event::MailCmd(
             Some(addr!("ned@ymir.claremont.edu")),
             None,
-            Some("<>".to_string())
+            Some("<>".to_string()),
+            None,
+            DsnMail::default(),
+            false
         ))
     );
     assert_eq!(
@@ -289,17 +356,168 @@ fn command_mail_from_auth() {
     );
 }
 
+#[test]
+fn command_mail_from_size() {
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> SIZE=500000"),
+        Ok(Event::MailCmd(
+            Some(addr!("ned@ymir.claremont.edu")),
+            None,
+            None,
+            Some(500_000),
+            DsnMail::default(),
+            false
+        ))
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> BODY=8BITMIME SIZE=0"),
+        Ok(Event::MailCmd(
+            Some(addr!("ned@ymir.claremont.edu")),
+            Some(MimeBodyType::EightBitMime),
+            None,
+            Some(0),
+            DsnMail::default(),
+            false
+        ))
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> SIZE=foo"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> SIZE=-1"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> SIZE=10 SIZE=20"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd(&format!(
+            "MAIL FROM:<{}@ymir.claremont.edu> SIZE=10000000",
+            "a".repeat(60)
+        )),
+        Ok(Event::MailCmd(
+            Some(addr!(&format!("{}@ymir.claremont.edu", "a".repeat(60)))),
+            None,
+            None,
+            Some(10_000_000),
+            DsnMail::default(),
+            false
+        ))
+    );
+}
+
+#[test]
+fn command_mail_from_binarymime() {
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> BODY=BINARYMIME"),
+        Ok(Event::MailCmd(
+            Some(addr!("ned@ymir.claremont.edu")),
+            Some(MimeBodyType::Binary),
+            None,
+            None,
+            DsnMail::default(),
+            false
+        ))
+    );
+}
+
+#[test]
+fn command_mail_from_dsn() {
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> RET=HDRS ENVID=QQ314159+2Bx"),
+        Ok(Event::MailCmd(
+            Some(addr!("ned@ymir.claremont.edu")),
+            None,
+            None,
+            None,
+            DsnMail {
+                ret: Some(ReturnContent::Headers),
+                envid: Some("QQ314159+x".to_string())
+            },
+            false
+        ))
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> RET=full"),
+        Ok(Event::MailCmd(
+            Some(addr!("ned@ymir.claremont.edu")),
+            None,
+            None,
+            None,
+            DsnMail {
+                ret: Some(ReturnContent::Full),
+                envid: None
+            },
+            false
+        ))
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> RET=foo"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> RET=FULL RET=HDRS"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("MAIL FROM:<ned@ymir.claremont.edu> ENVID=a=b"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd(&format!(
+            "MAIL FROM:<ned@ymir.claremont.edu> ENVID={}",
+            "a".repeat(101)
+        )),
+        Err(SMTPReplyCode::Code501)
+    );
+}
+
+#[test]
+fn command_bdat() {
+    assert_eq!(
+        Event::parse_cmd("BDAT 1000"),
+        Ok(Event::BdatCmd(1000, false))
+    );
+    assert_eq!(
+        Event::parse_cmd("BDAT 1000 LAST"),
+        Ok(Event::BdatCmd(1000, true))
+    );
+    assert_eq!(Event::parse_cmd("bdat 0 last"), Ok(Event::BdatCmd(0, true)));
+    assert_eq!(Event::parse_cmd("BDAT"), Err(SMTPReplyCode::Code501));
+    assert_eq!(Event::parse_cmd("BDAT -1"), Err(SMTPReplyCode::Code501));
+    assert_eq!(
+        Event::parse_cmd("BDAT foo LAST"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("BDAT 10 FIRST"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("BDAT 10 LAST foo"),
+        Err(SMTPReplyCode::Code501)
+    );
+}
+
 #[test]
 fn command_rcpt_to() {
     // TODO: RCPT TO:<@hosta.int,@jkl.org:userc@d.bar.org>
 
     assert_eq!(
         Event::parse_cmd("RcPt To:<valid@forward.path.com>"),
-        Ok(Event::RcptCmd(addr!("valid@forward.path.com")))
+        Ok(Event::RcptCmd(
+            addr!("valid@forward.path.com"),
+            DsnRcpt::default()
+        ))
     );
     assert_eq!(
         Event::parse_cmd("rCpT TO: <valid2@forward.path.com>"),
-        Ok(Event::RcptCmd(addr!("valid2@forward.path.com")))
+        Ok(Event::RcptCmd(
+            addr!("valid2@forward.path.com"),
+            DsnRcpt::default()
+        ))
     );
     assert_eq!(
         Event::parse_cmd("RCPT TO:   <>  "),
@@ -311,7 +529,10 @@ fn command_rcpt_to() {
     // );
     assert_eq!(
         Event::parse_cmd("rcpt to:   <\"john..doe\"@example.org>  "),
-        Ok(Event::RcptCmd(addr!("\"john..doe\"@example.org")))
+        Ok(Event::RcptCmd(
+            addr!("\"john..doe\"@example.org"),
+            DsnRcpt::default()
+        ))
     );
     assert_eq!(
         Event::parse_cmd("RCPT TO:   <ibm@com>  extra_arg "),
@@ -327,7 +548,52 @@ fn command_rcpt_to() {
 fn command_rcpt_to_international() {
     assert_eq!(
         Event::parse_cmd("RCPT TO:<用户@例子.广告>"),
-        Ok(Event::RcptCmd(addr!("用户@例子.广告")))
+        Ok(Event::RcptCmd(addr!("用户@例子.广告"), DsnRcpt::default()))
+    );
+}
+
+#[test]
+fn command_rcpt_to_dsn() {
+    assert_eq!(
+        Event::parse_cmd(
+            "RCPT TO:<Bob@Example.COM> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;Bob+2Bx@Example.COM"
+        ),
+        Ok(Event::RcptCmd(
+            addr!("Bob@Example.COM"),
+            DsnRcpt {
+                notify: Some(NotifyOn {
+                    success: true,
+                    failure: true,
+                    delay: false
+                }),
+                orcpt: Some(OriginalRecipient {
+                    addr_type: "rfc822".to_string(),
+                    mailbox: "Bob+x@Example.COM".to_string()
+                })
+            }
+        ))
+    );
+    assert_eq!(
+        Event::parse_cmd("RCPT TO:<Bob@Example.COM> NOTIFY=NEVER"),
+        Ok(Event::RcptCmd(
+            addr!("Bob@Example.COM"),
+            DsnRcpt {
+                notify: Some(NotifyOn::never()),
+                orcpt: None
+            }
+        ))
+    );
+    assert_eq!(
+        Event::parse_cmd("RCPT TO:<Bob@Example.COM> NOTIFY=NEVER,DELAY"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("RCPT TO:<Bob@Example.COM> NOTIFY=DELAY NOTIFY=SUCCESS"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("RCPT TO:<Bob@Example.COM> ORCPT=Bob@Example.COM"),
+        Err(SMTPReplyCode::Code501)
     );
 }
 
@@ -448,6 +714,78 @@ fn command_auth() {
     );
 }
 
+#[test]
+fn command_xclient() {
+    assert_eq!(Event::parse_cmd("XCLIENT"), Err(SMTPReplyCode::Code501));
+    assert_eq!(
+        Event::parse_cmd("XCLIENT ADDR=192.0.2.1 PORT=4242 HELO=mx.example.com LOGIN=john+2Bdoe"),
+        Ok(Event::XClientCmd(ForwardedClient {
+            addr: Some("192.0.2.1".parse().unwrap()),
+            port: Some(4242),
+            helo: Some("mx.example.com".to_string()),
+            login: Some("john+doe".to_string()),
+        }))
+    );
+    assert_eq!(
+        Event::parse_cmd("xclient addr=IPV6:2001:db8::1 NAME=[UNAVAILABLE] proto=ESMTP"),
+        Ok(Event::XClientCmd(ForwardedClient {
+            addr: Some("2001:db8::1".parse().unwrap()),
+            ..ForwardedClient::default()
+        }))
+    );
+    assert_eq!(
+        Event::parse_cmd("XCLIENT ADDR=[TEMPUNAVAIL]"),
+        Ok(Event::XClientCmd(ForwardedClient::default()))
+    );
+    assert_eq!(
+        Event::parse_cmd("XCLIENT ADDR=not_an_ip"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("XCLIENT PORT=65536"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("XCLIENT IDENT=foo"),
+        Err(SMTPReplyCode::Code501)
+    );
+    assert_eq!(
+        Event::parse_cmd("XCLIENT ADDR"),
+        Err(SMTPReplyCode::Code501)
+    );
+}
+
+#[test]
+fn command_xforward() {
+    assert_eq!(
+        Event::parse_cmd("XFORWARD ADDR=192.0.2.1 HELO=mx.example.com IDENT=123 SOURCE=REMOTE"),
+        Ok(Event::XForwardCmd(ForwardedClient {
+            addr: Some("192.0.2.1".parse().unwrap()),
+            helo: Some("mx.example.com".to_string()),
+            ..ForwardedClient::default()
+        }))
+    );
+    assert_eq!(
+        Event::parse_cmd("XFORWARD LOGIN=john"),
+        Err(SMTPReplyCode::Code501)
+    );
+}
+
+#[test]
+fn command_etrn() {
+    assert_eq!(
+        Event::parse_cmd("ETRN example.com"),
+        Ok(Event::EtrnCmd("example.com".to_string()))
+    );
+    assert_eq!(
+        Event::parse_cmd("etrn @example.com"),
+        Ok(Event::EtrnCmd("@example.com".to_string()))
+    );
+    assert_eq!(Event::parse_cmd("ETRN #queue"), Err(SMTPReplyCode::Code501));
+    assert_eq!(Event::parse_cmd("ETRN @"), Err(SMTPReplyCode::Code501));
+    assert_eq!(Event::parse_cmd("ETRN"), Err(SMTPReplyCode::Code501));
+}
+
 #[test]
 #[allow(clippy::too_many_lines, clippy::cognitive_complexity)]
 fn parse_path() {
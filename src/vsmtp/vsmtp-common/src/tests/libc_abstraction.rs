@@ -15,7 +15,8 @@
  *
 */
 use crate::libc_abstraction::{
-    chown, fork, if_indextoname, if_nametoindex, setgid, setsid, setuid, ForkResult,
+    chown, fork, if_indextoname, if_nametoindex, set_cloexec, setgid, setsid, setuid,
+    socket_family, ForkResult,
 };
 
 #[test]
@@ -92,3 +93,20 @@ fn test_chown_file() {
 
     std::fs::remove_file(file_to_create).unwrap();
 }
+
+#[test]
+fn test_socket_family() {
+    let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let fd = std::os::unix::io::AsRawFd::as_raw_fd(&tcp);
+    assert_eq!(socket_family(fd).unwrap(), libc::AF_INET);
+    assert!(set_cloexec(fd).is_ok());
+
+    let (unix, _) = std::os::unix::net::UnixStream::pair().unwrap();
+    assert_eq!(
+        socket_family(std::os::unix::io::AsRawFd::as_raw_fd(&unix)).unwrap(),
+        libc::AF_UNIX
+    );
+
+    assert!(socket_family(-1).is_err());
+    assert!(set_cloexec(-1).is_err());
+}
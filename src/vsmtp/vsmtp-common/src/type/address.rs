@@ -43,7 +43,7 @@ impl TryFrom<String> for Address {
             anyhow::bail!("'{}' is not a valid address: {}", value, error)
         }
         Ok(Self {
-            at_sign: unsafe { value.find('@').unwrap_unchecked() },
+            at_sign: unsafe { value.rfind('@').unwrap_unchecked() },
             full: value,
         })
     }
@@ -91,6 +91,29 @@ impl Address {
     pub fn domain(&self) -> &str {
         &self.full[self.at_sign + 1..]
     }
+
+    /// get the fqdn of the address, converted to its ascii form (punycode)
+    /// if it is an internationalized domain name.
+    ///
+    /// the domain is returned as-is if the conversion failed.
+    #[must_use]
+    pub fn domain_ascii(&self) -> String {
+        idna::domain_to_ascii(self.domain()).unwrap_or_else(|_| self.domain().to_string())
+    }
+
+    /// does the address contains non-ascii characters, and thus require
+    /// the `SMTPUTF8` extension to be transmitted. (RFC 6531)
+    #[must_use]
+    pub fn is_utf8(&self) -> bool {
+        !self.full.is_ascii()
+    }
+
+    /// can the address be transmitted to a server that does not support
+    /// the `SMTPUTF8` extension, by converting its domain to punycode.
+    #[must_use]
+    pub fn has_ascii_local_part(&self) -> bool {
+        self.local_part().is_ascii()
+    }
 }
 
 #[cfg(test)]
@@ -123,4 +146,23 @@ mod tests {
             r#""hello@domain.com""#
         );
     }
+
+    #[test]
+    fn internationalized() {
+        let ascii = addr!("hello@domain.com");
+        assert!(!ascii.is_utf8());
+        assert_eq!(ascii.domain_ascii(), "domain.com");
+
+        let idn = addr!("hello@bücher.example");
+        assert!(idn.is_utf8());
+        assert!(idn.has_ascii_local_part());
+        assert_eq!(idn.domain(), "bücher.example");
+        assert_eq!(idn.domain_ascii(), "xn--bcher-kva.example");
+
+        let utf8 = addr!("用户@例子.广告");
+        assert!(utf8.is_utf8());
+        assert!(!utf8.has_ascii_local_part());
+        assert_eq!(utf8.local_part(), "用户");
+        assert_eq!(utf8.domain_ascii(), "xn--fsqu00a.xn--4rr70v");
+    }
 }
@@ -18,10 +18,11 @@ use super::{wants::WantsValidate, with::Builder};
 use crate::{
     config::{
         ConfigApp, ConfigAppLogs, ConfigAppVSL, ConfigServer, ConfigServerInterfaces,
-        ConfigServerLogs, ConfigServerQueues, ConfigServerSMTP, ConfigServerSMTPError,
-        ConfigServerSMTPTimeoutClient, ConfigServerSystem, ConfigServerSystemThreadPool,
+        ConfigServerLogs, ConfigServerMtaSts, ConfigServerQueues, ConfigServerSMTP,
+        ConfigServerSMTPError, ConfigServerSMTPTimeoutClient, ConfigServerSystem,
+        ConfigServerSystemThreadPool,
     },
-    Config,
+    Config, ConfigServerDNS, ConfigServerTlsRpt, TlsSecurityLevel,
 };
 use vsmtp_common::{
     auth::Mechanism,
@@ -62,6 +63,8 @@ impl Builder<WantsValidate> {
                     user: srv_syst.user,
                     group: srv_syst.group,
                     group_local: srv_syst.group_local,
+                    pid_file: srv_syst.pid_file,
+                    shutdown_grace_period: srv_syst.shutdown_grace_period,
                     thread_pool: ConfigServerSystemThreadPool {
                         receiver: srv_syst.thread_pool_receiver,
                         processing: srv_syst.thread_pool_processing,
@@ -72,6 +75,9 @@ impl Builder<WantsValidate> {
                     addr: srv_inet.addr,
                     addr_submission: srv_inet.addr_submission,
                     addr_submissions: srv_inet.addr_submissions,
+                    addr_lmtp: srv_inet.addr_lmtp,
+                    listeners: srv_inet.listeners,
+                    proxy_protocol: srv_inet.proxy_protocol,
                 },
                 logs: ConfigServerLogs {
                     filepath: srv_logs.filepath,
@@ -88,7 +94,14 @@ impl Builder<WantsValidate> {
                 tls: srv_tls.tls,
                 smtp: ConfigServerSMTP {
                     rcpt_count_max: smtp_opt.rcpt_count_max,
+                    message_size_max: smtp_opt.message_size_max,
                     disable_ehlo: smtp_opt.disable_ehlo,
+                    rate_limit: smtp_opt.rate_limit,
+                    greet_pause: smtp_opt.greet_pause,
+                    trusted_forwarders: smtp_opt.trusted_forwarders,
+                    enable_vrfy: smtp_opt.enable_vrfy,
+                    enable_expn: smtp_opt.enable_expn,
+                    enable_etrn: smtp_opt.enable_etrn,
                     required_extension: smtp_opt.required_extension,
                     error: ConfigServerSMTPError {
                         soft_count: smtp_error.error.soft_count,
@@ -107,6 +120,9 @@ impl Builder<WantsValidate> {
                 },
                 dns: dns.config,
                 r#virtual: virtual_entries.r#virtual,
+                tls_policy: std::collections::BTreeMap::default(),
+                mta_sts: ConfigServerMtaSts::default(),
+                tls_rpt: ConfigServerTlsRpt::default(),
             },
             app: ConfigApp {
                 dirpath: app.dirpath,
@@ -150,6 +166,78 @@ impl Config {
             "Worker threads cannot be set to 0"
         );
 
+        for listener in &config.server.interfaces.listeners {
+            anyhow::ensure!(
+                !listener.addr.is_empty() || listener.unix.is_some(),
+                "A listener of kind '{:?}' has no address to bind",
+                listener.kind
+            );
+            anyhow::ensure!(
+                !listener.proxy_protocol || config.server.interfaces.proxy_protocol.is_some(),
+                "Listener {:?} expects a PROXY header but no trusted proxy is configured",
+                listener.addr
+            );
+            anyhow::ensure!(
+                !listener.tls_required || config.server.tls.is_some(),
+                "Listener {:?} requires TLS but no TLS configuration is provided",
+                listener.addr
+            );
+        }
+
+        // "SIZE 0" would mean no limit for the clients (rfc1870 section 4), but every message is rejected.
+        anyhow::ensure!(
+            config.server.smtp.message_size_max != 0,
+            "The maximum size of the messages cannot be zero"
+        );
+
+        if let Some(rate_limit) = &config.server.smtp.rate_limit {
+            anyhow::ensure!(
+                rate_limit.ipv4.iter().all(|network| network.prefix <= 32)
+                    && rate_limit.ipv6.iter().all(|network| network.prefix <= 128),
+                "The prefix of the networks of the rate limit is out of range"
+            );
+            anyhow::ensure!(
+                !rate_limit.window.is_zero(),
+                "The window of the rate limit cannot be zero"
+            );
+        }
+
+        // the tlsa records can only be trusted if the dns responses are validated with dnssec,
+        // any resolver can be used to look them up.
+        if config
+            .server
+            .tls
+            .iter()
+            .map(|tls| tls.sender_security_level)
+            .chain(
+                config
+                    .server
+                    .r#virtual
+                    .values()
+                    .filter_map(|entry| entry.tls.as_ref())
+                    .map(|tls| tls.sender_security_level),
+            )
+            .any(|level| matches!(level, TlsSecurityLevel::Dane { .. }))
+        {
+            anyhow::ensure!(
+                std::iter::once(&config.server.dns)
+                    .chain(
+                        config
+                            .server
+                            .r#virtual
+                            .values()
+                            .filter_map(|entry| entry.dns.as_ref())
+                    )
+                    .all(|dns| match dns {
+                        ConfigServerDNS::System => false,
+                        ConfigServerDNS::Google { options }
+                        | ConfigServerDNS::CloudFlare { options }
+                        | ConfigServerDNS::Custom { options, .. } => options.dnssec,
+                    }),
+                "DANE requires the dns responses to be validated with dnssec, the 'system' resolver cannot be used and 'dnssec' must be enabled"
+            );
+        }
+
         {
             let default_values = ConfigServerSMTP::default_smtp_codes();
             let reply_codes = &mut config.server.smtp.codes;
@@ -199,6 +287,26 @@ impl Config {
                     })
                     .unwrap_or_default(),
                 "STARTTLS\r\n",
+                "PIPELINING\r\n",
+                &format!("SIZE {}\r\n", config.server.smtp.message_size_max),
+                "CHUNKING\r\n",
+                "BINARYMIME\r\n",
+                "DSN\r\n",
+                if config.server.smtp.enable_vrfy {
+                    "VRFY\r\n"
+                } else {
+                    ""
+                },
+                if config.server.smtp.enable_expn {
+                    "EXPN\r\n"
+                } else {
+                    ""
+                },
+                if config.server.smtp.enable_etrn {
+                    "ETRN\r\n"
+                } else {
+                    ""
+                },
                 "8BITMIME\r\n",
                 "SMTPUTF8\r\n",
             ]
@@ -213,6 +321,26 @@ impl Config {
                     .as_ref()
                     .map(|(must_be_secured, _)| mech_list_to_code(must_be_secured))
                     .unwrap_or_default(),
+                "PIPELINING\r\n",
+                &format!("SIZE {}\r\n", config.server.smtp.message_size_max),
+                "CHUNKING\r\n",
+                "BINARYMIME\r\n",
+                "DSN\r\n",
+                if config.server.smtp.enable_vrfy {
+                    "VRFY\r\n"
+                } else {
+                    ""
+                },
+                if config.server.smtp.enable_expn {
+                    "EXPN\r\n"
+                } else {
+                    ""
+                },
+                if config.server.smtp.enable_etrn {
+                    "ETRN\r\n"
+                } else {
+                    ""
+                },
                 "8BITMIME\r\n",
                 "SMTPUTF8\r\n",
             ]
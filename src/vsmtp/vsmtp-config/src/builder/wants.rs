@@ -20,7 +20,8 @@ use vsmtp_common::{code::SMTPReplyCode, re::log};
 
 use crate::{
     config::{
-        ConfigQueueDelivery, ConfigQueueWorking, ConfigServerDNS, ConfigServerSMTPError,
+        ConfigQueueDelivery, ConfigQueueWorking, ConfigServerDNS, ConfigServerInterfacesProxy,
+        ConfigServerListener, ConfigServerSMTPError, ConfigServerSMTPRateLimit,
         ConfigServerSMTPTimeoutClient, ConfigServerTls,
     },
     ConfigServerSMTPAuth, ConfigServerVirtual,
@@ -49,6 +50,8 @@ pub struct WantsServerInterfaces {
     pub(super) user: users::User,
     pub(super) group: users::Group,
     pub(super) group_local: Option<users::Group>,
+    pub(super) pid_file: Option<std::path::PathBuf>,
+    pub(super) shutdown_grace_period: std::time::Duration,
     pub(super) thread_pool_receiver: usize,
     pub(super) thread_pool_processing: usize,
     pub(super) thread_pool_delivery: usize,
@@ -60,6 +63,9 @@ pub struct WantsServerLogs {
     pub(super) addr: Vec<std::net::SocketAddr>,
     pub(super) addr_submission: Vec<std::net::SocketAddr>,
     pub(super) addr_submissions: Vec<std::net::SocketAddr>,
+    pub(super) addr_lmtp: Vec<std::net::SocketAddr>,
+    pub(super) listeners: Vec<ConfigServerListener>,
+    pub(super) proxy_protocol: Option<ConfigServerInterfacesProxy>,
 }
 
 ///
@@ -90,7 +96,14 @@ pub struct WantsServerSMTPConfig1 {
 pub struct WantsServerSMTPConfig2 {
     pub(crate) parent: WantsServerSMTPConfig1,
     pub(super) rcpt_count_max: usize,
+    pub(super) message_size_max: usize,
     pub(super) disable_ehlo: bool,
+    pub(super) rate_limit: Option<ConfigServerSMTPRateLimit>,
+    pub(super) greet_pause: Option<std::time::Duration>,
+    pub(super) trusted_forwarders: Vec<ipnet::IpNet>,
+    pub(super) enable_vrfy: bool,
+    pub(super) enable_expn: bool,
+    pub(super) enable_etrn: bool,
     pub(super) required_extension: Vec<String>,
 }
 
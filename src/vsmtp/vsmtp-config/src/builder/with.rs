@@ -191,6 +191,8 @@ impl Builder<WantsServerSystem> {
                 user,
                 group,
                 group_local,
+                pid_file: ConfigServerSystem::default_pid_file(),
+                shutdown_grace_period: ConfigServerSystem::default_shutdown_grace_period(),
                 thread_pool_receiver,
                 thread_pool_processing,
                 thread_pool_delivery,
@@ -226,6 +228,8 @@ impl Builder<WantsServerSystem> {
                 } else {
                     None
                 },
+                pid_file: ConfigServerSystem::default_pid_file(),
+                shutdown_grace_period: ConfigServerSystem::default_shutdown_grace_period(),
                 thread_pool_receiver,
                 thread_pool_processing,
                 thread_pool_delivery,
@@ -260,6 +264,9 @@ impl Builder<WantsServerInterfaces> {
                 addr: addr.to_vec(),
                 addr_submission: addr_submission.to_vec(),
                 addr_submissions: addr_submissions.to_vec(),
+                addr_lmtp: vec![],
+                listeners: vec![],
+                proxy_protocol: None,
             },
         }
     }
@@ -353,12 +360,14 @@ impl Builder<WantsServerTLSConfig> {
                 parent: self.state,
                 tls: Some(ConfigServerTls {
                     security_level: TlsSecurityLevel::May,
+                    sender_security_level: ConfigServerTls::default_sender_security_level(),
                     preempt_cipherlist: false,
                     handshake_timeout: std::time::Duration::from_millis(200),
                     protocol_version: vec![rustls::ProtocolVersion::TLSv1_3],
                     certificate: tls_certificate::from_string(certificate)?,
                     private_key: tls_private_key::from_string(private_key)?,
                     cipher_suite: ConfigServerTls::default_cipher_suite(),
+                    client_auth: None,
                 }),
             },
         })
@@ -388,12 +397,32 @@ impl Builder<WantsServerSMTPConfig1> {
     pub fn with_rcpt_count_and_default(
         self,
         rcpt_count_max: usize,
+    ) -> Builder<WantsServerSMTPConfig2> {
+        self.with_rcpt_count_and_message_size(
+            rcpt_count_max,
+            ConfigServerSMTP::default_message_size_max(),
+        )
+    }
+
+    ///
+    #[must_use]
+    pub fn with_rcpt_count_and_message_size(
+        self,
+        rcpt_count_max: usize,
+        message_size_max: usize,
     ) -> Builder<WantsServerSMTPConfig2> {
         Builder::<WantsServerSMTPConfig2> {
             state: WantsServerSMTPConfig2 {
                 parent: self.state,
                 rcpt_count_max,
+                message_size_max,
                 disable_ehlo: ConfigServerSMTP::default_disable_ehlo(),
+                rate_limit: ConfigServerSMTP::default_rate_limit(),
+                greet_pause: ConfigServerSMTP::default_greet_pause(),
+                trusted_forwarders: ConfigServerSMTP::default_trusted_forwarders(),
+                enable_vrfy: ConfigServerSMTP::default_enable_vrfy(),
+                enable_expn: ConfigServerSMTP::default_enable_expn(),
+                enable_etrn: ConfigServerSMTP::default_enable_etrn(),
                 required_extension: ConfigServerSMTP::default_required_extension(),
             },
         }
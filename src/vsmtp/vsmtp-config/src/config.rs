@@ -63,6 +63,14 @@ pub struct ConfigServer {
     pub dns: ConfigServerDNS,
     #[serde(default)]
     pub r#virtual: std::collections::BTreeMap<String, ConfigServerVirtual>,
+    /// tls policy of the connections opened to deliver the messages, by destination domain
+    /// or by mail exchanger. the destinations that are not listed use opportunistic tls.
+    #[serde(default)]
+    pub tls_policy: std::collections::BTreeMap<String, ConfigTlsPolicy>,
+    #[serde(default)]
+    pub mta_sts: ConfigServerMtaSts,
+    #[serde(default)]
+    pub tls_rpt: ConfigServerTlsRpt,
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -86,6 +94,15 @@ pub struct ConfigServerSystem {
         deserialize_with = "crate::parser::syst_group::opt_deserialize"
     )]
     pub group_local: Option<users::Group>,
+    /// file where the pid of the server is written, used to signal the running instance
+    #[serde(default)]
+    pub pid_file: Option<std::path::PathBuf>,
+    /// on SIGTERM, delay left to the messages being received and processed before exiting
+    #[serde(
+        with = "humantime_serde",
+        default = "ConfigServerSystem::default_shutdown_grace_period"
+    )]
+    pub shutdown_grace_period: std::time::Duration,
     #[serde(default)]
     pub thread_pool: ConfigServerSystemThreadPool,
 }
@@ -94,6 +111,8 @@ impl PartialEq for ConfigServerSystem {
     fn eq(&self, other: &Self) -> bool {
         self.user.uid() == other.user.uid()
             && self.group.gid() == other.group.gid()
+            && self.pid_file == other.pid_file
+            && self.shutdown_grace_period == other.shutdown_grace_period
             && self.thread_pool == other.thread_pool
     }
 }
@@ -111,12 +130,167 @@ pub struct ConfigServerSystemThreadPool {
 #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigServerInterfaces {
-    #[serde(deserialize_with = "crate::parser::socket_addr::deserialize")]
+    #[serde(default, deserialize_with = "crate::parser::socket_addr::deserialize")]
     pub addr: Vec<std::net::SocketAddr>,
-    #[serde(deserialize_with = "crate::parser::socket_addr::deserialize")]
+    #[serde(default, deserialize_with = "crate::parser::socket_addr::deserialize")]
     pub addr_submission: Vec<std::net::SocketAddr>,
-    #[serde(deserialize_with = "crate::parser::socket_addr::deserialize")]
+    #[serde(default, deserialize_with = "crate::parser::socket_addr::deserialize")]
     pub addr_submissions: Vec<std::net::SocketAddr>,
+    #[serde(default, deserialize_with = "crate::parser::socket_addr::deserialize")]
+    pub addr_lmtp: Vec<std::net::SocketAddr>,
+    #[serde(default)]
+    pub listeners: Vec<ConfigServerListener>,
+    #[serde(default)]
+    pub proxy_protocol: Option<ConfigServerInterfacesProxy>,
+}
+
+impl ConfigServerInterfaces {
+    /// every listener of the server: the `addr*` fields are shorthands for
+    /// listeners with the default settings, followed by the `listeners` entries.
+    #[must_use]
+    pub fn listeners(&self) -> Vec<ConfigServerListener> {
+        let proxy = self.proxy_protocol.as_ref();
+
+        [
+            (
+                &self.addr,
+                ListenerKind::Plain,
+                proxy.map_or(false, |proxy| proxy.addr),
+            ),
+            (
+                &self.addr_submission,
+                ListenerKind::Submission,
+                proxy.map_or(false, |proxy| proxy.addr_submission),
+            ),
+            (
+                &self.addr_submissions,
+                ListenerKind::Tunneled,
+                proxy.map_or(false, |proxy| proxy.addr_submissions),
+            ),
+            (
+                &self.addr_lmtp,
+                ListenerKind::Lmtp,
+                proxy.map_or(false, |proxy| proxy.addr_lmtp),
+            ),
+        ]
+        .into_iter()
+        .filter(|(addr, _, _)| !addr.is_empty())
+        .map(|(addr, kind, proxy_protocol)| ConfigServerListener {
+            proxy_protocol,
+            ..ConfigServerListener::new(addr.clone(), kind)
+        })
+        .chain(self.listeners.iter().cloned())
+        .collect()
+    }
+}
+
+/// how the connections accepted by a listener are handled
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerKind {
+    /// SMTP, the connection may be upgraded with STARTTLS
+    Plain,
+    /// message submission (rfc6409), the connection may be upgraded with STARTTLS
+    Submission,
+    /// the connection is under TLS from the start (rfc8314)
+    Tunneled,
+    /// Local Mail Transfer Protocol (rfc2033)
+    Lmtp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigServerListener {
+    #[serde(default, deserialize_with = "crate::parser::socket_addr::deserialize")]
+    pub addr: Vec<std::net::SocketAddr>,
+    /// unix domain socket to bind, for the local applications
+    #[serde(default)]
+    pub unix: Option<ConfigServerListenerUnix>,
+    pub kind: ListenerKind,
+    /// the client must be authenticated before sending a message
+    #[serde(default)]
+    pub auth_required: bool,
+    /// the connection must be under TLS before sending a message
+    #[serde(default)]
+    pub tls_required: bool,
+    /// a PROXY header is expected at the beginning of each connection,
+    /// see [`ConfigServerInterfacesProxy`]
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// vsl entry point of the connections accepted by this listener,
+    /// the rules of `app.vsl.filepath` are used if missing
+    #[serde(default)]
+    pub vsl: Option<std::path::PathBuf>,
+}
+
+impl ConfigServerListener {
+    /// create a listener of the given kind, without any additional requirement.
+    #[must_use]
+    pub const fn new(addr: Vec<std::net::SocketAddr>, kind: ListenerKind) -> Self {
+        Self {
+            addr,
+            unix: None,
+            kind,
+            auth_required: false,
+            tls_required: false,
+            proxy_protocol: false,
+            vsl: None,
+        }
+    }
+}
+
+/// unix domain socket of a listener, the credentials of the peer are available in the rules
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigServerListenerUnix {
+    /// path of the socket, an existing file is replaced
+    pub path: std::path::PathBuf,
+    /// owner of the socket file
+    #[serde(default)]
+    #[serde(
+        serialize_with = "crate::parser::syst_user::opt_serialize",
+        deserialize_with = "crate::parser::syst_user::opt_deserialize"
+    )]
+    pub user: Option<users::User>,
+    /// group of the socket file
+    #[serde(default)]
+    #[serde(
+        serialize_with = "crate::parser::syst_group::opt_serialize",
+        deserialize_with = "crate::parser::syst_group::opt_deserialize"
+    )]
+    pub group: Option<users::Group>,
+    /// permissions of the socket file, for example `0o660`
+    #[serde(default)]
+    pub mode: Option<u32>,
+}
+
+impl PartialEq for ConfigServerListenerUnix {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+            && self.user.as_ref().map(users::User::uid) == other.user.as_ref().map(users::User::uid)
+            && self.group.as_ref().map(users::Group::gid)
+                == other.group.as_ref().map(users::Group::gid)
+            && self.mode == other.mode
+    }
+}
+
+impl Eq for ConfigServerListenerUnix {}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigServerInterfacesProxy {
+    #[serde(default)]
+    pub addr: bool,
+    #[serde(default)]
+    pub addr_submission: bool,
+    #[serde(default)]
+    pub addr_submissions: bool,
+    #[serde(default)]
+    pub addr_lmtp: bool,
+    pub trusted: Vec<ipnet::IpNet>,
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "ConfigServerInterfacesProxy::default_timeout")]
+    pub timeout: std::time::Duration,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
@@ -138,6 +312,13 @@ pub struct ConfigServerLogs {
 #[serde(deny_unknown_fields)]
 pub struct ConfigQueueWorking {
     pub channel_size: usize,
+    /// period between two scans of the pickup directory,
+    /// where the messages submitted with `vsendmail` are dropped
+    #[serde(
+        with = "humantime_serde",
+        default = "ConfigQueueWorking::default_pickup_period"
+    )]
+    pub pickup_period: std::time::Duration,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
@@ -271,13 +452,76 @@ pub enum TlsSecurityLevel {
     /// Connection must be under a TLS tunnel (using STARTTLS mechanism or using port 465)
     Encrypt,
     /// DANE protocol using TLSA dns records to establish a secure connection with a distant server.
+    ///
+    /// `port` is the port of the mail exchangers, used to connect to them and to look up
+    /// their `_{port}._tcp` TLSA records. The dns responses must be validated with dnssec
+    /// (see `server.dns.options.dnssec`), the configuration is rejected otherwise.
     Dane { port: u16 },
 }
 
+/// How the connections opened to deliver the messages to a destination are secured.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ConfigTlsPolicy {
+    /// The connection stays in plain text, for the servers with a broken tls support.
+    None,
+    /// STARTTLS is used if the server offers it, but the certificate is not verified.
+    May,
+    /// STARTTLS is required, but the certificate is not verified.
+    Encrypt,
+    /// STARTTLS is required, and the certificate must be valid for `name` (the mail exchanger
+    /// by default), issued by a public authority or by one of the `ca_file` authorities.
+    Verify {
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(
+            default,
+            deserialize_with = "crate::parser::tls_certificate::deserialize_bundle"
+        )]
+        #[serde(skip_serializing)]
+        ca_file: Vec<rustls::Certificate>,
+    },
+    /// STARTTLS is required, and the sha256 fingerprint of the certificate must be one of `sha256`.
+    Fingerprint {
+        #[serde(deserialize_with = "crate::parser::fingerprint::deserialize")]
+        sha256: Vec<String>,
+    },
+}
+
+/// MTA-STS policies of the destination domains, see https://www.rfc-editor.org/rfc/rfc8461
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigServerMtaSts {
+    /// the policies are fetched, cached in `{server.queues.dirpath}/mta-sts`
+    /// and enforced when delivering the messages.
+    #[serde(default)]
+    pub enable: bool,
+}
+
+/// SMTP TLS reporting to the destination domains, see https://www.rfc-editor.org/rfc/rfc8460
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigServerTlsRpt {
+    /// the results of the tls sessions opened to deliver the messages are recorded in
+    /// `{server.queues.dirpath}/tls-rpt`, and a daily report is sent to the destination
+    /// domains publishing a `_smtp._tls` record.
+    #[serde(default)]
+    pub enable: bool,
+    /// name of the organization sending the reports, `server.domain` by default.
+    #[serde(default)]
+    pub organization_name: Option<String>,
+    /// contact of the organization sending the reports, `mailto:postmaster@{server.domain}` by default.
+    #[serde(default)]
+    pub contact_info: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigServerTls {
     pub security_level: TlsSecurityLevel,
+    /// security level of the connections opened to deliver the messages of the root domain
+    #[serde(default = "ConfigServerTls::default_sender_security_level")]
+    pub sender_security_level: TlsSecurityLevel,
     pub preempt_cipherlist: bool,
     #[serde(with = "humantime_serde")]
     pub handshake_timeout: std::time::Duration,
@@ -304,6 +548,24 @@ pub struct ConfigServerTls {
     )]
     #[serde(skip_serializing)]
     pub private_key: rustls::PrivateKey,
+    /// verification of the certificates of the clients, none are requested if missing
+    #[serde(default)]
+    pub client_auth: Option<ConfigServerTlsClientAuth>,
+}
+
+/// The clients are asked for a certificate signed by one of the `ca_file` authorities,
+/// its identity is given to the rules and to the `EXTERNAL` authentication mechanism.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigServerTlsClientAuth {
+    /// certificates of the authorities trusted to sign the certificates of the clients
+    #[serde(deserialize_with = "crate::parser::tls_certificate::deserialize_bundle")]
+    #[serde(skip_serializing)]
+    pub ca_file: Vec<rustls::Certificate>,
+    /// the handshake fails if the client does not send a certificate,
+    /// otherwise the clients without certificate are accepted as anonymous
+    #[serde(default = "ConfigServerTlsClientAuth::default_required")]
+    pub required: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
@@ -348,8 +610,18 @@ pub struct ConfigServerSMTPAuth {
 pub struct ConfigServerSMTP {
     #[serde(default = "ConfigServerSMTP::default_rcpt_count_max")]
     pub rcpt_count_max: usize,
+    #[serde(default = "ConfigServerSMTP::default_message_size_max")]
+    pub message_size_max: usize,
     #[serde(default = "ConfigServerSMTP::default_disable_ehlo")]
     pub disable_ehlo: bool,
+    #[serde(default = "ConfigServerSMTP::default_enable_vrfy")]
+    pub enable_vrfy: bool,
+    #[serde(default = "ConfigServerSMTP::default_enable_expn")]
+    pub enable_expn: bool,
+    /// allow the clients to start the delivery of the deferred messages of a domain
+    /// with the ETRN command, subject to the `etrn` stage of the rules
+    #[serde(default = "ConfigServerSMTP::default_enable_etrn")]
+    pub enable_etrn: bool,
     // TODO: parse extension enum
     #[serde(default = "ConfigServerSMTP::default_required_extension")]
     pub required_extension: Vec<String>,
@@ -361,6 +633,55 @@ pub struct ConfigServerSMTP {
     pub codes: std::collections::BTreeMap<SMTPReplyCode, String>,
     // NOTE: extension settings here
     pub auth: Option<ConfigServerSMTPAuth>,
+    /// limits of each network of clients, no limit if missing
+    #[serde(default)]
+    pub rate_limit: Option<ConfigServerSMTPRateLimit>,
+    /// delay before the greetings, the clients sending data during this delay are early talkers
+    #[serde(default, with = "humantime_serde")]
+    pub greet_pause: Option<std::time::Duration>,
+    /// networks of the proxies allowed to forward the information of their clients
+    /// with the XCLIENT and XFORWARD commands
+    #[serde(default = "ConfigServerSMTP::default_trusted_forwarders")]
+    pub trusted_forwarders: Vec<ipnet::IpNet>,
+}
+
+/// The limits of the clients are checked for each network of their family,
+/// the rates are counted over a sliding `window`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigServerSMTPRateLimit {
+    /// duration over which the connections, messages and recipients are counted
+    #[serde(
+        with = "humantime_serde",
+        default = "ConfigServerSMTPRateLimit::default_window"
+    )]
+    pub window: std::time::Duration,
+    /// limits of the IPv4 networks, IPv4-mapped IPv6 clients included
+    #[serde(default)]
+    pub ipv4: Vec<ConfigServerSMTPRateLimitNetwork>,
+    /// limits of the IPv6 networks
+    #[serde(default)]
+    pub ipv6: Vec<ConfigServerSMTPRateLimitNetwork>,
+}
+
+/// The clients of the same network (see `prefix`) share these limits.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigServerSMTPRateLimitNetwork {
+    /// length of the prefix of the networks
+    pub prefix: u8,
+    /// maximum number of simultaneous connections of a network
+    #[serde(default)]
+    pub concurrent_connections: Option<usize>,
+    /// maximum number of connections of a network in the window
+    #[serde(default)]
+    pub connections: Option<usize>,
+    /// maximum number of messages (MAIL FROM) of a network in the window
+    #[serde(default)]
+    pub messages: Option<usize>,
+    /// maximum number of recipients (RCPT TO) of a network in the window
+    #[serde(default)]
+    pub recipients: Option<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
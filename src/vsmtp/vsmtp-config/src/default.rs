@@ -19,11 +19,13 @@
 use crate::{
     config::{
         ConfigApp, ConfigAppLogs, ConfigAppVSL, ConfigQueueDelivery, ConfigQueueWorking,
-        ConfigServer, ConfigServerDNS, ConfigServerInterfaces, ConfigServerLogs,
-        ConfigServerQueues, ConfigServerSMTP, ConfigServerSMTPAuth, ConfigServerSMTPError,
+        ConfigServer, ConfigServerDNS, ConfigServerInterfaces, ConfigServerInterfacesProxy,
+        ConfigServerLogs, ConfigServerMtaSts, ConfigServerQueues, ConfigServerSMTP,
+        ConfigServerSMTPAuth, ConfigServerSMTPError, ConfigServerSMTPRateLimit,
         ConfigServerSMTPTimeoutClient, ConfigServerSystem, ConfigServerSystemThreadPool,
     },
-    Config, ConfigServerTls, ConfigServerVirtualTls, ResolverOptsWrapper, TlsSecurityLevel,
+    Config, ConfigServerTls, ConfigServerTlsClientAuth, ConfigServerTlsRpt, ConfigServerVirtualTls,
+    ResolverOptsWrapper, TlsSecurityLevel,
 };
 use vsmtp_common::{
     auth::Mechanism,
@@ -56,6 +58,9 @@ impl Default for ConfigServer {
             smtp: ConfigServerSMTP::default(),
             dns: ConfigServerDNS::default(),
             r#virtual: std::collections::BTreeMap::default(),
+            tls_policy: std::collections::BTreeMap::default(),
+            mta_sts: ConfigServerMtaSts::default(),
+            tls_rpt: ConfigServerTlsRpt::default(),
         }
     }
 }
@@ -76,12 +81,22 @@ impl Default for ConfigServerSystem {
             user: Self::default_user(),
             group: Self::default_group(),
             group_local: None,
+            pid_file: Self::default_pid_file(),
+            shutdown_grace_period: Self::default_shutdown_grace_period(),
             thread_pool: ConfigServerSystemThreadPool::default(),
         }
     }
 }
 
 impl ConfigServerSystem {
+    pub(crate) const fn default_pid_file() -> Option<std::path::PathBuf> {
+        None
+    }
+
+    pub(crate) const fn default_shutdown_grace_period() -> std::time::Duration {
+        std::time::Duration::from_secs(30)
+    }
+
     pub(crate) fn default_user() -> users::User {
         users::get_user_by_name(match option_env!("CI") {
             Some(_) => "root",
@@ -135,10 +150,19 @@ impl ConfigServerInterfaces {
             addr: vec!["127.0.0.1:25".parse().expect("valid")],
             addr_submission: vec!["127.0.0.1:587".parse().expect("valid")],
             addr_submissions: vec!["127.0.0.1:465".parse().expect("valid")],
+            addr_lmtp: vec![],
+            listeners: vec![],
+            proxy_protocol: None,
         }
     }
 }
 
+impl ConfigServerInterfacesProxy {
+    pub(crate) const fn default_timeout() -> std::time::Duration {
+        std::time::Duration::from_secs(5)
+    }
+}
+
 impl Default for ConfigServerLogs {
     fn default() -> Self {
         Self {
@@ -175,7 +199,17 @@ impl ConfigServerLogs {
     }
 }
 
+impl ConfigServerTlsClientAuth {
+    pub(crate) const fn default_required() -> bool {
+        false
+    }
+}
+
 impl ConfigServerTls {
+    pub(crate) const fn default_sender_security_level() -> TlsSecurityLevel {
+        TlsSecurityLevel::May
+    }
+
     pub(crate) fn default_cipher_suite() -> Vec<rustls::CipherSuite> {
         vec![
             // TLS1.3 suites
@@ -211,7 +245,16 @@ impl ConfigServerQueues {
 
 impl Default for ConfigQueueWorking {
     fn default() -> Self {
-        Self { channel_size: 32 }
+        Self {
+            channel_size: 32,
+            pickup_period: Self::default_pickup_period(),
+        }
+    }
+}
+
+impl ConfigQueueWorking {
+    pub(crate) const fn default_pickup_period() -> std::time::Duration {
+        std::time::Duration::from_secs(5)
     }
 }
 
@@ -231,6 +274,22 @@ impl ConfigServerVirtualTls {
     }
 }
 
+impl Default for ConfigServerSMTPRateLimit {
+    fn default() -> Self {
+        Self {
+            window: Self::default_window(),
+            ipv4: vec![],
+            ipv6: vec![],
+        }
+    }
+}
+
+impl ConfigServerSMTPRateLimit {
+    pub(crate) const fn default_window() -> std::time::Duration {
+        std::time::Duration::from_secs(60)
+    }
+}
+
 impl Default for ConfigServerSMTPAuth {
     fn default() -> Self {
         Self {
@@ -251,7 +310,10 @@ impl ConfigServerSMTPAuth {
     /// Return all the supported SASL mechanisms
     #[must_use]
     pub fn default_mechanisms() -> Vec<Mechanism> {
-        <Mechanism as strum::IntoEnumIterator>::iter().collect::<Vec<_>>()
+        // EXTERNAL requires the certificates of the clients, see `server.tls.client_auth`.
+        <Mechanism as strum::IntoEnumIterator>::iter()
+            .filter(|mechanism| *mechanism != Mechanism::External)
+            .collect::<Vec<_>>()
     }
 
     pub(crate) const fn default_attempt_count_max() -> i64 {
@@ -267,12 +329,19 @@ impl Default for ConfigServerSMTP {
     fn default() -> Self {
         Self {
             rcpt_count_max: Self::default_rcpt_count_max(),
+            message_size_max: Self::default_message_size_max(),
             disable_ehlo: Self::default_disable_ehlo(),
+            enable_vrfy: Self::default_enable_vrfy(),
+            enable_expn: Self::default_enable_expn(),
+            enable_etrn: Self::default_enable_etrn(),
             required_extension: Self::default_required_extension(),
             error: ConfigServerSMTPError::default(),
             timeout_client: ConfigServerSMTPTimeoutClient::default(),
             codes: Self::default_smtp_codes(),
             auth: None,
+            rate_limit: Self::default_rate_limit(),
+            greet_pause: Self::default_greet_pause(),
+            trusted_forwarders: Self::default_trusted_forwarders(),
         }
     }
 }
@@ -282,10 +351,38 @@ impl ConfigServerSMTP {
         1000
     }
 
+    pub(crate) const fn default_message_size_max() -> usize {
+        20_000_000
+    }
+
+    pub(crate) const fn default_rate_limit() -> Option<ConfigServerSMTPRateLimit> {
+        None
+    }
+
+    pub(crate) const fn default_greet_pause() -> Option<std::time::Duration> {
+        None
+    }
+
+    pub(crate) const fn default_trusted_forwarders() -> Vec<ipnet::IpNet> {
+        Vec::new()
+    }
+
     pub(crate) const fn default_disable_ehlo() -> bool {
         false
     }
 
+    pub(crate) const fn default_enable_vrfy() -> bool {
+        false
+    }
+
+    pub(crate) const fn default_enable_expn() -> bool {
+        false
+    }
+
+    pub(crate) const fn default_enable_etrn() -> bool {
+        false
+    }
+
     pub(crate) fn default_required_extension() -> Vec<String> {
         ["STARTTLS", "SMTPUTF8", "8BITMIME", "AUTH"]
             .into_iter()
@@ -299,6 +396,8 @@ impl ConfigServerSMTP {
             SMTPReplyCode::Greetings => "220 {domain} Service ready".to_string(),
             SMTPReplyCode::Code221 => "221 Service closing transmission channel".to_string(),
             SMTPReplyCode::Code250 => "250 Ok".to_string(),
+            SMTPReplyCode::Code252 =>
+                "252 Cannot VRFY user, but will accept message and attempt delivery".to_string(),
             SMTPReplyCode::Code354 => "354 Start mail input; end with <CRLF>.<CRLF>".to_string(),
             SMTPReplyCode::Code451 => "451 Requested action aborted: local error in processing".to_string(),
             SMTPReplyCode::Code451Timeout => "451 Timeout - closing connection.".to_string(),
@@ -313,10 +412,28 @@ impl ConfigServerSMTP {
             SMTPReplyCode::BadSequence => "503 Bad sequence of commands".to_string(),
             SMTPReplyCode::Code504 => "504 Command parameter not implemented".to_string(),
             SMTPReplyCode::Code530 => "530 Must issue a STARTTLS command first".to_string(),
+            SMTPReplyCode::Code552MessageSizeExceeded =>
+                "552 5.3.4 Message size exceeds fixed maximum message size".to_string(),
+            SMTPReplyCode::Code553NonAsciiAddress =>
+                "553 5.6.7 Non-ASCII addresses not permitted for that sender/recipient".to_string(),
             SMTPReplyCode::Code554 => "554 permanent problems with the remote server".to_string(),
             SMTPReplyCode::Code554tls => "554 Command refused due to lack of security".to_string(),
+            SMTPReplyCode::Code554InvalidMessageContent =>
+                "554 5.6.0 Message content cannot be handled by the server".to_string(),
             SMTPReplyCode::TlsAlreadyUnderTls => "554 5.5.1 Error: TLS already active".to_string(),
             SMTPReplyCode::ConnectionMaxReached => "554 Cannot process connection, closing.".to_string(),
+            SMTPReplyCode::ConnectionRateExceeded =>
+                "421 4.7.0 Too many connections from your network, closing.".to_string(),
+            SMTPReplyCode::MessageRateExceeded =>
+                "451 4.7.1 Too many messages from your network, try again later".to_string(),
+            SMTPReplyCode::RcptRateExceeded =>
+                "451 4.7.1 Too many recipients from your network, try again later".to_string(),
+            SMTPReplyCode::ShuttingDown =>
+                "421 4.3.2 Service shutting down, closing transmission channel".to_string(),
+            SMTPReplyCode::ForwardNotAllowed => "550 5.7.0 Error: insufficient authorization".to_string(),
+            SMTPReplyCode::EtrnStarted => "250 Queuing started".to_string(),
+            SMTPReplyCode::EtrnUnavailable => "458 Unable to queue messages for node".to_string(),
+            SMTPReplyCode::EtrnNotAllowed => "459 Node not allowed".to_string(),
             SMTPReplyCode::AuthMechanismNotSupported => "504 5.5.4 Mechanism is not supported".to_string(),
             SMTPReplyCode::AuthSucceeded => "235 2.7.0 Authentication succeeded".to_string(),
             // 538 5.7.11 (for documentation purpose)
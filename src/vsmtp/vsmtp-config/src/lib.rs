@@ -39,6 +39,7 @@ pub mod log_channel {
 mod tests;
 
 mod parser {
+    pub mod fingerprint;
     pub mod semver;
     pub mod socket_addr;
     pub mod syst_group;
@@ -75,6 +76,7 @@ pub use trust_dns_helper::build_resolvers;
 /// Re-exported dependencies
 pub mod re {
     pub use humantime_serde::re::humantime;
+    pub use ipnet;
     pub use log4rs;
     pub use rustls;
     // NOTE: this one should not be re-exported (because tests only)
@@ -0,0 +1,68 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+
+/// the sha256 fingerprints of the certificates, in hexadecimal, the bytes can be separated by ':'.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let fingerprints = <Vec<String> as serde::Deserialize>::deserialize(deserializer)?;
+
+    if fingerprints.is_empty() {
+        return Err(serde::de::Error::custom("no fingerprint is pinned"));
+    }
+
+    for fingerprint in &fingerprints {
+        let digits = fingerprint.replace(':', "");
+        if digits.len() != 64 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(serde::de::Error::custom(format!(
+                "'{fingerprint}' is not a sha256 fingerprint"
+            )));
+        }
+    }
+
+    Ok(fingerprints)
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[derive(serde::Deserialize)]
+    struct S {
+        #[serde(deserialize_with = "crate::parser::fingerprint::deserialize")]
+        v: Vec<String>,
+    }
+
+    #[test]
+    fn error() {
+        assert!(toml::from_str::<S>(r#"v = []"#).is_err());
+        assert!(toml::from_str::<S>(r#"v = ["dbeb64"]"#).is_err());
+        assert!(toml::from_str::<S>(&format!(r#"v = ["{}"]"#, "zz".repeat(32))).is_err());
+        assert!(toml::from_str::<S>(&format!(r#"v = ["{}"]"#, "00".repeat(33))).is_err());
+    }
+
+    #[test]
+    fn valid() {
+        let fingerprint = ["AB"; 32].join(":");
+        assert_eq!(
+            toml::from_str::<S>(&format!(r#"v = ["{}", "{fingerprint}"]"#, "0f".repeat(32)))
+                .unwrap()
+                .v,
+            vec!["0f".repeat(32), fingerprint]
+        );
+    }
+}
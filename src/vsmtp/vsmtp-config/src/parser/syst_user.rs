@@ -30,6 +30,31 @@ where
         .ok_or_else(|| serde::de::Error::custom(format!("user not found: '{}'", user_name)))
 }
 
+pub fn opt_serialize<S>(user: &Option<users::User>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if let Some(user) = user {
+        serde::Serialize::serialize(&user.name().to_str().unwrap(), serializer)
+    } else {
+        serializer.serialize_none()
+    }
+}
+
+pub fn opt_deserialize<'de, D>(deserializer: D) -> Result<Option<users::User>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let user_name = &<Option<String> as serde::Deserialize>::deserialize(deserializer)?;
+    if let Some(user_name) = user_name {
+        Ok(Some(users::get_user_by_name(user_name).ok_or_else(
+            || serde::de::Error::custom(format!("user not found: '{}'", user_name)),
+        )?))
+    } else {
+        Ok(None)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -62,4 +87,36 @@ mod tests {
             .unwrap()
         );
     }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct OptS {
+        #[serde(default)]
+        #[serde(
+            serialize_with = "crate::parser::syst_user::opt_serialize",
+            deserialize_with = "crate::parser::syst_user::opt_deserialize"
+        )]
+        v: Option<users::User>,
+    }
+
+    #[test]
+    fn optional() {
+        assert_eq!(
+            serde_json::from_str::<OptS>("{\"v\":\"root\"}")
+                .unwrap()
+                .v
+                .unwrap()
+                .uid(),
+            users::get_user_by_name("root").unwrap().uid()
+        );
+
+        assert!(serde_json::from_str::<OptS>("{\"v\":null}")
+            .unwrap()
+            .v
+            .is_none());
+
+        assert_eq!(
+            "{\"v\":null}",
+            serde_json::to_string(&OptS { v: None }).unwrap()
+        );
+    }
 }
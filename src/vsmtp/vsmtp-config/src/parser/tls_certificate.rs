@@ -34,6 +34,36 @@ pub fn from_string(input: &str) -> anyhow::Result<rustls::Certificate> {
         .ok_or_else(|| anyhow::anyhow!("certificate path is valid but empty: '{}'", path.display()))
 }
 
+/// every certificate of the file, at least one is required.
+pub fn bundle_from_string(input: &str) -> anyhow::Result<Vec<rustls::Certificate>> {
+    let path = std::path::Path::new(&input);
+    anyhow::ensure!(
+        path.exists(),
+        format!("certificate path does not exists: '{}'", path.display())
+    );
+    let mut reader = std::io::BufReader::new(std::fs::File::open(&path)?);
+
+    let bundle = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+
+    anyhow::ensure!(
+        !bundle.is_empty(),
+        "certificate path is valid but empty: '{}'",
+        path.display()
+    );
+    Ok(bundle)
+}
+
+pub fn deserialize_bundle<'de, D>(deserializer: D) -> Result<Vec<rustls::Certificate>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let path = <String as serde::Deserialize>::deserialize(deserializer)?;
+    bundle_from_string(&path).map_err(serde::de::Error::custom)
+}
+
 pub fn deserialize<'de, D>(deserializer: D) -> Result<rustls::Certificate, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -108,6 +138,42 @@ mod tests {
         serde_json::from_str::<S>(r#"{"v": "./tmp/crt"}"#).unwrap();
     }
 
+    #[derive(Debug, serde::Deserialize)]
+    struct Bundle {
+        #[serde(deserialize_with = "crate::parser::tls_certificate::deserialize_bundle")]
+        v: Vec<rustls::Certificate>,
+    }
+
+    #[test]
+    fn bundle() {
+        let _droppable = std::fs::DirBuilder::new().create("./tmp");
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open("./tmp/bundle")
+            .unwrap();
+        file.write_all(
+            [
+                get_tls_file::get_certificate(),
+                get_tls_file::get_certificate(),
+            ]
+            .concat()
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<Bundle>(r#"{"v": "./tmp/bundle"}"#)
+                .unwrap()
+                .v
+                .len(),
+            2
+        );
+        serde_json::from_str::<Bundle>(r#"{"v": "foobar"}"#).unwrap_err();
+    }
+
     #[test]
     fn not_a_string() {
         serde_json::from_str::<S>(r#"{"v": 10}"#).unwrap_err();
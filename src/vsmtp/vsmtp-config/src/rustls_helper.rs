@@ -59,6 +59,28 @@ fn to_supported_cipher_suite(
         .collect::<Vec<_>>()
 }
 
+fn get_client_cert_verifier(
+    config: &ConfigServerTls,
+) -> anyhow::Result<std::sync::Arc<dyn rustls::server::ClientCertVerifier>> {
+    let client_auth = match &config.client_auth {
+        Some(client_auth) => client_auth,
+        None => return Ok(rustls::server::NoClientAuth::new()),
+    };
+
+    let mut roots = rustls::RootCertStore::empty();
+    for certificate in &client_auth.ca_file {
+        roots
+            .add(certificate)
+            .map_err(|e| anyhow::anyhow!("cannot add the client certificate authority: {e}"))?;
+    }
+
+    Ok(if client_auth.required {
+        rustls::server::AllowAnyAuthenticatedClient::new(roots)
+    } else {
+        rustls::server::AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+    })
+}
+
 #[doc(hidden)]
 pub fn get_rustls_config(
     config: &ConfigServerTls,
@@ -85,7 +107,7 @@ pub fn get_rustls_config(
         .with_kx_groups(&rustls::ALL_KX_GROUPS)
         .with_protocol_versions(protocol_version)
         .map_err(|e| anyhow::anyhow!("cannot initialize tls config: '{e}'"))?
-        .with_client_cert_verifier(rustls::server::NoClientAuth::new())
+        .with_client_cert_verifier(get_client_cert_verifier(config)?)
         .with_cert_resolver(std::sync::Arc::new(CertResolver {
             sni_resolver: virtual_entries.iter().fold(
                 anyhow::Ok(rustls::server::ResolvesServerCertUsingSni::new()),
@@ -16,11 +16,21 @@
 */
 mod root_example {
     mod antivirus;
+    mod client_auth;
+    mod forwarders;
+    mod greet_pause;
+    mod listeners;
     mod logging;
     mod minimal;
+    mod mta_sts;
+    mod proxy;
+    mod rate_limit;
     mod secured;
+    mod signals;
     mod simple;
     mod tls;
+    mod tls_policy;
+    mod tls_rpt;
 }
 
 mod validate;
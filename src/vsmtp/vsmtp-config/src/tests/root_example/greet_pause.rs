@@ -0,0 +1,52 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::Config;
+
+#[test]
+fn parse() {
+    let toml = include_str!("../../../../../../examples/config/greet_pause.toml");
+
+    let mut expected = Config::builder()
+        .with_version_str(">=1.0.0")
+        .unwrap()
+        .with_server_name("my.fqdn.com")
+        .with_user_group_and_default_system("root", "root")
+        .unwrap()
+        .with_interfaces(
+            &["0.0.0.0:25".parse().unwrap()],
+            &["0.0.0.0:587".parse().unwrap()],
+            &["0.0.0.0:465".parse().unwrap()],
+        )
+        .with_default_logs_settings()
+        .with_default_delivery()
+        .without_tls_support()
+        .with_default_smtp_options()
+        .with_default_smtp_error_handler()
+        .with_default_smtp_codes()
+        .without_auth()
+        .with_default_app()
+        .with_default_vsl_settings()
+        .with_default_app_logs()
+        .with_system_dns()
+        .without_virtual_entries()
+        .validate()
+        .unwrap();
+
+    expected.server.smtp.greet_pause = Some(std::time::Duration::from_secs(2));
+
+    pretty_assertions::assert_eq!(Config::from_toml(toml).unwrap(), expected);
+}
@@ -0,0 +1,147 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{Config, ConfigServerListener, ConfigServerListenerUnix, ListenerKind};
+
+#[test]
+fn parse() {
+    let toml = include_str!("../../../../../../examples/config/listeners.toml");
+
+    let mut expected = Config::builder()
+        .with_version_str(">=1.0.0")
+        .unwrap()
+        .with_server_name("my.fqdn.com")
+        .with_default_system()
+        .with_interfaces(&[], &[], &[])
+        .with_default_logs_settings()
+        .with_default_delivery()
+        .with_safe_tls_config(
+            "../../../examples/config/tls/certificate.crt",
+            "../../../examples/config/tls/private_key.key",
+        )
+        .unwrap()
+        .with_default_smtp_options()
+        .with_default_smtp_error_handler()
+        .with_default_smtp_codes()
+        .without_auth()
+        .with_default_app()
+        .with_default_vsl_settings()
+        .with_default_app_logs()
+        .with_system_dns()
+        .without_virtual_entries()
+        .validate()
+        .unwrap();
+
+    expected.server.interfaces.listeners = vec![
+        ConfigServerListener {
+            addr: vec!["0.0.0.0:25".parse().unwrap(), "[::]:25".parse().unwrap()],
+            kind: ListenerKind::Plain,
+            auth_required: false,
+            tls_required: false,
+            proxy_protocol: false,
+            vsl: None,
+            unix: None,
+        },
+        ConfigServerListener {
+            addr: vec!["0.0.0.0:587".parse().unwrap()],
+            kind: ListenerKind::Submission,
+            auth_required: true,
+            tls_required: true,
+            proxy_protocol: false,
+            vsl: None,
+            unix: None,
+        },
+        ConfigServerListener {
+            addr: vec!["0.0.0.0:465".parse().unwrap()],
+            kind: ListenerKind::Tunneled,
+            auth_required: true,
+            tls_required: false,
+            proxy_protocol: false,
+            vsl: None,
+            unix: None,
+        },
+        ConfigServerListener {
+            addr: vec!["127.0.0.1:24".parse().unwrap()],
+            kind: ListenerKind::Lmtp,
+            auth_required: false,
+            tls_required: false,
+            proxy_protocol: false,
+            vsl: Some("/etc/vsmtp/rules/lmtp.vsl".into()),
+            unix: None,
+        },
+        ConfigServerListener {
+            addr: vec![],
+            kind: ListenerKind::Submission,
+            auth_required: false,
+            tls_required: false,
+            proxy_protocol: false,
+            vsl: None,
+            unix: Some(ConfigServerListenerUnix {
+                path: "/var/run/vsmtp/submission.sock".into(),
+                user: users::get_user_by_name("root"),
+                group: users::get_group_by_name("root"),
+                mode: Some(0o660),
+            }),
+        },
+    ];
+
+    let parsed = Config::from_toml(toml).unwrap();
+    pretty_assertions::assert_eq!(parsed, expected);
+    pretty_assertions::assert_eq!(
+        parsed.server.interfaces.listeners(),
+        expected.server.interfaces.listeners
+    );
+}
+
+#[test]
+fn shorthands() {
+    let config = Config::default();
+
+    pretty_assertions::assert_eq!(
+        config
+            .server
+            .interfaces
+            .listeners()
+            .into_iter()
+            .map(|listener| (listener.addr, listener.kind))
+            .collect::<Vec<_>>(),
+        vec![
+            (vec!["127.0.0.1:25".parse().unwrap()], ListenerKind::Plain),
+            (
+                vec!["127.0.0.1:587".parse().unwrap()],
+                ListenerKind::Submission
+            ),
+            (
+                vec!["127.0.0.1:465".parse().unwrap()],
+                ListenerKind::Tunneled
+            ),
+        ]
+    );
+}
+
+#[test]
+fn tls_required_without_tls() {
+    let toml = r#"
+version_requirement = ">=1.0.0"
+
+[[server.interfaces.listeners]]
+addr = ["0.0.0.0:587"]
+kind = "submission"
+tls_required = true
+"#;
+
+    assert!(Config::from_toml(toml).is_err());
+}
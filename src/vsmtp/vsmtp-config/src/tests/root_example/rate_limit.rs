@@ -0,0 +1,103 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::{code::SMTPReplyCode, collection};
+
+use crate::{Config, ConfigServerSMTPRateLimit, ConfigServerSMTPRateLimitNetwork};
+
+#[test]
+fn parse() {
+    let toml = include_str!("../../../../../../examples/config/rate_limit.toml");
+
+    let mut expected = Config::builder()
+        .with_version_str(">=1.0.0")
+        .unwrap()
+        .with_server_name("my.fqdn.com")
+        .with_user_group_and_default_system("root", "root")
+        .unwrap()
+        .with_interfaces(
+            &["0.0.0.0:25".parse().unwrap()],
+            &["0.0.0.0:587".parse().unwrap()],
+            &["0.0.0.0:465".parse().unwrap()],
+        )
+        .with_default_logs_settings()
+        .with_default_delivery()
+        .without_tls_support()
+        .with_default_smtp_options()
+        .with_default_smtp_error_handler()
+        .with_smtp_codes(collection! {
+            SMTPReplyCode::MessageRateExceeded =>
+                "451 4.7.1 Slow down, try again later\r\n".to_string(),
+        })
+        .without_auth()
+        .with_default_app()
+        .with_default_vsl_settings()
+        .with_default_app_logs()
+        .with_system_dns()
+        .without_virtual_entries()
+        .validate()
+        .unwrap();
+
+    expected.server.smtp.rate_limit = Some(ConfigServerSMTPRateLimit {
+        window: std::time::Duration::from_secs(600),
+        ipv4: vec![
+            ConfigServerSMTPRateLimitNetwork {
+                prefix: 32,
+                concurrent_connections: Some(5),
+                connections: None,
+                messages: None,
+                recipients: None,
+            },
+            ConfigServerSMTPRateLimitNetwork {
+                prefix: 24,
+                concurrent_connections: None,
+                connections: Some(50),
+                messages: Some(100),
+                recipients: Some(500),
+            },
+        ],
+        ipv6: vec![
+            ConfigServerSMTPRateLimitNetwork {
+                prefix: 64,
+                concurrent_connections: Some(5),
+                connections: None,
+                messages: None,
+                recipients: None,
+            },
+            ConfigServerSMTPRateLimitNetwork {
+                prefix: 48,
+                concurrent_connections: None,
+                connections: Some(50),
+                messages: Some(100),
+                recipients: Some(500),
+            },
+        ],
+    });
+
+    pretty_assertions::assert_eq!(Config::from_toml(toml).unwrap(), expected);
+}
+
+#[test]
+fn prefix_out_of_range() {
+    let toml = r#"
+version_requirement = ">=1.0.0"
+
+[[server.smtp.rate_limit.ipv4]]
+prefix = 33
+"#;
+
+    assert!(Config::from_toml(toml).is_err());
+}
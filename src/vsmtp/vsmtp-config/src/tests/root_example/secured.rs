@@ -35,7 +35,10 @@ fn parse() {
             .with_default_logs_settings()
             .with_spool_dir_and_queues(
                 "/var/spool/vsmtp",
-                ConfigQueueWorking { channel_size: 16 },
+                ConfigQueueWorking {
+                    channel_size: 16,
+                    pickup_period: std::time::Duration::from_secs(10)
+                },
                 ConfigQueueDelivery {
                     channel_size: 16,
                     deferred_retry_max: 10,
@@ -43,7 +46,7 @@ fn parse() {
                 }
             )
             .without_tls_support()
-            .with_rcpt_count_and_default(25)
+            .with_rcpt_count_and_message_size(25, 10_000_000)
             .with_error_handler_and_timeout(
                 5,
                 10,
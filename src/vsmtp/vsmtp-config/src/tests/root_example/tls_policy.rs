@@ -0,0 +1,73 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{Config, ConfigTlsPolicy};
+
+#[test]
+fn parse() {
+    let toml = include_str!("../../../../../../examples/config/tls_policy.toml");
+
+    let mut expected = Config::builder()
+        .with_version_str(">=1.0.0")
+        .unwrap()
+        .with_server_name("my.fqdn.com")
+        .with_user_group_and_default_system("root", "root")
+        .unwrap()
+        .with_interfaces(
+            &["0.0.0.0:25".parse().unwrap()],
+            &["0.0.0.0:587".parse().unwrap()],
+            &["0.0.0.0:465".parse().unwrap()],
+        )
+        .with_default_logs_settings()
+        .with_default_delivery()
+        .without_tls_support()
+        .with_default_smtp_options()
+        .with_default_smtp_error_handler()
+        .with_default_smtp_codes()
+        .without_auth()
+        .with_default_app()
+        .with_default_vsl_settings()
+        .with_default_app_logs()
+        .with_system_dns()
+        .without_virtual_entries()
+        .validate()
+        .unwrap();
+
+    expected.server.tls_policy = [
+        ("partner.example", ConfigTlsPolicy::Encrypt),
+        ("mx.legacy.example", ConfigTlsPolicy::None),
+        (
+            "bank.example",
+            ConfigTlsPolicy::Verify {
+                name: Some("mx.bank.example".to_string()),
+                ca_file: vec![],
+            },
+        ),
+        (
+            "pinned.example",
+            ConfigTlsPolicy::Fingerprint {
+                sha256: vec![
+                    "dbeb64fe9e0c839e2b754b152be398cb96ab767617a985a5db4b1c478038b043".to_string(),
+                ],
+            },
+        ),
+    ]
+    .into_iter()
+    .map(|(destination, policy)| (destination.to_string(), policy))
+    .collect();
+
+    pretty_assertions::assert_eq!(Config::from_toml(toml).unwrap(), expected);
+}
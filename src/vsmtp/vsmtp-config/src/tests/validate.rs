@@ -14,7 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::Config;
+use crate::{Config, ConfigServerDNS, ConfigServerVirtual, ResolverOptsWrapper, TlsSecurityLevel};
 use vsmtp_common::{auth::Mechanism, code::SMTPReplyCode};
 
 fn get_mechanism_from_config(config: &Config, tls: bool) -> Vec<Mechanism> {
@@ -93,3 +93,41 @@ fn auth_mechanism() {
         [Mechanism::Login, Mechanism::Plain, Mechanism::CramMd5]
     );
 }
+
+#[test]
+fn dane_requires_dnssec() {
+    let dnssec = ConfigServerDNS::Google {
+        options: ResolverOptsWrapper {
+            dnssec: true,
+            ..ResolverOptsWrapper::default()
+        },
+    };
+
+    let mut entry = ConfigServerVirtual::with_tls_and_dns(
+        "../../../examples/config/tls/certificate.crt",
+        "../../../examples/config/tls/private_key.key",
+        dnssec.clone(),
+    )
+    .unwrap();
+    entry.tls.as_mut().unwrap().sender_security_level = TlsSecurityLevel::Dane { port: 25 };
+
+    let mut config = Config::default();
+    config
+        .server
+        .r#virtual
+        .insert("example.com".to_string(), entry);
+
+    // the tlsa records of any domain can be looked up with the resolver of the root domain.
+    config.server.dns = ConfigServerDNS::System;
+    assert!(Config::ensure(config.clone()).is_err());
+
+    config.server.dns = dnssec;
+    assert!(Config::ensure(config).is_ok());
+}
+
+#[test]
+fn message_size_max_zero() {
+    let mut config = Config::default();
+    config.server.smtp.message_size_max = 0;
+    assert!(Config::ensure(config).is_err());
+}
@@ -0,0 +1,483 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::tls_rpt;
+use trust_dns_resolver::{
+    error::ResolveErrorKind,
+    proto::rr::{
+        rdata::tlsa::{CertUsage, Matching, Selector, TLSA},
+        RData, RecordType,
+    },
+    TokioAsyncResolver,
+};
+use vsmtp_common::re::anyhow;
+
+/// the description of the failure of the verification, used to report it.
+pub(crate) const NO_MATCHING_RECORD: &str = "none of the tlsa records match the certificate";
+
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// fetch the tlsa records of the mail exchanger `host` listening on `port`.
+///
+/// `Ok(None)` is returned if the mail exchanger does not publish tlsa records,
+/// the resolver must validate the responses with dnssec for the records to be trusted.
+///
+/// # Errors
+///
+/// * the lookup failed, the mail exchanger must not be used, a [`tls_rpt::Failure`]
+///   is returned so that it can be reported.
+///   see https://www.rfc-editor.org/rfc/rfc7672#section-2.2
+pub async fn get_tlsa_records(
+    resolver: &TokioAsyncResolver,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<Option<Vec<TLSA>>> {
+    let query = format!("_{port}._tcp.{}.", host.trim_end_matches('.'));
+
+    match resolver.lookup(query.as_str(), RecordType::TLSA).await {
+        Ok(lookup) => Ok(Some(
+            lookup
+                .iter()
+                .filter_map(|record| match record {
+                    RData::TLSA(tlsa) => Some(tlsa.clone()),
+                    _ => None,
+                })
+                .collect(),
+        )),
+        Err(error) => match error.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => Ok(None),
+            _ => Err(tls_rpt::Failure {
+                result_type: tls_rpt::ResultType::DnssecInvalid,
+                message: format!("failed to get the tlsa records '{query}': {error}"),
+            }
+            .into()),
+        },
+    }
+}
+
+/// verify the certificate chain of a mail exchanger with its tlsa records.
+///
+/// only the `DANE-TA(2)` and `DANE-EE(3)` usages are supported, as required by
+/// https://www.rfc-editor.org/rfc/rfc7672#section-3.1
+pub struct DaneVerifier {
+    records: Vec<TLSA>,
+}
+
+impl DaneVerifier {
+    /// create a verifier for the tlsa records of a mail exchanger.
+    #[must_use]
+    pub fn new(records: Vec<TLSA>) -> Self {
+        Self {
+            records: records.into_iter().filter(is_usable).collect(),
+        }
+    }
+
+    /// if none of the records is usable, the connection must be encrypted
+    /// but the certificate is not authenticated.
+    #[must_use]
+    pub fn has_usable_records(&self) -> bool {
+        !self.records.is_empty()
+    }
+
+    /// verify the certificate `end_entity` of the mail exchanger `host`, `intermediates`
+    /// are the remaining certificates sent by the server.
+    ///
+    /// # Errors
+    ///
+    /// * none of the records match the chain.
+    pub fn verify(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        host: &str,
+        now: std::time::SystemTime,
+    ) -> anyhow::Result<()> {
+        if !self.has_usable_records() {
+            return Ok(());
+        }
+
+        // the name and the validity period of the certificate are not checked with DANE-EE.
+        // see https://www.rfc-editor.org/rfc/rfc7672#section-3.1.1
+        for record in self
+            .records
+            .iter()
+            .filter(|record| matches!(record.cert_usage(), CertUsage::DomainIssued))
+        {
+            if is_matching(record, end_entity)? {
+                return Ok(());
+            }
+        }
+
+        for record in self
+            .records
+            .iter()
+            .filter(|record| matches!(record.cert_usage(), CertUsage::TrustAnchor))
+        {
+            for trust_anchor in intermediates {
+                if is_matching(record, trust_anchor)?
+                    && verify_chain(end_entity, intermediates, trust_anchor, host, now).is_ok()
+                {
+                    return Ok(());
+                }
+            }
+        }
+
+        anyhow::bail!("{NO_MATCHING_RECORD} of '{host}'")
+    }
+}
+
+impl rustls::client::ServerCertVerifier for DaneVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &rustls::ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            rustls::ServerName::DnsName(name) => name.as_ref(),
+            _ => {
+                return Err(rustls::Error::General(
+                    "DANE requires the name of the mail exchanger".to_string(),
+                ))
+            }
+        };
+
+        self.verify(end_entity, intermediates, host, now)
+            .map(|_| rustls::client::ServerCertVerified::assertion())
+            .map_err(|e| rustls::Error::InvalidCertificateData(e.to_string()))
+    }
+}
+
+/// build a tls configuration authenticating the mail exchanger with its tlsa records.
+#[must_use]
+pub fn get_client_config(verifier: DaneVerifier) -> rustls::ClientConfig {
+    rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(std::sync::Arc::new(verifier))
+        .with_no_client_auth()
+}
+
+/// the usages PKIX-TA(0) and PKIX-EE(1) are not used by smtp clients,
+/// and unknown parameters cannot be verified.
+fn is_usable(record: &TLSA) -> bool {
+    matches!(
+        record.cert_usage(),
+        CertUsage::TrustAnchor | CertUsage::DomainIssued
+    ) && matches!(record.selector(), Selector::Full | Selector::Spki)
+        && matches!(
+            record.matching(),
+            Matching::Raw | Matching::Sha256 | Matching::Sha512
+        )
+}
+
+fn is_matching(record: &TLSA, certificate: &rustls::Certificate) -> anyhow::Result<bool> {
+    let data = match record.selector() {
+        Selector::Full => certificate.0.clone(),
+        Selector::Spki => x509_parser::parse_x509_certificate(&certificate.0)
+            .map_err(|error| anyhow::anyhow!("invalid certificate: {error}"))?
+            .1
+            .public_key()
+            .raw
+            .to_vec(),
+        _ => return Ok(false),
+    };
+
+    Ok(match record.matching() {
+        Matching::Raw => data == record.cert_data(),
+        Matching::Sha256 => {
+            ring::digest::digest(&ring::digest::SHA256, &data).as_ref() == record.cert_data()
+        }
+        Matching::Sha512 => {
+            ring::digest::digest(&ring::digest::SHA512, &data).as_ref() == record.cert_data()
+        }
+        _ => false,
+    })
+}
+
+/// the trust anchor designated by a DANE-TA record must issue the certificate of `host`.
+/// see https://www.rfc-editor.org/rfc/rfc7672#section-3.1.2
+fn verify_chain(
+    end_entity: &rustls::Certificate,
+    intermediates: &[rustls::Certificate],
+    trust_anchor: &rustls::Certificate,
+    host: &str,
+    now: std::time::SystemTime,
+) -> anyhow::Result<()> {
+    let certificate = webpki::EndEntityCert::try_from(end_entity.0.as_slice())
+        .map_err(|e| anyhow::anyhow!("invalid certificate: {e}"))?;
+
+    let trust_anchors = [webpki::TrustAnchor::try_from_cert_der(&trust_anchor.0)
+        .map_err(|e| anyhow::anyhow!("invalid trust anchor: {e}"))?];
+
+    let intermediates = intermediates
+        .iter()
+        .filter(|intermediate| *intermediate != trust_anchor)
+        .map(|intermediate| intermediate.0.as_slice())
+        .collect::<Vec<_>>();
+
+    certificate
+        .verify_is_valid_tls_server_cert(
+            SUPPORTED_SIG_ALGS,
+            &webpki::TlsServerTrustAnchors(&trust_anchors),
+            &intermediates,
+            webpki::Time::try_from(now).map_err(|_| anyhow::anyhow!("invalid time"))?,
+        )
+        .map_err(|e| anyhow::anyhow!("invalid certificate chain: {e}"))?;
+
+    certificate
+        .verify_is_valid_for_dns_name(
+            webpki::DnsNameRef::try_from_ascii_str(host.trim_end_matches('.'))
+                .map_err(|e| anyhow::anyhow!("invalid name '{host}': {e}"))?,
+        )
+        .map_err(|e| anyhow::anyhow!("the certificate is not valid for '{host}': {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_tlsa_records, DaneVerifier};
+    use crate::tls_rpt;
+    use trust_dns_resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        proto::{
+            op::{Message, MessageType, OpCode, ResponseCode},
+            rr::{
+                rdata::tlsa::{CertUsage, Matching, Selector, TLSA},
+                RData, Record,
+            },
+        },
+        TokioAsyncResolver,
+    };
+    use vsmtp_config::re::rustls_pemfile;
+
+    const ZONE: &str = "
+; DANE-EE, the public key of the server.
+_25._tcp.ee-spki.example.   IN TLSA 3 1 1 fae03a4995f695dcce166882b542617d3bf0a7845eb63079517917544e3ccc92
+; DANE-EE, the whole certificate of the server.
+_25._tcp.ee-full.example.   IN TLSA 3 0 2 5a38000bf5a079c28d5f1355f35ff598842d1855e2a94a2b71bb81d7c0e54dbad7b30099d3bf77b9e4ee4170602b1ef3b2e4a353995df91c0638151735e51fce
+; DANE-TA, the intermediate authority.
+_25._tcp.testserver.com.    IN TLSA 2 0 1 b5216506b9b531a856adc73b59570a44f6772df0e27d0b0d1d14f5fc4e9177ed
+; DANE-TA, the public key of the root authority.
+_25._tcp.localhost.         IN TLSA 2 1 1 fb860c9049b47796ac64a444d1acb09c4c13236fe126b248d8ea801677178337
+; PKIX-EE is not used by smtp clients.
+_25._tcp.pkix.example.      IN TLSA 1 1 1 fae03a4995f695dcce166882b542617d3bf0a7845eb63079517917544e3ccc92
+; a key that is not the one of the server.
+_25._tcp.mismatch.example.  IN TLSA 3 1 1 0000000000000000000000000000000000000000000000000000000000000000
+";
+
+    /// the tlsa records of `name` in the zone.
+    fn lookup(name: &str) -> Vec<TLSA> {
+        ZONE.lines()
+            .filter(|line| !line.starts_with(';'))
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|record| record.first() == Some(&name))
+            .map(|record| {
+                TLSA::new(
+                    CertUsage::from(record[3].parse::<u8>().unwrap()),
+                    Selector::from(record[4].parse::<u8>().unwrap()),
+                    Matching::from(record[5].parse::<u8>().unwrap()),
+                    (0..record[6].len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(&record[6][i..i + 2], 16).unwrap())
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// a local dns server answering the tlsa queries with the records of the zone,
+    /// the queries for the names under `servfail.example` fail.
+    async fn serve_zone() -> TokioAsyncResolver {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buffer = [0; 4096];
+            while let Ok((size, peer)) = socket.recv_from(&mut buffer).await {
+                let request = Message::from_vec(&buffer[..size]).unwrap();
+                let query = request.queries()[0].clone();
+                let name = query.name().to_ascii();
+
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .set_recursion_desired(request.recursion_desired())
+                    .set_recursion_available(true)
+                    .add_query(query.clone());
+
+                let records = lookup(&name);
+                if name.ends_with(".servfail.example.") {
+                    response.set_response_code(ResponseCode::ServFail);
+                } else if records.is_empty() {
+                    response.set_response_code(ResponseCode::NXDomain);
+                } else {
+                    for record in records {
+                        response.add_answer(Record::from_rdata(
+                            query.name().clone(),
+                            60,
+                            RData::TLSA(record),
+                        ));
+                    }
+                }
+
+                socket
+                    .send_to(&response.to_vec().unwrap(), peer)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        TokioAsyncResolver::tokio(
+            ResolverConfig::from_parts(
+                None,
+                vec![],
+                NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true),
+            ),
+            ResolverOpts {
+                attempts: 1,
+                timeout: std::time::Duration::from_secs(1),
+                ..ResolverOpts::default()
+            },
+        )
+        .unwrap()
+    }
+
+    /// the certificate of `testserver.com`, followed by its authorities.
+    fn get_chain() -> (rustls::Certificate, Vec<rustls::Certificate>) {
+        let mut chain = rustls_pemfile::certs(&mut std::io::BufReader::new(
+            std::fs::File::open("../vsmtp-test/src/template/certs/certificate.crt").unwrap(),
+        ))
+        .unwrap()
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+
+        let end_entity = chain.remove(0);
+        (end_entity, chain)
+    }
+
+    /// the certificates of the test chain are valid at this time.
+    fn now() -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_654_041_600)
+    }
+
+    fn verify(name: &str, host: &str, now: std::time::SystemTime) -> bool {
+        let (end_entity, intermediates) = get_chain();
+        DaneVerifier::new(lookup(name))
+            .verify(&end_entity, &intermediates, host, now)
+            .is_ok()
+    }
+
+    #[test]
+    fn dane_ee() {
+        assert!(verify(
+            "_25._tcp.ee-spki.example.",
+            "ee-spki.example",
+            now()
+        ));
+        assert!(verify(
+            "_25._tcp.ee-full.example.",
+            "ee-full.example",
+            now()
+        ));
+        // the validity period is not checked.
+        assert!(verify(
+            "_25._tcp.ee-spki.example.",
+            "ee-spki.example",
+            std::time::SystemTime::now()
+        ));
+    }
+
+    #[test]
+    fn dane_ta() {
+        assert!(verify("_25._tcp.testserver.com.", "testserver.com", now()));
+        assert!(verify("_25._tcp.localhost.", "localhost", now()));
+        // the certificate is not issued for this name.
+        assert!(!verify(
+            "_25._tcp.testserver.com.",
+            "mx.testserver.org",
+            now()
+        ));
+        // the certificate has expired.
+        assert!(!verify(
+            "_25._tcp.testserver.com.",
+            "testserver.com",
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(2_000_000_000)
+        ));
+    }
+
+    #[test]
+    fn mismatch() {
+        assert!(!verify(
+            "_25._tcp.mismatch.example.",
+            "mismatch.example",
+            now()
+        ));
+    }
+
+    #[test]
+    fn unusable_records() {
+        assert!(!DaneVerifier::new(lookup("_25._tcp.pkix.example.")).has_usable_records());
+        // the connection is encrypted, but the certificate is not authenticated.
+        assert!(verify("_25._tcp.pkix.example.", "pkix.example", now()));
+    }
+
+    #[tokio::test]
+    async fn tlsa_records() {
+        let resolver = serve_zone().await;
+
+        assert_eq!(
+            get_tlsa_records(&resolver, "ee-spki.example.", 25)
+                .await
+                .unwrap(),
+            Some(lookup("_25._tcp.ee-spki.example."))
+        );
+
+        // the mail exchanger does not publish tlsa records.
+        assert_eq!(
+            get_tlsa_records(&resolver, "unknown.example", 25)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // the mail exchanger must not be used if the lookup failed.
+        let error = get_tlsa_records(&resolver, "mx.servfail.example", 25)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            tls_rpt::get_result_type(&error),
+            Some(tls_rpt::ResultType::DnssecInvalid)
+        );
+    }
+}
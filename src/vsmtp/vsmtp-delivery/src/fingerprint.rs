@@ -0,0 +1,151 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_common::re::anyhow;
+
+/// verify that the sha256 fingerprint of the certificate of the mail exchanger
+/// is one of the pinned fingerprints.
+pub struct FingerprintVerifier {
+    fingerprints: Vec<Vec<u8>>,
+}
+
+impl FingerprintVerifier {
+    /// create a verifier from the hexadecimal fingerprints, the bytes can be separated by ':'.
+    ///
+    /// # Errors
+    ///
+    /// * a fingerprint is not a sha256 digest in hexadecimal.
+    pub fn new(fingerprints: &[String]) -> anyhow::Result<Self> {
+        Ok(Self {
+            fingerprints: fingerprints
+                .iter()
+                .map(String::as_str)
+                .map(from_hex)
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        })
+    }
+
+    /// verify the certificate `end_entity` of the mail exchanger.
+    ///
+    /// # Errors
+    ///
+    /// * the fingerprint of the certificate is not pinned.
+    pub fn verify(&self, end_entity: &rustls::Certificate) -> anyhow::Result<()> {
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, &end_entity.0);
+
+        if self
+            .fingerprints
+            .iter()
+            .any(|pinned| pinned.as_slice() == fingerprint.as_ref())
+        {
+            Ok(())
+        } else {
+            anyhow::bail!("the fingerprint of the certificate is not pinned")
+        }
+    }
+}
+
+impl rustls::client::ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _: &[rustls::Certificate],
+        _: &rustls::ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        self.verify(end_entity)
+            .map(|_| rustls::client::ServerCertVerified::assertion())
+            .map_err(|e| rustls::Error::InvalidCertificateData(e.to_string()))
+    }
+}
+
+/// build a tls configuration authenticating the mail exchanger with the fingerprint of its certificate.
+#[must_use]
+pub fn get_client_config(verifier: FingerprintVerifier) -> rustls::ClientConfig {
+    rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(std::sync::Arc::new(verifier))
+        .with_no_client_auth()
+}
+
+fn from_hex(fingerprint: &str) -> anyhow::Result<Vec<u8>> {
+    let digits = fingerprint.replace(':', "");
+    anyhow::ensure!(
+        digits.len() == 64 && digits.is_ascii(),
+        "'{fingerprint}' is not a sha256 fingerprint"
+    );
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| anyhow::anyhow!("'{fingerprint}' is not a sha256 fingerprint"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FingerprintVerifier;
+    use vsmtp_config::re::rustls_pemfile;
+
+    const TEST_SERVER_SHA256: &str =
+        "dbeb64fe9e0c839e2b754b152be398cb96ab767617a985a5db4b1c478038b043";
+
+    fn get_certificate() -> rustls::Certificate {
+        rustls::Certificate(
+            rustls_pemfile::certs(&mut std::io::BufReader::new(
+                std::fs::File::open("../vsmtp-test/src/template/certs/certificate.crt").unwrap(),
+            ))
+            .unwrap()
+            .remove(0),
+        )
+    }
+
+    #[test]
+    fn pinned() {
+        let verifier =
+            FingerprintVerifier::new(&["00".repeat(32), TEST_SERVER_SHA256.to_uppercase()])
+                .unwrap();
+        assert!(verifier.verify(&get_certificate()).is_ok());
+    }
+
+    #[test]
+    fn separated_by_colons() {
+        let fingerprint = (0..TEST_SERVER_SHA256.len())
+            .step_by(2)
+            .map(|i| &TEST_SERVER_SHA256[i..i + 2])
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let verifier = FingerprintVerifier::new(&[fingerprint]).unwrap();
+        assert!(verifier.verify(&get_certificate()).is_ok());
+    }
+
+    #[test]
+    fn not_pinned() {
+        let verifier = FingerprintVerifier::new(&["00".repeat(32)]).unwrap();
+        assert!(verifier.verify(&get_certificate()).is_err());
+    }
+
+    #[test]
+    fn invalid_fingerprint() {
+        assert!(FingerprintVerifier::new(&["dbeb64".to_string()]).is_err());
+        assert!(FingerprintVerifier::new(&["zz".repeat(32)]).is_err());
+    }
+}
@@ -27,13 +27,47 @@
 //
 #![allow(clippy::doc_markdown)]
 
+/// authentication of the mail exchangers with their TLSA records (DANE).
+pub mod dane;
+/// authentication of the mail exchangers with the fingerprint of their certificate.
+pub mod fingerprint;
+/// policies of the domains requiring authenticated tls with their mail exchangers (MTA-STS).
+pub mod mta_sts;
+/// reporting of the results of the tls sessions to the destination domains (TLS-RPT).
+pub mod tls_rpt;
+
+/// the name of the file storing the data of `domain` in the spool of the server.
+fn get_domain_file_name(domain: &str) -> vsmtp_common::re::anyhow::Result<String> {
+    let domain = domain.trim_end_matches('.').to_lowercase();
+
+    vsmtp_common::re::anyhow::ensure!(
+        !domain.is_empty()
+            && !domain.starts_with('.')
+            && domain
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '.'),
+        "invalid domain '{domain}'"
+    );
+
+    Ok(domain)
+}
+
 /// a few helpers to create systems that will deliver emails.
 pub mod transport {
+    use crate::{dane, fingerprint, mta_sts, tls_rpt};
     use anyhow::Context;
-    use lettre::Tokio1Executor;
-    use trust_dns_resolver::TokioAsyncResolver;
-    use vsmtp_common::{mail_context::MessageMetadata, rcpt::Rcpt, re::anyhow, Address};
-    use vsmtp_config::Config;
+    use lettre::{
+        transport::smtp::client::{Certificate, Tls, TlsParameters},
+        Tokio1Executor,
+    };
+    use trust_dns_resolver::{proto::rr::rdata::tlsa::TLSA, TokioAsyncResolver};
+    use vsmtp_common::{
+        mail_context::MessageMetadata,
+        rcpt::Rcpt,
+        re::{anyhow, log},
+        Address,
+    };
+    use vsmtp_config::{Config, ConfigTlsPolicy, TlsSecurityLevel};
 
     mod log_channels {
         pub const DELIVER: &str = "server::delivery::deliver";
@@ -42,17 +76,41 @@ pub mod transport {
         pub const MBOX: &str = "server::delivery::mbox";
     }
 
+    /// the message to deliver.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Content<'a> {
+        /// a text message.
+        Text(&'a str),
+        /// a message received with `BODY=BINARYMIME`, relayed as is with the `BDAT` command.
+        /// (RFC 3030)
+        Binary(&'a [u8]),
+    }
+
+    impl<'a> Content<'a> {
+        /// the bytes of the message.
+        #[must_use]
+        pub const fn as_bytes(self) -> &'a [u8] {
+            match self {
+                Self::Text(text) => text.as_bytes(),
+                Self::Binary(bytes) => bytes,
+            }
+        }
+    }
+
     /// allowing the [ServerVSMTP] to deliver a mail.
     #[async_trait::async_trait]
     pub trait Transport {
-        /// the deliver method of the [Resolver] trait
+        /// the deliver method of the [Resolver] trait, `from` is `None` for the null
+        /// reverse-path, `smtputf8` is set if the message has been received with
+        /// the `SMTPUTF8` extension.
         async fn deliver(
             &mut self,
             config: &Config,
             metadata: &MessageMetadata,
-            from: &Address,
+            from: Option<&Address>,
+            smtputf8: bool,
             to: &mut [Rcpt],
-            content: &str,
+            content: Content<'_>,
         ) -> anyhow::Result<()>;
     }
 
@@ -64,6 +122,8 @@ pub mod transport {
     pub mod maildir;
     /// mbox transport.
     pub mod mbox;
+    /// smtp client verifying the certificate of the server with a custom policy.
+    pub mod starttls;
 
     /// no transfer will be made if this resolver is selected.
     pub struct NoTransfer;
@@ -74,87 +134,572 @@ pub mod transport {
             &mut self,
             _: &Config,
             _: &MessageMetadata,
-            _: &Address,
+            _: Option<&Address>,
+            _: bool,
             _: &mut [Rcpt],
-            _: &str,
+            _: Content<'_>,
         ) -> anyhow::Result<()> {
             Ok(())
         }
     }
 
-    /// build a [lettre] envelop using from address & recipients.
+    /// convert an address to a [lettre] address.
+    ///
+    /// the domain is converted to its ascii form (punycode), so internationalized
+    /// domains can be relayed to servers that do not support the `SMTPUTF8` extension.
+    pub(super) fn to_lettre_address(address: &Address) -> anyhow::Result<lettre::Address> {
+        lettre::Address::new(address.local_part(), address.domain_ascii())
+            .with_context(|| format!("failed to convert '{address}' to a lettre address"))
+    }
+
+    /// build the envelope of a message, `smtputf8` is set if the message has been
+    /// received with the `SMTPUTF8` extension.
+    pub(super) fn build_envelop(
+        from: Option<&Address>,
+        rcpt: &[&mut Rcpt],
+        smtputf8: bool,
+    ) -> starttls::Envelope {
+        starttls::Envelope {
+            from: from.cloned(),
+            to: rcpt.iter().map(|rcpt| rcpt.address.clone()).collect(),
+            // non-ascii local parts cannot be downgraded.
+            smtputf8: smtputf8
+                || from.map_or(false, |from| !from.has_ascii_local_part())
+                || rcpt.iter().any(|rcpt| !rcpt.address.has_ascii_local_part()),
+        }
+    }
+
+    /// is the failure caused by a server that does not support an extension required
+    /// by the message, the message cannot be sent to it.
+    pub(super) fn is_extension_not_supported(error: &anyhow::Error) -> bool {
+        error.is::<starttls::ExtensionNotSupported>()
+    }
+
+    /// build a [lettre] envelop, the addresses must have ascii local parts.
     pub(super) fn build_lettre_envelop(
-        from: &vsmtp_common::Address,
-        rcpt: &[Rcpt],
+        envelop: &starttls::Envelope,
     ) -> anyhow::Result<lettre::address::Envelope> {
         Ok(lettre::address::Envelope::new(
-            Some(
-                from.full()
-                    .parse()
-                    .context("failed to parse from address")?,
-            ),
-            rcpt.iter()
-                // NOTE: address that couldn't be converted will be silently dropped.
-                .flat_map(|rcpt| rcpt.address.full().parse::<lettre::Address>())
-                .collect(),
+            envelop
+                .from
+                .as_ref()
+                .map(to_lettre_address)
+                .transpose()
+                .context("failed to parse from address")?,
+            envelop
+                .to
+                .iter()
+                .map(to_lettre_address)
+                .collect::<anyhow::Result<Vec<_>>>()?,
         )?)
     }
 
-    /// build a transport using opportunistic tls and toml specified certificates.
-    /// TODO: resulting transport should be cached.
-    fn build_transport(
+    /// the domain of the sender, the server itself for the null reverse-path.
+    pub(super) fn get_sender_domain<'a>(config: &'a Config, from: Option<&'a Address>) -> &'a str {
+        from.map_or(config.server.domain.as_str(), Address::domain)
+    }
+
+    /// the security level of the connections opened for the domain of the sender.
+    pub(super) fn get_sender_security_level(
+        config: &Config,
+        domain: &str,
+    ) -> Option<TlsSecurityLevel> {
+        if config.server.domain == domain {
+            config
+                .server
+                .tls
+                .as_ref()
+                .map(|tls| tls.sender_security_level)
+        } else {
+            config
+                .server
+                .r#virtual
+                .get(domain)
+                .and_then(|domain| domain.tls.as_ref())
+                .map(|tls| tls.sender_security_level)
+        }
+    }
+
+    /// the tls policy enforced on the connection to a mail exchanger.
+    pub(super) enum TlsPolicy<'a> {
+        /// the policy of the destination in `server.tls_policy`.
+        Configured {
+            destination: &'a str,
+            policy: &'a ConfigTlsPolicy,
+        },
+        /// the sender requires DANE, see [`TlsSecurityLevel::Dane`], the MTA-STS
+        /// policy of the destination applies if the mail exchanger has no tlsa records.
+        Dane {
+            port: u16,
+            mta_sts: Option<&'a mta_sts::Policy>,
+        },
+        /// the MTA-STS policy of the destination.
+        MtaSts(&'a mta_sts::Policy),
+        /// STARTTLS is used if the server offers it.
+        Opportunistic,
+    }
+
+    impl std::fmt::Display for TlsPolicy<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Configured {
+                    destination,
+                    policy,
+                } => write!(
+                    f,
+                    "tls policy '{}' of '{destination}'",
+                    match policy {
+                        ConfigTlsPolicy::None => "none",
+                        ConfigTlsPolicy::May => "may",
+                        ConfigTlsPolicy::Encrypt => "encrypt",
+                        ConfigTlsPolicy::Verify { .. } => "verify",
+                        ConfigTlsPolicy::Fingerprint { .. } => "fingerprint",
+                    }
+                ),
+                Self::Dane {
+                    port,
+                    mta_sts: None,
+                } => write!(f, "DANE on port {port}"),
+                Self::Dane {
+                    port,
+                    mta_sts: Some(mta_sts),
+                } => write!(
+                    f,
+                    "DANE on port {port} or MTA-STS policy '{}'",
+                    mta_sts.mode
+                ),
+                Self::MtaSts(mta_sts) => write!(f, "MTA-STS policy '{}'", mta_sts.mode),
+                Self::Opportunistic => f.write_str("opportunistic tls"),
+            }
+        }
+    }
+
+    /// how much a configured policy protects the connection, the policies of a same
+    /// level authenticate the mail exchanger with different methods.
+    const fn get_policy_level(policy: &ConfigTlsPolicy) -> u8 {
+        match policy {
+            ConfigTlsPolicy::None => 0,
+            ConfigTlsPolicy::May => 1,
+            ConfigTlsPolicy::Encrypt => 2,
+            ConfigTlsPolicy::Verify { .. } | ConfigTlsPolicy::Fingerprint { .. } => 3,
+        }
+    }
+
+    /// the policy of the destination `domain` applies, the policy of the mail exchanger
+    /// `target` is used instead only if it is as strict: the mx records are not authenticated,
+    /// and could point to a mail exchanger with a weaker policy.
+    /// DANE is used if none are configured and `dane` is set, then the MTA-STS policy
+    /// of the destination.
+    pub(super) fn get_tls_policy<'a>(
+        config: &'a Config,
+        domain: &str,
+        target: &str,
+        dane: Option<u16>,
+        mta_sts: Option<&'a mta_sts::Policy>,
+    ) -> TlsPolicy<'a> {
+        let by_domain = config.server.tls_policy.get_key_value(domain);
+        let by_target = config
+            .server
+            .tls_policy
+            .get_key_value(target.trim_end_matches('.'));
+
+        match (by_domain, by_target) {
+            (Some(by_domain), Some(by_target))
+                if get_policy_level(by_target.1) < get_policy_level(by_domain.1) =>
+            {
+                log::warn!(
+                    target: log_channels::DELIVER,
+                    "the tls policy of '{}' is weaker than the policy of '{}', ignoring it",
+                    by_target.0,
+                    by_domain.0
+                );
+                Some(by_domain)
+            }
+            (by_domain, by_target) => by_target.or(by_domain),
+        }
+        .map_or_else(
+            || match (dane, mta_sts) {
+                (Some(port), mta_sts) => TlsPolicy::Dane { port, mta_sts },
+                (None, Some(mta_sts)) => TlsPolicy::MtaSts(mta_sts),
+                (None, None) => TlsPolicy::Opportunistic,
+            },
+            |(destination, policy)| TlsPolicy::Configured {
+                destination,
+                policy,
+            },
+        )
+    }
+
+    /// send a message to the mail exchanger `target` of the destination `domain`,
+    /// enforcing the tls `policy`.
+    ///
+    /// the policy applied to the tls session is returned with the result of the delivery,
+    /// so that it can be reported.
+    pub(super) async fn send_email(
         config: &Config,
-        // will be used for tlsa record resolving.
-        _: &TokioAsyncResolver,
-        from: &vsmtp_common::Address,
+        resolver: &TokioAsyncResolver,
+        policy: &TlsPolicy<'_>,
+        domain: &str,
         target: &str,
-    ) -> anyhow::Result<lettre::AsyncSmtpTransport<Tokio1Executor>> {
-        let tls_builder =
-            lettre::transport::smtp::client::TlsParameters::builder(target.to_string());
-
-        // from's domain could match the root domain of the server.
-        let tls_parameters =
-            if config.server.domain == from.domain() && config.server.tls.is_some() {
-                tls_builder.add_root_certificate(
-                    lettre::transport::smtp::client::Certificate::from_der(
-                        config.server.tls.as_ref().unwrap().certificate.0.clone(),
-                    )
-                    .context("failed to parse certificate as der")?,
-                )
+        envelop: &starttls::Envelope,
+        content: Content<'_>,
+    ) -> (tls_rpt::AppliedPolicy, anyhow::Result<()>) {
+        // the mail exchanger is not used if its tlsa records cannot be fetched.
+        let records = match policy {
+            TlsPolicy::Dane { port, .. } => {
+                match dane::get_tlsa_records(resolver, target, *port).await {
+                    Ok(records) => records,
+                    Err(err) => return (get_applied_policy(policy, Some(&[]), domain), Err(err)),
+                }
+            }
+            _ => None,
+        };
+
+        (
+            get_applied_policy(policy, records.as_deref(), domain),
+            send_with_policy(config, policy, records, target, envelop, content).await,
+        )
+    }
+
+    /// send the message, the tlsa `records` are fetched beforehand with DANE.
+    async fn send_with_policy(
+        config: &Config,
+        policy: &TlsPolicy<'_>,
+        records: Option<Vec<TLSA>>,
+        target: &str,
+        envelop: &starttls::Envelope,
+        content: Content<'_>,
+    ) -> anyhow::Result<()> {
+        let sender_domain = get_sender_domain(config, envelop.from.as_ref());
+        let hello_name = envelop
+            .from
+            .as_ref()
+            .map_or_else(|| config.server.domain.clone(), Address::domain_ascii);
+        let port = match policy {
+            TlsPolicy::Dane { port, .. } => *port,
+            _ => lettre::transport::smtp::SMTP_PORT,
+        };
+
+        // [lettre] cannot verify the certificate of the server with a custom policy.
+        let tls_config = match policy {
+            // the message is not sent if the certificate does not match the tlsa records.
+            TlsPolicy::Dane { .. } => {
+                records.map(|records| dane::get_client_config(dane::DaneVerifier::new(records)))
             }
-            // or a domain from one of the virtual domains.
-            else if let Some(tls_config) = config
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::Fingerprint { sha256 },
+                ..
+            } => Some(fingerprint::get_client_config(
+                fingerprint::FingerprintVerifier::new(sha256)?,
+            )),
+            _ => None,
+        };
+
+        if let Some(tls_config) = tls_config {
+            return starttls::send(
+                target,
+                port,
+                &hello_name,
+                &starttls::Tls::Required(starttls::TlsParameters::new(
+                    std::sync::Arc::new(tls_config),
+                    target,
+                )),
+                envelop,
+                content,
+            )
+            .await;
+        }
+
+        // without tlsa records, the MTA-STS policy of the destination applies.
+        let fallback;
+        let policy = match policy {
+            TlsPolicy::Dane {
+                mta_sts: Some(mta_sts),
+                ..
+            } => {
+                fallback = TlsPolicy::MtaSts(mta_sts);
+                &fallback
+            }
+            _ => policy,
+        };
+
+        if let TlsPolicy::MtaSts(sts_policy) = policy {
+            if !sts_policy.matches(target) {
+                match sts_policy.mode {
+                    mta_sts::Mode::Enforce => {
+                        return Err(tls_rpt::Failure {
+                            result_type: tls_rpt::ResultType::ValidationFailure,
+                            message: format!("'{target}' is not listed in the MTA-STS policy"),
+                        }
+                        .into());
+                    }
+                    mta_sts::Mode::Testing | mta_sts::Mode::None => log::warn!(
+                        target: log_channels::DELIVER,
+                        "'{target}' is not listed in the MTA-STS policy, delivering anyway in testing mode"
+                    ),
+                }
+            }
+        }
+
+        // [lettre] cannot transmit non-ascii local parts, nor use the BDAT command.
+        if envelop.smtputf8 || matches!(content, Content::Binary(_)) {
+            return starttls::send(
+                target,
+                port,
+                &hello_name,
+                &get_client_tls(config, sender_domain, target, policy)?,
+                envelop,
+                content,
+            )
+            .await;
+        }
+
+        lettre::AsyncTransport::send_raw(
+            // TODO: transport should be cached.
+            &build_transport(config, sender_domain, &hello_name, target, port, policy)?,
+            &build_lettre_envelop(envelop)?,
+            content.as_bytes(),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// the same security as [`build_transport`], for the client of [`starttls`].
+    fn get_client_tls(
+        config: &Config,
+        domain: &str,
+        target: &str,
+        policy: &TlsPolicy<'_>,
+    ) -> anyhow::Result<starttls::Tls> {
+        let unverified = || {
+            starttls::TlsParameters::new(
+                std::sync::Arc::new(starttls::get_unverified_client_config()),
+                target,
+            )
+        };
+        let pkix = |name: &str, roots: &[rustls::Certificate]| {
+            anyhow::Ok(starttls::TlsParameters::new(
+                std::sync::Arc::new(starttls::get_pkix_client_config(roots)?),
+                name,
+            ))
+        };
+
+        Ok(match policy {
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::None,
+                ..
+            } => starttls::Tls::None,
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::May,
+                ..
+            } => starttls::Tls::Opportunistic(unverified()),
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::Encrypt,
+                ..
+            } => starttls::Tls::Required(unverified()),
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::Verify { name, ca_file },
+                ..
+            } => starttls::Tls::Required(pkix(name.as_deref().unwrap_or(target), ca_file)?),
+            TlsPolicy::MtaSts(sts_policy) if sts_policy.mode == mta_sts::Mode::Enforce => {
+                starttls::Tls::Required(pkix(target, &[])?)
+            }
+            _ => starttls::Tls::Opportunistic(pkix(
+                target,
+                &get_sender_certificate(config, domain)
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            )?),
+        })
+    }
+
+    /// the certificate of the domain of the sender, trusted for opportunistic tls.
+    fn get_sender_certificate<'a>(
+        config: &'a Config,
+        domain: &str,
+    ) -> Option<&'a rustls::Certificate> {
+        if config.server.domain == domain {
+            config.server.tls.as_ref().map(|tls| &tls.certificate)
+        } else {
+            config
                 .server
                 .r#virtual
-                .get(from.domain())
+                .get(domain)
                 .and_then(|domain| domain.tls.as_ref())
-            {
-                tls_builder.add_root_certificate(
-                    lettre::transport::smtp::client::Certificate::from_der(
-                        tls_config.certificate.0.clone(),
-                    )
-                    .context("failed to parse certificate as der")?,
+                .map(|tls| &tls.certificate)
+        }
+    }
+
+    /// the policy applied to the tls session with a mail exchanger publishing
+    /// the tlsa `records`, as published by the destination `domain`.
+    fn get_applied_policy(
+        policy: &TlsPolicy<'_>,
+        records: Option<&[TLSA]>,
+        domain: &str,
+    ) -> tls_rpt::AppliedPolicy {
+        let mta_sts = match policy {
+            TlsPolicy::Dane { mta_sts, .. } => {
+                if let Some(records) = records {
+                    return tls_rpt::AppliedPolicy {
+                        policy_type: tls_rpt::PolicyType::Tlsa,
+                        policy_string: records
+                            .iter()
+                            .map(|record| {
+                                format!(
+                                    "{} {} {} {}",
+                                    u8::from(record.cert_usage()),
+                                    u8::from(record.selector()),
+                                    u8::from(record.matching()),
+                                    record
+                                        .cert_data()
+                                        .iter()
+                                        .map(|byte| format!("{byte:02x}"))
+                                        .collect::<String>()
+                                )
+                            })
+                            .collect(),
+                        policy_domain: domain.to_string(),
+                        mx_host: vec![],
+                    };
+                }
+                *mta_sts
+            }
+            TlsPolicy::MtaSts(mta_sts) => Some(*mta_sts),
+            TlsPolicy::Configured { .. } | TlsPolicy::Opportunistic => None,
+        };
+
+        match mta_sts {
+            Some(mta_sts) => tls_rpt::AppliedPolicy {
+                policy_type: tls_rpt::PolicyType::Sts,
+                policy_string: mta_sts.to_string().lines().map(str::to_string).collect(),
+                policy_domain: domain.to_string(),
+                mx_host: mta_sts.mx.clone(),
+            },
+            None => tls_rpt::AppliedPolicy {
+                policy_type: tls_rpt::PolicyType::NoPolicyFound,
+                policy_string: vec![],
+                policy_domain: domain.to_string(),
+                mx_host: vec![],
+            },
+        }
+    }
+
+    /// build a transport enforcing the tls `policy`, using toml specified certificates
+    /// with opportunistic tls.
+    /// TODO: resulting transport should be cached.
+    fn build_transport(
+        config: &Config,
+        domain: &str,
+        hello_name: &str,
+        target: &str,
+        port: u16,
+        policy: &TlsPolicy<'_>,
+    ) -> anyhow::Result<lettre::AsyncSmtpTransport<Tokio1Executor>> {
+        let tls = match policy {
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::None,
+                ..
+            } => Tls::None,
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::May,
+                ..
+            } => Tls::Opportunistic(
+                TlsParameters::builder(target.to_string())
+                    .dangerous_accept_invalid_certs(true)
+                    .build_rustls()
+                    .context("failed to build tls parameters")?,
+            ),
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::Encrypt,
+                ..
+            } => Tls::Required(
+                TlsParameters::builder(target.to_string())
+                    .dangerous_accept_invalid_certs(true)
+                    .build_rustls()
+                    .context("failed to build tls parameters")?,
+            ),
+            TlsPolicy::Configured {
+                policy: ConfigTlsPolicy::Verify { name, ca_file },
+                ..
+            } => Tls::Required(
+                ca_file
+                    .iter()
+                    .try_fold(
+                        TlsParameters::builder(
+                            name.clone()
+                                .unwrap_or_else(|| target.trim_end_matches('.').to_string()),
+                        ),
+                        |tls_builder, certificate| {
+                            anyhow::Ok(
+                                tls_builder.add_root_certificate(
+                                    Certificate::from_der(certificate.0.clone())
+                                        .context("failed to parse certificate as der")?,
+                                ),
+                            )
+                        },
+                    )?
+                    .build_rustls()
+                    .context("failed to build tls parameters")?,
+            ),
+            // the certificate must be valid for the mail exchanger.
+            // see https://www.rfc-editor.org/rfc/rfc8461#section-4.2
+            TlsPolicy::MtaSts(sts_policy) if sts_policy.mode == mta_sts::Mode::Enforce => {
+                Tls::Required(
+                    TlsParameters::builder(target.trim_end_matches('.').to_string())
+                        .build_rustls()
+                        .context("failed to build tls parameters")?,
                 )
-            // if not, no certificate are used.
-            } else {
-                tls_builder
             }
-            .build_rustls()
-            .context("failed to build tls parameters")?;
+            // MTA-STS in testing mode, and DANE if the mail exchanger has no tlsa records.
+            _ => Tls::Opportunistic(get_opportunistic_tls_parameters(config, domain, target)?),
+        };
 
         Ok(
             lettre::AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(target)
                 .hello_name(lettre::transport::smtp::extension::ClientId::Domain(
-                    from.domain().to_string(),
-                ))
-                .port(lettre::transport::smtp::SMTP_PORT)
-                .tls(lettre::transport::smtp::client::Tls::Opportunistic(
-                    tls_parameters,
+                    hello_name.to_string(),
                 ))
+                .port(port)
+                .tls(tls)
                 .build(),
         )
     }
 
+    fn get_opportunistic_tls_parameters(
+        config: &Config,
+        domain: &str,
+        target: &str,
+    ) -> anyhow::Result<TlsParameters> {
+        let tls_builder = TlsParameters::builder(target.to_string());
+
+        // the sender's domain could match the root domain of the server.
+        if config.server.domain == domain && config.server.tls.is_some() {
+            tls_builder.add_root_certificate(
+                Certificate::from_der(config.server.tls.as_ref().unwrap().certificate.0.clone())
+                    .context("failed to parse certificate as der")?,
+            )
+        }
+        // or a domain from one of the virtual domains.
+        else if let Some(tls_config) = config
+            .server
+            .r#virtual
+            .get(domain)
+            .and_then(|domain| domain.tls.as_ref())
+        {
+            tls_builder.add_root_certificate(
+                Certificate::from_der(tls_config.certificate.0.clone())
+                    .context("failed to parse certificate as der")?,
+            )
+        // if not, no certificate are used.
+        } else {
+            tls_builder
+        }
+        .build_rustls()
+        .context("failed to build tls parameters")
+    }
+
     /// fetch mx records for a specific domain and order them by priority.
     async fn get_mx_records(
         resolver: &trust_dns_resolver::TokioAsyncResolver,
@@ -184,6 +729,10 @@ pub mod test {
                 is_authenticated: false,
                 is_secured: false,
                 server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
             },
             client_addr: std::net::SocketAddr::new(
                 std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
@@ -197,9 +746,10 @@ pub mod test {
         }
     }
 
-    use super::transport::build_lettre_envelop;
+    use super::transport::{build_envelop, build_lettre_envelop, get_tls_policy, TlsPolicy};
     use vsmtp_common::{
         addr,
+        dsn::DsnRcpt,
         mail_context::ConnectionContext,
         rcpt::Rcpt,
         transfer::{EmailTransferStatus, Transfer},
@@ -207,16 +757,16 @@ pub mod test {
 
     #[test]
     fn test_build_lettre_envelop() {
+        let mut rcpt = Rcpt {
+            address: addr!("b@b.b"),
+            transfer_method: Transfer::None,
+            email_status: EmailTransferStatus::Sent,
+            dsn: DsnRcpt::default(),
+        };
+
         assert_eq!(
-            build_lettre_envelop(
-                &addr!("a@a.a"),
-                &[Rcpt {
-                    address: addr!("b@b.b"),
-                    transfer_method: Transfer::None,
-                    email_status: EmailTransferStatus::Sent
-                }]
-            )
-            .expect("failed to build lettre envelop"),
+            build_lettre_envelop(&build_envelop(Some(&addr!("a@a.a")), &[&mut rcpt], false))
+                .expect("failed to build lettre envelop"),
             lettre::address::Envelope::new(
                 Some("a@a.a".parse().unwrap()),
                 vec!["b@b.b".parse().unwrap()]
@@ -224,4 +774,140 @@ pub mod test {
             .unwrap()
         );
     }
+
+    #[test]
+    fn test_build_lettre_envelop_null_reverse_path() {
+        let mut rcpt = Rcpt::new(addr!("b@b.b"));
+
+        assert_eq!(
+            build_lettre_envelop(&build_envelop(None, &[&mut rcpt], false))
+                .expect("failed to build lettre envelop"),
+            lettre::address::Envelope::new(None, vec!["b@b.b".parse().unwrap()]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_build_lettre_envelop_idn() {
+        let mut rcpt = Rcpt::new(addr!("b@例子.广告"));
+
+        let envelop = build_envelop(Some(&addr!("a@bücher.example")), &[&mut rcpt], false);
+        assert!(!envelop.smtputf8);
+        assert_eq!(
+            build_lettre_envelop(&envelop).expect("failed to build lettre envelop"),
+            lettre::address::Envelope::new(
+                Some("a@xn--bcher-kva.example".parse().unwrap()),
+                vec!["b@xn--fsqu00a.xn--4rr70v".parse().unwrap()]
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_build_envelop_smtputf8() {
+        let mut ascii = Rcpt::new(addr!("john@bücher.example"));
+        let mut utf8 = Rcpt::new(addr!("用户@例子.广告"));
+
+        assert!(!build_envelop(Some(&addr!("a@a.a")), &[&mut ascii], false).smtputf8);
+        assert!(build_envelop(Some(&addr!("a@a.a")), &[&mut ascii], true).smtputf8);
+        assert!(build_envelop(Some(&addr!("用户@a.a")), &[&mut ascii], false).smtputf8);
+
+        let envelop = build_envelop(Some(&addr!("a@a.a")), &[&mut ascii, &mut utf8], false);
+        assert!(envelop.smtputf8);
+        assert_eq!(
+            envelop.to,
+            vec![addr!("john@bücher.example"), addr!("用户@例子.广告")]
+        );
+    }
+
+    #[test]
+    fn test_get_tls_policy() {
+        let mut config = vsmtp_config::Config::default();
+        config.server.tls_policy = [
+            ("example.com", vsmtp_config::ConfigTlsPolicy::Encrypt),
+            ("mx.example.com", vsmtp_config::ConfigTlsPolicy::None),
+            (
+                "mx3.example.com",
+                vsmtp_config::ConfigTlsPolicy::Verify {
+                    name: None,
+                    ca_file: vec![],
+                },
+            ),
+        ]
+        .into_iter()
+        .map(|(destination, policy)| (destination.to_string(), policy))
+        .collect();
+
+        // the policy of the mail exchanger cannot weaken the policy of the domain.
+        let policy = get_tls_policy(&config, "example.com", "mx.example.com.", Some(25), None);
+        assert!(matches!(
+            policy,
+            TlsPolicy::Configured {
+                destination: "example.com",
+                policy: vsmtp_config::ConfigTlsPolicy::Encrypt
+            }
+        ));
+        assert_eq!(policy.to_string(), "tls policy 'encrypt' of 'example.com'");
+
+        let policy = get_tls_policy(&config, "example.com", "mx3.example.com.", Some(25), None);
+        assert_eq!(
+            policy.to_string(),
+            "tls policy 'verify' of 'mx3.example.com'"
+        );
+
+        // without a policy for the domain, the policy of the mail exchanger applies.
+        let policy = get_tls_policy(&config, "example.net", "mx.example.com.", Some(25), None);
+        assert_eq!(policy.to_string(), "tls policy 'none' of 'mx.example.com'");
+
+        let policy = get_tls_policy(&config, "example.com", "mx2.example.com.", Some(25), None);
+        assert_eq!(policy.to_string(), "tls policy 'encrypt' of 'example.com'");
+
+        let policy = get_tls_policy(&config, "example.org", "mx.example.org.", Some(25), None);
+        assert!(matches!(
+            policy,
+            TlsPolicy::Dane {
+                port: 25,
+                mta_sts: None
+            }
+        ));
+        assert_eq!(policy.to_string(), "DANE on port 25");
+
+        let policy = get_tls_policy(&config, "example.org", "mx.example.org.", None, None);
+        assert!(matches!(policy, TlsPolicy::Opportunistic));
+
+        // DANE takes precedence over MTA-STS, but not over the configured policies.
+        let mta_sts = "version: STSv1\nmode: enforce\nmx: mx.example.org\nmax_age: 86400\n"
+            .parse::<crate::mta_sts::Policy>()
+            .unwrap();
+
+        let policy = get_tls_policy(
+            &config,
+            "example.com",
+            "mx2.example.com.",
+            Some(25),
+            Some(&mta_sts),
+        );
+        assert_eq!(policy.to_string(), "tls policy 'encrypt' of 'example.com'");
+
+        let policy = get_tls_policy(
+            &config,
+            "example.org",
+            "mx.example.org.",
+            Some(25),
+            Some(&mta_sts),
+        );
+        assert_eq!(
+            policy.to_string(),
+            "DANE on port 25 or MTA-STS policy 'enforce'"
+        );
+
+        let policy = get_tls_policy(
+            &config,
+            "example.org",
+            "mx.example.org.",
+            None,
+            Some(&mta_sts),
+        );
+        assert!(matches!(policy, TlsPolicy::MtaSts(_)));
+        assert_eq!(policy.to_string(), "MTA-STS policy 'enforce'");
+    }
 }
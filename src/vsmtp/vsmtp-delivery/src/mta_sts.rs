@@ -0,0 +1,796 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::tls_rpt;
+use anyhow::Context;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use trust_dns_resolver::{error::ResolveErrorKind, TokioAsyncResolver};
+use vsmtp_common::re::{anyhow, log, serde_json};
+
+const LOG_CHANNEL: &str = "server::delivery::mta_sts";
+
+/// timeout of the connection to the policy host and of the whole request.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// path of the policy file on the policy host `mta-sts.{domain}`.
+pub const POLICY_PATH: &str = "/.well-known/mta-sts.txt";
+
+/// the responses of the policy hosts are limited to 64 KiB.
+/// see https://www.rfc-editor.org/rfc/rfc8461#section-3.3
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// a policy cannot be cached for more than a year.
+/// see https://www.rfc-editor.org/rfc/rfc8461#section-3.2
+const MAX_AGE_MAX: u64 = 31_557_600;
+
+/// how the policy must be applied by the senders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// the messages are not delivered to the mail exchangers which do not satisfy the policy.
+    Enforce,
+    /// the failures are reported, but the messages are delivered anyway.
+    Testing,
+    /// the domain does not have an active policy.
+    None,
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Enforce => "enforce",
+            Self::Testing => "testing",
+            Self::None => "none",
+        })
+    }
+}
+
+/// the MTA-STS policy of a domain.
+/// see https://www.rfc-editor.org/rfc/rfc8461#section-3.2
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Policy {
+    /// how the policy must be applied.
+    pub mode: Mode,
+    /// the patterns of the mail exchangers allowed to receive the messages of the domain.
+    pub mx: Vec<String>,
+    /// lifetime of the policy in seconds.
+    pub max_age: u64,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut version = None;
+        let mut mode = None;
+        let mut max_age = None;
+        let mut mx = vec![];
+
+        for line in s.lines().map(str::trim_end).filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("invalid line in the policy: '{line}'"))?;
+            let value = value.trim();
+
+            match key {
+                "version" => version = Some(value),
+                "mode" => {
+                    mode = Some(match value {
+                        "enforce" => Mode::Enforce,
+                        "testing" => Mode::Testing,
+                        "none" => Mode::None,
+                        _ => anyhow::bail!("invalid mode in the policy: '{value}'"),
+                    });
+                }
+                "max_age" => {
+                    max_age = Some(
+                        value
+                            .parse::<u64>()
+                            .with_context(|| format!("invalid max_age in the policy: '{value}'"))?
+                            .min(MAX_AGE_MAX),
+                    );
+                }
+                "mx" => mx.push(value.to_ascii_lowercase()),
+                // unknown fields must be ignored.
+                _ => {}
+            }
+        }
+
+        anyhow::ensure!(
+            version == Some("STSv1"),
+            "unsupported policy version: {version:?}"
+        );
+        let mode = mode.context("missing field 'mode' in the policy")?;
+        anyhow::ensure!(
+            mode == Mode::None || !mx.is_empty(),
+            "missing field 'mx' in the policy"
+        );
+
+        Ok(Self {
+            mode,
+            mx,
+            max_age: max_age.context("missing field 'max_age' in the policy")?,
+        })
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "version: STSv1")?;
+        writeln!(f, "mode: {}", self.mode)?;
+        for mx in &self.mx {
+            writeln!(f, "mx: {mx}")?;
+        }
+        writeln!(f, "max_age: {}", self.max_age)
+    }
+}
+
+impl Policy {
+    /// is the mail exchanger `host` allowed by the policy.
+    ///
+    /// a wildcard pattern `*.example.com` only matches the left-most label of the host.
+    /// see https://www.rfc-editor.org/rfc/rfc8461#section-4.1
+    #[must_use]
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+        self.mx
+            .iter()
+            .any(|pattern| match pattern.strip_prefix("*.") {
+                Some(suffix) => host.split_once('.').map_or(false, |(label, domain)| {
+                    !label.is_empty() && domain == suffix
+                }),
+                None => *pattern == host,
+            })
+    }
+}
+
+/// parse the `_mta-sts` TXT record of a domain and return the id of its policy.
+/// see https://www.rfc-editor.org/rfc/rfc8461#section-3.1
+fn parse_record(record: &str) -> anyhow::Result<String> {
+    let mut fields = record.split(';').map(str::trim);
+
+    anyhow::ensure!(
+        fields.next() == Some("v=STSv1"),
+        "unsupported record version: '{record}'"
+    );
+
+    fields
+        .filter_map(|field| field.split_once('='))
+        .find(|(key, _)| *key == "id")
+        .map(|(_, id)| id.to_string())
+        .filter(|id| (1..=32).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric()))
+        .ok_or_else(|| anyhow::anyhow!("missing or invalid id in the record: '{record}'"))
+}
+
+/// fetch the `_mta-sts` TXT record of `domain`, and return the id of its policy.
+///
+/// `Ok(None)` is returned if the domain does not publish a record, or publishes
+/// more than one record.
+///
+/// # Errors
+///
+/// * the lookup failed.
+/// * the record is invalid.
+pub async fn get_policy_id(
+    resolver: &TokioAsyncResolver,
+    domain: &str,
+) -> anyhow::Result<Option<String>> {
+    let query = format!("_mta-sts.{}.", domain.trim_end_matches('.'));
+
+    match resolver.txt_lookup(query.as_str()).await {
+        Ok(lookup) => {
+            let mut records = lookup
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|data| String::from_utf8_lossy(data))
+                        .collect::<String>()
+                })
+                .filter(|record| record.starts_with("v=STSv1"));
+
+            match (records.next(), records.next()) {
+                (Some(record), None) => parse_record(&record).map(Some),
+                _ => Ok(None),
+            }
+        }
+        Err(error) => match error.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => Ok(None),
+            _ => Err(anyhow::anyhow!(
+                "failed to get the TXT record '{query}': {error}"
+            )),
+        },
+    }
+}
+
+/// retrieve the policy files of the domains.
+#[async_trait::async_trait]
+pub trait PolicyFetcher: Send + Sync {
+    /// fetch the policy file of `domain`, served at `https://mta-sts.{domain}/.well-known/mta-sts.txt`.
+    ///
+    /// # Errors
+    ///
+    /// * the policy host could not be reached, or did not serve the file.
+    async fn fetch(&self, domain: &str) -> anyhow::Result<String>;
+}
+
+/// fetch the policy files over HTTPS, the certificate of the policy host
+/// must be issued by a public authority.
+#[derive(Debug, Default)]
+pub struct HttpsPolicyFetcher;
+
+#[async_trait::async_trait]
+impl PolicyFetcher for HttpsPolicyFetcher {
+    async fn fetch(&self, domain: &str) -> anyhow::Result<String> {
+        let host = format!("mta-sts.{}", domain.trim_end_matches('.'));
+        let server_name = rustls::ServerName::try_from(host.as_str())
+            .with_context(|| format!("invalid policy host '{host}'"))?;
+
+        let mut root_store = rustls::RootCertStore::empty();
+        root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        let tls_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+
+        tokio::time::timeout(TIMEOUT, async {
+            let stream = tokio::net::TcpStream::connect((host.as_str(), 443))
+                .await
+                .with_context(|| format!("failed to connect to '{host}'"))?;
+            let stream = tokio_rustls::TlsConnector::from(std::sync::Arc::new(tls_config))
+                .connect(server_name, stream)
+                .await
+                .map_err(|error| tls_rpt::Failure {
+                    result_type: tls_rpt::ResultType::StsWebpkiInvalid,
+                    message: format!("tls handshake with '{host}' failed: {error}"),
+                })?;
+
+            http_get(stream, &host).await
+        })
+        .await
+        .with_context(|| format!("timeout while fetching the policy of '{host}'"))?
+    }
+}
+
+/// request the policy file of `host` on `stream`, and return the body of the response.
+///
+/// the redirections are not followed, and the policy must be served as `text/plain`.
+/// see https://www.rfc-editor.org/rfc/rfc8461#section-3.3
+///
+/// # Errors
+///
+/// * the connection failed.
+/// * the response is invalid, too large, or is not a `200 OK` with a `text/plain` body.
+pub async fn http_get<S>(mut stream: S, host: &str) -> anyhow::Result<String>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
+{
+    stream
+        .write_all(
+            format!("GET {POLICY_PATH} HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .await?;
+    stream.flush().await?;
+
+    let mut response = vec![];
+    match (&mut stream)
+        .take(MAX_RESPONSE_SIZE as u64 + 1)
+        .read_to_end(&mut response)
+        .await
+    {
+        // the server can close the connection without a tls close_notify.
+        Err(error) if error.kind() != std::io::ErrorKind::UnexpectedEof => {
+            return Err(error).with_context(|| format!("failed to read the response of '{host}'"));
+        }
+        _ => {}
+    }
+    anyhow::ensure!(
+        response.len() <= MAX_RESPONSE_SIZE,
+        "the response of '{host}' is too large"
+    );
+
+    let response = String::from_utf8(response)
+        .with_context(|| format!("the response of '{host}' is not valid utf8"))?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("invalid http response from '{host}'"))?;
+    let mut head = head.lines();
+
+    let status = head
+        .next()
+        .filter(|line| line.starts_with("HTTP/1."))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .ok_or_else(|| anyhow::anyhow!("invalid http response from '{host}'"))?;
+    anyhow::ensure!(
+        status == "200",
+        "'{host}' replied with the http status {status}"
+    );
+
+    anyhow::ensure!(
+        head.filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-type"))
+            .map_or(false, |(_, value)| value
+                .trim()
+                .to_ascii_lowercase()
+                .starts_with("text/plain")),
+        "the policy of '{host}' is not served as text/plain"
+    );
+
+    Ok(body.to_string())
+}
+
+/// a policy stored in the cache, with the id of the record announcing it.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct CachedPolicy {
+    id: String,
+    /// unix timestamp of the expiration of the policy.
+    expires_at: u64,
+    policy: Policy,
+}
+
+fn get_cache_path(cache_dir: &std::path::Path, domain: &str) -> anyhow::Result<std::path::PathBuf> {
+    Ok(cache_dir.join(format!("{}.json", crate::get_domain_file_name(domain)?)))
+}
+
+fn read_cache(path: &std::path::Path) -> Option<CachedPolicy> {
+    match std::fs::read(path) {
+        Ok(content) => match serde_json::from_slice(&content) {
+            Ok(cached) => Some(cached),
+            Err(error) => {
+                log::warn!(
+                    target: LOG_CHANNEL,
+                    "invalid cached policy '{}': {error}",
+                    path.display()
+                );
+                None
+            }
+        },
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+        Err(error) => {
+            log::warn!(
+                target: LOG_CHANNEL,
+                "failed to read the cached policy '{}': {error}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+fn write_cache(path: &std::path::Path, cached: &CachedPolicy) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create the folder '{}'", parent.display()))?;
+    }
+
+    // the policy is replaced atomically, another delivery could be reading it.
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec(cached)?)
+        .with_context(|| format!("failed to write '{}'", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to write '{}'", path.display()))?;
+
+    Ok(())
+}
+
+/// fetch and parse the policy of `domain`.
+async fn fetch_policy(
+    fetcher: &dyn PolicyFetcher,
+    domain: &str,
+) -> Result<Policy, tls_rpt::Failure> {
+    let body = fetcher.fetch(domain).await.map_err(|error| {
+        match error.downcast::<tls_rpt::Failure>() {
+            Ok(failure) => failure,
+            Err(error) => tls_rpt::Failure {
+                result_type: tls_rpt::ResultType::StsPolicyFetchError,
+                message: format!("failed to fetch the policy of '{domain}': {error:#}"),
+            },
+        }
+    })?;
+
+    body.parse::<Policy>().map_err(|error| tls_rpt::Failure {
+        result_type: tls_rpt::ResultType::StsPolicyInvalid,
+        message: format!("invalid policy for '{domain}': {error:#}"),
+    })
+}
+
+/// get the policy of `domain` from the cache located in `cache_dir`, it is fetched again
+/// if the record announces a new `id` or if the cached policy expired.
+///
+/// a valid cached policy is still used if the domain does not publish a record anymore,
+/// or if the new policy cannot be fetched.
+/// see https://www.rfc-editor.org/rfc/rfc8461#section-5.1
+///
+/// # Errors
+///
+/// * `domain` cannot be used to cache its policy.
+/// * the policy could not be fetched or is invalid, and no valid policy is cached,
+///   a [`tls_rpt::Failure`] is returned so that it can be reported.
+pub async fn resolve_policy(
+    fetcher: &dyn PolicyFetcher,
+    cache_dir: &std::path::Path,
+    domain: &str,
+    id: Option<&str>,
+    now: std::time::SystemTime,
+) -> anyhow::Result<Option<Policy>> {
+    let path = get_cache_path(cache_dir, domain)?;
+    let now = now.duration_since(std::time::UNIX_EPOCH)?.as_secs();
+
+    let cached = read_cache(&path).filter(|cached| cached.expires_at > now);
+
+    let (id, policy) = match (id, cached) {
+        (None, cached) => return Ok(cached.map(|cached| cached.policy)),
+        (Some(id), Some(cached)) if cached.id == id => return Ok(Some(cached.policy)),
+        (Some(id), cached) => match fetch_policy(fetcher, domain).await {
+            Ok(policy) => (id, policy),
+            Err(failure) => {
+                log::warn!(target: LOG_CHANNEL, "{failure}");
+                return match cached {
+                    Some(cached) => Ok(Some(cached.policy)),
+                    None => Err(failure.into()),
+                };
+            }
+        },
+    };
+
+    if let Err(error) = write_cache(
+        &path,
+        &CachedPolicy {
+            id: id.to_string(),
+            expires_at: now + policy.max_age,
+            policy: policy.clone(),
+        },
+    ) {
+        log::warn!(
+            target: LOG_CHANNEL,
+            "failed to cache the policy of '{domain}': {error:#}"
+        );
+    }
+
+    Ok(Some(policy))
+}
+
+/// get the policy of `domain`, using the cache located in `cache_dir`.
+/// see [`resolve_policy`].
+///
+/// # Errors
+///
+/// * `domain` cannot be used to cache its policy.
+/// * the policy could not be fetched or is invalid, and no valid policy is cached.
+pub async fn get_policy(
+    resolver: &TokioAsyncResolver,
+    fetcher: &dyn PolicyFetcher,
+    cache_dir: &std::path::Path,
+    domain: &str,
+) -> anyhow::Result<Option<Policy>> {
+    let id = match get_policy_id(resolver, domain).await {
+        Ok(id) => id,
+        Err(error) => {
+            log::warn!(target: LOG_CHANNEL, "{error:#}");
+            None
+        }
+    };
+
+    resolve_policy(
+        fetcher,
+        cache_dir,
+        domain,
+        id.as_deref(),
+        std::time::SystemTime::now(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{http_get, parse_record, resolve_policy, Mode, Policy, PolicyFetcher};
+    use crate::tls_rpt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use vsmtp_common::re::anyhow;
+
+    const POLICY: &str = "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\nmx: *.example.net\r\nmax_age: 86400\r\n";
+
+    /// a local http server standing in for the policy host of the domains.
+    struct LocalPolicyHost {
+        addr: std::net::SocketAddr,
+    }
+
+    impl LocalPolicyHost {
+        /// serve `response` to each request.
+        async fn serve(response: &'static str) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let mut request = vec![0; 1024];
+                    let size = stream.read(&mut request).await.unwrap();
+                    assert!(String::from_utf8_lossy(&request[..size])
+                        .starts_with("GET /.well-known/mta-sts.txt HTTP/1.0\r\n"));
+
+                    // the client can stop reading a response too large.
+                    let _ = stream.write_all(response.as_bytes()).await;
+                }
+            });
+
+            Self { addr }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl PolicyFetcher for LocalPolicyHost {
+        async fn fetch(&self, domain: &str) -> anyhow::Result<String> {
+            http_get(
+                tokio::net::TcpStream::connect(self.addr).await?,
+                &format!("mta-sts.{domain}"),
+            )
+            .await
+        }
+    }
+
+    /// a policy host which cannot be reached.
+    struct Unreachable;
+
+    #[async_trait::async_trait]
+    impl PolicyFetcher for Unreachable {
+        async fn fetch(&self, _: &str) -> anyhow::Result<String> {
+            anyhow::bail!("unreachable")
+        }
+    }
+
+    fn response(status: &str, content_type: &str, body: &str) -> &'static str {
+        Box::leak(
+            format!(
+                "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .into_boxed_str(),
+        )
+    }
+
+    fn get_cache_dir(name: &str) -> std::path::PathBuf {
+        let cache_dir = std::path::PathBuf::from(format!("./tmp/mta-sts/{name}"));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        cache_dir
+    }
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(
+            POLICY.parse::<Policy>().unwrap(),
+            Policy {
+                mode: Mode::Enforce,
+                mx: vec!["mail.example.com".to_string(), "*.example.net".to_string()],
+                max_age: 86400,
+            }
+        );
+
+        assert_eq!(
+            "version: STSv1\nmode: testing\nmx: MX.example.com\nmax_age: 99999999999\nunknown: field\n"
+                .parse::<Policy>()
+                .unwrap(),
+            Policy {
+                mode: Mode::Testing,
+                mx: vec!["mx.example.com".to_string()],
+                max_age: 31_557_600,
+            }
+        );
+
+        assert_eq!(
+            "version: STSv1\nmode: none\nmax_age: 0\n"
+                .parse::<Policy>()
+                .unwrap()
+                .mode,
+            Mode::None
+        );
+    }
+
+    #[test]
+    fn display_policy() {
+        let policy = POLICY.parse::<Policy>().unwrap();
+        assert_eq!(policy.to_string(), POLICY.replace("\r\n", "\n"));
+        assert_eq!(policy.to_string().parse::<Policy>().unwrap(), policy);
+    }
+
+    #[test]
+    fn parse_invalid_policy() {
+        for policy in [
+            "",
+            "version: STSv2\nmode: enforce\nmx: mx.example.com\nmax_age: 86400\n",
+            "version: STSv1\nmode: strict\nmx: mx.example.com\nmax_age: 86400\n",
+            "version: STSv1\nmode: enforce\nmax_age: 86400\n",
+            "version: STSv1\nmode: enforce\nmx: mx.example.com\n",
+            "version: STSv1\nmode: enforce\nmx: mx.example.com\nmax_age: -1\n",
+            "<html>version: STSv1</html>",
+        ] {
+            assert!(policy.parse::<Policy>().is_err(), "{policy}");
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let policy = POLICY.parse::<Policy>().unwrap();
+
+        assert!(policy.matches("mail.example.com"));
+        assert!(policy.matches("MAIL.example.com."));
+        assert!(policy.matches("mx1.example.net."));
+        assert!(!policy.matches("example.net"));
+        assert!(!policy.matches("a.mx1.example.net"));
+        assert!(!policy.matches("mail.example.com.evil.com"));
+        assert!(!policy.matches("mx.example.org"));
+    }
+
+    #[test]
+    fn record() {
+        assert_eq!(
+            parse_record("v=STSv1; id=20160831085700Z;").unwrap(),
+            "20160831085700Z"
+        );
+        assert_eq!(parse_record("v=STSv1;id=abc").unwrap(), "abc");
+        assert!(parse_record("v=STSv2; id=abc").is_err());
+        assert!(parse_record("v=STSv1;").is_err());
+        assert!(parse_record("v=STSv1; id=not-alphanumeric").is_err());
+        assert!(parse_record(&format!("v=STSv1; id={}", "a".repeat(33))).is_err());
+    }
+
+    #[tokio::test]
+    async fn fetch() {
+        let host =
+            LocalPolicyHost::serve(response("200 OK", "text/plain; charset=utf-8", POLICY)).await;
+        assert_eq!(host.fetch("example.com").await.unwrap(), POLICY);
+    }
+
+    #[tokio::test]
+    async fn fetch_invalid_response() {
+        for reply in [
+            response("404 Not Found", "text/plain", ""),
+            response("301 Moved Permanently", "text/plain", POLICY),
+            response("200 OK", "text/html", POLICY),
+            "not http",
+        ] {
+            let host = LocalPolicyHost::serve(reply).await;
+            assert!(host.fetch("example.com").await.is_err(), "{reply}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_too_large() {
+        let body = format!("{POLICY}{}", "mx: mx.example.com\r\n".repeat(4000));
+        let host = LocalPolicyHost::serve(response("200 OK", "text/plain", &body)).await;
+        assert!(host.fetch("example.com").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cached() {
+        let cache_dir = get_cache_dir("cached");
+        let now = std::time::SystemTime::now();
+        let host = LocalPolicyHost::serve(response("200 OK", "text/plain", POLICY)).await;
+
+        let policy = resolve_policy(&host, &cache_dir, "example.com", Some("1"), now)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(policy, POLICY.parse::<Policy>().unwrap());
+        assert!(cache_dir.join("example.com.json").exists());
+
+        // the cached policy is used, even if the policy host is down, or if the
+        // record is not published anymore.
+        for id in [Some("1"), Some("2"), None] {
+            assert_eq!(
+                resolve_policy(&Unreachable, &cache_dir, "example.com", id, now)
+                    .await
+                    .unwrap(),
+                Some(policy.clone())
+            );
+        }
+
+        // but not once it expired.
+        let later = now + std::time::Duration::from_secs(policy.max_age);
+        assert_eq!(
+            resolve_policy(&Unreachable, &cache_dir, "example.com", None, later)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            tls_rpt::get_result_type(
+                &resolve_policy(&Unreachable, &cache_dir, "example.com", Some("1"), later)
+                    .await
+                    .unwrap_err()
+            ),
+            Some(tls_rpt::ResultType::StsPolicyFetchError)
+        );
+    }
+
+    #[tokio::test]
+    async fn refreshed() {
+        let cache_dir = get_cache_dir("refreshed");
+        let now = std::time::SystemTime::now();
+
+        let host = LocalPolicyHost::serve(response("200 OK", "text/plain", POLICY)).await;
+        resolve_policy(&host, &cache_dir, "example.com", Some("1"), now)
+            .await
+            .unwrap();
+
+        // a new id announces a new policy.
+        let host = LocalPolicyHost::serve(response(
+            "200 OK",
+            "text/plain",
+            "version: STSv1\nmode: none\nmax_age: 86400\n",
+        ))
+        .await;
+        assert_eq!(
+            resolve_policy(&host, &cache_dir, "example.com", Some("2"), now)
+                .await
+                .unwrap()
+                .unwrap()
+                .mode,
+            Mode::None
+        );
+        assert_eq!(
+            resolve_policy(&Unreachable, &cache_dir, "example.com", Some("2"), now)
+                .await
+                .unwrap()
+                .unwrap()
+                .mode,
+            Mode::None
+        );
+    }
+
+    #[tokio::test]
+    async fn no_policy() {
+        let cache_dir = get_cache_dir("no_policy");
+        let now = std::time::SystemTime::now();
+
+        assert_eq!(
+            resolve_policy(&Unreachable, &cache_dir, "example.com", None, now)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            tls_rpt::get_result_type(
+                &resolve_policy(&Unreachable, &cache_dir, "example.com", Some("1"), now)
+                    .await
+                    .unwrap_err()
+            ),
+            Some(tls_rpt::ResultType::StsPolicyFetchError)
+        );
+
+        let host = LocalPolicyHost::serve(response("200 OK", "text/plain", "<html></html>")).await;
+        assert_eq!(
+            tls_rpt::get_result_type(
+                &resolve_policy(&host, &cache_dir, "example.com", Some("1"), now)
+                    .await
+                    .unwrap_err()
+            ),
+            Some(tls_rpt::ResultType::StsPolicyInvalid)
+        );
+
+        assert!(
+            resolve_policy(&Unreachable, &cache_dir, "../example.com", Some("1"), now)
+                .await
+                .is_err()
+        );
+    }
+}
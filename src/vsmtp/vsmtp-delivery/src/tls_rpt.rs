@@ -0,0 +1,743 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use anyhow::Context;
+use std::io::Write;
+use trust_dns_resolver::{error::ResolveErrorKind, TokioAsyncResolver};
+use vsmtp_common::re::{anyhow, serde_json};
+
+/// the results are recorded and reported by day.
+const DAY: u64 = 24 * 60 * 60;
+
+/// the type of the policy applied to a tls session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyType {
+    /// the tlsa records of the mail exchanger (DANE).
+    Tlsa,
+    /// the MTA-STS policy of the domain.
+    Sts,
+    /// the domain does not publish a policy.
+    NoPolicyFound,
+}
+
+/// the policy applied to a tls session.
+/// see https://www.rfc-editor.org/rfc/rfc8460#section-4.4
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AppliedPolicy {
+    /// the type of the policy.
+    pub policy_type: PolicyType,
+    /// the tlsa records, or the lines of the MTA-STS policy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policy_string: Vec<String>,
+    /// the domain the messages were delivered to.
+    pub policy_domain: String,
+    /// the patterns of the mail exchangers allowed by the MTA-STS policy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mx_host: Vec<String>,
+}
+
+/// the reason of the failure of a tls session.
+/// see https://www.rfc-editor.org/rfc/rfc8460#section-4.3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResultType {
+    /// the mail exchanger does not offer STARTTLS.
+    StarttlsNotSupported,
+    /// the certificate is not valid for the name of the mail exchanger.
+    CertificateHostMismatch,
+    /// the certificate expired, or is not valid yet.
+    CertificateExpired,
+    /// the certificate is not issued by a trusted authority.
+    CertificateNotTrusted,
+    /// any other failure, the mail exchanger not allowed by the policy for example.
+    ValidationFailure,
+    /// none of the tlsa records match the certificate of the mail exchanger.
+    TlsaInvalid,
+    /// the tlsa records could not be fetched, or their dnssec validation failed.
+    DnssecInvalid,
+    /// the MTA-STS policy could not be fetched.
+    StsPolicyFetchError,
+    /// the MTA-STS policy is invalid.
+    StsPolicyInvalid,
+    /// the certificate of the MTA-STS policy host is invalid.
+    StsWebpkiInvalid,
+}
+
+/// a failure of a tls session, raised by the transports so that it can be reported.
+#[derive(Debug)]
+pub struct Failure {
+    /// the reason of the failure.
+    pub result_type: ResultType,
+    /// a description of the failure.
+    pub message: String,
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// the reason of the failure of a tls session, `None` if the failure is not related
+/// to tls (the mail exchanger cannot be reached, a command has been rejected, ...).
+#[must_use]
+pub fn get_result_type(error: &anyhow::Error) -> Option<ResultType> {
+    error.chain().find_map(|error| {
+        if let Some(failure) = error.downcast_ref::<Failure>() {
+            return Some(failure.result_type);
+        }
+
+        // the handshake errors are wrapped in io errors by tokio-rustls.
+        let tls_error = error.downcast_ref::<rustls::Error>().or_else(|| {
+            error
+                .downcast_ref::<std::io::Error>()
+                .and_then(std::io::Error::get_ref)
+                .and_then(|error| error.downcast_ref::<rustls::Error>())
+        });
+        if let Some(tls_error) = tls_error {
+            return Some(match tls_error {
+                rustls::Error::InvalidCertificateData(reason)
+                    if reason.contains(crate::dane::NO_MATCHING_RECORD) =>
+                {
+                    ResultType::TlsaInvalid
+                }
+                rustls::Error::InvalidCertificateData(reason)
+                    if reason.contains("CertNotValidForName") =>
+                {
+                    ResultType::CertificateHostMismatch
+                }
+                rustls::Error::InvalidCertificateData(reason)
+                    if reason.contains("CertExpired") || reason.contains("CertNotValidYet") =>
+                {
+                    ResultType::CertificateExpired
+                }
+                rustls::Error::InvalidCertificateData(reason)
+                    if reason.contains("UnknownIssuer") =>
+                {
+                    ResultType::CertificateNotTrusted
+                }
+                _ => ResultType::ValidationFailure,
+            });
+        }
+
+        // lettre does not expose the kind of its client errors.
+        error
+            .downcast_ref::<lettre::transport::smtp::Error>()
+            .filter(|error| error.to_string().contains("STARTTLS"))
+            .map(|_| ResultType::StarttlsNotSupported)
+    })
+}
+
+/// the result of a tls session, recorded until it is reported.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionResult {
+    /// the policy applied to the session.
+    pub policy: AppliedPolicy,
+    /// the mail exchanger the session was opened with, empty if the session failed
+    /// before a mail exchanger was selected.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub receiving_mx_hostname: String,
+    /// the reason of the failure, `None` if the session succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_type: Option<ResultType>,
+    /// a description of the failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_reason_code: Option<String>,
+}
+
+/// append `session` to the results of its policy domain for the day of `now`,
+/// the results are stored in `{dir}/{timestamp of the day}/{policy domain}.jsonl`.
+///
+/// # Errors
+///
+/// * the policy domain cannot be used as a file name.
+/// * the result could not be written.
+pub fn record(
+    dir: &std::path::Path,
+    session: &SessionResult,
+    now: std::time::SystemTime,
+) -> anyhow::Result<()> {
+    let day = now.duration_since(std::time::UNIX_EPOCH)?.as_secs() / DAY * DAY;
+    let dir = dir.join(day.to_string());
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create the folder '{}'", dir.display()))?;
+
+    let path = dir.join(format!(
+        "{}.jsonl",
+        crate::get_domain_file_name(&session.policy.policy_domain)?
+    ));
+
+    let mut line = serde_json::to_vec(session)?;
+    line.push(b'\n');
+
+    // a single write in append mode, the sessions are recorded concurrently.
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&line))
+        .with_context(|| format!("failed to write '{}'", path.display()))
+}
+
+/// the results recorded for a policy domain during a day.
+#[derive(Debug)]
+pub struct DailyResults {
+    /// unix timestamp of the start of the day.
+    pub day: u64,
+    /// the domain the results must be reported to.
+    pub policy_domain: String,
+    /// the file storing the results.
+    pub path: std::path::PathBuf,
+}
+
+impl DailyResults {
+    /// read the sessions recorded during the day.
+    ///
+    /// # Errors
+    ///
+    /// * the file could not be read.
+    pub fn read(&self) -> anyhow::Result<Vec<SessionResult>> {
+        Ok(std::fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read '{}'", self.path.display()))?
+            .lines()
+            // a line can be truncated if the server stopped while recording it.
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// remove the results once they have been reported, and the folder of the day
+    /// if all its results have been reported.
+    ///
+    /// # Errors
+    ///
+    /// * the file could not be removed.
+    pub fn remove(&self) -> anyhow::Result<()> {
+        std::fs::remove_file(&self.path)
+            .with_context(|| format!("failed to remove '{}'", self.path.display()))?;
+
+        if let Some(dir) = self.path.parent() {
+            // fails if the folder is not empty.
+            let _ = std::fs::remove_dir(dir);
+        }
+
+        Ok(())
+    }
+}
+
+/// the results stored in `dir` for the days elapsed before `now`, ready to be reported.
+///
+/// # Errors
+///
+/// * `dir` could not be read.
+pub fn get_elapsed_days(
+    dir: &std::path::Path,
+    now: std::time::SystemTime,
+) -> anyhow::Result<Vec<DailyResults>> {
+    let today = now.duration_since(std::time::UNIX_EPOCH)?.as_secs() / DAY * DAY;
+
+    let days = match std::fs::read_dir(dir) {
+        Ok(days) => days,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read '{}'", dir.display()))
+        }
+    };
+
+    let mut out = vec![];
+    for day in days {
+        let day = day?.path();
+        let timestamp = match day
+            .file_name()
+            .and_then(std::ffi::OsStr::to_str)
+            .and_then(|name| name.parse::<u64>().ok())
+        {
+            Some(timestamp) if timestamp < today => timestamp,
+            _ => continue,
+        };
+
+        for results in std::fs::read_dir(&day)
+            .with_context(|| format!("failed to read '{}'", day.display()))?
+        {
+            let path = results?.path();
+            if let Some(policy_domain) = path
+                .file_name()
+                .and_then(std::ffi::OsStr::to_str)
+                .and_then(|name| name.strip_suffix(".jsonl"))
+            {
+                out.push(DailyResults {
+                    day: timestamp,
+                    policy_domain: policy_domain.to_string(),
+                    path,
+                });
+            }
+        }
+    }
+
+    out.sort_by(|a, b| (a.day, &a.policy_domain).cmp(&(b.day, &b.policy_domain)));
+    Ok(out)
+}
+
+/// the period covered by a report.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DateRange {
+    /// start of the period, formatted as in rfc 3339.
+    pub start_datetime: String,
+    /// end of the period, formatted as in rfc 3339.
+    pub end_datetime: String,
+}
+
+/// the number of sessions that succeeded and failed for a policy.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Summary {
+    /// sessions that succeeded.
+    pub total_successful_session_count: u64,
+    /// sessions that failed.
+    pub total_failure_session_count: u64,
+}
+
+/// the sessions that failed for the same reason with the same mail exchanger.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FailureDetails {
+    /// the reason of the failure.
+    pub result_type: ResultType,
+    /// the mail exchanger the sessions were opened with.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub receiving_mx_hostname: String,
+    /// the number of sessions that failed.
+    pub failed_session_count: u64,
+    /// a description of the failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_reason_code: Option<String>,
+}
+
+/// the results of the sessions for a policy.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PolicyResults {
+    /// the policy applied to the sessions.
+    pub policy: AppliedPolicy,
+    /// the count of the sessions.
+    pub summary: Summary,
+    /// the sessions that failed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failure_details: Vec<FailureDetails>,
+}
+
+/// an aggregate report of the tls sessions opened with the mail exchangers of a domain.
+/// see https://www.rfc-editor.org/rfc/rfc8460#section-4.4
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Report {
+    /// the organization sending the report.
+    pub organization_name: String,
+    /// the period covered by the report.
+    pub date_range: DateRange,
+    /// the contact of the organization sending the report.
+    pub contact_info: String,
+    /// a unique identifier of the report.
+    pub report_id: String,
+    /// the results of the sessions for each policy.
+    pub policies: Vec<PolicyResults>,
+}
+
+impl Report {
+    /// aggregate the `sessions` recorded during the `day`.
+    ///
+    /// # Errors
+    ///
+    /// * the period of the report could not be formatted.
+    pub fn new(
+        organization_name: &str,
+        contact_info: &str,
+        report_id: &str,
+        day: u64,
+        sessions: &[SessionResult],
+    ) -> anyhow::Result<Self> {
+        let to_rfc3339 = |timestamp: u64| {
+            time::OffsetDateTime::from_unix_timestamp(i64::try_from(timestamp)?)?
+                .format(&time::format_description::well_known::Rfc3339)
+                .map_err(anyhow::Error::from)
+        };
+
+        let mut policies = Vec::<PolicyResults>::new();
+        for session in sessions {
+            let index = match policies
+                .iter()
+                .position(|results| results.policy == session.policy)
+            {
+                Some(index) => index,
+                None => {
+                    policies.push(PolicyResults {
+                        policy: session.policy.clone(),
+                        summary: Summary {
+                            total_successful_session_count: 0,
+                            total_failure_session_count: 0,
+                        },
+                        failure_details: vec![],
+                    });
+                    policies.len() - 1
+                }
+            };
+            let results = &mut policies[index];
+
+            let result_type = match session.result_type {
+                Some(result_type) => result_type,
+                None => {
+                    results.summary.total_successful_session_count += 1;
+                    continue;
+                }
+            };
+            results.summary.total_failure_session_count += 1;
+
+            match results.failure_details.iter_mut().find(|details| {
+                details.result_type == result_type
+                    && details.receiving_mx_hostname == session.receiving_mx_hostname
+                    && details.failure_reason_code == session.failure_reason_code
+            }) {
+                Some(details) => details.failed_session_count += 1,
+                None => results.failure_details.push(FailureDetails {
+                    result_type,
+                    receiving_mx_hostname: session.receiving_mx_hostname.clone(),
+                    failed_session_count: 1,
+                    failure_reason_code: session.failure_reason_code.clone(),
+                }),
+            }
+        }
+
+        Ok(Self {
+            organization_name: organization_name.to_string(),
+            date_range: DateRange {
+                start_datetime: to_rfc3339(day)?,
+                end_datetime: to_rfc3339(day + DAY - 1)?,
+            },
+            contact_info: contact_info.to_string(),
+            report_id: report_id.to_string(),
+            policies,
+        })
+    }
+}
+
+/// parse the `_smtp._tls` TXT record of a domain, and return its `mailto:` reporting addresses.
+/// see https://www.rfc-editor.org/rfc/rfc8460#section-3
+fn parse_record(record: &str) -> anyhow::Result<Vec<String>> {
+    let mut fields = record.split(';').map(str::trim);
+
+    anyhow::ensure!(
+        fields.next() == Some("v=TLSRPTv1"),
+        "unsupported record version: '{record}'"
+    );
+
+    Ok(fields
+        .filter_map(|field| field.split_once('='))
+        .find(|(key, _)| *key == "rua")
+        .ok_or_else(|| anyhow::anyhow!("missing rua in the record: '{record}'"))?
+        .1
+        .split(',')
+        // the reports are not submitted with https.
+        .filter_map(|uri| uri.trim().strip_prefix("mailto:"))
+        .map(|address| {
+            address
+                .split_once('?')
+                .map_or(address, |(address, _)| address)
+                .to_string()
+        })
+        .collect())
+}
+
+/// fetch the `_smtp._tls` TXT record of `domain`, and return the addresses the reports
+/// must be sent to, empty if the domain does not want to receive reports.
+///
+/// # Errors
+///
+/// * the lookup failed.
+/// * the record is invalid.
+pub async fn get_reporting_addresses(
+    resolver: &TokioAsyncResolver,
+    domain: &str,
+) -> anyhow::Result<Vec<String>> {
+    let query = format!("_smtp._tls.{}.", domain.trim_end_matches('.'));
+
+    match resolver.txt_lookup(query.as_str()).await {
+        Ok(lookup) => {
+            let mut records = lookup
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|data| String::from_utf8_lossy(data))
+                        .collect::<String>()
+                })
+                .filter(|record| record.starts_with("v=TLSRPTv1"));
+
+            // multiple records are treated as if there was none.
+            match (records.next(), records.next()) {
+                (Some(record), None) => parse_record(&record),
+                _ => Ok(vec![]),
+            }
+        }
+        Err(error) => match error.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => Ok(vec![]),
+            _ => Err(anyhow::anyhow!(
+                "failed to get the TXT record '{query}': {error}"
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_elapsed_days, get_result_type, parse_record, record, AppliedPolicy, Failure,
+        PolicyType, Report, ResultType, SessionResult, DAY,
+    };
+    use vsmtp_common::re::{anyhow, serde_json};
+
+    // 2022-06-01T00:00:00Z
+    const DAY_START: u64 = 1_654_041_600;
+
+    fn sts_policy() -> AppliedPolicy {
+        AppliedPolicy {
+            policy_type: PolicyType::Sts,
+            policy_string: vec![
+                "version: STSv1".to_string(),
+                "mode: testing".to_string(),
+                "mx: *.example.com".to_string(),
+                "max_age: 86400".to_string(),
+            ],
+            policy_domain: "example.com".to_string(),
+            mx_host: vec!["*.example.com".to_string()],
+        }
+    }
+
+    fn session(policy: &AppliedPolicy, mx: &str, result_type: Option<ResultType>) -> SessionResult {
+        SessionResult {
+            policy: policy.clone(),
+            receiving_mx_hostname: mx.to_string(),
+            result_type,
+            failure_reason_code: result_type.map(|_| "failure".to_string()),
+        }
+    }
+
+    #[test]
+    fn result_type() {
+        assert_eq!(
+            get_result_type(&anyhow::Error::from(Failure {
+                result_type: ResultType::StarttlsNotSupported,
+                message: "no STARTTLS".to_string(),
+            })),
+            Some(ResultType::StarttlsNotSupported)
+        );
+
+        let handshake = |reason: &str| {
+            anyhow::Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                rustls::Error::InvalidCertificateData(format!(
+                    "invalid peer certificate: {reason}"
+                )),
+            ))
+            .context("tls handshake with 'mx.example.com' failed")
+        };
+
+        for (reason, result_type) in [
+            ("CertNotValidForName", ResultType::CertificateHostMismatch),
+            ("CertExpired", ResultType::CertificateExpired),
+            ("UnknownIssuer", ResultType::CertificateNotTrusted),
+            (
+                "none of the tlsa records match the certificate of 'mx.example.com'",
+                ResultType::TlsaInvalid,
+            ),
+            ("BadSignature", ResultType::ValidationFailure),
+        ] {
+            assert_eq!(get_result_type(&handshake(reason)), Some(result_type));
+        }
+
+        assert_eq!(
+            get_result_type(&anyhow::anyhow!("connection refused").context("failed to send")),
+            None
+        );
+    }
+
+    #[test]
+    fn recorded_by_day() {
+        let dir = std::path::PathBuf::from("./tmp/tls-rpt/recorded_by_day");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let day = std::time::UNIX_EPOCH + std::time::Duration::from_secs(DAY_START);
+        let next_day = day + std::time::Duration::from_secs(DAY);
+
+        let policy = sts_policy();
+        record(&dir, &session(&policy, "mx1.example.com", None), day).unwrap();
+        record(
+            &dir,
+            &session(
+                &policy,
+                "mx1.example.com",
+                Some(ResultType::StarttlsNotSupported),
+            ),
+            day + std::time::Duration::from_secs(DAY - 1),
+        )
+        .unwrap();
+        record(&dir, &session(&policy, "mx1.example.com", None), next_day).unwrap();
+
+        // the results of the current day are not reported yet.
+        assert!(get_elapsed_days(&dir, day).unwrap().is_empty());
+
+        let elapsed = get_elapsed_days(&dir, next_day).unwrap();
+        assert_eq!(elapsed.len(), 1);
+        assert_eq!(elapsed[0].day, DAY_START);
+        assert_eq!(elapsed[0].policy_domain, "example.com");
+        assert_eq!(
+            elapsed[0].read().unwrap(),
+            vec![
+                session(&policy, "mx1.example.com", None),
+                session(
+                    &policy,
+                    "mx1.example.com",
+                    Some(ResultType::StarttlsNotSupported)
+                ),
+            ]
+        );
+
+        elapsed[0].remove().unwrap();
+        assert!(!dir.join(DAY_START.to_string()).exists());
+        assert_eq!(
+            get_elapsed_days(&dir, next_day + std::time::Duration::from_secs(DAY))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn report() {
+        let sts = sts_policy();
+        let no_policy = AppliedPolicy {
+            policy_type: PolicyType::NoPolicyFound,
+            policy_string: vec![],
+            policy_domain: "example.com".to_string(),
+            mx_host: vec![],
+        };
+
+        let report = Report::new(
+            "viridIT",
+            "mailto:postmaster@testserver.com",
+            "1654041600.example.com@testserver.com",
+            DAY_START,
+            &[
+                session(&sts, "mx1.example.com", None),
+                session(&sts, "mx1.example.com", None),
+                session(
+                    &sts,
+                    "mx1.example.com",
+                    Some(ResultType::CertificateExpired),
+                ),
+                session(
+                    &sts,
+                    "mx2.example.com",
+                    Some(ResultType::CertificateExpired),
+                ),
+                session(
+                    &sts,
+                    "mx2.example.com",
+                    Some(ResultType::CertificateExpired),
+                ),
+                session(&no_policy, "mx1.example.com", None),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "organization-name": "viridIT",
+                "date-range": {
+                    "start-datetime": "2022-06-01T00:00:00Z",
+                    "end-datetime": "2022-06-01T23:59:59Z"
+                },
+                "contact-info": "mailto:postmaster@testserver.com",
+                "report-id": "1654041600.example.com@testserver.com",
+                "policies": [
+                    {
+                        "policy": {
+                            "policy-type": "sts",
+                            "policy-string": [
+                                "version: STSv1",
+                                "mode: testing",
+                                "mx: *.example.com",
+                                "max_age: 86400"
+                            ],
+                            "policy-domain": "example.com",
+                            "mx-host": ["*.example.com"]
+                        },
+                        "summary": {
+                            "total-successful-session-count": 2,
+                            "total-failure-session-count": 3
+                        },
+                        "failure-details": [
+                            {
+                                "result-type": "certificate-expired",
+                                "receiving-mx-hostname": "mx1.example.com",
+                                "failed-session-count": 1,
+                                "failure-reason-code": "failure"
+                            },
+                            {
+                                "result-type": "certificate-expired",
+                                "receiving-mx-hostname": "mx2.example.com",
+                                "failed-session-count": 2,
+                                "failure-reason-code": "failure"
+                            }
+                        ]
+                    },
+                    {
+                        "policy": {
+                            "policy-type": "no-policy-found",
+                            "policy-domain": "example.com"
+                        },
+                        "summary": {
+                            "total-successful-session-count": 1,
+                            "total-failure-session-count": 0
+                        }
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn reporting_addresses() {
+        assert_eq!(
+            parse_record("v=TLSRPTv1; rua=mailto:reports@example.com").unwrap(),
+            vec!["reports@example.com"]
+        );
+        assert_eq!(
+            parse_record(
+                "v=TLSRPTv1;rua=https://reporting.example.com/v1/tlsrpt,mailto:tls@example.com?subject=tls"
+            )
+            .unwrap(),
+            vec!["tls@example.com"]
+        );
+        assert!(parse_record("v=TLSRPTv2; rua=mailto:reports@example.com").is_err());
+        assert!(parse_record("v=TLSRPTv1;").is_err());
+    }
+}
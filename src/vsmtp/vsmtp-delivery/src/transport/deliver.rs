@@ -14,9 +14,8 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use super::{get_mx_records, Transport};
-use crate::transport::log_channels;
-use anyhow::Context;
+use super::{get_mx_records, get_tls_policy, send_email, Content, Transport};
+use crate::{mta_sts, tls_rpt, transport::log_channels};
 use trust_dns_resolver::TokioAsyncResolver;
 use vsmtp_common::{
     mail_context::MessageMetadata,
@@ -24,18 +23,33 @@ use vsmtp_common::{
     re::{anyhow, log},
     transfer::EmailTransferStatus,
 };
-use vsmtp_config::Config;
+use vsmtp_config::{Config, TlsSecurityLevel};
 
 /// the email will be forwarded to another mail exchanger via mx record resolution & smtp.
 pub struct Deliver<'r> {
     resolver: &'r TokioAsyncResolver,
+    policy_fetcher: std::sync::Arc<dyn mta_sts::PolicyFetcher>,
 }
 
 impl<'r> Deliver<'r> {
     /// create a new deliver with a resolver to get data from the distant dns server.
     #[must_use]
-    pub const fn new(resolver: &'r TokioAsyncResolver) -> Self {
-        Self { resolver }
+    pub fn new(resolver: &'r TokioAsyncResolver) -> Self {
+        Self {
+            resolver,
+            policy_fetcher: std::sync::Arc::new(mta_sts::HttpsPolicyFetcher),
+        }
+    }
+
+    /// fetch the MTA-STS policies of the destinations with `policy_fetcher`
+    /// instead of HTTPS.
+    #[must_use]
+    pub fn with_policy_fetcher(
+        mut self,
+        policy_fetcher: std::sync::Arc<dyn mta_sts::PolicyFetcher>,
+    ) -> Self {
+        self.policy_fetcher = policy_fetcher;
+        self
     }
 }
 
@@ -45,23 +59,68 @@ impl<'r> Transport for Deliver<'r> {
         &mut self,
         config: &Config,
         metadata: &MessageMetadata,
-        from: &vsmtp_common::Address,
+        from: Option<&vsmtp_common::Address>,
+        smtputf8: bool,
         to: &mut [Rcpt],
-        content: &str,
+        content: Content<'_>,
     ) -> anyhow::Result<()> {
-        for (query, rcpt) in &mut filter_by_domain_mut(to) {
-            let envelop = super::build_lettre_envelop(
-                from,
-                // TODO: 'to' parameter should be immutable, and the deliver
-                //       implementor should return a new set of recipients.
-                &rcpt.iter().map(|rcpt| (**rcpt).clone()).collect::<Vec<_>>()[..],
-            )
-            .context(format!(
-                "failed to build envelop to deliver email for '{query}'"
-            ))?;
+        let sender = from.map_or_else(|| "<>".to_string(), ToString::to_string);
+
+        for (query, mut rcpt) in filter_by_domain_mut(to) {
+            // TODO: 'to' parameter should be immutable, and the deliver
+            //       implementor should return a new set of recipients.
+            let rcpt = &mut rcpt[..];
+            let envelop = super::build_envelop(from, rcpt, smtputf8);
+
+            // the configuration ensures the resolvers validate the tlsa records with dnssec.
+            let dane = match super::get_sender_security_level(
+                config,
+                super::get_sender_domain(config, from),
+            ) {
+                Some(TlsSecurityLevel::Dane { port }) => Some(port),
+                _ => None,
+            };
+
+            let mta_sts = if config.server.mta_sts.enable {
+                match mta_sts::get_policy(
+                    self.resolver,
+                    self.policy_fetcher.as_ref(),
+                    &config.server.queues.dirpath.join("mta-sts"),
+                    &query,
+                )
+                .await
+                {
+                    Ok(policy) => policy.filter(|policy| policy.mode != mta_sts::Mode::None),
+                    Err(err) => {
+                        log::warn!(
+                            target: log_channels::DELIVER,
+                            "(msg={}) failed to get the MTA-STS policy of '{query}': {err}",
+                            metadata.message_id
+                        );
+
+                        // the message is delivered without the policy, but the failure is reported.
+                        if config.server.tls_rpt.enable {
+                            record_tls_session(
+                                config,
+                                tls_rpt::AppliedPolicy {
+                                    policy_type: tls_rpt::PolicyType::Sts,
+                                    policy_string: vec![],
+                                    policy_domain: query.clone(),
+                                    mx_host: vec![],
+                                },
+                                "",
+                                &Err(err),
+                            );
+                        }
+                        None
+                    }
+                }
+            } else {
+                None
+            };
 
             // getting mx records for a set of recipients.
-            let records = match get_mx_records(self.resolver, query).await {
+            let records = match get_mx_records(self.resolver, &query).await {
                 Ok(records) => records,
                 Err(err) => {
                     log::warn!(
@@ -86,21 +145,55 @@ impl<'r> Transport for Deliver<'r> {
 
                 // using directly the AAAA record instead of an mx record.
                 // see https://www.rfc-editor.org/rfc/rfc5321#section-5.1
-                match send_email(config, self.resolver, query, &envelop, from, content).await {
-                    Ok(()) => update_rcpt_sent(rcpt),
+                let policy = get_tls_policy(config, &query, &query, dane, mta_sts.as_ref());
+                let (applied_policy, result) = send_email(
+                    config,
+                    self.resolver,
+                    &policy,
+                    &query,
+                    &query,
+                    &envelop,
+                    content,
+                )
+                .await;
+
+                if config.server.tls_rpt.enable {
+                    record_tls_session(config, applied_policy, &query, &result);
+                }
+
+                match result {
+                    Ok(()) => {
+                        log::info!(
+                            target: log_channels::DELIVER,
+                            "(msg={}) message from '{sender}' sent to '{query}' with {policy}",
+                            metadata.message_id
+                        );
+
+                        update_rcpt_sent(rcpt);
+                    }
+                    Err(err) if super::is_extension_not_supported(&err) => {
+                        log::warn!(
+                            target: log_channels::DELIVER,
+                            "(msg={}) failed to send message from '{sender}' for '{query}': {err}",
+                            metadata.message_id
+                        );
+
+                        update_rcpt_failed(rcpt, &err.to_string());
+                    }
                     Err(err) => {
                         update_rcpt_held_back(rcpt);
 
                         log::error!(
                             target: log_channels::DELIVER,
-                            "(msg={}) failed to send message from '{from}' for '{query}': {err}",
+                            "(msg={}) failed to send message from '{sender}' for '{query}' with {policy}: {err}",
                             metadata.message_id
                         );
                     }
                 }
             } else {
-                let mut records = records.iter();
-                for record in records.by_ref() {
+                // the status of the recipients, `None` if no mail exchanger accepted the message.
+                let mut status = None;
+                for record in &records {
                     let host = record.exchange().to_ascii();
 
                     // checking for a null mx record.
@@ -112,32 +205,69 @@ impl<'r> Transport for Deliver<'r> {
                             metadata.message_id
                         );
 
-                        update_rcpt_failed(rcpt, "null record found for this domain");
-
+                        status = Some(EmailTransferStatus::Failed(
+                            "null record found for this domain".to_string(),
+                        ));
                         break;
                     }
 
-                    match send_email(config, self.resolver, &host, &envelop, from, content).await {
+                    let policy = get_tls_policy(config, &query, &host, dane, mta_sts.as_ref());
+                    let (applied_policy, result) = send_email(
+                        config,
+                        self.resolver,
+                        &policy,
+                        &query,
+                        &host,
+                        &envelop,
+                        content,
+                    )
+                    .await;
+
+                    if config.server.tls_rpt.enable {
+                        record_tls_session(config, applied_policy, &host, &result);
+                    }
+
+                    match result {
                         // if a transfer succeeded, we can stop the lookup.
-                        Ok(_) => break,
+                        Ok(_) => {
+                            log::info!(
+                                target: log_channels::DELIVER,
+                                "(msg={}) message from '{sender}' sent to '{host}' for '{query}' with {policy}",
+                                metadata.message_id
+                            );
+                            status = Some(EmailTransferStatus::Sent);
+                            break;
+                        }
+                        // the message cannot be downgraded for this domain.
+                        Err(err) if super::is_extension_not_supported(&err) => {
+                            log::warn!(
+                                target: log_channels::DELIVER,
+                                "(msg={}) failed to send message from '{sender}' to '{host}' for '{query}': {err}",
+                                metadata.message_id
+                            );
+                            status = Some(EmailTransferStatus::Failed(err.to_string()));
+                            break;
+                        }
                         Err(err) => log::warn!(
                             target: log_channels::DELIVER,
-                            "(msg={}) failed to send message from '{from}' for '{query}': {err}",
+                            "(msg={}) failed to send message from '{sender}' to '{host}' for '{query}' with {policy}: {err}",
                             metadata.message_id
                         ),
                     }
                 }
 
-                if records.next().is_none() {
-                    log::error!(
-                        target: log_channels::DELIVER,
-                        "(msg={}) no valid mail exchanger found for '{query}', check warnings above.",
-                        metadata.message_id
-                    );
+                match status {
+                    Some(EmailTransferStatus::Sent) => update_rcpt_sent(rcpt),
+                    Some(EmailTransferStatus::Failed(reason)) => update_rcpt_failed(rcpt, &reason),
+                    _ => {
+                        log::error!(
+                            target: log_channels::DELIVER,
+                            "(msg={}) no valid mail exchanger found for '{query}', check warnings above.",
+                            metadata.message_id
+                        );
 
-                    update_rcpt_held_back(rcpt);
-                } else {
-                    update_rcpt_sent(rcpt);
+                        update_rcpt_held_back(rcpt);
+                    }
                 }
             }
         }
@@ -146,24 +276,41 @@ impl<'r> Transport for Deliver<'r> {
     }
 }
 
-/// send an email using [lettre].
-async fn send_email(
+/// record the result of the tls session opened with `target` under the applied `policy`,
+/// the results are reported daily to the destination domain (see [`tls_rpt`]).
+fn record_tls_session(
     config: &Config,
-    resolver: &TokioAsyncResolver,
+    policy: tls_rpt::AppliedPolicy,
     target: &str,
-    envelop: &lettre::address::Envelope,
-    from: &vsmtp_common::Address,
-    content: &str,
-) -> anyhow::Result<()> {
-    lettre::AsyncTransport::send_raw(
-        // TODO: transport should be cached.
-        &crate::transport::build_transport(config, resolver, from, target)?,
-        envelop,
-        content.as_bytes(),
-    )
-    .await?;
-
-    Ok(())
+    result: &anyhow::Result<()>,
+) {
+    let (result_type, failure_reason_code) = match result {
+        Ok(()) => (None, None),
+        Err(err) => match tls_rpt::get_result_type(err) {
+            Some(result_type) => (Some(result_type), Some(format!("{err:#}"))),
+            // the session failed before or after the tls negotiation.
+            None => return,
+        },
+    };
+
+    let session = tls_rpt::SessionResult {
+        policy,
+        receiving_mx_hostname: target.trim_end_matches('.').to_string(),
+        result_type,
+        failure_reason_code,
+    };
+
+    if let Err(err) = tls_rpt::record(
+        &config.server.queues.dirpath.join("tls-rpt"),
+        &session,
+        std::time::SystemTime::now(),
+    ) {
+        log::warn!(
+            target: log_channels::DELIVER,
+            "failed to record the tls session with '{target}' for '{}': {err}",
+            session.policy.policy_domain
+        );
+    }
 }
 
 fn update_rcpt_held_back(rcpt: &mut [&mut Rcpt]) {
@@ -194,8 +341,10 @@ mod test {
     use vsmtp_common::{addr, rcpt::Rcpt, transfer::EmailTransferStatus};
     use vsmtp_config::{Config, ConfigServerDNS};
 
-    use crate::transport::deliver::{
-        get_mx_records, send_email, update_rcpt_failed, update_rcpt_sent,
+    use crate::transport::{
+        deliver::{get_mx_records, send_email, update_rcpt_failed, update_rcpt_sent},
+        starttls::Envelope,
+        Content, TlsPolicy,
     };
 
     use super::update_rcpt_held_back;
@@ -250,16 +399,18 @@ mod test {
         assert!(send_email(
             &config,
             &TokioAsyncResolver::tokio_from_system_conf().unwrap(),
+            &TlsPolicy::Opportunistic,
+            "localhost",
             "localhost",
-            &lettre::address::Envelope::new(
-                Some("a@a.a".parse().unwrap()),
-                vec!["b@b.b".parse().unwrap()]
-            )
-            .unwrap(),
-            &addr!("a@a.a"),
-            "content"
+            &Envelope {
+                from: Some(addr!("a@a.a")),
+                to: vec![addr!("b@b.b")],
+                smtputf8: false,
+            },
+            Content::Text("content")
         )
         .await
+        .1
         .is_err());
     }
 }
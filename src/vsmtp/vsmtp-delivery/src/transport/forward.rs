@@ -14,7 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use super::Transport;
+use super::{get_tls_policy, send_email, Content, Transport};
 use crate::transport::log_channels;
 use anyhow::Context;
 use trust_dns_resolver::TokioAsyncResolver;
@@ -50,12 +50,13 @@ impl<'r> Transport for Forward<'r> {
         &mut self,
         config: &Config,
         metadata: &MessageMetadata,
-        from: &vsmtp_common::Address,
+        from: Option<&vsmtp_common::Address>,
+        smtputf8: bool,
         to: &mut [Rcpt],
-        content: &str,
+        content: Content<'_>,
     ) -> anyhow::Result<()> {
-        let envelop = super::build_lettre_envelop(from, &to[..])
-            .context("failed to build envelop to forward email")?;
+        let mut to = to.iter_mut().collect::<Vec<_>>();
+        let envelop = super::build_envelop(from, &to, smtputf8);
 
         // if the domain is unknown, we ask the dns to get it (tls parameters required the domain).
         let target = match &self.to {
@@ -71,18 +72,48 @@ impl<'r> Transport for Forward<'r> {
                 .to_string(),
         };
 
-        match send_email(config, self.resolver, from, &target, &envelop, content).await {
+        let policy = get_tls_policy(config, &target, &target, None, None);
+        let (_, result) = send_email(
+            config,
+            self.resolver,
+            &policy,
+            &target,
+            &target,
+            &envelop,
+            content,
+        )
+        .await;
+
+        match result {
             Ok(()) => {
+                log::info!(
+                    target: log_channels::FORWARD,
+                    "(msg={}) email forwarded to '{target}' with {policy}",
+                    metadata.message_id,
+                );
+
                 to.iter_mut()
                     .for_each(|rcpt| rcpt.email_status = EmailTransferStatus::Sent);
                 return Ok(());
             }
+            // the message cannot be downgraded.
+            Err(err) if super::is_extension_not_supported(&err) => {
+                log::warn!(
+                    target: log_channels::FORWARD,
+                    "(msg={}) failed to forward email to '{target}': {err}",
+                    metadata.message_id,
+                );
+
+                for rcpt in to.iter_mut() {
+                    rcpt.email_status = EmailTransferStatus::Failed(err.to_string());
+                }
+                Ok(())
+            }
             Err(err) => {
                 log::debug!(
                     target: log_channels::FORWARD,
-                    "(msg={}) failed to forward email to '{}': {err}",
+                    "(msg={}) failed to forward email to '{target}' with {policy}: {err}",
                     metadata.message_id,
-                    &target
                 );
 
                 for rcpt in to.iter_mut() {
@@ -99,22 +130,3 @@ impl<'r> Transport for Forward<'r> {
         }
     }
 }
-
-async fn send_email(
-    config: &Config,
-    resolver: &TokioAsyncResolver,
-    from: &vsmtp_common::Address,
-    target: &str,
-    envelop: &lettre::address::Envelope,
-    content: &str,
-) -> anyhow::Result<()> {
-    lettre::AsyncTransport::send_raw(
-        // TODO: transport should be cached.
-        &crate::transport::build_transport(config, resolver, from, target)?,
-        envelop,
-        content.as_bytes(),
-    )
-    .await?;
-
-    Ok(())
-}
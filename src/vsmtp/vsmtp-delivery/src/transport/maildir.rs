@@ -14,7 +14,7 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use super::Transport;
+use super::{Content, Transport};
 use crate::transport::log_channels;
 use anyhow::Context;
 use vsmtp_common::{
@@ -38,9 +38,10 @@ impl Transport for Maildir {
         &mut self,
         config: &Config,
         metadata: &MessageMetadata,
-        _: &vsmtp_common::Address,
+        _: Option<&vsmtp_common::Address>,
+        _: bool,
         to: &mut [Rcpt],
-        content: &str,
+        content: Content<'_>,
     ) -> anyhow::Result<()> {
         for rcpt in to.iter_mut() {
             if let Some(user) = users::get_user_by_name(rcpt.address.local_part()) {
@@ -49,7 +50,7 @@ impl Transport for Maildir {
                     &user,
                     config.server.system.group_local.as_ref(),
                     metadata,
-                    content,
+                    content.as_bytes(),
                 ) {
                     log::error!(
                         target: log_channels::MAILDIR,
@@ -117,7 +118,7 @@ fn write_to_maildir(
     user: &users::User,
     group_local: Option<&users::Group>,
     metadata: &MessageMetadata,
-    content: &str,
+    content: &[u8],
 ) -> anyhow::Result<()> {
     let maildir = create_maildir(user, group_local, metadata)?;
 
@@ -126,7 +127,7 @@ fn write_to_maildir(
         .write(true)
         .open(&maildir)?;
 
-    std::io::Write::write_all(&mut email, content.as_bytes())?;
+    std::io::Write::write_all(&mut email, content)?;
 
     chown(
         &maildir,
@@ -181,7 +182,7 @@ mod test {
                 message_id: message_id.to_string(),
                 ..MessageMetadata::default()
             },
-            "email content",
+            b"email content",
         )
         .expect("could not write email to maildir");
 
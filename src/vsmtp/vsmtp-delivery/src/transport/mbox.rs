@@ -16,7 +16,7 @@
 */
 use crate::transport::log_channels;
 
-use super::Transport;
+use super::{Content, Transport};
 
 use anyhow::Context;
 use vsmtp_common::{
@@ -44,12 +44,13 @@ impl Transport for MBox {
         &mut self,
         config: &Config,
         metadata: &MessageMetadata,
-        from: &vsmtp_common::Address,
+        from: Option<&vsmtp_common::Address>,
+        _: bool,
         to: &mut [Rcpt],
-        content: &str,
+        content: Content<'_>,
     ) -> anyhow::Result<()> {
         let timestamp = get_mbox_timestamp_format(metadata);
-        let content = build_mbox_message(from, &timestamp, content);
+        let content = build_mbox_message(from, &timestamp, content.as_bytes());
 
         // FIXME: use UsersCache.
         for rcpt in to.iter_mut() {
@@ -104,11 +105,21 @@ fn get_mbox_timestamp_format(metadata: &MessageMetadata) -> String {
 }
 
 fn build_mbox_message(
-    from: &vsmtp_common::Address,
+    from: Option<&vsmtp_common::Address>,
     timestamp: &str,
-    content: &str,
-) -> std::string::String {
-    format!("From {} {}\n{}\n", from, timestamp, content)
+    content: &[u8],
+) -> Vec<u8> {
+    [
+        format!(
+            "From {} {}\n",
+            from.map_or_else(|| "MAILER-DAEMON".to_string(), ToString::to_string),
+            timestamp,
+        )
+        .as_bytes(),
+        content,
+        b"\n",
+    ]
+    .concat()
 }
 
 fn write_content_to_mbox(
@@ -116,7 +127,7 @@ fn write_content_to_mbox(
     user: &users::User,
     group_local: Option<&users::Group>,
     metadata: &MessageMetadata,
-    content: &str,
+    content: &[u8],
 ) -> anyhow::Result<()> {
     let mut file = std::fs::OpenOptions::new()
         .create(true)
@@ -127,7 +138,7 @@ fn write_content_to_mbox(
     chown(mbox, Some(user.uid()), group_local.map(users::Group::gid))
         .with_context(|| format!("could not set owner for '{:?}' mbox", mbox))?;
 
-    std::io::Write::write_all(&mut file, content.as_bytes())
+    std::io::Write::write_all(&mut file, content)
         .with_context(|| format!("could not write email to '{:?}' mbox", mbox))?;
 
     log::debug!(
@@ -174,7 +185,7 @@ This is a raw email."#;
             ..MessageMetadata::default()
         });
 
-        let message = build_mbox_message(&from, &timestamp, content);
+        let message = build_mbox_message(Some(&from), &timestamp, content.as_bytes());
 
         assert_eq!(
             r#"From john@doe.com Thu Jan  1 00:00:00 1970
@@ -183,7 +194,8 @@ to: green@foo.net
 subject: test email
 
 This is a raw email.
-"#,
+"#
+            .as_bytes(),
             message
         );
     }
@@ -200,7 +212,7 @@ This is a raw email.
 
         std::fs::create_dir_all("./tests/generated/").expect("could not create temporary folders");
 
-        write_content_to_mbox(&mbox, &user, None, &metadata, content)
+        write_content_to_mbox(&mbox, &user, None, &metadata, content.as_bytes())
             .expect("could not write to mbox");
 
         assert_eq!(
@@ -0,0 +1,409 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::transport::{log_channels, Content};
+use anyhow::Context;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use vsmtp_common::{
+    re::{anyhow, log},
+    Address,
+};
+
+/// timeout of the connection, of the handshake and of each reply of the server.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// maximum length of a reply line, including the CRLF.
+/// see https://www.rfc-editor.org/rfc/rfc5321#section-4.5.3.1.5
+const MAX_REPLY_LINE_LENGTH: usize = 512;
+
+/// maximum number of lines of a reply, the extensions advertised in the reply of EHLO.
+const MAX_REPLY_LINES: usize = 100;
+
+/// reply of the server, the code and the text of each line.
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    /// is the extension advertised in the reply of EHLO.
+    fn has_extension(&self, name: &str) -> bool {
+        self.lines.iter().skip(1).any(|line| {
+            line.split_whitespace()
+                .next()
+                .map_or(false, |keyword| keyword.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+async fn read_reply<S>(stream: &mut S) -> anyhow::Result<Reply>
+where
+    S: tokio::io::AsyncBufRead + Unpin + Send,
+{
+    let mut lines = vec![];
+    loop {
+        anyhow::ensure!(
+            lines.len() < MAX_REPLY_LINES,
+            "reply of more than {MAX_REPLY_LINES} lines"
+        );
+
+        let mut line = String::new();
+        if (&mut *stream)
+            .take(MAX_REPLY_LINE_LENGTH as u64)
+            .read_line(&mut line)
+            .await?
+            == 0
+        {
+            anyhow::bail!("connection closed by the server");
+        }
+        anyhow::ensure!(
+            line.ends_with('\n'),
+            "reply line longer than {MAX_REPLY_LINE_LENGTH} bytes"
+        );
+        let line = line.trim_end();
+
+        let code = line
+            .get(..3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| anyhow::anyhow!("invalid reply '{line}'"))?;
+        lines.push(line.get(4..).unwrap_or_default().to_string());
+
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(Reply { code, lines });
+        }
+    }
+}
+
+async fn expect<S>(stream: &mut S, context: &str, expected: &[u16]) -> anyhow::Result<Reply>
+where
+    S: tokio::io::AsyncBufRead + Unpin + Send,
+{
+    let reply = tokio::time::timeout(TIMEOUT, read_reply(stream))
+        .await
+        .with_context(|| format!("timeout waiting for the reply to {context}"))??;
+
+    if expected.contains(&reply.code) {
+        Ok(reply)
+    } else {
+        anyhow::bail!(
+            "unexpected reply to {context}: '{} {}'",
+            reply.code,
+            reply.lines.join(" ")
+        )
+    }
+}
+
+async fn command<S>(
+    stream: &mut tokio::io::BufReader<S>,
+    command: &str,
+    expected: &[u16],
+) -> anyhow::Result<Reply>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
+{
+    stream.write_all(command.as_bytes()).await?;
+    expect(stream, &format!("'{}'", command.trim_end()), expected).await
+}
+
+/// how the connection with the server is secured.
+pub enum Tls {
+    /// the connection stays in plain text.
+    None,
+    /// STARTTLS is used if the server offers it.
+    Opportunistic(TlsParameters),
+    /// STARTTLS is required.
+    Required(TlsParameters),
+}
+
+/// the verification of the certificate of the server.
+pub struct TlsParameters {
+    config: std::sync::Arc<rustls::ClientConfig>,
+    server_name: String,
+}
+
+impl TlsParameters {
+    /// the certificate is verified by `config`, and must be valid for `server_name`
+    /// if `config` verifies the name.
+    #[must_use]
+    pub fn new(config: std::sync::Arc<rustls::ClientConfig>, server_name: &str) -> Self {
+        Self {
+            config,
+            server_name: server_name.trim_end_matches('.').to_string(),
+        }
+    }
+}
+
+/// the envelope of the message sent to the server.
+pub struct Envelope {
+    /// the reverse path, `None` for the null reverse-path `<>`.
+    pub from: Option<Address>,
+    /// the forward paths.
+    pub to: Vec<Address>,
+    /// the message must be sent with the `SMTPUTF8` extension: an address has a non-ascii
+    /// local part, or it has been received with the extension. (RFC 6531)
+    pub smtputf8: bool,
+}
+
+/// the message requires an extension the server does not support, `SMTPUTF8` for
+/// non-ascii addresses, `8BITMIME` for non-ascii content or `CHUNKING` and `BINARYMIME`
+/// for binary content. the message cannot be downgraded, the failure is permanent.
+/// (RFC 6531 section 3.2, RFC 6152 section 3, RFC 3030 section 3)
+#[derive(Debug)]
+pub struct ExtensionNotSupported {
+    /// the server.
+    pub host: String,
+    /// the extension required by the message.
+    pub extension: &'static str,
+}
+
+impl std::fmt::Display for ExtensionNotSupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' does not support {}", self.host, self.extension)
+    }
+}
+
+impl std::error::Error for ExtensionNotSupported {}
+
+/// certificates are accepted without verification, the connection is only encrypted.
+struct NoVerifier;
+
+impl rustls::client::ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _: &rustls::Certificate,
+        _: &[rustls::Certificate],
+        _: &rustls::ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+/// build a tls configuration accepting any certificate.
+#[must_use]
+pub fn get_unverified_client_config() -> rustls::ClientConfig {
+    rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(std::sync::Arc::new(NoVerifier))
+        .with_no_client_auth()
+}
+
+/// build a tls configuration verifying the certificates with the public authorities,
+/// and the `roots` certificates.
+///
+/// # Errors
+///
+/// * a certificate of `roots` is not valid.
+pub fn get_pkix_client_config(
+    roots: &[rustls::Certificate],
+) -> anyhow::Result<rustls::ClientConfig> {
+    let mut root_store = rustls::RootCertStore::empty();
+    root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    for certificate in roots {
+        root_store
+            .add(certificate)
+            .map_err(|e| anyhow::anyhow!("failed to add a root certificate: {e:?}"))?;
+    }
+
+    Ok(rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store)
+        .with_no_client_auth())
+}
+
+/// send a message to the mail exchanger `target`, the connection is secured as specified by `tls`.
+///
+/// [lettre] does not allow to customize the verification of the certificates, nor to
+/// send addresses with non-ascii local parts or binary content, this client is used when
+/// the certificate is authenticated by vsmtp (with DANE for example), or when the message
+/// requires `SMTPUTF8` or `BINARYMIME`.
+///
+/// # Errors
+///
+/// * the server does not offer STARTTLS, and it is required.
+/// * the tls handshake failed, or the certificate has been rejected.
+/// * the message requires `SMTPUTF8`, `8BITMIME` or `BINARYMIME` and the server does not
+///   support it, see [`ExtensionNotSupported`].
+/// * the server replied with an error.
+pub async fn send(
+    target: &str,
+    port: u16,
+    hello_name: &str,
+    tls: &Tls,
+    envelop: &Envelope,
+    content: Content<'_>,
+) -> anyhow::Result<()> {
+    let host = target.trim_end_matches('.');
+
+    let stream = tokio::time::timeout(TIMEOUT, tokio::net::TcpStream::connect((host, port)))
+        .await
+        .with_context(|| format!("timeout connecting to '{host}:{port}'"))??;
+    let mut stream = tokio::io::BufReader::new(stream);
+
+    expect(&mut stream, "the connection", &[220]).await?;
+    let reply = command(&mut stream, &format!("EHLO {hello_name}\r\n"), &[250]).await?;
+
+    let parameters = match tls {
+        Tls::Required(_) if !reply.has_extension("STARTTLS") => {
+            return Err(crate::tls_rpt::Failure {
+                result_type: crate::tls_rpt::ResultType::StarttlsNotSupported,
+                message: format!("'{host}' does not offer STARTTLS"),
+            }
+            .into());
+        }
+        Tls::Required(parameters) => parameters,
+        Tls::Opportunistic(parameters) if reply.has_extension("STARTTLS") => parameters,
+        Tls::Opportunistic(_) | Tls::None => {
+            transaction(&mut stream, host, &reply, envelop, content).await?;
+            return quit(&mut stream, host).await;
+        }
+    };
+
+    let server_name = rustls::ServerName::try_from(parameters.server_name.as_str())
+        .map_err(|e| anyhow::anyhow!("invalid name '{}': {e}", parameters.server_name))?;
+
+    command(&mut stream, "STARTTLS\r\n", &[220]).await?;
+
+    // nothing can be sent by the server before the handshake.
+    if !stream.buffer().is_empty() {
+        anyhow::bail!("'{host}' sent data before the tls handshake");
+    }
+
+    let stream = tokio::time::timeout(
+        TIMEOUT,
+        tokio_rustls::TlsConnector::from(parameters.config.clone())
+            .connect(server_name, stream.into_inner()),
+    )
+    .await
+    .with_context(|| format!("timeout during the tls handshake with '{host}'"))?
+    .with_context(|| format!("tls handshake with '{host}' failed"))?;
+    let mut stream = tokio::io::BufReader::new(stream);
+
+    let reply = command(&mut stream, &format!("EHLO {hello_name}\r\n"), &[250]).await?;
+    transaction(&mut stream, host, &reply, envelop, content).await?;
+    quit(&mut stream, host).await
+}
+
+async fn quit<S>(stream: &mut tokio::io::BufReader<S>, host: &str) -> anyhow::Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
+{
+    // the message has been accepted, the server can close the connection without replying.
+    if let Err(error) = command(stream, "QUIT\r\n", &[221]).await {
+        log::debug!(target: log_channels::DELIVER, "'{host}' did not reply to QUIT: {error}");
+    }
+
+    Ok(())
+}
+
+/// the address as written in the envelope, the domain is converted to its ascii form.
+fn to_path(address: &Address) -> String {
+    format!("{}@{}", address.local_part(), address.domain_ascii())
+}
+
+async fn transaction<S>(
+    stream: &mut tokio::io::BufReader<S>,
+    host: &str,
+    ehlo: &Reply,
+    envelop: &Envelope,
+    content: Content<'_>,
+) -> anyhow::Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
+{
+    let binary = matches!(content, Content::Binary(_));
+    let required = [
+        (envelop.smtputf8, "SMTPUTF8"),
+        (binary, "CHUNKING"),
+        (binary, "BINARYMIME"),
+        (!binary && !content.as_bytes().is_ascii(), "8BITMIME"),
+    ];
+    if let Some((_, extension)) = required
+        .into_iter()
+        .find(|(required, extension)| *required && !ehlo.has_extension(extension))
+    {
+        return Err(ExtensionNotSupported {
+            host: host.to_string(),
+            extension,
+        }
+        .into());
+    }
+
+    let body = match content {
+        Content::Binary(_) => " BODY=BINARYMIME",
+        Content::Text(text) if !text.is_ascii() => " BODY=8BITMIME",
+        Content::Text(_) => "",
+    };
+    let smtputf8 = if envelop.smtputf8 { " SMTPUTF8" } else { "" };
+
+    command(
+        stream,
+        &format!(
+            "MAIL FROM:<{}>{body}{smtputf8}\r\n",
+            envelop.from.as_ref().map(to_path).unwrap_or_default()
+        ),
+        &[250],
+    )
+    .await?;
+
+    for rcpt in &envelop.to {
+        command(
+            stream,
+            &format!("RCPT TO:<{}>\r\n", to_path(rcpt)),
+            &[250, 251],
+        )
+        .await?;
+    }
+
+    let text = match content {
+        Content::Text(text) => text,
+        // the content is sent as is, in a single chunk.
+        Content::Binary(bytes) => {
+            stream
+                .write_all(format!("BDAT {} LAST\r\n", bytes.len()).as_bytes())
+                .await?;
+            stream.write_all(bytes).await?;
+            expect(stream, "the message", &[250]).await?;
+            return Ok(());
+        }
+    };
+
+    command(stream, "DATA\r\n", &[354]).await?;
+
+    let mut data = String::with_capacity(text.len() + 5);
+    for line in text.lines() {
+        // see https://www.rfc-editor.org/rfc/rfc5321#section-4.5.2
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push_str(".\r\n");
+
+    stream.write_all(data.as_bytes()).await?;
+    expect(stream, "the message", &[250]).await?;
+
+    Ok(())
+}
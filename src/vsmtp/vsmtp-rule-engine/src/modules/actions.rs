@@ -39,6 +39,10 @@ mod test {
                 is_authenticated: false,
                 is_secured: false,
                 server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
             },
             client_addr: std::net::SocketAddr::new(
                 std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
@@ -21,7 +21,7 @@ use rhai::plugin::{
 
 #[rhai::plugin::export_module]
 pub mod headers {
-    use crate::{modules::actions::MailContext, modules::EngineResult};
+    use crate::{modules::actions::MailContext, modules::load_spooled_body, modules::EngineResult};
     use vsmtp_common::{mail_context::Body, Address};
 
     /// check if a given header exists in the top level headers.
@@ -30,12 +30,13 @@ pub mod headers {
         this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
         header: &str,
     ) -> EngineResult<bool> {
-        Ok(this
-            .read()
+        let body = &mut this
+            .write()
             .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
-            .body
-            .get_header(header)
-            .is_some())
+            .body;
+        load_spooled_body(body)?;
+
+        Ok(body.get_header(header).is_some())
     }
 
     /// return the value of a header if it exists. Otherwise, returns an empty string.
@@ -44,10 +45,13 @@ pub mod headers {
         this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
         header: &str,
     ) -> EngineResult<String> {
-        Ok(this
-            .read()
+        let body = &mut this
+            .write()
             .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
-            .body
+            .body;
+        load_spooled_body(body)?;
+
+        Ok(body
             .get_header(header)
             .map(ToString::to_string)
             .unwrap_or_default())
@@ -60,10 +64,18 @@ pub mod headers {
         header: &str,
         value: &str,
     ) -> EngineResult<()> {
-        this.write()
+        let body = &mut this
+            .write()
             .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
-            .body
-            .add_header(header, value);
+            .body;
+        load_spooled_body(body)?;
+        if let Body::Binary(_) = body {
+            return Err(
+                "failed to add header: a binary email (BODY=BINARYMIME) cannot be modified.".into(),
+            );
+        }
+
+        body.add_header(header, value);
 
         Ok(())
     }
@@ -75,10 +87,18 @@ pub mod headers {
         header: &str,
         value: &str,
     ) -> EngineResult<()> {
-        this.write()
+        let body = &mut this
+            .write()
             .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
-            .body
-            .set_header(header, value);
+            .body;
+        load_spooled_body(body)?;
+        if let Body::Binary(_) = body {
+            return Err(
+                "failed to set header: a binary email (BODY=BINARYMIME) cannot be modified.".into(),
+            );
+        }
+
+        body.set_header(header, value);
         Ok(())
     }
 
@@ -102,10 +122,12 @@ pub mod headers {
             .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?;
 
         email.envelop.mail_from = new_addr.clone();
+        email.envelop.null_reverse_path = false;
 
         match &mut email.body {
                 Body::Empty => Err("failed to rewrite mail_from: the email has not been received yet. Use this method in postq or later.".into()),
-                Body::Raw(_) => Err("failed to rewrite mail_from: the email has not been parsed yet. Use this method in postq or later.".into()),
+                Body::Raw(_) | Body::Spooled(_) => Err("failed to rewrite mail_from: the email has not been parsed yet. Use this method in postq or later.".into()),
+                Body::Binary(_) => Err("failed to rewrite mail_from: a binary email (BODY=BINARYMIME) cannot be modified.".into()),
                 Body::Parsed(body) => {
                     body.rewrite_mail_from(new_addr.full());
                     Ok(())
@@ -138,14 +160,19 @@ pub mod headers {
                 .into()
             })?;
 
-        match &mut this
+        let body = &mut this
             .write()
             .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
-            .body
-        {
-            Body::Empty | Body::Raw(_) => {
+            .body;
+
+        match body {
+            Body::Empty | Body::Raw(_) | Body::Spooled(_) => {
                 Err("failed to rewrite rcpt: the email has not been parsed yet.".into())
             }
+            Body::Binary(_) => Err(
+                "failed to rewrite rcpt: a binary email (BODY=BINARYMIME) cannot be modified."
+                    .into(),
+            ),
             Body::Parsed(body) => {
                 body.rewrite_rcpt(old_addr.full(), new_addr.full());
                 Ok(())
@@ -211,14 +238,18 @@ pub mod headers {
             )
         })?;
 
-        match &mut this
+        let body = &mut this
             .write()
             .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
-            .body
-        {
-            Body::Empty | Body::Raw(_) => {
+            .body;
+
+        match body {
+            Body::Empty | Body::Raw(_) | Body::Spooled(_) => {
                 Err("failed to add rcpt: the email has not been parsed yet.".into())
             }
+            Body::Binary(_) => Err(
+                "failed to add rcpt: a binary email (BODY=BINARYMIME) cannot be modified.".into(),
+            ),
             Body::Parsed(body) => {
                 body.add_rcpt(new_addr.full());
                 Ok(())
@@ -257,18 +288,23 @@ pub mod headers {
         let addr = Address::try_from(addr.to_string())
             .map_err(|_| format!("{} could not be converted to a valid rcpt address", addr))?;
 
-        match &mut this
+        let body = &mut this
             .write()
             .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
-            .body
-        {
+            .body;
+
+        match body {
             Body::Parsed(body) => {
                 body.remove_rcpt(addr.full());
                 Ok(())
             }
-            Body::Empty | Body::Raw(_) => {
+            Body::Empty | Body::Raw(_) | Body::Spooled(_) => {
                 Err("failed to remove rcpt: the email has not been parsed yet.".into())
             }
+            Body::Binary(_) => Err(
+                "failed to remove rcpt: a binary email (BODY=BINARYMIME) cannot be modified."
+                    .into(),
+            ),
         }
     }
 
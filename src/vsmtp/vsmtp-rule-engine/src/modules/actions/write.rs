@@ -66,6 +66,9 @@ pub mod write {
                         )
                     }
                     Body::Raw(raw) => std::io::Write::write_all(&mut writer, raw.as_bytes()),
+                    Body::Spooled(path) | Body::Binary(path) => std::fs::File::open(path)
+                        .and_then(|mut spool| std::io::copy(&mut spool, &mut writer))
+                        .map(|_| ()),
                     Body::Parsed(email) => {
                         std::io::Write::write_all(&mut writer, email.to_raw().as_bytes())
                     }
@@ -94,6 +97,15 @@ pub mod write {
 
         dir.push(format!("{}.json", message_id(&mut ctx)?));
 
+        // the body is written along with the metadata, even if it still is in the spool.
+        let mut dumped = ctx
+            .read()
+            .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+            .clone();
+        dumped.body = dumped.body.load().map_err::<Box<EvalAltResult>, _>(|err| {
+            format!("failed to dump email at {dir:?}: {err}").into()
+        })?;
+
         match std::fs::OpenOptions::new()
             .create(true)
             .write(true)
@@ -101,15 +113,11 @@ pub mod write {
         {
             Ok(mut file) => std::io::Write::write_all(
                 &mut file,
-                vsmtp_common::re::serde_json::to_string_pretty(
-                    &*ctx
-                        .read()
-                        .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?,
-                )
-                .map_err::<Box<EvalAltResult>, _>(|err| {
-                    format!("failed to dump email at {dir:?}: {err}").into()
-                })?
-                .as_bytes(),
+                vsmtp_common::re::serde_json::to_string_pretty(&dumped)
+                    .map_err::<Box<EvalAltResult>, _>(|err| {
+                        format!("failed to dump email at {dir:?}: {err}").into()
+                    })?
+                    .as_bytes(),
             )
             .map_err(|err| format!("failed to dump email at {dir:?}: {err}").into()),
             Err(err) => Err(format!("failed to dump email at {dir:?}: {err}").into()),
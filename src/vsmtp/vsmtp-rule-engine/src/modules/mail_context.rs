@@ -96,6 +96,109 @@ pub mod mail_context {
             .is_authenticated)
     }
 
+    /// is the client a local process connected with a unix domain socket ?
+    #[rhai_fn(global, get = "is_local", return_raw, pure)]
+    pub fn is_local(
+        this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
+    ) -> EngineResult<bool> {
+        Ok(this
+            .read()
+            .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+            .connection
+            .peer_credentials
+            .is_some())
+    }
+
+    /// has the client sent data before the greetings ? (see `server.smtp.greet_pause`)
+    #[rhai_fn(global, get = "early_talker", return_raw, pure)]
+    pub fn early_talker(
+        this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
+    ) -> EngineResult<bool> {
+        Ok(this
+            .read()
+            .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+            .connection
+            .early_talker)
+    }
+
+    /// has the client sent a certificate verified during the tls handshake.
+    #[rhai_fn(global, get = "has_client_cert", return_raw, pure)]
+    pub fn has_client_cert(
+        this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
+    ) -> EngineResult<bool> {
+        Ok(this
+            .read()
+            .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+            .connection
+            .client_certificate
+            .is_some())
+    }
+
+    /// distinguished name of the subject of the certificate verified during the tls handshake
+    /// (see `server.tls.client_auth`).
+    #[rhai_fn(global, get = "client_cert_subject", return_raw, pure)]
+    pub fn client_cert_subject(
+        this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
+    ) -> EngineResult<String> {
+        Ok(this
+            .read()
+            .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+            .connection
+            .client_certificate
+            .as_ref()
+            .ok_or("the client did not send a certificate")?
+            .subject
+            .clone())
+    }
+
+    /// dns names, email addresses and uris of the certificate of the client, see `client_cert_subject`.
+    #[rhai_fn(global, get = "client_cert_alt_names", return_raw, pure)]
+    pub fn client_cert_alt_names(
+        this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
+    ) -> EngineResult<rhai::Array> {
+        Ok(this
+            .read()
+            .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+            .connection
+            .client_certificate
+            .as_ref()
+            .ok_or("the client did not send a certificate")?
+            .subject_alt_names()
+            .cloned()
+            .map(rhai::Dynamic::from)
+            .collect())
+    }
+
+    /// user id of the local process, see `is_local`.
+    #[rhai_fn(global, get = "peer_uid", return_raw, pure)]
+    pub fn peer_uid(
+        this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
+    ) -> EngineResult<i64> {
+        Ok(i64::from(
+            this.read()
+                .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+                .connection
+                .peer_credentials
+                .ok_or("is none")?
+                .uid,
+        ))
+    }
+
+    /// group id of the local process, see `is_local`.
+    #[rhai_fn(global, get = "peer_gid", return_raw, pure)]
+    pub fn peer_gid(
+        this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
+    ) -> EngineResult<i64> {
+        Ok(i64::from(
+            this.read()
+                .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+                .connection
+                .peer_credentials
+                .ok_or("is none")?
+                .gid,
+        ))
+    }
+
     #[rhai_fn(global, get = "auth", return_raw, pure)]
     pub fn auth(
         this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
@@ -141,6 +244,19 @@ pub mod mail_context {
         }
     }
 
+    #[rhai_fn(global, get = "query", return_raw, pure)]
+    pub fn query(
+        this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>,
+    ) -> EngineResult<String> {
+        Ok(this
+            .read()
+            .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+            .connection
+            .query
+            .clone()
+            .ok_or("`query` is only available in the `vrfy`, `expn` and `etrn` stages")?)
+    }
+
     #[rhai_fn(global, get = "helo", return_raw, pure)]
     pub fn helo(this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>) -> EngineResult<String> {
         Ok(this
@@ -210,17 +326,13 @@ pub mod mail_context {
 
     #[rhai_fn(global, get = "mail", return_raw, pure)]
     pub fn mail(this: &mut std::sync::Arc<std::sync::RwLock<MailContext>>) -> EngineResult<String> {
-        Ok(
-            match &this
-                .read()
-                .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
-                .body
-            {
-                vsmtp_common::mail_context::Body::Empty => String::default(),
-                vsmtp_common::mail_context::Body::Raw(raw) => raw.clone(),
-                vsmtp_common::mail_context::Body::Parsed(parsed) => parsed.to_raw(),
-            },
-        )
+        this.read()
+            .map_err::<Box<EvalAltResult>, _>(|e| e.to_string().into())?
+            .body
+            .to_raw()
+            .map_err::<Box<EvalAltResult>, _>(|err| {
+                format!("failed to read the email: {err}").into()
+            })
     }
 
     #[rhai_fn(global, name = "to_string", pure)]
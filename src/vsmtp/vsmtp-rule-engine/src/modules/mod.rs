@@ -22,6 +22,18 @@ pub(crate) mod types;
 
 pub(crate) type EngineResult<T> = Result<T, Box<EvalAltResult>>;
 
+/// read the body if it is still in the spool, so that headers can be read or modified.
+pub(crate) fn load_spooled_body(body: &mut vsmtp_common::mail_context::Body) -> EngineResult<()> {
+    if let vsmtp_common::mail_context::Body::Spooled(_) = body {
+        *body = std::mem::replace(body, vsmtp_common::mail_context::Body::Empty)
+            .load()
+            .map_err::<Box<EvalAltResult>, _>(|err| {
+                format!("failed to read the spooled email: {err}").into()
+            })?;
+    }
+    Ok(())
+}
+
 rhai::def_package! {
     /// vsl's standard api.
     pub StandardVSLPackage(module) {
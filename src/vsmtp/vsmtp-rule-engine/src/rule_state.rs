@@ -34,6 +34,10 @@ impl RuleState {
                 is_authenticated: false,
                 is_secured: false,
                 server_name: config.server.domain.clone(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
             },
             client_addr: std::net::SocketAddr::new(
                 std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
@@ -99,6 +99,10 @@ fn test_rule_state() {
                 is_authenticated: false,
                 is_secured: false,
                 server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
             },
             client_addr: std::net::SocketAddr::new(
                 std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
@@ -108,6 +112,9 @@ fn test_rule_state() {
                 helo: "test".to_string(),
                 mail_from: vsmtp_common::addr!("a@a.a"),
                 rcpt: vec![],
+                dsn: vsmtp_common::dsn::DsnMail::default(),
+                smtputf8: false,
+                null_reverse_path: false,
             },
             body: vsmtp_common::mail_context::Body::Empty,
             metadata: None,
@@ -38,6 +38,23 @@ fn test_connect_rules() {
     );
 }
 
+#[test]
+fn test_early_talker_rules() {
+    let re = RuleEngine::new(
+        &vsmtp_config::Config::default(),
+        &Some(root_example!["rules/connect.vsl"]),
+    )
+    .unwrap();
+    let (mut state, _) = get_default_state("./tmp/app");
+
+    state.context().write().unwrap().client_addr = "127.0.0.1:0".parse().unwrap();
+    state.context().write().unwrap().connection.early_talker = true;
+    assert_eq!(
+        re.run_when(&mut state, &StateSMTP::Connect),
+        Status::Deny(None)
+    );
+}
+
 #[test]
 fn test_helo_rules() {
     let re = RuleEngine::new(
@@ -68,6 +68,10 @@ impl
             unsafe { sasl.retrieve() }.ok_or(vsmtp_rsasl::ReturnCode::GSASL_INTEGRITY_ERROR)?;
         sasl.store(config.clone());
 
+        if matches!(prop, vsmtp_rsasl::Property::GSASL_VALIDATE_EXTERNAL) {
+            return Self::validate_external(session);
+        }
+
         let credentials = match prop {
             vsmtp_rsasl::Property::GSASL_PASSWORD => AuthCredentials::Query {
                 authid: session
@@ -129,3 +133,26 @@ impl
         }
     }
 }
+
+impl Callback {
+    /// the client is authenticated by the certificate verified during the tls handshake,
+    /// it can only ask to act as the identity of this certificate.
+    fn validate_external(session: &mut Session) -> Result<(), vsmtp_rsasl::ReturnCode> {
+        let identity = session
+            .retrieve_mut()
+            .ok_or(vsmtp_rsasl::ReturnCode::GSASL_INTEGRITY_ERROR)?
+            .1
+            .client_certificate
+            .as_ref()
+            .ok_or(vsmtp_rsasl::ReturnCode::GSASL_AUTHENTICATION_ERROR)?
+            .identity()
+            .to_string();
+
+        match session.get_property(vsmtp_rsasl::Property::GSASL_AUTHZID) {
+            Some(authzid) if authzid.to_str() != Ok(identity.as_str()) => {
+                Err(vsmtp_rsasl::ReturnCode::GSASL_AUTHENTICATION_ERROR)
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -25,7 +25,9 @@ mod log_channels {
 }
 
 mod channel_message;
+mod rate_limit;
 mod receiver;
+mod reload;
 mod runtime;
 mod server;
 mod processes {
@@ -37,10 +39,14 @@ pub use receiver::MailHandler;
 
 /// SMTP auth extension implementation
 pub mod auth;
+/// socket activation and notifications of the systemd service manager
+pub mod systemd;
 pub use channel_message::ProcessMessage;
+pub use rate_limit::{ConnectionGuard, RateLimiter};
 pub use receiver::{handle_connection, AbstractIO, Connection, ConnectionKind, OnMail};
+pub use reload::{Reloader, Snapshot};
 pub use runtime::start_runtime;
-pub use server::Server;
+pub use server::{Server, Socket};
 
 /// re-exported module
 pub mod re {
@@ -21,6 +21,7 @@ use crate::{
         deferred::flush_deferred_queue,
         deliver::{flush_deliver_queue, handle_one_in_delivery_queue},
     },
+    reload::Reloader,
 };
 use anyhow::Context;
 use time::format_description::well_known::Rfc2822;
@@ -34,15 +35,25 @@ use vsmtp_common::{
     transfer::{EmailTransferStatus, Transfer},
 };
 use vsmtp_config::Config;
-use vsmtp_delivery::transport::{deliver as deliver2, forward, maildir, mbox, Transport};
-use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_delivery::transport::{deliver as deliver2, forward, maildir, mbox, Content, Transport};
 
 mod deferred;
 mod deliver;
+mod report;
+mod tls_report;
+
+/// the results of a day are reported on the first tick following its end.
+const TLS_REPORT_PERIOD: std::time::Duration = std::time::Duration::from_secs(60 * 60);
 
 /// process used to deliver incoming emails force accepted by the smtp process
 /// or parsed by the vMime process.
 ///
+/// The deferred messages are delivered every `deferred_retry_period`, or right away
+/// for the recipients of the nodes received on `deferred_flush` (see the ETRN command).
+/// The tls reports are sent daily if `server.tls_rpt` is enabled.
+///
+/// Return once the delivery channel is closed and every delivery in progress is done.
+///
 /// # Errors
 ///
 /// *
@@ -51,27 +62,35 @@ mod deliver;
 ///
 /// * tokio::select!
 pub async fn start(
-    config: std::sync::Arc<Config>,
-    rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+    reloader: std::sync::Arc<Reloader>,
     mut delivery_receiver: tokio::sync::mpsc::Receiver<ProcessMessage>,
+    mut deferred_flush: tokio::sync::mpsc::Receiver<String>,
 ) -> anyhow::Result<()> {
     log::info!(target: log_channels::DELIVERY, "booting, flushing queue.",);
 
-    let resolvers = std::sync::Arc::new(
-        vsmtp_config::build_resolvers(&config).context("could not initialize dns for delivery")?,
-    );
-
-    flush_deliver_queue(&config, &resolvers, &rule_engine).await?;
+    let snapshot = reloader.current();
+    flush_deliver_queue(&snapshot.config, &snapshot.resolvers, &snapshot.rule_engine).await?;
 
     let mut flush_deferred_interval =
-        tokio::time::interval(config.server.queues.delivery.deferred_retry_period);
+        tokio::time::interval(snapshot.config.server.queues.delivery.deferred_retry_period);
+    let mut tls_report_interval = tokio::time::interval(TLS_REPORT_PERIOD);
+
+    // each delivery holds a copy of the sender, the channel is closed once they are all done.
+    let (pending_sender, mut pending_receiver) = tokio::sync::mpsc::channel::<()>(1);
 
     loop {
         tokio::select! {
-            Some(pm) = delivery_receiver.recv() => {
-                let copy_config = config.clone();
-                let copy_rule_engine = rule_engine.clone();
-                let copy_resolvers = resolvers.clone();
+            pm = delivery_receiver.recv() => {
+                let pm = match pm {
+                    Some(pm) => pm,
+                    None => break,
+                };
+                // the message is delivered with the configuration and rules loaded when it is taken.
+                let snapshot = reloader.current();
+                let copy_config = snapshot.config.clone();
+                let copy_rule_engine = snapshot.rule_engine.clone();
+                let copy_resolvers = snapshot.resolvers.clone();
+                let pending = pending_sender.clone();
                 tokio::spawn(async move {
                     let path = queue_path!(&copy_config.server.queues.dirpath, Queue::Deliver);
 
@@ -88,6 +107,7 @@ pub async fn start(
                         log::error!(target: log_channels::DELIVERY,
                              "(msg={}) could not deliver email: {error:?}", pm.message_id);
                     }
+                    drop(pending);
                 });
 
                 if cfg!(test) {
@@ -99,20 +119,50 @@ pub async fn start(
                     target: log_channels::DEFERRED,
                     "cronjob delay elapsed, flushing queue.",
                 );
-                flush_deferred_queue(&config, &resolvers).await?;
+                let snapshot = reloader.current();
+                flush_deferred_queue(&snapshot.config, &snapshot.resolvers, None).await?;
+            }
+            Some(node) = deferred_flush.recv() => {
+                log::info!(
+                    target: log_channels::DEFERRED,
+                    "delivery of '{node}' requested, flushing queue.",
+                );
+                let snapshot = reloader.current();
+                if let Err(error) = flush_deferred_queue(&snapshot.config, &snapshot.resolvers, Some(&node)).await {
+                    log::error!(
+                        target: log_channels::DEFERRED,
+                        "failed to flush the deferred queue for '{node}': {error:?}",
+                    );
+                }
+            }
+            _ = tls_report_interval.tick() => {
+                let snapshot = reloader.current();
+                if snapshot.config.server.tls_rpt.enable {
+                    if let Err(error) = tls_report::send_tls_reports(&snapshot.config, &snapshot.resolvers).await {
+                        log::error!(
+                            target: log_channels::DELIVERY,
+                            "failed to send the tls reports: {error:?}",
+                        );
+                    }
+                }
             }
         };
     }
+
+    log::info!(target: log_channels::DELIVERY, "delivery channel closed, waiting for the deliveries in progress.");
+    drop(pending_sender);
+    pending_receiver.recv().await;
+    Ok(())
 }
 
 /// send the email following each recipient transport method.
 /// return a list of recipients with updated email_status field.
-/// recipients tagged with the Sent email_status are discarded.
 async fn send_email(
     config: &Config,
     resolvers: &std::collections::HashMap<String, TokioAsyncResolver>,
     metadata: &vsmtp_common::mail_context::MessageMetadata,
-    from: &vsmtp_common::Address,
+    from: Option<&vsmtp_common::Address>,
+    smtputf8: bool,
     to: &[vsmtp_common::rcpt::Rcpt],
     body: &Body,
 ) -> anyhow::Result<Vec<vsmtp_common::rcpt::Rcpt>> {
@@ -120,13 +170,21 @@ async fn send_email(
     let mut triage = vsmtp_common::rcpt::filter_by_transfer_method(to);
 
     // getting a raw copy of the email.
+    let text;
+    let bytes;
     let content = match &body {
         Body::Empty => anyhow::bail!(
             "empty body found in message '{}' in delivery queue",
             metadata.message_id
         ),
-        Body::Raw(raw) => raw.clone(),
-        Body::Parsed(parsed) => parsed.to_raw(),
+        Body::Binary(_) => {
+            bytes = body.to_bytes()?;
+            Content::Binary(&bytes)
+        }
+        otherwise => {
+            text = otherwise.to_raw()?;
+            Content::Text(&text)
+        }
     };
 
     for (method, rcpt) in &mut triage {
@@ -155,7 +213,7 @@ async fn send_email(
         };
 
         transport
-            .deliver(config, metadata, from, &mut rcpt[..], &content)
+            .deliver(config, metadata, from, smtputf8, &mut rcpt[..], content)
             .await
             .with_context(|| {
                 format!("failed to deliver email using '{method}' for group '{rcpt:?}'")
@@ -163,14 +221,92 @@ async fn send_email(
     }
 
     // recipient email transfer status could have been updated.
-    // we also filter out recipients if they have been sent the message already.
     Ok(triage
         .into_iter()
         .flat_map(|(_, rcpt)| rcpt)
-        .filter(|rcpt| !matches!(rcpt.email_status, EmailTransferStatus::Sent))
         .collect::<Vec<_>>())
 }
 
+/// send a delivery status notification to the sender for the recipients that asked for it
+/// (NOTIFY parameter of the RCPT TO command), see https://datatracker.ietf.org/doc/html/rfc3461#section-4.1
+async fn notify_sender(
+    config: &Config,
+    resolvers: &std::collections::HashMap<String, TokioAsyncResolver>,
+    ctx: &MailContext,
+) -> anyhow::Result<()> {
+    let report = match report::build_report(config, ctx)
+        .context("failed to build the delivery status notification")?
+    {
+        Some(report) => report,
+        None => return Ok(()),
+    };
+
+    log::info!(
+        target: log_channels::DELIVERY,
+        "(msg={}) sending delivery status notification '{}' to '{}'",
+        ctx.metadata
+            .as_ref()
+            .map_or("unknown", |metadata| metadata.message_id.as_str()),
+        report
+            .metadata
+            .as_ref()
+            .map_or("unknown", |metadata| metadata.message_id.as_str()),
+        ctx.envelop.mail_from,
+    );
+
+    send_generated_message(config, resolvers, report)
+        .await
+        .context("failed to send the delivery status notification")
+}
+
+/// send a message generated by the server (a delivery status notification, a tls report...).
+///
+/// the message goes through the delivery queue and is sent right away,
+/// it is moved to the deferred / dead queue like any other message if its delivery fails.
+async fn send_generated_message(
+    config: &Config,
+    resolvers: &std::collections::HashMap<String, TokioAsyncResolver>,
+    mut message: MailContext,
+) -> anyhow::Result<()> {
+    let message_id = message
+        .metadata
+        .as_ref()
+        .map(|metadata| metadata.message_id.clone())
+        .ok_or_else(|| anyhow::anyhow!("missing message metadata"))?;
+
+    Queue::Deliver
+        .write_to_queue(&config.server.queues.dirpath, &message)
+        .with_context(|| format!("failed to write '{message_id}' in the delivery queue"))?;
+
+    message.envelop.rcpt = send_email(
+        config,
+        resolvers,
+        message.metadata.as_ref().unwrap(),
+        message.envelop.reverse_path(),
+        message.envelop.smtputf8,
+        &message.envelop.rcpt,
+        &message.body,
+    )
+    .await
+    .with_context(|| format!("failed to send '{message_id}'"))?;
+
+    message
+        .envelop
+        .rcpt
+        .retain(|rcpt| !matches!(rcpt.email_status, EmailTransferStatus::Sent));
+
+    move_to_queue(config, &message)?;
+
+    std::fs::remove_file(queue_path!(
+        &config.server.queues.dirpath,
+        Queue::Deliver,
+        &message_id
+    ))
+    .with_context(|| format!("failed to remove '{message_id}' from the delivery queue"))?;
+
+    Ok(())
+}
+
 // FIXME: could be optimized by checking both conditions with the same iterator.
 /// copy the message into the deferred / dead queue if any recipient is held back or have failed delivery.
 fn move_to_queue(config: &Config, ctx: &MailContext) -> anyhow::Result<()> {
@@ -223,10 +359,26 @@ fn add_trace_information(
         rule_engine_result,
     );
 
+    // the spool file is left untouched, the trace headers are added to a copy of the message.
+    ctx.body = std::mem::replace(&mut ctx.body, Body::Empty).load()?;
+
     match &mut ctx.body {
         Body::Empty => {
             anyhow::bail!("could not add trace information to email header: body is empty")
         }
+        Body::Spooled(_) => unreachable!("the body has been loaded above"),
+        // the content is kept as received, the copy is written next to the spool file.
+        Body::Binary(path) => {
+            let copy = path.with_extension("delivery");
+            let trace =
+                format!("Received: {stamp}\nX-VSMTP: {vsmtp_status}\n").replace('\n', "\r\n");
+            std::fs::write(
+                &copy,
+                [trace.as_bytes(), &std::fs::read(path.as_path())?].concat(),
+            )
+            .with_context(|| format!("failed to write '{}'", copy.display()))?;
+            *path = copy;
+        }
         Body::Raw(raw) => {
             *raw = format!("Received: {}\nX-VSMTP: {}\n{}", stamp, vsmtp_status, raw);
         }
@@ -311,6 +463,10 @@ mod test {
                 is_authenticated: false,
                 is_secured: false,
                 server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
             },
             client_addr: std::net::SocketAddr::new(
                 std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
@@ -320,6 +476,9 @@ mod test {
                 helo: "localhost".to_string(),
                 mail_from: vsmtp_common::addr!("a@a.a"),
                 rcpt: vec![],
+                dsn: vsmtp_common::dsn::DsnMail::default(),
+                smtputf8: false,
+                null_reverse_path: false,
             },
             metadata: Some(vsmtp_common::mail_context::MessageMetadata {
                 timestamp: std::time::SystemTime::UNIX_EPOCH,
@@ -356,4 +515,62 @@ mod test {
             ))
         );
     }
+
+    #[test]
+    fn trace_binary() {
+        let spool = Body::spool_path(std::path::Path::new("./tmp/spool"), "trace_binary");
+        std::fs::create_dir_all(spool.parent().unwrap()).unwrap();
+        std::fs::write(&spool, b"subject: hello\r\n\r\n\xff\x00").unwrap();
+
+        let mut ctx = vsmtp_common::mail_context::MailContext {
+            body: Body::Binary(spool.clone()),
+            connection: ConnectionContext {
+                timestamp: std::time::SystemTime::UNIX_EPOCH,
+                credentials: None,
+                is_authenticated: false,
+                is_secured: false,
+                server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
+            },
+            client_addr: std::net::SocketAddr::new(
+                std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
+                0,
+            ),
+            envelop: vsmtp_common::envelop::Envelop::default(),
+            metadata: Some(vsmtp_common::mail_context::MessageMetadata {
+                message_id: "trace_binary".to_string(),
+                ..vsmtp_common::mail_context::MessageMetadata::default()
+            }),
+        };
+
+        add_trace_information(
+            &vsmtp_config::Config::default(),
+            &mut ctx,
+            &vsmtp_common::status::Status::Next,
+        )
+        .unwrap();
+
+        // the spool file is left untouched.
+        assert_eq!(
+            std::fs::read(&spool).unwrap(),
+            b"subject: hello\r\n\r\n\xff\x00"
+        );
+
+        let copy = match &ctx.body {
+            Body::Binary(copy) => copy.clone(),
+            _ => panic!("the body should stay binary"),
+        };
+        let content = std::fs::read(&copy).unwrap();
+        assert!(content.starts_with(b"Received: from "));
+        assert!(content.ends_with(b"\tstatus='next'\r\nsubject: hello\r\n\r\n\xff\x00"));
+        assert!(!content
+            .windows(2)
+            .any(|window| window[1] == b'\n' && window[0] != b'\r'));
+
+        std::fs::remove_file(spool).unwrap();
+        std::fs::remove_file(copy).unwrap();
+    }
 }
@@ -14,10 +14,13 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::{log_channels, processes::delivery::send_email};
+use crate::{
+    log_channels,
+    processes::delivery::{notify_sender, send_email},
+};
 use trust_dns_resolver::TokioAsyncResolver;
 use vsmtp_common::{
-    mail_context::MailContext,
+    mail_context::{Body, MailContext},
     queue::Queue,
     queue_path,
     rcpt::Rcpt,
@@ -29,14 +32,17 @@ use vsmtp_common::{
 };
 use vsmtp_config::Config;
 
+/// retry the delivery of the messages of the deferred queue,
+/// restricted to the recipients of `node` if given (see the ETRN command).
 pub async fn flush_deferred_queue(
     config: &Config,
     resolvers: &std::collections::HashMap<String, TokioAsyncResolver>,
+    node: Option<&str>,
 ) -> anyhow::Result<()> {
     let dir_entries =
         std::fs::read_dir(queue_path!(&config.server.queues.dirpath, Queue::Deferred))?;
     for path in dir_entries {
-        if let Err(e) = handle_one_in_deferred_queue(config, resolvers, &path?.path()).await {
+        if let Err(e) = handle_one_in_deferred_queue(config, resolvers, &path?.path(), node).await {
             log::warn!(target: log_channels::DEFERRED, "{}", e);
         }
     }
@@ -51,6 +57,7 @@ async fn handle_one_in_deferred_queue(
     config: &Config,
     resolvers: &std::collections::HashMap<String, TokioAsyncResolver>,
     path: &std::path::Path,
+    node: Option<&str>,
 ) -> anyhow::Result<()> {
     let message_id = path.file_name().and_then(std::ffi::OsStr::to_str).unwrap();
 
@@ -74,14 +81,24 @@ async fn handle_one_in_deferred_queue(
         .as_ref()
         .ok_or_else(|| anyhow::anyhow!("email metadata not available in deferred email"))?;
 
+    // the recipients of the other nodes wait for the next retry.
+    let (to_send, others): (Vec<_>, Vec<_>) = std::mem::take(&mut ctx.envelop.rcpt)
+        .into_iter()
+        .partition(|rcpt| node.map_or(true, |node| is_of_node(rcpt.address.domain(), node)));
+
+    if to_send.is_empty() {
+        return Ok(());
+    }
+
     // TODO: at this point, only HeldBack recipients should be present in the queue.
     //       check if it is true or not.
     ctx.envelop.rcpt = send_email(
         config,
         resolvers,
         metadata,
-        &ctx.envelop.mail_from,
-        &ctx.envelop.rcpt,
+        ctx.envelop.reverse_path(),
+        ctx.envelop.smtputf8,
+        &to_send,
         &ctx.body,
     )
     .await
@@ -100,6 +117,7 @@ async fn handle_one_in_deferred_queue(
                     ))
                 }
                 EmailTransferStatus::HeldBack(count) => EmailTransferStatus::HeldBack(count + 1),
+                EmailTransferStatus::Sent => EmailTransferStatus::Sent,
                 status => EmailTransferStatus::Failed(format!(
                     "wrong recipient status '{status}' found in the deferred queue"
                 )),
@@ -108,6 +126,19 @@ async fn handle_one_in_deferred_queue(
         })
         .collect();
 
+    if let Err(error) = notify_sender(config, resolvers, &ctx).await {
+        log::error!(
+            target: log_channels::DEFERRED,
+            "(msg={message_id}) could not notify the sender: {error:?}"
+        );
+    }
+
+    // recipients that have been sent the message are discarded.
+    ctx.envelop
+        .rcpt
+        .retain(|rcpt| !matches!(rcpt.email_status, EmailTransferStatus::Sent));
+    ctx.envelop.rcpt.extend(others);
+
     if ctx
         .envelop
         .rcpt
@@ -119,16 +150,33 @@ async fn handle_one_in_deferred_queue(
     } else {
         // otherwise, we remove the file from the deferred queue.
         std::fs::remove_file(&path)?;
+
+        if let Body::Binary(copy) = &ctx.body {
+            std::fs::remove_file(copy)?;
+        }
     }
 
     Ok(())
 }
 
+/// `node` is a domain, or a domain and its subdomains if prefixed by '@' (rfc1985).
+fn is_of_node(domain: &str, node: &str) -> bool {
+    let domain = domain.to_ascii_lowercase();
+    match node.strip_prefix('@') {
+        Some(parent) => {
+            let parent = parent.to_ascii_lowercase();
+            domain == parent || domain.ends_with(&format!(".{parent}"))
+        }
+        None => domain.eq_ignore_ascii_case(node),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use vsmtp_common::{
         addr,
+        dsn::{DsnMail, DsnRcpt},
         envelop::Envelop,
         mail_context::{Body, ConnectionContext, MailContext, MessageMetadata},
         rcpt::Rcpt,
@@ -155,6 +203,10 @@ mod tests {
                         is_authenticated: false,
                         is_secured: false,
                         server_name: "testserver.com".to_string(),
+                        query: None,
+                        peer_credentials: None,
+                        early_talker: false,
+                        client_certificate: None,
                     },
                     client_addr: "127.0.0.1:80".parse().unwrap(),
                     envelop: Envelop {
@@ -165,13 +217,18 @@ mod tests {
                                 address: addr!("to+1@client.com"),
                                 transfer_method: Transfer::Maildir,
                                 email_status: EmailTransferStatus::Waiting,
+                                dsn: DsnRcpt::default(),
                             },
                             Rcpt {
                                 address: addr!("to+2@client.com"),
                                 transfer_method: Transfer::Maildir,
                                 email_status: EmailTransferStatus::Waiting,
+                                dsn: DsnRcpt::default(),
                             },
                         ],
+                        dsn: DsnMail::default(),
+                        smtputf8: false,
+                        null_reverse_path: false,
                     },
                     body: Body::Raw("Date: bar\r\nFrom: foo\r\nHello world\r\n".to_string()),
                     metadata: Some(MessageMetadata {
@@ -189,6 +246,7 @@ mod tests {
             &config,
             &resolvers,
             &config.server.queues.dirpath.join("deferred/test"),
+            None,
         )
         .await
         .unwrap();
@@ -202,6 +260,10 @@ mod tests {
                     is_authenticated: false,
                     is_secured: false,
                     server_name: "testserver.com".to_string(),
+                    query: None,
+                    peer_credentials: None,
+                    early_talker: false,
+                    client_certificate: None,
                 },
                 client_addr: "127.0.0.1:80".parse().unwrap(),
                 envelop: Envelop {
@@ -212,13 +274,18 @@ mod tests {
                             address: addr!("to+1@client.com"),
                             transfer_method: Transfer::Maildir,
                             email_status: EmailTransferStatus::HeldBack(1),
+                            dsn: DsnRcpt::default(),
                         },
                         Rcpt {
                             address: addr!("to+2@client.com"),
                             transfer_method: Transfer::Maildir,
                             email_status: EmailTransferStatus::HeldBack(1),
+                            dsn: DsnRcpt::default(),
                         },
                     ],
+                    dsn: DsnMail::default(),
+                    smtputf8: false,
+                    null_reverse_path: false,
                 },
                 body: Body::Raw("Date: bar\r\nFrom: foo\r\nHello world\r\n".to_string()),
                 metadata: Some(MessageMetadata {
@@ -229,4 +296,87 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn node() {
+        assert!(is_of_node("example.com", "example.com"));
+        assert!(is_of_node("Example.COM", "example.com"));
+        assert!(!is_of_node("mx.example.com", "example.com"));
+        assert!(is_of_node("example.com", "@example.com"));
+        assert!(is_of_node("mx.example.com", "@Example.com"));
+        assert!(!is_of_node("notexample.com", "@example.com"));
+        assert!(!is_of_node("example.org", "@example.com"));
+    }
+
+    #[tokio::test]
+    async fn restricted_to_node() {
+        let mut config = config::local_test();
+        config.server.queues.dirpath = "./tmp".into();
+        config.app.vsl.filepath = Some("./src/tests/empty_main.vsl".into());
+
+        let now = std::time::SystemTime::now();
+        let rcpt = |address, email_status| Rcpt {
+            address,
+            transfer_method: Transfer::Maildir,
+            email_status,
+            dsn: DsnRcpt::default(),
+        };
+        let ctx = MailContext {
+            connection: ConnectionContext {
+                timestamp: now,
+                credentials: None,
+                is_authenticated: false,
+                is_secured: false,
+                server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
+            },
+            client_addr: "127.0.0.1:80".parse().unwrap(),
+            envelop: Envelop {
+                helo: "client.com".to_string(),
+                mail_from: addr!("from@testserver.com"),
+                rcpt: vec![
+                    rcpt(addr!("to@client.com"), EmailTransferStatus::HeldBack(1)),
+                    rcpt(addr!("to@other.com"), EmailTransferStatus::HeldBack(1)),
+                ],
+                dsn: DsnMail::default(),
+                smtputf8: false,
+                null_reverse_path: false,
+            },
+            body: Body::Raw("Date: bar\r\nFrom: foo\r\nHello world\r\n".to_string()),
+            metadata: Some(MessageMetadata {
+                timestamp: now,
+                message_id: "test_etrn".to_string(),
+                skipped: None,
+            }),
+        };
+        Queue::Deferred
+            .write_to_queue(&config.server.queues.dirpath, &ctx)
+            .unwrap();
+
+        let resolvers = build_resolvers(&config).unwrap();
+
+        handle_one_in_deferred_queue(
+            &config,
+            &resolvers,
+            &config.server.queues.dirpath.join("deferred/test_etrn"),
+            Some("@client.com"),
+        )
+        .await
+        .unwrap();
+
+        // only the recipient of the node has been retried.
+        pretty_assertions::assert_eq!(
+            MailContext::from_file(&config.server.queues.dirpath.join("deferred/test_etrn"))
+                .unwrap()
+                .envelop
+                .rcpt,
+            vec![
+                rcpt(addr!("to@client.com"), EmailTransferStatus::HeldBack(2)),
+                rcpt(addr!("to@other.com"), EmailTransferStatus::HeldBack(1)),
+            ]
+        );
+    }
 }
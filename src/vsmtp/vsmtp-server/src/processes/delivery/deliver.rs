@@ -16,11 +16,11 @@
 */
 use crate::{
     log_channels,
-    processes::delivery::{add_trace_information, move_to_queue, send_email},
+    processes::delivery::{add_trace_information, move_to_queue, notify_sender, send_email},
 };
 use trust_dns_resolver::TokioAsyncResolver;
 use vsmtp_common::{
-    mail_context::MailContext,
+    mail_context::{Body, MailContext},
     queue::Queue,
     queue_path,
     re::{
@@ -88,6 +88,11 @@ pub async fn handle_one_in_delivery_queue(
         &message_id
     ))?;
 
+    let spool_path = match &ctx.body {
+        Body::Spooled(path) | Body::Binary(path) => Some(path.clone()),
+        _ => None,
+    };
+
     let (state, result) = {
         let rule_engine = rule_engine
             .read()
@@ -125,7 +130,8 @@ pub async fn handle_one_in_delivery_queue(
                 config,
                 resolvers,
                 metadata,
-                &ctx.envelop.mail_from,
+                ctx.envelop.reverse_path(),
+                ctx.envelop.smtputf8,
                 &ctx.envelop.rcpt,
                 &ctx.body,
             )
@@ -134,7 +140,27 @@ pub async fn handle_one_in_delivery_queue(
                 "failed to send '{message_id}' located in the delivery queue"
             ))?;
 
+            if let Err(error) = notify_sender(config, resolvers, &ctx).await {
+                log::error!(
+                    target: log_channels::DELIVERY,
+                    "(msg={message_id}) could not notify the sender: {error:?}"
+                );
+            }
+
+            // recipients that have been sent the message are discarded.
+            ctx.envelop
+                .rcpt
+                .retain(|rcpt| !matches!(rcpt.email_status, EmailTransferStatus::Sent));
+
             move_to_queue(config, &ctx)?;
+
+            // the copy of a binary body is only kept for the deferred and dead queues.
+            if ctx.envelop.rcpt.is_empty() {
+                if let Body::Binary(copy) = &ctx.body {
+                    std::fs::remove_file(copy)
+                        .context(format!("failed to remove the copy of '{message_id}'"))?;
+                }
+            }
         }
     }
 
@@ -143,6 +169,12 @@ pub async fn handle_one_in_delivery_queue(
         "failed to remove '{message_id}' from the delivery queue"
     ))?;
 
+    // the other queues store the body along with the envelop, or a copy of a binary body.
+    if let Some(spool_path) = spool_path {
+        std::fs::remove_file(&spool_path)
+            .context(format!("failed to remove the spool file of '{message_id}'"))?;
+    }
+
     Ok(())
 }
 
@@ -151,6 +183,7 @@ mod tests {
     use super::*;
     use vsmtp_common::{
         addr,
+        dsn::{DsnMail, DsnRcpt},
         envelop::Envelop,
         mail_context::{Body, ConnectionContext, MailContext, MessageMetadata},
         rcpt::Rcpt,
@@ -179,6 +212,10 @@ mod tests {
                         is_authenticated: false,
                         is_secured: false,
                         server_name: "testserver.com".to_string(),
+                        query: None,
+                        peer_credentials: None,
+                        early_talker: false,
+                        client_certificate: None,
                     },
                     client_addr: "127.0.0.1:80".parse().unwrap(),
                     envelop: Envelop {
@@ -189,13 +226,18 @@ mod tests {
                                 address: addr!("to+1@client.com"),
                                 transfer_method: Transfer::Maildir,
                                 email_status: EmailTransferStatus::Waiting,
+                                dsn: DsnRcpt::default(),
                             },
                             Rcpt {
                                 address: addr!("to+2@client.com"),
                                 transfer_method: Transfer::Maildir,
                                 email_status: EmailTransferStatus::Waiting,
+                                dsn: DsnRcpt::default(),
                             },
                         ],
+                        dsn: DsnMail::default(),
+                        smtputf8: false,
+                        null_reverse_path: false,
                     },
                     body: Body::Raw("Date: bar\r\nFrom: foo\r\nHello world\r\n".to_string()),
                     metadata: Some(MessageMetadata {
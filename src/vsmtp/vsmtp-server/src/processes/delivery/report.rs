@@ -0,0 +1,516 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use time::format_description::well_known::Rfc2822;
+use vsmtp_common::{
+    dsn::{DsnMail, DsnRcpt, NotifyOn, ReturnContent},
+    envelop::Envelop,
+    mail_context::{Body, ConnectionContext, MailContext, MessageMetadata},
+    rcpt::Rcpt,
+    re::anyhow::{self, Context},
+    transfer::EmailTransferStatus,
+    Address,
+};
+use vsmtp_config::Config;
+
+/// build a delivery status notification for the recipients of `ctx`
+/// that asked to be notified of their delivery status.
+/// see https://datatracker.ietf.org/doc/html/rfc3464
+///
+/// the report is sent by the mailer daemon with the null reverse-path and NOTIFY=NEVER
+/// on its recipient, so that the failure of a report never produces another report.
+/// see https://datatracker.ietf.org/doc/html/rfc3461#section-6.2
+///
+/// return `None` if no recipient must be notified, or if the message has been sent
+/// with the null reverse-path or by a mailer daemon.
+#[allow(clippy::too_many_lines)]
+pub fn build_report(config: &Config, ctx: &MailContext) -> anyhow::Result<Option<MailContext>> {
+    match ctx.envelop.reverse_path() {
+        Some(sender) if !sender.local_part().eq_ignore_ascii_case("mailer-daemon") => {}
+        _ => return Ok(None),
+    }
+
+    let to_notify = ctx
+        .envelop
+        .rcpt
+        .iter()
+        .filter(|rcpt| rcpt.dsn.must_notify(&rcpt.email_status))
+        .collect::<Vec<_>>();
+
+    if to_notify.is_empty() {
+        return Ok(None);
+    }
+
+    let now = std::time::SystemTime::now();
+    let message_id = generate_message_id(now);
+    let domain = &config.server.domain;
+    let boundary = format!("{message_id}/{domain}");
+
+    let has_failed = to_notify
+        .iter()
+        .any(|rcpt| matches!(rcpt.email_status, EmailTransferStatus::Failed(_)));
+
+    // a binary message cannot be embedded in the report, only its headers are returned.
+    let full = has_failed
+        && ctx.envelop.dsn.ret == Some(ReturnContent::Full)
+        && !matches!(ctx.body, Body::Binary(_));
+
+    let report = [
+        format!("From: Mail Delivery System <MAILER-DAEMON@{domain}>\n"),
+        format!("To: <{}>\n", ctx.envelop.mail_from),
+        format!("Subject: {}\n", subject(&to_notify)),
+        format!(
+            "Date: {}\n",
+            time::OffsetDateTime::from(now).format(&Rfc2822)?
+        ),
+        format!("Message-ID: <{message_id}@{domain}>\n"),
+        "Auto-Submitted: auto-replied\n".to_string(),
+        "MIME-Version: 1.0\n".to_string(),
+        format!(
+            "Content-Type: multipart/report; report-type=delivery-status;\n\tboundary=\"{boundary}\"\n"
+        ),
+        "\n".to_string(),
+        "This is a MIME-encapsulated message.\n".to_string(),
+        "\n".to_string(),
+        format!("--{boundary}\n"),
+        "Content-Description: Notification\n".to_string(),
+        "Content-Type: text/plain; charset=utf-8\n".to_string(),
+        "\n".to_string(),
+        notification(domain, &to_notify),
+        "\n".to_string(),
+        format!("--{boundary}\n"),
+        "Content-Description: Delivery report\n".to_string(),
+        "Content-Type: message/delivery-status\n".to_string(),
+        "\n".to_string(),
+        delivery_status(domain, ctx, &to_notify)?,
+        "\n".to_string(),
+        format!("--{boundary}\n"),
+        if full {
+            "Content-Description: Undelivered Message\nContent-Type: message/rfc822\n".to_string()
+        } else {
+            "Content-Description: Message Headers\nContent-Type: text/rfc822-headers\n".to_string()
+        },
+        "\n".to_string(),
+        original_content(&ctx.body, full)?,
+        "\n".to_string(),
+        format!("--{boundary}--\n"),
+    ]
+    .concat();
+
+    build_message(
+        config,
+        now,
+        message_id,
+        vec![ctx.envelop.mail_from.clone()],
+        report,
+    )
+    .map(|mut message| {
+        message.envelop.null_reverse_path = true;
+        Some(message)
+    })
+}
+
+/// a unique identifier for the messages generated by the server.
+pub(super) fn generate_message_id(now: std::time::SystemTime) -> String {
+    format!(
+        "{}{}{}",
+        now.duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap_or(std::time::Duration::ZERO)
+            .as_micros(),
+        std::iter::repeat_with(fastrand::alphanumeric)
+            .take(36)
+            .collect::<String>(),
+        std::process::id()
+    )
+}
+
+/// a message sent by the mailer daemon of the server to `rcpt`.
+///
+/// NOTIFY=NEVER is set on the recipients, so that the failure of the message
+/// never produces a delivery status notification.
+pub(super) fn build_message(
+    config: &Config,
+    now: std::time::SystemTime,
+    message_id: String,
+    rcpt: Vec<Address>,
+    body: String,
+) -> anyhow::Result<MailContext> {
+    let domain = &config.server.domain;
+
+    Ok(MailContext {
+        connection: ConnectionContext {
+            timestamp: now,
+            credentials: None,
+            server_name: domain.clone(),
+            is_authenticated: false,
+            is_secured: false,
+            query: None,
+            peer_credentials: None,
+            early_talker: false,
+            client_certificate: None,
+        },
+        client_addr: std::net::SocketAddr::new(
+            std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+            0,
+        ),
+        envelop: Envelop {
+            helo: domain.clone(),
+            mail_from: Address::try_from(format!("mailer-daemon@{domain}"))
+                .context("failed to create the sender address of the report")?,
+            rcpt: rcpt
+                .into_iter()
+                .map(|address| Rcpt {
+                    dsn: DsnRcpt {
+                        notify: Some(NotifyOn::never()),
+                        orcpt: None,
+                    },
+                    ..Rcpt::new(address)
+                })
+                .collect(),
+            dsn: DsnMail::default(),
+            smtputf8: false,
+            null_reverse_path: false,
+        },
+        body: Body::Raw(body),
+        metadata: Some(MessageMetadata {
+            timestamp: now,
+            message_id,
+            skipped: None,
+        }),
+    })
+}
+
+fn subject(to_notify: &[&Rcpt]) -> &'static str {
+    if to_notify
+        .iter()
+        .any(|rcpt| matches!(rcpt.email_status, EmailTransferStatus::Failed(_)))
+    {
+        "Undelivered Mail Returned to Sender"
+    } else if to_notify
+        .iter()
+        .any(|rcpt| matches!(rcpt.email_status, EmailTransferStatus::HeldBack(_)))
+    {
+        "Delayed Mail (still being retried)"
+    } else {
+        "Successful Mail Delivery Report"
+    }
+}
+
+/// human readable part of the report.
+fn notification(domain: &str, to_notify: &[&Rcpt]) -> String {
+    let mut out = format!("This is the mail system at host {domain}.\n");
+
+    let sections: [(&str, fn(&EmailTransferStatus) -> bool); 3] = [
+        (
+            "Your message could not be delivered to the following recipients:",
+            |status| matches!(status, EmailTransferStatus::Failed(_)),
+        ),
+        (
+            "Your message could not be delivered yet to the following recipients, the delivery will be retried:",
+            |status| matches!(status, EmailTransferStatus::HeldBack(_)),
+        ),
+        (
+            "Your message was successfully delivered to the following recipients:",
+            |status| matches!(status, EmailTransferStatus::Sent),
+        ),
+    ];
+
+    for (title, filter) in sections {
+        let rcpt = to_notify
+            .iter()
+            .filter(|rcpt| filter(&rcpt.email_status))
+            .collect::<Vec<_>>();
+
+        if rcpt.is_empty() {
+            continue;
+        }
+
+        out.push_str(&format!("\n{title}\n\n"));
+        for rcpt in rcpt {
+            match &rcpt.email_status {
+                EmailTransferStatus::Failed(reason) => {
+                    out.push_str(&format!("<{}>: {}\n", rcpt.address, one_line(reason)));
+                }
+                _ => out.push_str(&format!("<{}>\n", rcpt.address)),
+            }
+        }
+    }
+
+    out
+}
+
+/// machine readable part of the report.
+/// see https://datatracker.ietf.org/doc/html/rfc3464#section-2
+fn delivery_status(domain: &str, ctx: &MailContext, to_notify: &[&Rcpt]) -> anyhow::Result<String> {
+    let mut out = format!("Reporting-MTA: dns; {domain}\n");
+
+    if let Some(envid) = &ctx.envelop.dsn.envid {
+        out.push_str(&format!("Original-Envelope-Id: {envid}\n"));
+    }
+    if let Some(metadata) = &ctx.metadata {
+        out.push_str(&format!(
+            "Arrival-Date: {}\n",
+            time::OffsetDateTime::from(metadata.timestamp).format(&Rfc2822)?
+        ));
+    }
+
+    for rcpt in to_notify {
+        out.push_str(&format!("\nFinal-Recipient: rfc822; {}\n", rcpt.address));
+        if let Some(orcpt) = &rcpt.dsn.orcpt {
+            out.push_str(&format!("Original-Recipient: {orcpt}\n"));
+        }
+        match &rcpt.email_status {
+            EmailTransferStatus::Failed(reason) => {
+                out.push_str("Action: failed\nStatus: 5.0.0\n");
+                out.push_str(&format!("Diagnostic-Code: X-VSMTP; {}\n", one_line(reason)));
+            }
+            EmailTransferStatus::HeldBack(_) => out.push_str("Action: delayed\nStatus: 4.0.0\n"),
+            EmailTransferStatus::Sent => out.push_str("Action: delivered\nStatus: 2.0.0\n"),
+            EmailTransferStatus::Waiting => {}
+        }
+    }
+
+    Ok(out)
+}
+
+/// the full message or only its headers, always the headers of a binary message.
+fn original_content(body: &Body, full: bool) -> anyhow::Result<String> {
+    match body {
+        Body::Empty => anyhow::bail!("could not build the report: body is empty"),
+        Body::Raw(raw) if full => Ok(raw.clone()),
+        Body::Raw(raw) => Ok(raw
+            .split_once("\r\n\r\n")
+            .or_else(|| raw.split_once("\n\n"))
+            .map_or_else(|| raw.clone(), |(headers, _)| headers.to_string())),
+        Body::Parsed(parsed) if full => Ok(parsed.to_raw()),
+        Body::Parsed(parsed) => Ok(parsed.raw_headers()),
+        Body::Spooled(_) => original_content(&body.to_raw().map(Body::Raw)?, full),
+        Body::Binary(_) => {
+            let content = body.to_bytes()?;
+            let headers = content
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .map_or(content.as_slice(), |end| &content[..end]);
+            Ok(String::from_utf8_lossy(headers).into_owned())
+        }
+    }
+}
+
+fn one_line(input: &str) -> String {
+    input.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_common::{addr, transfer::Transfer};
+    use vsmtp_test::config;
+
+    fn get_ctx(rcpt: Vec<Rcpt>, dsn: DsnMail) -> MailContext {
+        MailContext {
+            connection: ConnectionContext {
+                timestamp: std::time::SystemTime::now(),
+                credentials: None,
+                is_authenticated: false,
+                is_secured: false,
+                server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: None,
+                early_talker: false,
+                client_certificate: None,
+            },
+            client_addr: "127.0.0.1:80".parse().unwrap(),
+            envelop: Envelop {
+                helo: "client.com".to_string(),
+                mail_from: addr!("from@client.com"),
+                rcpt,
+                dsn,
+                smtputf8: false,
+                null_reverse_path: false,
+            },
+            body: Body::Raw("Date: bar\nFrom: foo\n\nHello world\n".to_string()),
+            metadata: Some(MessageMetadata {
+                timestamp: std::time::SystemTime::now(),
+                message_id: "message_from_deferred_queue".to_string(),
+                skipped: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn nothing_to_notify() {
+        let ctx = get_ctx(
+            vec![
+                Rcpt {
+                    address: addr!("to+1@client.com"),
+                    transfer_method: Transfer::Deliver,
+                    email_status: EmailTransferStatus::Sent,
+                    dsn: DsnRcpt::default(),
+                },
+                Rcpt {
+                    address: addr!("to+2@client.com"),
+                    transfer_method: Transfer::Deliver,
+                    email_status: EmailTransferStatus::Failed("foo".to_string()),
+                    dsn: DsnRcpt {
+                        notify: Some(NotifyOn::never()),
+                        orcpt: None,
+                    },
+                },
+            ],
+            DsnMail::default(),
+        );
+
+        assert_eq!(build_report(&config::local_test(), &ctx).unwrap(), None);
+    }
+
+    #[test]
+    fn no_report_to_null_reverse_path() {
+        let failed = || Rcpt {
+            address: addr!("to+1@client.com"),
+            transfer_method: Transfer::Deliver,
+            email_status: EmailTransferStatus::Failed("foo".to_string()),
+            dsn: DsnRcpt::default(),
+        };
+
+        let mut ctx = get_ctx(vec![failed()], DsnMail::default());
+        ctx.envelop.null_reverse_path = true;
+        assert_eq!(build_report(&config::local_test(), &ctx).unwrap(), None);
+
+        let mut ctx = get_ctx(vec![failed()], DsnMail::default());
+        ctx.envelop.mail_from = addr!("MAILER-DAEMON@client.com");
+        assert_eq!(build_report(&config::local_test(), &ctx).unwrap(), None);
+    }
+
+    #[test]
+    fn failure() {
+        let ctx = get_ctx(
+            vec![Rcpt {
+                address: addr!("to+1@client.com"),
+                transfer_method: Transfer::Deliver,
+                email_status: EmailTransferStatus::Failed(
+                    "maximum retry count of '10' reached".to_string(),
+                ),
+                dsn: DsnRcpt {
+                    notify: None,
+                    orcpt: Some("rfc822;to+2B1@client.com".parse().unwrap()),
+                },
+            }],
+            DsnMail {
+                ret: None,
+                envid: Some("QQ314159".to_string()),
+            },
+        );
+
+        let report = build_report(&config::local_test(), &ctx).unwrap().unwrap();
+
+        assert_eq!(
+            report.envelop.mail_from,
+            addr!("mailer-daemon@testserver.com")
+        );
+        assert_eq!(report.envelop.reverse_path(), None);
+        assert_eq!(report.envelop.rcpt, vec![addr!("from@client.com").into()]);
+        assert_eq!(report.envelop.rcpt[0].dsn.notify, Some(NotifyOn::never()));
+
+        let body = match report.body {
+            Body::Raw(raw) => raw,
+            _ => panic!("the report should be raw"),
+        };
+        assert!(body.contains("Content-Type: multipart/report; report-type=delivery-status;"));
+        assert!(body.contains("Subject: Undelivered Mail Returned to Sender\n"));
+        assert!(body.contains("Reporting-MTA: dns; testserver.com\n"));
+        assert!(body.contains("Original-Envelope-Id: QQ314159\n"));
+        assert!(body.contains(
+            [
+                "Final-Recipient: rfc822; to+1@client.com\n",
+                "Original-Recipient: rfc822;to+1@client.com\n",
+                "Action: failed\n",
+                "Status: 5.0.0\n",
+                "Diagnostic-Code: X-VSMTP; maximum retry count of '10' reached\n",
+            ]
+            .concat()
+            .as_str()
+        ));
+        assert!(body.contains("Content-Type: text/rfc822-headers\n\nDate: bar\nFrom: foo\n"));
+        assert!(!body.contains("Hello world"));
+    }
+
+    #[test]
+    fn failure_full_content() {
+        let ctx = get_ctx(
+            vec![Rcpt {
+                address: addr!("to+1@client.com"),
+                transfer_method: Transfer::Deliver,
+                email_status: EmailTransferStatus::Failed("foo".to_string()),
+                dsn: DsnRcpt::default(),
+            }],
+            DsnMail {
+                ret: Some(ReturnContent::Full),
+                envid: None,
+            },
+        );
+
+        let report = build_report(&config::local_test(), &ctx).unwrap().unwrap();
+        let body = report.body.to_string();
+
+        assert!(
+            body.contains("Content-Type: message/rfc822\n\nDate: bar\nFrom: foo\n\nHello world\n")
+        );
+        assert!(!body.contains("Original-Envelope-Id"));
+    }
+
+    #[test]
+    fn delay_and_success() {
+        let ctx = get_ctx(
+            vec![
+                Rcpt {
+                    address: addr!("to+1@client.com"),
+                    transfer_method: Transfer::Deliver,
+                    email_status: EmailTransferStatus::HeldBack(0),
+                    dsn: DsnRcpt::default(),
+                },
+                Rcpt {
+                    address: addr!("to+2@client.com"),
+                    transfer_method: Transfer::Deliver,
+                    email_status: EmailTransferStatus::Sent,
+                    dsn: DsnRcpt {
+                        notify: Some("SUCCESS".parse().unwrap()),
+                        orcpt: None,
+                    },
+                },
+            ],
+            DsnMail {
+                ret: Some(ReturnContent::Full),
+                envid: None,
+            },
+        );
+
+        let body = build_report(&config::local_test(), &ctx)
+            .unwrap()
+            .unwrap()
+            .body
+            .to_string();
+
+        assert!(body.contains("Subject: Delayed Mail (still being retried)\n"));
+        assert!(body.contains(
+            "Final-Recipient: rfc822; to+1@client.com\nAction: delayed\nStatus: 4.0.0\n"
+        ));
+        assert!(body.contains(
+            "Final-Recipient: rfc822; to+2@client.com\nAction: delivered\nStatus: 2.0.0\n"
+        ));
+        // only failures return the full content.
+        assert!(body.contains("Content-Type: text/rfc822-headers\n"));
+    }
+}
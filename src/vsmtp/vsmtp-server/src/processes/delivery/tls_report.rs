@@ -0,0 +1,330 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use super::report::{build_message, generate_message_id};
+use crate::log_channels;
+use std::io::Write;
+use time::format_description::well_known::Rfc2822;
+use trust_dns_resolver::TokioAsyncResolver;
+use vsmtp_common::{
+    mail_context::MailContext,
+    re::{
+        anyhow::{self, Context},
+        base64, log, serde_json,
+    },
+    Address,
+};
+use vsmtp_config::Config;
+use vsmtp_delivery::tls_rpt;
+
+/// the results of a domain whose `_smtp._tls` record cannot be fetched are dropped
+/// once they are older than a week.
+const MAX_RESULTS_AGE: u64 = 7 * 24 * 60 * 60;
+
+/// send the reports of the tls sessions recorded during the days elapsed to the
+/// destination domains publishing a `_smtp._tls` record.
+/// see https://www.rfc-editor.org/rfc/rfc8460
+///
+/// the results are kept if the record of a domain could not be fetched,
+/// and reported on the next call, until they are older than [`MAX_RESULTS_AGE`].
+pub async fn send_tls_reports(
+    config: &Config,
+    resolvers: &std::collections::HashMap<String, TokioAsyncResolver>,
+) -> anyhow::Result<()> {
+    let resolver = resolvers
+        .get(&config.server.domain)
+        .ok_or_else(|| anyhow::anyhow!("no resolver for '{}'", config.server.domain))?;
+
+    let now = std::time::SystemTime::now();
+    for results in tls_rpt::get_elapsed_days(&config.server.queues.dirpath.join("tls-rpt"), now)? {
+        let rua = match tls_rpt::get_reporting_addresses(resolver, &results.policy_domain).await {
+            Ok(rua) => rua,
+            Err(error) if is_expired(&results, now) => {
+                log::warn!(
+                    target: log_channels::DELIVERY,
+                    "failed to get the reporting addresses of '{}', dropping the results of {}: {error}",
+                    results.policy_domain,
+                    results.day
+                );
+                results.remove()?;
+                continue;
+            }
+            Err(error) => {
+                log::warn!(
+                    target: log_channels::DELIVERY,
+                    "failed to get the reporting addresses of '{}', the report will be sent later: {error}",
+                    results.policy_domain
+                );
+                continue;
+            }
+        };
+
+        let rcpt = rua
+            .into_iter()
+            .filter_map(|address| match Address::try_from(address.clone()) {
+                Ok(address) => Some(address),
+                Err(error) => {
+                    log::warn!(
+                        target: log_channels::DELIVERY,
+                        "invalid reporting address '{address}' for '{}': {error}",
+                        results.policy_domain
+                    );
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let sessions = results.read()?;
+
+        if rcpt.is_empty() || sessions.is_empty() {
+            log::debug!(
+                target: log_channels::DELIVERY,
+                "no tls report sent to '{}'",
+                results.policy_domain
+            );
+        } else {
+            let message = build_tls_report(config, &results, &sessions, rcpt)?;
+
+            log::info!(
+                target: log_channels::DELIVERY,
+                "(msg={}) sending the tls report of '{}'",
+                message
+                    .metadata
+                    .as_ref()
+                    .map_or("unknown", |metadata| metadata.message_id.as_str()),
+                results.policy_domain
+            );
+
+            super::send_generated_message(config, resolvers, message)
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to send the tls report of '{}'",
+                        results.policy_domain
+                    )
+                })?;
+        }
+
+        results.remove()?;
+    }
+
+    Ok(())
+}
+
+/// are the `results` too old to be kept until they can be reported.
+fn is_expired(results: &tls_rpt::DailyResults, now: std::time::SystemTime) -> bool {
+    now.duration_since(std::time::UNIX_EPOCH)
+        .map_or(false, |now| {
+            now.as_secs().saturating_sub(results.day) > MAX_RESULTS_AGE
+        })
+}
+
+/// build the message sending the report of `sessions` to `rcpt`, the json report
+/// is compressed with gzip and attached to the message.
+/// see https://www.rfc-editor.org/rfc/rfc8460#section-5.3
+fn build_tls_report(
+    config: &Config,
+    results: &tls_rpt::DailyResults,
+    sessions: &[tls_rpt::SessionResult],
+    rcpt: Vec<Address>,
+) -> anyhow::Result<MailContext> {
+    let now = std::time::SystemTime::now();
+    let message_id = generate_message_id(now);
+    let domain = &config.server.domain;
+    let policy_domain = &results.policy_domain;
+    let report_id = format!("{message_id}@{domain}");
+    let boundary = format!("{message_id}/{domain}");
+
+    let report = tls_rpt::Report::new(
+        config
+            .server
+            .tls_rpt
+            .organization_name
+            .as_deref()
+            .unwrap_or(domain),
+        &config
+            .server
+            .tls_rpt
+            .contact_info
+            .clone()
+            .unwrap_or_else(|| format!("mailto:postmaster@{domain}")),
+        &report_id,
+        results.day,
+        sessions,
+    )?;
+
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(&serde_json::to_vec(&report)?)?;
+    let attachment = base64::encode(encoder.finish()?);
+
+    let body = [
+        format!("From: TLS Reporting <mailer-daemon@{domain}>\n"),
+        format!(
+            "To: {}\n",
+            rcpt.iter()
+                .map(|address| format!("<{address}>"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        format!(
+            "Subject: Report Domain: {policy_domain} Submitter: {domain} Report-ID: <{report_id}>\n"
+        ),
+        format!(
+            "Date: {}\n",
+            time::OffsetDateTime::from(now).format(&Rfc2822)?
+        ),
+        format!("Message-ID: <{message_id}@{domain}>\n"),
+        format!("TLS-Report-Domain: {policy_domain}\n"),
+        format!("TLS-Report-Submitter: {domain}\n"),
+        "Auto-Submitted: auto-generated\n".to_string(),
+        "MIME-Version: 1.0\n".to_string(),
+        format!(
+            "Content-Type: multipart/report; report-type=\"tlsrpt\";\n\tboundary=\"{boundary}\"\n"
+        ),
+        "\n".to_string(),
+        "This is a MIME-encapsulated message.\n".to_string(),
+        "\n".to_string(),
+        format!("--{boundary}\n"),
+        "Content-Type: text/plain; charset=utf-8\n".to_string(),
+        "\n".to_string(),
+        format!("This is an aggregate TLS report from {domain}.\n"),
+        "\n".to_string(),
+        format!("--{boundary}\n"),
+        "Content-Type: application/tlsrpt+gzip\n".to_string(),
+        "Content-Transfer-Encoding: base64\n".to_string(),
+        format!(
+            "Content-Disposition: attachment;\n\tfilename=\"{domain}!{policy_domain}!{}!{}!{message_id}.json.gz\"\n",
+            results.day,
+            results.day + 24 * 60 * 60 - 1
+        ),
+        "\n".to_string(),
+        attachment
+            .as_bytes()
+            .chunks(76)
+            .map(|line| format!("{}\n", String::from_utf8_lossy(line)))
+            .collect::<String>(),
+        format!("--{boundary}--\n"),
+    ]
+    .concat();
+
+    build_message(config, now, message_id, rcpt, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use vsmtp_common::{addr, mail_context::Body};
+    use vsmtp_test::config;
+
+    #[test]
+    fn expired() {
+        let results = tls_rpt::DailyResults {
+            day: 1_654_041_600,
+            policy_domain: "example.com".to_string(),
+            path: std::path::PathBuf::from("./tmp/tls-rpt/expired/example.com.jsonl"),
+        };
+        let day = std::time::UNIX_EPOCH + std::time::Duration::from_secs(results.day);
+
+        assert!(!is_expired(
+            &results,
+            day + std::time::Duration::from_secs(MAX_RESULTS_AGE)
+        ));
+        assert!(is_expired(
+            &results,
+            day + std::time::Duration::from_secs(MAX_RESULTS_AGE + 1)
+        ));
+    }
+
+    #[test]
+    fn report() {
+        let dir = std::path::PathBuf::from("./tmp/tls-rpt/report");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // 2022-06-01T00:00:00Z
+        let day = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_654_041_600);
+        let policy = tls_rpt::AppliedPolicy {
+            policy_type: tls_rpt::PolicyType::NoPolicyFound,
+            policy_string: vec![],
+            policy_domain: "example.com".to_string(),
+            mx_host: vec![],
+        };
+        for result_type in [None, Some(tls_rpt::ResultType::StarttlsNotSupported)] {
+            tls_rpt::record(
+                &dir,
+                &tls_rpt::SessionResult {
+                    policy: policy.clone(),
+                    receiving_mx_hostname: "mx.example.com".to_string(),
+                    result_type,
+                    failure_reason_code: None,
+                },
+                day,
+            )
+            .unwrap();
+        }
+
+        let results = tls_rpt::get_elapsed_days(&dir, std::time::SystemTime::now())
+            .unwrap()
+            .remove(0);
+        let message = build_tls_report(
+            &config::local_test(),
+            &results,
+            &results.read().unwrap(),
+            vec![addr!("tls-reports@example.com")],
+        )
+        .unwrap();
+
+        assert_eq!(
+            message.envelop.mail_from,
+            addr!("mailer-daemon@testserver.com")
+        );
+        assert_eq!(
+            message.envelop.rcpt,
+            vec![addr!("tls-reports@example.com").into()]
+        );
+
+        let body = match message.body {
+            Body::Raw(raw) => raw,
+            _ => panic!("the report should be raw"),
+        };
+        assert!(body.contains("To: <tls-reports@example.com>\n"));
+        assert!(body.contains("TLS-Report-Domain: example.com\n"));
+        assert!(body.contains("TLS-Report-Submitter: testserver.com\n"));
+        assert!(body.contains("Content-Type: multipart/report; report-type=\"tlsrpt\";"));
+        assert!(body.contains("filename=\"testserver.com!example.com!1654041600!1654127999!"));
+
+        let attachment = body
+            .split_once("Content-Transfer-Encoding: base64\n")
+            .and_then(|(_, attachment)| attachment.split_once("\n\n"))
+            .and_then(|(_, attachment)| attachment.split_once("--"))
+            .unwrap()
+            .0
+            .replace('\n', "");
+
+        let mut report = String::new();
+        flate2::read::GzDecoder::new(&base64::decode(attachment).unwrap()[..])
+            .read_to_string(&mut report)
+            .unwrap();
+        let report = serde_json::from_str::<tls_rpt::Report>(&report).unwrap();
+
+        assert_eq!(report.organization_name, "testserver.com");
+        assert_eq!(report.contact_info, "mailto:postmaster@testserver.com");
+        assert_eq!(report.date_range.start_datetime, "2022-06-01T00:00:00Z");
+        assert_eq!(report.policies.len(), 1);
+        assert_eq!(report.policies[0].summary.total_successful_session_count, 1);
+        assert_eq!(report.policies[0].summary.total_failure_session_count, 1);
+    }
+}
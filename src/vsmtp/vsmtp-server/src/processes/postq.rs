@@ -14,13 +14,15 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use crate::{log_channels, ProcessMessage};
+use crate::{log_channels, reload::Reloader, ProcessMessage};
 use anyhow::Context;
+use std::os::unix::fs::OpenOptionsExt;
 use vsmtp_common::{
-    mail_context::MailContext,
+    mail_context::{Body, MailContext, MessageMetadata, PeerCredentials},
     queue::Queue,
     queue_path,
-    re::{anyhow, log},
+    rcpt::Rcpt,
+    re::{anyhow, libc, log, serde_json, strum},
     state::StateSMTP,
     status::Status,
 };
@@ -30,27 +32,196 @@ use vsmtp_rule_engine::{rule_engine::RuleEngine, rule_state::RuleState};
 
 /// process that treats incoming email offline with the postq stage.
 ///
+/// Return once the working channel is closed and every message received is processed.
+///
 /// # Errors
 pub async fn start(
-    config: std::sync::Arc<Config>,
-    rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+    reloader: std::sync::Arc<Reloader>,
     mut working_receiver: tokio::sync::mpsc::Receiver<ProcessMessage>,
     delivery_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
 ) -> anyhow::Result<()> {
+    let mut flush_pickup_interval = tokio::time::interval(
+        reloader
+            .current()
+            .config
+            .server
+            .queues
+            .working
+            .pickup_period,
+    );
+
     loop {
-        if let Some(pm) = working_receiver.recv().await {
-            if let Err(err) = tokio::spawn(handle_one_in_working_queue(
-                config.clone(),
-                rule_engine.clone(),
-                pm,
-                delivery_sender.clone(),
-            ))
-            .await
-            {
-                log::error!(target: log_channels::POSTQ, "{}", err);
+        tokio::select! {
+            pm = working_receiver.recv() => {
+                let pm = match pm {
+                    Some(pm) => pm,
+                    None => break,
+                };
+                // the message is processed with the configuration and rules loaded when it is taken.
+                let snapshot = reloader.current();
+                if let Err(err) = tokio::spawn(handle_one_in_working_queue(
+                    snapshot.config.clone(),
+                    snapshot.rule_engine.clone(),
+                    pm,
+                    delivery_sender.clone(),
+                ))
+                .await
+                {
+                    log::error!(target: log_channels::POSTQ, "{}", err);
+                }
+            }
+            _ = flush_pickup_interval.tick() => {
+                let snapshot = reloader.current();
+                for pm in flush_pickup_directory(&snapshot.config) {
+                    if let Err(err) = tokio::spawn(handle_one_in_working_queue(
+                        snapshot.config.clone(),
+                        snapshot.rule_engine.clone(),
+                        pm,
+                        delivery_sender.clone(),
+                    ))
+                    .await
+                    {
+                        log::error!(target: log_channels::POSTQ, "{}", err);
+                    }
+                }
             }
         }
     }
+
+    log::info!(target: log_channels::POSTQ, "working channel closed, every message is processed.");
+    Ok(())
+}
+
+/// move the messages submitted locally (with `vsendmail`) from the pickup directory
+/// to the working queue, and return the messages to process.
+///
+/// the pickup directory is writable by the local users, so the files are not trusted:
+/// the connection is tagged with the owner of the file, and the fields that the
+/// submitter could forge are reset.
+fn flush_pickup_directory(config: &Config) -> Vec<ProcessMessage> {
+    let pickup = queue_path!(&config.server.queues.dirpath, "pickup");
+
+    let entries = match std::fs::read_dir(&pickup) {
+        Ok(entries) => entries,
+        // the directory is created when the server starts.
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return vec![],
+        Err(error) => {
+            log::error!(
+                target: log_channels::POSTQ,
+                "failed to read the pickup directory '{}': {error}",
+                pickup.display()
+            );
+            return vec![];
+        }
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        // files starting with a dot are still being written.
+        .filter(|path| {
+            path.file_name()
+                .and_then(std::ffi::OsStr::to_str)
+                .map_or(false, |name| !name.starts_with('.'))
+        })
+        .filter_map(|path| match handle_one_in_pickup_directory(config, &path) {
+            Ok(pm) => Some(pm),
+            Err(error) => {
+                log::error!(
+                    target: log_channels::POSTQ,
+                    "failed to pick up '{}': {error:?}",
+                    path.display()
+                );
+                if let Err(error) = std::fs::remove_file(&path) {
+                    log::error!(target: log_channels::POSTQ, "{error}");
+                }
+                None
+            }
+        })
+        .collect()
+}
+
+fn handle_one_in_pickup_directory(
+    config: &Config,
+    path: &std::path::Path,
+) -> anyhow::Result<ProcessMessage> {
+    // a symbolic link would let the submitter read any file the server can open.
+    anyhow::ensure!(
+        std::fs::symlink_metadata(path)?.file_type().is_file(),
+        "not a regular file"
+    );
+
+    // the file can still be swapped between the check and the open: the link is not
+    // followed, and the owner is read from the opened file.
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)?;
+    let metadata = file.metadata()?;
+    anyhow::ensure!(metadata.is_file(), "not a regular file");
+
+    let mut content = String::new();
+    std::io::Read::read_to_string(&mut file, &mut content)?;
+    let mut ctx = serde_json::from_str::<MailContext>(&content)
+        .with_context(|| format!("failed to deserialize '{}'", path.display()))?;
+
+    anyhow::ensure!(
+        matches!(ctx.body, Body::Raw(_)),
+        "the body of a submitted message must be inlined"
+    );
+
+    let message_id = path
+        .file_name()
+        .and_then(std::ffi::OsStr::to_str)
+        .ok_or_else(|| anyhow::anyhow!("invalid file name"))?
+        .to_string();
+
+    // the name of the file is chosen by the submitter.
+    anyhow::ensure!(
+        <Queue as strum::IntoEnumIterator>::iter().all(|queue| !queue_path!(
+            &config.server.queues.dirpath,
+            queue,
+            &message_id
+        )
+        .exists()),
+        "a message with the id '{message_id}' is already queued"
+    );
+
+    ctx.client_addr = std::net::SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, 0));
+    ctx.connection.credentials = None;
+    ctx.connection.is_authenticated = false;
+    ctx.connection.is_secured = false;
+    ctx.connection.early_talker = false;
+    ctx.connection.client_certificate = None;
+    ctx.connection.peer_credentials = Some(PeerCredentials {
+        uid: std::os::unix::fs::MetadataExt::uid(&metadata),
+        gid: std::os::unix::fs::MetadataExt::gid(&metadata),
+        pid: None,
+    });
+    // the transfer methods are chosen by the rules.
+    for rcpt in &mut ctx.envelop.rcpt {
+        *rcpt = Rcpt {
+            dsn: rcpt.dsn.clone(),
+            ..Rcpt::new(rcpt.address.clone())
+        };
+    }
+    ctx.metadata = Some(MessageMetadata {
+        timestamp: std::time::SystemTime::now(),
+        message_id: message_id.clone(),
+        skipped: None,
+    });
+
+    log::debug!(
+        target: log_channels::POSTQ,
+        "(msg={}) picked up a message submitted by uid={}",
+        message_id,
+        std::os::unix::fs::MetadataExt::uid(&metadata)
+    );
+
+    Queue::Working.write_to_queue(&config.server.queues.dirpath, &ctx)?;
+    std::fs::remove_file(path)?;
+
+    Ok(ProcessMessage { message_id })
 }
 
 ///
@@ -87,6 +258,11 @@ async fn handle_one_in_working_queue(
         file_to_process.display()
     ))?;
 
+    // the body is parsed for the postq rules, and written back to the spool afterward.
+    let spool_path = match &ctx.body {
+        Body::Spooled(path) => Some(path.clone()),
+        _ => None,
+    };
     ctx.body = ctx.body.to_parsed::<MailMimeParser>()?;
 
     // locking the engine and freeing the lock before any await.
@@ -101,6 +277,17 @@ async fn handle_one_in_working_queue(
         (state, result)
     };
 
+    // the body could have been modified by the postq rules.
+    if let Some(path) = spool_path {
+        if let Err(error) = state.context().write().unwrap().body.spool(path) {
+            log::warn!(
+                target: log_channels::POSTQ,
+                "(msg={}) the body is kept in memory: {error:?}",
+                process_message.message_id,
+            );
+        }
+    }
+
     if let Status::Deny(_) = result {
         Queue::Dead.write_to_queue(
             &config.server.queues.dirpath,
@@ -120,10 +307,10 @@ async fn handle_one_in_working_queue(
             {
                 // skipping mime & delivery processes.
                 log::warn!(
-                target: log_channels::POSTQ,
-                "(msg={}) delivery skipped because all recipient's transfer method is set to None.",
-                process_message.message_id,
-            );
+                    target: log_channels::POSTQ,
+                    "(msg={}) delivery skipped because all recipient's transfer method is set to None.",
+                    process_message.message_id,
+                );
                 Queue::Dead.write_to_queue(&config.server.queues.dirpath, &ctx)?;
                 false
             } else {
@@ -160,6 +347,7 @@ mod tests {
     use crate::ProcessMessage;
     use vsmtp_common::{
         addr,
+        dsn::{DsnMail, DsnRcpt},
         envelop::Envelop,
         mail_context::{Body, ConnectionContext, MailContext, MessageMetadata},
         rcpt::Rcpt,
@@ -209,6 +397,10 @@ mod tests {
                         is_authenticated: false,
                         is_secured: false,
                         server_name: "testserver.com".to_string(),
+                        query: None,
+                        peer_credentials: None,
+                        early_talker: false,
+                        client_certificate: None,
                     },
                     client_addr: "127.0.0.1:80".parse().unwrap(),
                     envelop: Envelop {
@@ -219,13 +411,18 @@ mod tests {
                                 address: addr!("to+1@client.com"),
                                 transfer_method: Transfer::Deliver,
                                 email_status: EmailTransferStatus::Waiting,
+                                dsn: DsnRcpt::default(),
                             },
                             Rcpt {
                                 address: addr!("to+2@client.com"),
                                 transfer_method: Transfer::Maildir,
                                 email_status: EmailTransferStatus::Waiting,
+                                dsn: DsnRcpt::default(),
                             },
                         ],
+                        dsn: DsnMail::default(),
+                        smtputf8: false,
+                        null_reverse_path: false,
                     },
                     body: Body::Raw("Date: bar\r\nFrom: foo\r\nHello world\r\n".to_string()),
                     metadata: Some(MessageMetadata {
@@ -277,6 +474,10 @@ mod tests {
                         is_authenticated: false,
                         is_secured: false,
                         server_name: "testserver.com".to_string(),
+                        query: None,
+                        peer_credentials: None,
+                        early_talker: false,
+                        client_certificate: None,
                     },
                     client_addr: "127.0.0.1:80".parse().unwrap(),
                     envelop: Envelop {
@@ -287,13 +488,18 @@ mod tests {
                                 address: addr!("to+1@client.com"),
                                 transfer_method: Transfer::Deliver,
                                 email_status: EmailTransferStatus::Waiting,
+                                dsn: DsnRcpt::default(),
                             },
                             Rcpt {
                                 address: addr!("to+2@client.com"),
                                 transfer_method: Transfer::Maildir,
                                 email_status: EmailTransferStatus::Waiting,
+                                dsn: DsnRcpt::default(),
                             },
                         ],
+                        dsn: DsnMail::default(),
+                        smtputf8: false,
+                        null_reverse_path: false,
                     },
                     body: Body::Raw("Date: bar\r\nFrom: foo\r\nHello world\r\n".to_string()),
                     metadata: Some(MessageMetadata {
@@ -331,4 +537,108 @@ mod tests {
         assert!(!std::path::PathBuf::from("./tmp/working/test_denied").exists());
         assert!(std::path::PathBuf::from("./tmp/dead/test_denied").exists());
     }
+
+    #[test]
+    fn pickup() {
+        let mut config = config::local_test();
+        config.server.queues.dirpath = "./tmp/postq_pickup".into();
+        let _ = std::fs::remove_dir_all(&config.server.queues.dirpath);
+
+        // a submitter forging the fields set by the server.
+        let forged = MailContext {
+            connection: ConnectionContext {
+                timestamp: std::time::SystemTime::now(),
+                credentials: None,
+                is_authenticated: true,
+                is_secured: true,
+                server_name: "testserver.com".to_string(),
+                query: None,
+                peer_credentials: Some(PeerCredentials {
+                    uid: 0,
+                    gid: 0,
+                    pid: None,
+                }),
+                early_talker: false,
+                client_certificate: None,
+            },
+            client_addr: "192.168.1.1:25".parse().unwrap(),
+            envelop: Envelop {
+                helo: "localhost".to_string(),
+                mail_from: addr!("from@testserver.com"),
+                rcpt: vec![Rcpt {
+                    address: addr!("to@client.com"),
+                    transfer_method: Transfer::Maildir,
+                    email_status: EmailTransferStatus::Sent,
+                    dsn: DsnRcpt::default(),
+                }],
+                dsn: DsnMail::default(),
+                smtputf8: false,
+                null_reverse_path: false,
+            },
+            body: Body::Raw("From: from@testserver.com\r\n\r\nHello world\r\n".to_string()),
+            metadata: Some(MessageMetadata {
+                timestamp: std::time::SystemTime::now(),
+                message_id: "forged".to_string(),
+                skipped: Some(Status::Accept),
+            }),
+        };
+
+        let pickup =
+            queue_path!(create_if_missing => &config.server.queues.dirpath, "pickup").unwrap();
+        std::fs::write(
+            pickup.join("submitted"),
+            vsmtp_common::re::serde_json::to_string(&forged).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(pickup.join(".partial"), "{").unwrap();
+        // a link to a file the submitter cannot read.
+        let target = config.server.queues.dirpath.join("not_readable");
+        std::fs::write(
+            &target,
+            vsmtp_common::re::serde_json::to_string(&forged).unwrap(),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(std::fs::canonicalize(&target).unwrap(), pickup.join("link"))
+            .unwrap();
+
+        let picked_up = flush_pickup_directory(&config);
+
+        assert_eq!(
+            picked_up
+                .into_iter()
+                .map(|pm| pm.message_id)
+                .collect::<Vec<_>>(),
+            vec!["submitted".to_string()]
+        );
+        assert!(!pickup.join("submitted").exists());
+        assert!(pickup.join(".partial").exists());
+        assert!(std::fs::symlink_metadata(pickup.join("link")).is_err());
+        assert!(target.exists());
+
+        let ctx = MailContext::from_file(queue_path!(
+            &config.server.queues.dirpath,
+            Queue::Working,
+            "submitted"
+        ))
+        .unwrap();
+
+        assert_eq!(ctx.client_addr, "127.0.0.1:0".parse().unwrap());
+        assert!(!ctx.connection.is_authenticated);
+        assert!(!ctx.connection.is_secured);
+        assert_eq!(
+            ctx.connection.peer_credentials.unwrap().uid,
+            std::os::unix::fs::MetadataExt::uid(&std::fs::metadata(&pickup).unwrap())
+        );
+        assert_eq!(ctx.envelop.rcpt[0].transfer_method, Transfer::Deliver);
+        assert_eq!(
+            ctx.envelop.rcpt[0].email_status,
+            EmailTransferStatus::Waiting
+        );
+        let metadata = ctx.metadata.unwrap();
+        assert_eq!(metadata.message_id, "submitted");
+        assert_eq!(metadata.skipped, None);
+        assert_eq!(ctx.body, forged.body);
+
+        std::fs::remove_file(pickup.join(".partial")).unwrap();
+    }
 }
@@ -0,0 +1,414 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use vsmtp_config::{ConfigServerSMTPRateLimit, ConfigServerSMTPRateLimitNetwork};
+
+/// events counted in the window
+#[derive(Clone, Copy)]
+enum Event {
+    Connection,
+    Message,
+    Recipient,
+}
+
+impl Event {
+    const fn limit(self, limits: &ConfigServerSMTPRateLimitNetwork) -> Option<usize> {
+        match self {
+            Self::Connection => limits.connections,
+            Self::Message => limits.messages,
+            Self::Recipient => limits.recipients,
+        }
+    }
+}
+
+/// activity of a network of clients during the window
+#[derive(Default)]
+struct Network {
+    concurrent_connections: usize,
+    connections: std::collections::VecDeque<std::time::Instant>,
+    messages: std::collections::VecDeque<std::time::Instant>,
+    recipients: std::collections::VecDeque<std::time::Instant>,
+}
+
+impl Network {
+    /// forget the events which happened before `start`
+    fn prune(&mut self, start: std::time::Instant) {
+        for events in [
+            &mut self.connections,
+            &mut self.messages,
+            &mut self.recipients,
+        ] {
+            while events.front().map_or(false, |instant| *instant < start) {
+                events.pop_front();
+            }
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.concurrent_connections == 0
+            && self.connections.is_empty()
+            && self.messages.is_empty()
+            && self.recipients.is_empty()
+    }
+
+    fn events(&mut self, event: Event) -> &mut std::collections::VecDeque<std::time::Instant> {
+        match event {
+            Event::Connection => &mut self.connections,
+            Event::Message => &mut self.messages,
+            Event::Recipient => &mut self.recipients,
+        }
+    }
+}
+
+/// the networks of a client, with their limits
+type Networks<'a> = [(&'a ConfigServerSMTPRateLimitNetwork, &'a mut Network)];
+
+/// record an event at `now` in each network if none of them reached its limit,
+/// the events of the networks without limit are not recorded
+fn try_push(networks: &mut Networks<'_>, event: Event, now: std::time::Instant) -> bool {
+    if networks.iter_mut().any(|(limits, network)| {
+        event
+            .limit(limits)
+            .map_or(false, |limit| network.events(event).len() >= limit)
+    }) {
+        return false;
+    }
+    for (limits, network) in networks.iter_mut() {
+        if event.limit(limits).is_some() {
+            network.events(event).push_back(now);
+        }
+    }
+    true
+}
+
+struct State {
+    /// the networks of each limit of `ipv4`, then of `ipv6`
+    networks: Vec<std::collections::HashMap<std::net::IpAddr, Network>>,
+    last_purge: std::time::Instant,
+}
+
+/// Limits the connections, messages and recipients of each network of clients,
+/// shared by all the sessions of the server.
+pub struct RateLimiter {
+    config: ConfigServerSMTPRateLimit,
+    state: std::sync::Mutex<State>,
+}
+
+/// A connection counted by the [`RateLimiter`], released when dropped.
+pub struct ConnectionGuard {
+    limiter: std::sync::Arc<RateLimiter>,
+    client: std::net::IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.with_networks(self.client, |networks, _| {
+            for (limits, network) in networks {
+                if limits.concurrent_connections.is_some() {
+                    network.concurrent_connections =
+                        network.concurrent_connections.saturating_sub(1);
+                }
+            }
+        });
+    }
+}
+
+impl RateLimiter {
+    ///
+    #[must_use]
+    pub fn new(config: ConfigServerSMTPRateLimit) -> Self {
+        let networks = std::iter::repeat_with(std::collections::HashMap::new)
+            .take(config.ipv4.len() + config.ipv6.len())
+            .collect();
+
+        Self {
+            config,
+            state: std::sync::Mutex::new(State {
+                networks,
+                last_purge: std::time::Instant::now(),
+            }),
+        }
+    }
+
+    /// call `f` with the networks of the client for each limit of its family,
+    /// IPv4-mapped IPv6 addresses are handled as IPv4
+    fn with_networks<T>(
+        &self,
+        client: std::net::IpAddr,
+        f: impl FnOnce(&mut Networks<'_>, std::time::Instant) -> T,
+    ) -> T {
+        let client = match client {
+            std::net::IpAddr::V6(v6) => match v6.octets() {
+                [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                    std::net::IpAddr::V4(std::net::Ipv4Addr::new(a, b, c, d))
+                }
+                _ => client,
+            },
+            std::net::IpAddr::V4(_) => client,
+        };
+
+        let now = std::time::Instant::now();
+        let start = now.checked_sub(self.config.window);
+
+        // a poisoned lock only means a session panicked, the counters are still usable.
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        // the idle networks are forgotten once per window.
+        if now.duration_since(state.last_purge) >= self.config.window {
+            if let Some(start) = start {
+                for networks in &mut state.networks {
+                    networks.retain(|_, network| {
+                        network.prune(start);
+                        !network.is_idle()
+                    });
+                }
+            }
+            state.last_purge = now;
+        }
+
+        let (limits, networks) = match client {
+            std::net::IpAddr::V4(_) => (
+                &self.config.ipv4,
+                &mut state.networks[..self.config.ipv4.len()],
+            ),
+            std::net::IpAddr::V6(_) => (
+                &self.config.ipv6,
+                &mut state.networks[self.config.ipv4.len()..],
+            ),
+        };
+
+        let mut networks = limits
+            .iter()
+            .zip(networks.iter_mut())
+            .map(|(limits, networks)| {
+                let address = ipnet::IpNet::new(client, limits.prefix)
+                    .map_or(client, |network| network.network());
+
+                let network = networks.entry(address).or_default();
+                if let Some(start) = start {
+                    network.prune(start);
+                }
+                (limits, network)
+            })
+            .collect::<Vec<_>>();
+
+        f(&mut networks, now)
+    }
+
+    /// Count a new connection of the client, the connection is released
+    /// when the returned guard is dropped.
+    ///
+    /// Return `None` if a network of the client has too many connections.
+    #[must_use]
+    pub fn connect(
+        self: &std::sync::Arc<Self>,
+        client: std::net::IpAddr,
+    ) -> Option<ConnectionGuard> {
+        self.with_networks(client, |networks, now| {
+            if networks.iter().any(|(limits, network)| {
+                limits
+                    .concurrent_connections
+                    .map_or(false, |limit| network.concurrent_connections >= limit)
+            }) || !try_push(networks, Event::Connection, now)
+            {
+                return None;
+            }
+            for (limits, network) in networks.iter_mut() {
+                if limits.concurrent_connections.is_some() {
+                    network.concurrent_connections += 1;
+                }
+            }
+            Some(ConnectionGuard {
+                limiter: self.clone(),
+                client,
+            })
+        })
+    }
+
+    /// Count a new message of the client, return `false` if one of its networks sent too many.
+    #[must_use]
+    pub fn message(&self, client: std::net::IpAddr) -> bool {
+        self.with_networks(client, |networks, now| {
+            try_push(networks, Event::Message, now)
+        })
+    }
+
+    /// Count a new recipient of the client, return `false` if one of its networks sent too many.
+    #[must_use]
+    pub fn recipient(&self, client: std::net::IpAddr) -> bool {
+        self.with_networks(client, |networks, now| {
+            try_push(networks, Event::Recipient, now)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(
+        ipv4: Vec<ConfigServerSMTPRateLimitNetwork>,
+        ipv6: Vec<ConfigServerSMTPRateLimitNetwork>,
+    ) -> std::sync::Arc<RateLimiter> {
+        std::sync::Arc::new(RateLimiter::new(ConfigServerSMTPRateLimit {
+            ipv4,
+            ipv6,
+            ..ConfigServerSMTPRateLimit::default()
+        }))
+    }
+
+    const fn network(prefix: u8) -> ConfigServerSMTPRateLimitNetwork {
+        ConfigServerSMTPRateLimitNetwork {
+            prefix,
+            concurrent_connections: None,
+            connections: None,
+            messages: None,
+            recipients: None,
+        }
+    }
+
+    #[test]
+    fn concurrent_connections() {
+        let limiter = limiter(
+            vec![ConfigServerSMTPRateLimitNetwork {
+                concurrent_connections: Some(2),
+                ..network(32)
+            }],
+            vec![],
+        );
+        let client = "192.168.1.1".parse().unwrap();
+
+        let first = limiter.connect(client);
+        let second = limiter.connect(client);
+        assert!(first.is_some() && second.is_some());
+        assert!(limiter.connect(client).is_none());
+
+        drop(first);
+        assert!(limiter.connect(client).is_some());
+    }
+
+    #[test]
+    fn connection_rate() {
+        let limiter = limiter(
+            vec![ConfigServerSMTPRateLimitNetwork {
+                connections: Some(2),
+                ..network(32)
+            }],
+            vec![],
+        );
+        let client = "192.168.1.1".parse().unwrap();
+
+        // the closed connections are still counted in the window.
+        drop(limiter.connect(client).unwrap());
+        drop(limiter.connect(client).unwrap());
+        assert!(limiter.connect(client).is_none());
+        assert!(limiter.connect("192.168.1.2".parse().unwrap()).is_some());
+    }
+
+    #[test]
+    fn network_prefix() {
+        let limiter = limiter(
+            vec![ConfigServerSMTPRateLimitNetwork {
+                messages: Some(1),
+                ..network(24)
+            }],
+            vec![ConfigServerSMTPRateLimitNetwork {
+                messages: Some(1),
+                ..network(48)
+            }],
+        );
+
+        assert!(limiter.message("192.168.1.1".parse().unwrap()));
+        assert!(!limiter.message("192.168.1.200".parse().unwrap()));
+        assert!(!limiter.message("::ffff:192.168.1.3".parse().unwrap()));
+        assert!(limiter.message("192.168.2.1".parse().unwrap()));
+
+        assert!(limiter.message("2001:db8:1::1".parse().unwrap()));
+        assert!(!limiter.message("2001:db8:1:ffff::1".parse().unwrap()));
+        assert!(limiter.message("2001:db8:2::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn nested_networks() {
+        let limiter = limiter(
+            vec![
+                ConfigServerSMTPRateLimitNetwork {
+                    messages: Some(2),
+                    ..network(32)
+                },
+                ConfigServerSMTPRateLimitNetwork {
+                    messages: Some(3),
+                    ..network(24)
+                },
+            ],
+            vec![],
+        );
+
+        assert!(limiter.message("192.168.1.1".parse().unwrap()));
+        assert!(limiter.message("192.168.1.1".parse().unwrap()));
+        // the address reached its own limit, its network did not.
+        assert!(!limiter.message("192.168.1.1".parse().unwrap()));
+        assert!(limiter.message("192.168.1.2".parse().unwrap()));
+        // the network reached its limit.
+        assert!(!limiter.message("192.168.1.3".parse().unwrap()));
+        // the IPv6 clients have no limit.
+        assert!(limiter.message("2001:db8:1::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn unlimited_events_are_not_recorded() {
+        let limiter = limiter(
+            vec![ConfigServerSMTPRateLimitNetwork {
+                messages: Some(1),
+                ..network(32)
+            }],
+            vec![],
+        );
+        let client = "192.168.1.1".parse().unwrap();
+
+        for _ in 0..10 {
+            assert!(limiter.recipient(client));
+            drop(limiter.connect(client).unwrap());
+        }
+
+        let state = limiter.state.lock().unwrap();
+        let network = &state.networks[0][&client];
+        assert!(network.connections.is_empty() && network.recipients.is_empty());
+    }
+
+    #[test]
+    fn window() {
+        let limiter = std::sync::Arc::new(RateLimiter::new(ConfigServerSMTPRateLimit {
+            window: std::time::Duration::from_millis(100),
+            ipv4: vec![ConfigServerSMTPRateLimitNetwork {
+                recipients: Some(2),
+                ..network(32)
+            }],
+            ipv6: vec![],
+        }));
+        let client = "192.168.1.1".parse().unwrap();
+
+        assert!(limiter.recipient(client));
+        assert!(limiter.recipient(client));
+        assert!(!limiter.recipient(client));
+
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert!(limiter.recipient(client));
+    }
+}
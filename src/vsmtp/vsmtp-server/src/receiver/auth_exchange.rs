@@ -99,7 +99,14 @@ pub async fn on_authentication<S>(
 where
     S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin,
 {
-    // TODO: if initial data == "=" ; it mean empty ""
+    // "=" is an initial response of zero length (rfc4954 section 4)
+    let initial_response = initial_response.map(|initial_response| {
+        if initial_response == b"=" {
+            vec![]
+        } else {
+            initial_response
+        }
+    });
 
     if mechanism.must_be_under_tls() && !conn.is_secured {
         if conn
@@ -144,6 +151,10 @@ where
             is_authenticated: conn.is_authenticated,
             is_secured: conn.is_secured,
             server_name: conn.server_name.clone(),
+            query: None,
+            peer_credentials: conn.peer_credentials,
+            early_talker: conn.is_early_talker,
+            client_certificate: conn.client_certificate.clone(),
         },
     )));
 
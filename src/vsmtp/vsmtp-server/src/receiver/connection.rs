@@ -15,12 +15,13 @@
  *
 */
 // use super::io_service::{IoService, ReadError};
-use crate::{log_channels, AbstractIO};
+use crate::{log_channels, AbstractIO, RateLimiter};
 use vsmtp_common::{
     code::SMTPReplyCode,
+    mail_context::{AuthCredentials, ClientCertificate, PeerCredentials},
     re::{anyhow, log},
 };
-use vsmtp_config::Config;
+use vsmtp_config::{Config, ListenerKind, TlsSecurityLevel};
 
 /// how the server would react to tls interaction for this connection
 #[allow(clippy::module_name_repetitions)]
@@ -32,6 +33,19 @@ pub enum ConnectionKind {
     Submission,
     /// within TLS
     Tunneled,
+    /// Local Mail Transfer Protocol, one reply per recipient at the end of a message (rfc2033)
+    Lmtp,
+}
+
+impl From<ListenerKind> for ConnectionKind {
+    fn from(kind: ListenerKind) -> Self {
+        match kind {
+            ListenerKind::Plain => Self::Opportunistic,
+            ListenerKind::Submission => Self::Submission,
+            ListenerKind::Tunneled => Self::Tunneled,
+            ListenerKind::Lmtp => Self::Lmtp,
+        }
+    }
 }
 
 // TODO:? merge with [`ConnectionContext`]
@@ -60,8 +74,33 @@ where
     pub is_authenticated: bool,
     /// number of time the AUTH command has been received (and failed)
     pub authentication_attempt: i64,
+    /// the client must be under TLS before sending a message
+    pub tls_required: bool,
+    /// the client must be authenticated before sending a message
+    pub auth_required: bool,
+    /// credentials of the local process, if connected with a unix domain socket
+    pub peer_credentials: Option<PeerCredentials>,
+    /// limits of the network of the client, shared with the other sessions
+    pub rate_limiter: Option<std::sync::Arc<RateLimiter>>,
+    /// has the client sent data before the greetings ?
+    pub is_early_talker: bool,
+    /// the client is a trusted proxy, allowed to send XCLIENT and XFORWARD
+    pub is_forwarder: bool,
+    /// domain of the HELO / EHLO of the original client, given by a trusted proxy
+    pub forwarded_helo: Option<String>,
+    /// identity of the original client given by a trusted proxy,
+    /// or the identity of the certificate of a client authenticated with `AUTH EXTERNAL`
+    pub credentials: Option<AuthCredentials>,
+    /// certificate sent by the client during the tls handshake, see `server.tls.client_auth`
+    pub client_certificate: Option<ClientCertificate>,
+    /// set to `true` when the server is shutting down, see [`Connection::read_command`]
+    pub shutdown: Option<tokio::sync::watch::Receiver<bool>>,
+    /// pipe to the delivery process, receiving the nodes given to the ETRN command
+    pub deferred_flush: Option<tokio::sync::mpsc::Sender<String>>,
     /// inner stream
     pub inner: AbstractIO<S>,
+    /// replies not yet written on the stream (see PIPELINING rfc2920)
+    pending_reply: String,
 }
 
 impl<S> Connection<S>
@@ -80,6 +119,17 @@ where
             server_name: config.server.domain.clone(),
             timestamp: std::time::SystemTime::now(),
             is_alive: true,
+            tls_required: Self::default_tls_required(&config),
+            auth_required: Self::default_auth_required(&config),
+            peer_credentials: None,
+            rate_limiter: None,
+            is_early_talker: false,
+            is_forwarder: Self::default_is_forwarder(&config, &client_addr),
+            forwarded_helo: None,
+            credentials: None,
+            client_certificate: None,
+            shutdown: None,
+            deferred_flush: None,
             config,
             client_addr,
             error_count: 0,
@@ -87,6 +137,7 @@ where
             inner: AbstractIO::new(inner),
             is_authenticated: false,
             authentication_attempt: 0,
+            pending_reply: String::new(),
         }
     }
 
@@ -109,6 +160,17 @@ where
             server_name,
             timestamp,
             is_alive: true,
+            tls_required: Self::default_tls_required(&config),
+            auth_required: Self::default_auth_required(&config),
+            peer_credentials: None,
+            rate_limiter: None,
+            is_early_talker: false,
+            is_forwarder: Self::default_is_forwarder(&config, &client_addr),
+            forwarded_helo: None,
+            credentials: None,
+            client_certificate: None,
+            shutdown: None,
+            deferred_flush: None,
             config,
             client_addr,
             error_count,
@@ -116,8 +178,46 @@ where
             is_authenticated,
             authentication_attempt,
             inner: AbstractIO::new(inner),
+            pending_reply: String::new(),
         }
     }
+
+    /// the proxies of `trusted_forwarders` may give the information of their clients
+    fn default_is_forwarder(config: &Config, client_addr: &std::net::SocketAddr) -> bool {
+        config
+            .server
+            .smtp
+            .trusted_forwarders
+            .iter()
+            .any(|network| network.contains(&client_addr.ip()))
+    }
+
+    /// TLS is required on every listener if the security level is [`TlsSecurityLevel::Encrypt`]
+    fn default_tls_required(config: &Config) -> bool {
+        config.server.tls.as_ref().map(|smtps| smtps.security_level)
+            == Some(TlsSecurityLevel::Encrypt)
+    }
+
+    /// authentication is required on every listener if `must_be_authenticated` is set
+    fn default_auth_required(config: &Config) -> bool {
+        config
+            .server
+            .smtp
+            .auth
+            .as_ref()
+            .map_or(false, |auth| auth.must_be_authenticated)
+    }
+}
+
+fn get_message(config: &Config, code: SMTPReplyCode) -> String {
+    match code {
+        SMTPReplyCode::Custom(message) => message,
+        _ => config.server.smtp.codes.get(&code).unwrap().clone(),
+    }
+}
+
+fn make_fold(message: &str) -> String {
+    fold(&message[0..3], None, &message[4..])
 }
 
 fn fold(code: &str, enhanced: Option<&str>, message: &str) -> String {
@@ -274,17 +374,6 @@ where
     ///
     /// * a smtp code is missing, and thus config is ill-formed
     pub async fn send_code(&mut self, reply_to_send: SMTPReplyCode) -> anyhow::Result<()> {
-        fn get_message(config: &Config, code: SMTPReplyCode) -> String {
-            match code {
-                SMTPReplyCode::Custom(message) => message,
-                _ => config.server.smtp.codes.get(&code).unwrap().clone(),
-            }
-        }
-
-        fn make_fold(message: &str) -> String {
-            fold(&message[0..3], None, &message[4..])
-        }
-
         log::info!(
             target: log_channels::CONNECTION,
             "send=\"{:?}\"",
@@ -308,12 +397,14 @@ where
                 response.push_str("\r\n");
 
                 self.send(&response).await?;
+                self.flush().await?;
 
                 anyhow::bail!("{}", SMTPReplyCode::Code451TooManyError)
             }
 
             self.send(&make_fold(&get_message(&self.config, reply_to_send)))
                 .await?;
+            self.flush().await?;
 
             if soft_error != -1 && self.error_count >= soft_error {
                 std::thread::sleep(self.config.server.smtp.error.delay);
@@ -325,15 +416,92 @@ where
         Ok(())
     }
 
+    /// send the reply to the end of a message (DATA or BDAT LAST), `replies`
+    /// holds the status of each recipient.
+    ///
+    /// With LMTP, one reply is sent for each recipient (rfc2033 section 4.2),
+    /// they are the status of a single message and do not count as errors of the client.
+    ///
+    /// # Errors
+    ///
+    /// see [`Connection::send_code`]
+    ///
+    /// # Panics
+    ///
+    /// * a smtp code is missing, and thus config is ill-formed
+    pub async fn send_message_code(&mut self, replies: Vec<SMTPReplyCode>) -> anyhow::Result<()> {
+        match self.kind {
+            ConnectionKind::Lmtp => {
+                for reply_to_send in replies {
+                    log::info!(
+                        target: log_channels::CONNECTION,
+                        "send=\"{:?}\"",
+                        reply_to_send
+                    );
+                    self.send(&make_fold(&get_message(&self.config, reply_to_send)))
+                        .await?;
+                }
+                Ok(())
+            }
+            // the recipients share the status of the message.
+            _ => match replies.into_iter().next() {
+                Some(reply_to_send) => self.send_code(reply_to_send).await,
+                None => Ok(()),
+            },
+        }
+    }
+
     /// Send a buffer
     ///
+    /// If the client has pipelined other commands, the reply is kept in memory
+    /// and written with the following ones, as a group.
+    ///
     /// # Errors
     ///
     /// * internal connection writer error
     pub async fn send(&mut self, reply: &str) -> anyhow::Result<()> {
         log::info!(target: log_channels::CONNECTION, "send=\"{:?}\"", reply);
-        tokio::io::AsyncWriteExt::write_all(&mut self.inner.inner, reply.as_bytes()).await?;
+        self.pending_reply.push_str(reply);
+
+        if !self.inner.has_pending_line() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Write all the pending replies on the stream
+    ///
+    /// # Errors
+    ///
+    /// * internal connection writer error
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        if self.pending_reply.is_empty() {
+            return Ok(());
+        }
+        tokio::io::AsyncWriteExt::write_all(&mut self.inner.inner, self.pending_reply.as_bytes())
+            .await?;
         tokio::io::AsyncWriteExt::flush(&mut self.inner.inner).await?;
+        self.pending_reply.clear();
+        Ok(())
+    }
+
+    /// wait `delay` before sending the greetings, the client is an early talker
+    /// if it sends anything in the meantime. (the data is read as usual afterward)
+    ///
+    /// # Errors
+    ///
+    /// * stream's error
+    pub async fn greet_pause(&mut self, delay: std::time::Duration) -> std::io::Result<()> {
+        let deadline = tokio::time::Instant::now() + delay;
+        if self.inner.wait_for_data(delay).await? {
+            log::warn!(
+                target: log_channels::CONNECTION,
+                "client {} sent data before the greetings",
+                self.client_addr
+            );
+            self.is_early_talker = true;
+            tokio::time::sleep_until(deadline).await;
+        }
         Ok(())
     }
 
@@ -347,6 +515,67 @@ where
         &mut self,
         timeout: std::time::Duration,
     ) -> std::io::Result<Option<std::string::String>> {
+        // the client is waiting for our replies before sending anything else
+        if !self.inner.has_pending_line() {
+            self.flush().await?;
+        }
         self.inner.next_line(Some(timeout)).await
     }
+
+    /// read the next command of the client, unless the server is shutting down
+    ///
+    /// # Errors
+    ///
+    /// * [`std::io::ErrorKind::Interrupted`] the server is shutting down
+    /// * see [`Connection::read`]
+    pub async fn read_command(
+        &mut self,
+        timeout: std::time::Duration,
+    ) -> std::io::Result<Option<std::string::String>> {
+        fn shutting_down() -> std::io::Error {
+            std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "the server is shutting down",
+            )
+        }
+
+        let mut shutdown = match &self.shutdown {
+            Some(shutdown) if *shutdown.borrow() => return Err(shutting_down()),
+            Some(shutdown) => shutdown.clone(),
+            None => return self.read(timeout).await,
+        };
+
+        tokio::select! {
+            line = self.read(timeout) => line,
+            Ok(()) = shutdown.changed() => Err(shutting_down()),
+        }
+    }
+
+    /// read a chunk of `size` bytes from the client (BDAT)
+    ///
+    /// # Errors
+    ///
+    /// * timed-out
+    /// * stream's error
+    pub async fn read_chunk(
+        &mut self,
+        size: usize,
+        timeout: std::time::Duration,
+    ) -> std::io::Result<Vec<u8>> {
+        self.inner.next_chunk(size, Some(timeout)).await
+    }
+
+    /// read and discard a chunk of `size` bytes from the client (BDAT)
+    ///
+    /// # Errors
+    ///
+    /// * timed-out
+    /// * stream's error
+    pub async fn skip_chunk(
+        &mut self,
+        size: usize,
+        timeout: std::time::Duration,
+    ) -> std::io::Result<()> {
+        self.inner.skip_chunk(size, Some(timeout)).await
+    }
 }
@@ -64,6 +64,15 @@ where
         }
     }
 
+    /// has the client already sent a complete line which has not been read yet,
+    /// (the client is pipelining its commands)
+    #[must_use]
+    pub fn has_pending_line(&self) -> bool {
+        self.buf
+            .windows(NEEDLE.len())
+            .any(|window| window == NEEDLE)
+    }
+
     ///
     /// # Errors
     ///
@@ -78,6 +87,81 @@ where
         .await
         .map_err(|t| std::io::Error::new(std::io::ErrorKind::TimedOut, t))?
     }
+
+    /// wait at most `delay` for the client to send something,
+    /// the data received is kept for the next reads.
+    ///
+    /// # Errors
+    ///
+    /// * stream's error
+    pub async fn wait_for_data(&mut self, delay: std::time::Duration) -> std::io::Result<bool> {
+        match tokio::time::timeout(delay, tokio::io::AsyncBufReadExt::fill_buf(self)).await {
+            Ok(available) => Ok(!available?.is_empty()),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// read exactly `size` bytes, without interpreting them (see BDAT rfc3030)
+    ///
+    /// # Errors
+    ///
+    /// * timed-out
+    /// * the stream has been closed before `size` bytes has been read
+    pub async fn next_chunk(
+        &mut self,
+        size: usize,
+        timeout: Option<std::time::Duration>,
+    ) -> std::io::Result<Vec<u8>> {
+        let mut chunk = Vec::with_capacity(size);
+        self.consume_chunk(size, timeout, Some(&mut chunk)).await?;
+        Ok(chunk)
+    }
+
+    /// read and discard exactly `size` bytes
+    ///
+    /// # Errors
+    ///
+    /// * timed-out
+    /// * the stream has been closed before `size` bytes has been read
+    pub async fn skip_chunk(
+        &mut self,
+        size: usize,
+        timeout: Option<std::time::Duration>,
+    ) -> std::io::Result<()> {
+        self.consume_chunk(size, timeout, None).await
+    }
+
+    async fn consume_chunk(
+        &mut self,
+        mut size: usize,
+        timeout: Option<std::time::Duration>,
+        mut output: Option<&mut Vec<u8>>,
+    ) -> std::io::Result<()> {
+        tokio::time::timeout(
+            timeout.unwrap_or(std::time::Duration::from_millis(500)),
+            async {
+                while size != 0 {
+                    let available = tokio::io::AsyncBufReadExt::fill_buf(&mut *self).await?;
+                    if available.is_empty() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "connection closed in the middle of a chunk",
+                        ));
+                    }
+
+                    let len = std::cmp::min(size, available.len());
+                    if let Some(output) = output.as_mut() {
+                        output.extend_from_slice(&available[..len]);
+                    }
+                    tokio::io::AsyncBufReadExt::consume(&mut *self, len);
+                    size -= len;
+                }
+                Ok(())
+            },
+        )
+        .await
+        .map_err(|t| std::io::Error::new(std::io::ErrorKind::TimedOut, t))?
+    }
 }
 
 impl<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin> tokio::io::AsyncBufRead
@@ -221,6 +305,50 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn pending_line() {
+        let input = ["a\r\n", "b\r\n", "c"].concat().as_bytes().to_vec();
+        let mut written = Vec::new();
+        let mut io = AbstractIO::new(Mock::new(input.clone(), &mut written));
+
+        assert!(!io.has_pending_line());
+        assert_eq!(io.next_line(None).await.unwrap(), Some("a".to_string()));
+        assert!(io.has_pending_line());
+        assert_eq!(io.next_line(None).await.unwrap(), Some("b".to_string()));
+        assert!(!io.has_pending_line());
+    }
+
+    #[tokio::test]
+    async fn read_chunk() {
+        let input = [
+            &b"BDAT 8\r\n"[..],
+            &b"a\r\nb\xc3\x28cd"[..],
+            &b"QUIT\r\n"[..],
+        ]
+        .concat();
+        let mut written = Vec::new();
+        let mut io = AbstractIO::new(Mock::new(input.clone(), &mut written));
+
+        assert_eq!(
+            io.next_line(None).await.unwrap(),
+            Some("BDAT 8".to_string())
+        );
+        assert_eq!(io.next_chunk(8, None).await.unwrap(), b"a\r\nb\xc3\x28cd");
+        assert_eq!(io.next_line(None).await.unwrap(), Some("QUIT".to_string()));
+    }
+
+    #[tokio::test]
+    async fn skip_chunk() {
+        let mut written = Vec::new();
+        let mut io = AbstractIO::new(Mock::new(b"abc".to_vec(), &mut written));
+
+        io.skip_chunk(2, None).await.unwrap();
+        assert_eq!(
+            io.next_chunk(2, None).await.unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+
     #[tokio::test]
     async fn read_non_utf8() {
         let input = b"\xc3\x28".to_vec();
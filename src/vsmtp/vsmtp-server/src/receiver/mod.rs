@@ -15,11 +15,11 @@
  *
 */
 use self::transaction::{Transaction, TransactionResult};
-use crate::{auth, receiver::auth_exchange::on_authentication, ProcessMessage};
+use crate::{auth, log_channels, receiver::auth_exchange::on_authentication, ProcessMessage};
 use vsmtp_common::{
     auth::Mechanism,
     code::SMTPReplyCode,
-    mail_context::MailContext,
+    mail_context::{AuthCredentials, Body, ClientCertificate, MailContext},
     queue::Queue,
     re::{anyhow, log},
     status::Status,
@@ -30,6 +30,8 @@ use vsmtp_rule_engine::rule_engine::RuleEngine;
 mod auth_exchange;
 mod connection;
 mod io;
+pub(crate) mod proxy_protocol;
+mod spool;
 pub mod transaction;
 
 pub use connection::{Connection, ConnectionKind};
@@ -60,7 +62,7 @@ impl OnMail for MailHandler {
     async fn on_mail<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
         &mut self,
         conn: &mut Connection<S>,
-        mail: Box<MailContext>,
+        mut mail: Box<MailContext>,
         helo_domain: &mut Option<String>,
     ) -> anyhow::Result<()> {
         *helo_domain = Some(mail.envelop.helo.clone());
@@ -72,6 +74,15 @@ impl OnMail for MailHandler {
                 let mut path = create_app_folder(&conn.config, Some(path))?;
                 path.push(format!("{}.json", metadata.message_id));
 
+                // the message will not be processed further, its body is stored with the envelop.
+                // a binary body cannot be stored as text, its spool file is kept.
+                let spooled = if matches!(mail.body, Body::Binary(_)) {
+                    None
+                } else {
+                    let raw = mail.body.to_raw()?;
+                    Some(std::mem::replace(&mut mail.body, Body::Raw(raw)))
+                };
+
                 match std::fs::OpenOptions::new()
                     .create(true)
                     .append(true)
@@ -84,8 +95,13 @@ impl OnMail for MailHandler {
                     Err(err) => anyhow::bail!("failed to quarantine email: {err:?}"),
                 }?;
 
+                if let Some(Body::Spooled(spool)) = spooled {
+                    std::fs::remove_file(spool)?;
+                }
+
                 log::warn!("postq & delivery skipped due to quarantine.");
-                conn.send_code(SMTPReplyCode::Code250).await?;
+                conn.send_message_code(vec![SMTPReplyCode::Code250; mail.envelop.rcpt.len()])
+                    .await?;
                 return Ok(());
             }
             Some(reason) => {
@@ -114,7 +130,8 @@ impl OnMail for MailHandler {
             SMTPReplyCode::Code250
         };
 
-        conn.send_code(response).await?;
+        conn.send_message_code(vec![response; mail.envelop.rcpt.len()])
+            .await?;
         Ok(())
     }
 }
@@ -165,6 +182,15 @@ where
         Err(auth_exchange::AuthExchangeError::Other(e)) => anyhow::bail!("{}", e),
         Ok(_) => {
             conn.is_authenticated = true;
+            // the client is known by the identity of its certificate.
+            if mechanism == Mechanism::External {
+                conn.credentials =
+                    conn.client_certificate
+                        .as_ref()
+                        .map(|certificate| AuthCredentials::Query {
+                            authid: certificate.identity().to_string(),
+                        });
+            }
 
             // TODO: When a security layer takes effect
             // helo_domain = None;
@@ -175,6 +201,47 @@ where
     Ok(())
 }
 
+/// the subject and the alternative names of a certificate in DER format.
+fn read_client_certificate(der: &[u8]) -> anyhow::Result<ClientCertificate> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(der)
+        .map_err(|error| anyhow::anyhow!("invalid certificate: {error}"))?;
+
+    let mut client_certificate = ClientCertificate {
+        subject: certificate.subject().to_string(),
+        common_name: certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|common_name| common_name.as_str().ok())
+            .map(str::to_string),
+        dns_names: vec![],
+        email_addresses: vec![],
+        uris: vec![],
+    };
+
+    if let Some(extension) = certificate
+        .subject_alternative_name()
+        .map_err(|error| anyhow::anyhow!("invalid subject alternative name: {error}"))?
+    {
+        for name in &extension.value.general_names {
+            match name {
+                x509_parser::extensions::GeneralName::DNSName(name) => {
+                    client_certificate.dns_names.push((*name).to_string());
+                }
+                x509_parser::extensions::GeneralName::RFC822Name(name) => {
+                    client_certificate.email_addresses.push((*name).to_string());
+                }
+                x509_parser::extensions::GeneralName::URI(name) => {
+                    client_certificate.uris.push((*name).to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(client_certificate)
+}
+
 // NOTE: handle_connection and handle_connection_secured do the same things..
 // but i struggle to unify these function because of recursive type
 
@@ -209,48 +276,78 @@ where
 
     let mut helo_domain = None;
 
+    if let Some(delay) = conn.config.server.smtp.greet_pause {
+        conn.greet_pause(delay).await?;
+    }
     conn.send_code(SMTPReplyCode::Greetings).await?;
 
-    while conn.is_alive {
-        match Transaction::receive(conn, &helo_domain, rule_engine.clone()).await? {
-            TransactionResult::Nothing => {}
-            TransactionResult::Mail(mail) => {
-                mail_handler.on_mail(conn, mail, &mut helo_domain).await?;
-            }
-            TransactionResult::TlsUpgrade => {
-                if let Some(tls_config) = tls_config {
-                    return handle_connection_secured(
-                        conn,
-                        tls_config,
-                        rsasl,
-                        rule_engine,
-                        mail_handler,
-                    )
-                    .await;
+    let result: anyhow::Result<()> = async {
+        while conn.is_alive {
+            match Transaction::receive(conn, &helo_domain, rule_engine.clone()).await? {
+                TransactionResult::Nothing => {}
+                TransactionResult::Mail(mail, helo) => {
+                    mail_handler.on_mail(conn, mail, &mut helo_domain).await?;
+                    // the helo given with XFORWARD only applies to the mail.
+                    if let Some(helo) = helo {
+                        helo_domain = Some(helo);
+                    }
                 }
-                conn.send_code(SMTPReplyCode::Code454).await?;
-                anyhow::bail!("{}", SMTPReplyCode::Code454)
-            }
-            TransactionResult::Authentication(helo_pre_auth, mechanism, initial_response) => {
-                if let Some(rsasl) = &rsasl {
-                    handle_auth(
-                        conn,
-                        rsasl.clone(),
-                        rule_engine.clone(),
-                        &mut helo_domain,
-                        mechanism,
-                        initial_response,
-                        helo_pre_auth,
-                    )
-                    .await?;
-                } else {
-                    conn.send_code(SMTPReplyCode::Code502unimplemented).await?;
+                TransactionResult::TlsUpgrade => {
+                    if let Some(tls_config) = tls_config {
+                        return handle_connection_secured(
+                            conn,
+                            tls_config,
+                            rsasl,
+                            rule_engine,
+                            mail_handler,
+                        )
+                        .await;
+                    }
+                    conn.send_code(SMTPReplyCode::Code454).await?;
+                    anyhow::bail!("{}", SMTPReplyCode::Code454)
+                }
+                TransactionResult::Authentication(helo_pre_auth, mechanism, initial_response) => {
+                    if let Some(rsasl) = &rsasl {
+                        handle_auth(
+                            conn,
+                            rsasl.clone(),
+                            rule_engine.clone(),
+                            &mut helo_domain,
+                            mechanism,
+                            initial_response,
+                            helo_pre_auth,
+                        )
+                        .await?;
+                    } else {
+                        conn.send_code(SMTPReplyCode::Code502unimplemented).await?;
+                    }
                 }
             }
         }
+        Ok(())
     }
+    .await;
 
-    Ok(())
+    flush_on_error(conn, result).await
+}
+
+/// the replies buffered before a failure are still sent, the client may be waiting for them.
+async fn flush_on_error<S>(
+    conn: &mut Connection<S>,
+    result: anyhow::Result<()>,
+) -> anyhow::Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin + Sync,
+{
+    if result.is_err() {
+        if let Err(error) = conn.flush().await {
+            log::warn!(
+                target: log_channels::CONNECTION,
+                "cannot send the pending replies: {error}"
+            );
+        }
+    }
+    result
 }
 
 async fn handle_connection_secured<S, M>(
@@ -269,12 +366,32 @@ where
     })?;
     let acceptor = tokio_rustls::TlsAcceptor::from(tls_config);
 
+    // the "220 Ready to start TLS" must be sent before the handshake,
+    // commands pipelined after STARTTLS are discarded (rfc3207 4.2)
+    conn.flush().await?;
+
     let stream = tokio::time::timeout(
         smtps_config.handshake_timeout,
         acceptor.accept(&mut conn.inner.inner),
     )
     .await??;
 
+    // the certificate has been verified against `server.tls.client_auth` during the handshake.
+    let client_certificate = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(<[_]>::first)
+        .and_then(
+            |certificate| match read_client_certificate(&certificate.0) {
+                Ok(client_certificate) => Some(client_certificate),
+                Err(error) => {
+                    log::warn!("cannot read the certificate of the client: {error}");
+                    None
+                }
+            },
+        );
+
     let mut secured_conn = Connection::new_with(
         conn.kind,
         stream
@@ -292,45 +409,69 @@ where
         conn.authentication_attempt,
         stream,
     );
+    secured_conn.tls_required = conn.tls_required;
+    secured_conn.auth_required = conn.auth_required;
+    secured_conn.peer_credentials = conn.peer_credentials;
+    secured_conn.rate_limiter = conn.rate_limiter.clone();
+    secured_conn.is_early_talker = conn.is_early_talker;
+    secured_conn.is_forwarder = conn.is_forwarder;
+    secured_conn.forwarded_helo = conn.forwarded_helo.clone();
+    secured_conn.credentials = conn.credentials.clone();
+    secured_conn.client_certificate = client_certificate;
+    secured_conn.shutdown = conn.shutdown.clone();
+    secured_conn.deferred_flush = conn.deferred_flush.clone();
 
     if let ConnectionKind::Tunneled = secured_conn.kind {
+        if let Some(delay) = secured_conn.config.server.smtp.greet_pause {
+            secured_conn.greet_pause(delay).await?;
+        }
         secured_conn.send_code(SMTPReplyCode::Greetings).await?;
     }
 
     let mut helo_domain = None;
 
-    while secured_conn.is_alive {
-        match Transaction::receive(&mut secured_conn, &helo_domain, rule_engine.clone()).await? {
-            TransactionResult::Nothing => {}
-            TransactionResult::Mail(mail) => {
-                mail_handler
-                    .on_mail(&mut secured_conn, mail, &mut helo_domain)
-                    .await?;
-            }
-            TransactionResult::TlsUpgrade => {
-                secured_conn
-                    .send_code(SMTPReplyCode::TlsAlreadyUnderTls)
-                    .await?;
-            }
-            TransactionResult::Authentication(helo_pre_auth, mechanism, initial_response) => {
-                if let Some(rsasl) = &rsasl {
-                    handle_auth(
-                        &mut secured_conn,
-                        rsasl.clone(),
-                        rule_engine.clone(),
-                        &mut helo_domain,
-                        mechanism,
-                        initial_response,
-                        helo_pre_auth,
-                    )
-                    .await?;
-                } else {
+    let result: anyhow::Result<()> = async {
+        while secured_conn.is_alive {
+            match Transaction::receive(&mut secured_conn, &helo_domain, rule_engine.clone()).await?
+            {
+                TransactionResult::Nothing => {}
+                TransactionResult::Mail(mail, helo) => {
+                    mail_handler
+                        .on_mail(&mut secured_conn, mail, &mut helo_domain)
+                        .await?;
+                    // the helo given with XFORWARD only applies to the mail.
+                    if let Some(helo) = helo {
+                        helo_domain = Some(helo);
+                    }
+                }
+                TransactionResult::TlsUpgrade => {
                     secured_conn
-                        .send_code(SMTPReplyCode::Code502unimplemented)
+                        .send_code(SMTPReplyCode::TlsAlreadyUnderTls)
+                        .await?;
+                }
+                TransactionResult::Authentication(helo_pre_auth, mechanism, initial_response) => {
+                    if let Some(rsasl) = &rsasl {
+                        handle_auth(
+                            &mut secured_conn,
+                            rsasl.clone(),
+                            rule_engine.clone(),
+                            &mut helo_domain,
+                            mechanism,
+                            initial_response,
+                            helo_pre_auth,
+                        )
                         .await?;
+                    } else {
+                        secured_conn
+                            .send_code(SMTPReplyCode::Code502unimplemented)
+                            .await?;
+                    }
                 }
             }
         }
+        Ok(())
     }
-    Ok(())
+    .await;
+
+    flush_on_error(&mut secured_conn, result).await
 }
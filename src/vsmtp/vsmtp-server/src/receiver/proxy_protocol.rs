@@ -0,0 +1,266 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+//! HAProxy PROXY protocol, version 1 (text) and 2 (binary)
+//!
+//! see <https://www.haproxy.org/download/2.6/doc/proxy-protocol.txt>
+
+use vsmtp_common::re::anyhow;
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_MAX_LENGTH: usize = 107;
+
+/// Read the PROXY header sent by the proxy at the beginning of the connection.
+///
+/// Only the header is consumed, the stream is positioned on the first byte
+/// of the client's data.
+///
+/// Returns the address of the client, or `None` if the proxy did not relay one
+/// (`UNKNOWN` v1 header, `LOCAL` command or unsupported family in v2, used for health checks).
+///
+/// # Errors
+///
+/// * the stream is closed before the end of the header
+/// * the header is malformed
+pub async fn read_header<S: tokio::io::AsyncRead + Unpin + Send>(
+    stream: &mut S,
+) -> anyhow::Result<Option<std::net::SocketAddr>> {
+    let mut signature = [0; 12];
+    tokio::io::AsyncReadExt::read_exact(stream, &mut signature).await?;
+
+    if signature == V2_SIGNATURE {
+        let mut header = [0; 4];
+        tokio::io::AsyncReadExt::read_exact(stream, &mut header).await?;
+
+        let mut addresses = vec![0; usize::from(u16::from_be_bytes([header[2], header[3]]))];
+        tokio::io::AsyncReadExt::read_exact(stream, &mut addresses).await?;
+
+        parse_v2(header[0], header[1], &addresses)
+    } else {
+        // the line is read byte per byte to not consume the client's data.
+        let mut line = signature.to_vec();
+        while !line.ends_with(b"\r\n") {
+            anyhow::ensure!(line.len() < V1_MAX_LENGTH, "PROXY v1 header is too long");
+            line.push(tokio::io::AsyncReadExt::read_u8(stream).await?);
+        }
+
+        parse_v1(std::str::from_utf8(&line)?)
+    }
+}
+
+fn parse_v1(line: &str) -> anyhow::Result<Option<std::net::SocketAddr>> {
+    let mut words = line
+        .strip_suffix("\r\n")
+        .ok_or_else(|| anyhow::anyhow!("PROXY v1 header must end with <CRLF>"))?
+        .split(' ');
+
+    anyhow::ensure!(words.next() == Some("PROXY"), "not a PROXY header");
+
+    match words.next() {
+        // the rest of the line is ignored
+        Some("UNKNOWN") => Ok(None),
+        Some(protocol @ ("TCP4" | "TCP6")) => {
+            let addresses = words.collect::<Vec<_>>();
+            anyhow::ensure!(
+                addresses.len() == 4,
+                "PROXY v1 header must have 4 address fields, got {}",
+                addresses.len()
+            );
+
+            let source = std::net::SocketAddr::new(addresses[0].parse()?, addresses[2].parse()?);
+            let destination =
+                std::net::SocketAddr::new(addresses[1].parse()?, addresses[3].parse()?);
+            anyhow::ensure!(
+                source.is_ipv4() == (protocol == "TCP4")
+                    && destination.is_ipv4() == (protocol == "TCP4"),
+                "PROXY v1 addresses do not match the protocol '{protocol}'"
+            );
+
+            Ok(Some(source))
+        }
+        otherwise => anyhow::bail!("PROXY v1 protocol not supported: '{otherwise:?}'"),
+    }
+}
+
+fn parse_v2(
+    version_command: u8,
+    family: u8,
+    addresses: &[u8],
+) -> anyhow::Result<Option<std::net::SocketAddr>> {
+    anyhow::ensure!(
+        version_command >> 4 == 0x2,
+        "PROXY v2 header with an unknown version '{}'",
+        version_command >> 4
+    );
+
+    match version_command & 0x0F {
+        // LOCAL: the connection was established by the proxy itself
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => (),
+        otherwise => anyhow::bail!("PROXY v2 command not supported: '{otherwise}'"),
+    }
+
+    match family {
+        // TCP over IPv4
+        0x11 => {
+            anyhow::ensure!(addresses.len() >= 12, "PROXY v2 IPv4 addresses truncated");
+            Ok(Some(std::net::SocketAddr::new(
+                std::net::IpAddr::V4(std::net::Ipv4Addr::new(
+                    addresses[0],
+                    addresses[1],
+                    addresses[2],
+                    addresses[3],
+                )),
+                u16::from_be_bytes([addresses[8], addresses[9]]),
+            )))
+        }
+        // TCP over IPv6
+        0x21 => {
+            anyhow::ensure!(addresses.len() >= 36, "PROXY v2 IPv6 addresses truncated");
+            Ok(Some(std::net::SocketAddr::new(
+                std::net::IpAddr::V6(std::net::Ipv6Addr::from(<[u8; 16]>::try_from(
+                    &addresses[..16],
+                )?)),
+                u16::from_be_bytes([addresses[32], addresses[33]]),
+            )))
+        }
+        // UNSPEC, unix sockets and datagrams: the address of the connection is kept
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_header;
+
+    async fn read(mut input: &[u8]) -> (Option<std::net::SocketAddr>, &[u8]) {
+        let addr = read_header(&mut input).await.unwrap();
+        (addr, input)
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        assert_eq!(
+            read(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 25\r\nEHLO foo\r\n").await,
+            (
+                Some("192.168.0.1:56324".parse().unwrap()),
+                b"EHLO foo\r\n".as_slice()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        assert_eq!(
+            read(b"PROXY TCP6 2001:db8::1 2001:db8::2 4242 587\r\n").await,
+            (Some("[2001:db8::1]:4242".parse().unwrap()), b"".as_slice())
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        assert_eq!(
+            read(b"PROXY UNKNOWN\r\nQUIT\r\n").await,
+            (None, b"QUIT\r\n".as_slice())
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_invalid() {
+        for input in [
+            b"EHLO foo\r\n".as_slice(),
+            b"PROXY TCP4 192.168.0.1 192.168.0.11 56324\r\n",
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 4242 587\r\n",
+            b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 25",
+            b"PROXY UDP4 192.168.0.1 192.168.0.11 56324 25\r\n",
+            &[b'a'; 200],
+        ] {
+            assert!(read_header(&mut &input[..]).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn v2_tcp4() {
+        let input = [
+            b"\r\n\r\n\0\r\nQUIT\n".as_slice(),
+            &[0x21, 0x11, 0, 12],
+            &[192, 168, 0, 1, 192, 168, 0, 11, 0xDC, 0x04, 0, 25],
+            b"EHLO foo\r\n",
+        ]
+        .concat();
+
+        assert_eq!(
+            read(&input).await,
+            (
+                Some("192.168.0.1:56324".parse().unwrap()),
+                b"EHLO foo\r\n".as_slice()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_tcp6_with_tlv() {
+        let input = [
+            b"\r\n\r\n\0\r\nQUIT\n".as_slice(),
+            &[0x21, 0x21, 0, 36 + 4],
+            &[0x20, 0x01, 0x0D, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            &[0x20, 0x01, 0x0D, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2],
+            &[0x10, 0x92, 0x02, 0x4B],
+            // PP2_TYPE_NOOP
+            &[0x04, 0, 1, 0],
+        ]
+        .concat();
+
+        assert_eq!(
+            read(&input).await,
+            (Some("[2001:db8::1]:4242".parse().unwrap()), b"".as_slice())
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let input = [
+            b"\r\n\r\n\0\r\nQUIT\n".as_slice(),
+            &[0x20, 0x00, 0, 0],
+            b"QUIT\r\n",
+        ]
+        .concat();
+
+        assert_eq!(read(&input).await, (None, b"QUIT\r\n".as_slice()));
+    }
+
+    #[tokio::test]
+    async fn v2_invalid() {
+        for header in [
+            // version 1 in binary format
+            [0x11, 0x11, 0, 12],
+            // unknown command
+            [0x22, 0x11, 0, 12],
+            // truncated addresses
+            [0x21, 0x11, 0, 4],
+        ] {
+            let input = [
+                b"\r\n\r\n\0\r\nQUIT\n".as_slice(),
+                &header,
+                &[192, 168, 0, 1, 192, 168, 0, 11, 0xDC, 0x04, 0, 25],
+            ]
+            .concat();
+
+            assert!(read_header(&mut input.as_slice()).await.is_err());
+        }
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+//! Message body written to a spool file while it is received
+//!
+//! The body is stored with "\n" line endings, and must be valid utf8. A body received
+//! with `BODY=BINARYMIME` is stored as received.
+
+use vsmtp_common::{mail_context::Body, re::anyhow};
+
+/// The spool file of the message being received, removed if the transaction
+/// is not completed.
+pub struct Spool {
+    path: std::path::PathBuf,
+    writer: Option<std::io::BufWriter<std::fs::File>>,
+    /// the chunks are written as received, see [`Body::Binary`].
+    binary: bool,
+    /// the end of the last chunk, not written yet: a "\r" possibly followed
+    /// by a "\n", or an incomplete utf8 sequence.
+    pending: Vec<u8>,
+}
+
+impl Spool {
+    /// Create the spool file of the message `message_id`, `binary` if it has been
+    /// announced with `BODY=BINARYMIME`.
+    ///
+    /// # Errors
+    ///
+    /// * the spool directory or the file cannot be created
+    pub fn create(
+        queues_dirpath: &std::path::Path,
+        message_id: &str,
+        binary: bool,
+    ) -> anyhow::Result<Self> {
+        let path = Body::spool_path(queues_dirpath, message_id);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)?;
+
+        Ok(Self {
+            path,
+            writer: Some(std::io::BufWriter::new(file)),
+            binary,
+            pending: vec![],
+        })
+    }
+
+    fn writer(&mut self) -> std::io::Result<&mut std::io::BufWriter<std::fs::File>> {
+        self.writer.as_mut().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Other, "spool file already closed")
+        })
+    }
+
+    /// Append a line received with the DATA command.
+    ///
+    /// # Errors
+    ///
+    /// * failed to write to the spool file
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let writer = self.writer()?;
+        std::io::Write::write_all(writer, line.as_bytes())?;
+        std::io::Write::write_all(writer, b"\n")
+    }
+
+    /// Append a chunk received with the BDAT command, "\r\n" are converted to "\n".
+    /// The chunks of a binary body are written as received.
+    ///
+    /// # Errors
+    ///
+    /// * the content is not valid utf8, with [`std::io::ErrorKind::InvalidData`]
+    /// * failed to write to the spool file
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        if self.binary {
+            return std::io::Write::write_all(self.writer()?, chunk);
+        }
+
+        let mut buffer = std::mem::take(&mut self.pending);
+        buffer.extend_from_slice(chunk);
+
+        let valid_up_to = match std::str::from_utf8(&buffer) {
+            Ok(_) => buffer.len(),
+            // the sequence can be completed by the next chunk.
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(error) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, error)),
+        };
+
+        let (text, incomplete) = buffer.split_at(valid_up_to);
+        let text = std::str::from_utf8(text)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+
+        // a trailing "\r" could be followed by a "\n" in the next chunk.
+        let (text, carriage_return) = text
+            .strip_suffix('\r')
+            .map_or((text, false), |text| (text, true));
+
+        std::io::Write::write_all(self.writer()?, text.replace("\r\n", "\n").as_bytes())?;
+
+        if carriage_return {
+            self.pending.push(b'\r');
+        }
+        self.pending.extend_from_slice(incomplete);
+
+        Ok(())
+    }
+
+    /// Flush and close the spool file, returning the body of the message.
+    ///
+    /// # Errors
+    ///
+    /// * the content ends with an incomplete utf8 sequence, with [`std::io::ErrorKind::InvalidData`]
+    /// * failed to write to the spool file
+    pub fn finish(mut self) -> std::io::Result<Body> {
+        let pending = std::mem::take(&mut self.pending);
+        let mut writer = self.writer.take().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Other, "spool file already closed")
+        })?;
+
+        if pending != b"\r" && !pending.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "incomplete utf8 sequence at the end of the message",
+            ));
+        }
+        std::io::Write::write_all(&mut writer, &pending)?;
+        std::io::Write::flush(&mut writer)?;
+
+        let path = std::mem::take(&mut self.path);
+        Ok(if self.binary {
+            Body::Binary(path)
+        } else {
+            Body::Spooled(path)
+        })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        // the path is taken when the spool file is completed.
+        if !self.path.as_os_str().is_empty() {
+            self.writer = None;
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(body: &Body) -> String {
+        match body {
+            Body::Spooled(path) => std::fs::read_to_string(path).unwrap(),
+            _ => panic!("the body should be spooled"),
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let dirpath = std::path::Path::new("./tmp/spool");
+        let mut spool = Spool::create(dirpath, "lines", false).unwrap();
+        spool.write_line("subject: hello").unwrap();
+        spool.write_line("").unwrap();
+        spool.write_line("world").unwrap();
+
+        let body = spool.finish().unwrap();
+        assert_eq!(read(&body), "subject: hello\n\nworld\n");
+        std::fs::remove_file(Body::spool_path(dirpath, "lines")).unwrap();
+    }
+
+    #[test]
+    fn chunks() {
+        let dirpath = std::path::Path::new("./tmp/spool");
+        let mut spool = Spool::create(dirpath, "chunks", false).unwrap();
+        let content = "subject: 你好\r\n\r\nworld\r\n".as_bytes();
+
+        // splitting inside the "\r\n" and inside an utf8 sequence.
+        spool.write_chunk(&content[..10]).unwrap();
+        spool.write_chunk(&content[10..16]).unwrap();
+        spool.write_chunk(&content[16..17]).unwrap();
+        spool.write_chunk(&content[17..]).unwrap();
+
+        let body = spool.finish().unwrap();
+        assert_eq!(read(&body), "subject: 你好\n\nworld\n");
+        std::fs::remove_file(Body::spool_path(dirpath, "chunks")).unwrap();
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let dirpath = std::path::Path::new("./tmp/spool");
+
+        let mut spool = Spool::create(dirpath, "invalid", false).unwrap();
+        assert_eq!(
+            spool.write_chunk(b"hello \xff\r\n").unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        let mut spool = Spool::create(dirpath, "incomplete", false).unwrap();
+        spool.write_chunk(b"hello \xe4\xbd").unwrap();
+        assert_eq!(
+            spool.finish().unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn binary() {
+        let dirpath = std::path::Path::new("./tmp/spool");
+        let mut spool = Spool::create(dirpath, "binary", true).unwrap();
+        spool.write_chunk(b"subject: hello\r\n\r\n\xff").unwrap();
+        spool.write_chunk(b"\x00\r").unwrap();
+
+        let path = Body::spool_path(dirpath, "binary");
+        assert_eq!(spool.finish().unwrap(), Body::Binary(path.clone()));
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"subject: hello\r\n\r\n\xff\x00\r"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn removed_if_not_finished() {
+        let dirpath = std::path::Path::new("./tmp/spool");
+        let mut spool = Spool::create(dirpath, "dropped", false).unwrap();
+        spool.write_line("hello").unwrap();
+
+        assert!(Body::spool_path(dirpath, "dropped").exists());
+        drop(spool);
+        assert!(!Body::spool_path(dirpath, "dropped").exists());
+    }
+}
@@ -14,21 +14,23 @@
  * this program. If not, see https://www.gnu.org/licenses/.
  *
 */
-use super::connection::Connection;
+use super::connection::{Connection, ConnectionKind};
+use super::spool::Spool;
 use crate::log_channels;
 use vsmtp_common::{
     addr,
     auth::Mechanism,
     code::SMTPReplyCode,
+    dsn::{DsnMail, DsnRcpt},
     envelop::Envelop,
-    event::Event,
-    mail_context::{Body, ConnectionContext, MailContext, MessageMetadata, MAIL_CAPACITY},
+    event::{Event, ForwardedClient, MimeBodyType, XCLIENT_ATTRIBUTES, XFORWARD_ATTRIBUTES},
+    mail_context::{AuthCredentials, Body, ConnectionContext, MailContext, MessageMetadata},
     re::{anyhow, log},
     state::StateSMTP,
     status::{InfoPacket, Status},
     Address,
 };
-use vsmtp_config::{Config, TlsSecurityLevel};
+use vsmtp_config::Config;
 use vsmtp_rule_engine::{rule_engine::RuleEngine, rule_state::RuleState};
 const TIMEOUT_DEFAULT: u64 = 5 * 60 * 1000; // 5min
 
@@ -36,12 +38,21 @@ pub struct Transaction {
     state: StateSMTP,
     rule_state: RuleState,
     rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+    /// number of bytes received since the DATA or the first BDAT command
+    message_size: usize,
+    /// BODY parameter of the MAIL FROM command
+    body_type: Option<MimeBodyType>,
+    /// spool file of the message being received with DATA or BDAT
+    spool: Option<Spool>,
+    /// address and helo of the client replaced by XFORWARD, restored after the mail transaction
+    before_xforward: Option<(std::net::SocketAddr, String)>,
 }
 
 #[allow(clippy::module_name_repetitions)]
 pub enum TransactionResult {
     Nothing,
-    Mail(Box<MailContext>),
+    /// the mail received, and the helo of the session if XFORWARD replaced it for this mail
+    Mail(Box<MailContext>, Option<String>),
     TlsUpgrade,
     Authentication(String, Mechanism, Option<Vec<u8>>),
 }
@@ -52,7 +63,10 @@ enum ProcessedEvent {
     Reply(SMTPReplyCode),
     ChangeState(StateSMTP),
     ReplyChangeState(StateSMTP, SMTPReplyCode),
+    /// reply to the end of a message, for each recipient, see [`Connection::send_message_code`]
+    ReplyMessage(Option<StateSMTP>, Vec<SMTPReplyCode>),
     TransactionCompleted(Box<MailContext>),
+    ReceiveChunk(usize, bool),
 }
 
 impl Transaction {
@@ -60,7 +74,7 @@ impl Transaction {
         S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin,
     >(
         &mut self,
-        conn: &Connection<S>,
+        conn: &mut Connection<S>,
         client_message: &str,
     ) -> ProcessedEvent {
         log::trace!(
@@ -89,7 +103,7 @@ impl Transaction {
     #[allow(clippy::too_many_lines)]
     fn process_event<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
         &mut self,
-        conn: &Connection<S>,
+        conn: &mut Connection<S>,
         event: Event,
     ) -> ProcessedEvent {
         match (&self.state, event) {
@@ -98,28 +112,50 @@ impl Transaction {
             (_, Event::HelpCmd(_)) => ProcessedEvent::Reply(SMTPReplyCode::Help),
 
             (_, Event::RsetCmd) => {
-                {
-                    let state = self.rule_state.context();
-                    let mut ctx = state.write().unwrap();
-                    ctx.body = Body::Empty;
-                    ctx.metadata = None;
-                    ctx.envelop.rcpt.clear();
-                    ctx.envelop.mail_from = addr!("default@domain.com");
-                }
+                self.reset();
 
                 ProcessedEvent::ReplyChangeState(StateSMTP::Helo, SMTPReplyCode::Code250)
             }
 
-            (_, Event::ExpnCmd(_) | Event::VrfyCmd(_) /*| Event::PrivCmd*/) => {
+            (_, Event::VrfyCmd(_)) if !conn.config.server.smtp.enable_vrfy => {
+                ProcessedEvent::Reply(SMTPReplyCode::Code502unimplemented)
+            }
+
+            (_, Event::ExpnCmd(_)) if !conn.config.server.smtp.enable_expn => {
+                ProcessedEvent::Reply(SMTPReplyCode::Code502unimplemented)
+            }
+
+            (_, Event::VrfyCmd(user_or_mailbox)) => {
+                self.process_query(user_or_mailbox, &StateSMTP::Verify)
+            }
+
+            (_, Event::ExpnCmd(mailing_list)) => {
+                self.process_query(mailing_list, &StateSMTP::Expand)
+            }
+
+            (_, Event::EtrnCmd(_)) if !conn.config.server.smtp.enable_etrn => {
                 ProcessedEvent::Reply(SMTPReplyCode::Code502unimplemented)
             }
 
+            (StateSMTP::Helo, Event::EtrnCmd(node)) => self.process_etrn(conn, node),
+
             (_, Event::QuitCmd) => {
                 ProcessedEvent::ReplyChangeState(StateSMTP::Stop, SMTPReplyCode::Code221)
             }
 
+            // LMTP clients must use LHLO (rfc2033 section 4.1)
+            (_, Event::HeloCmd(_) | Event::EhloCmd(_))
+                if matches!(conn.kind, ConnectionKind::Lmtp) =>
+            {
+                ProcessedEvent::Reply(SMTPReplyCode::Code500)
+            }
+
+            (_, Event::LhloCmd(_)) if !matches!(conn.kind, ConnectionKind::Lmtp) => {
+                ProcessedEvent::Reply(SMTPReplyCode::Code500)
+            }
+
             (_, Event::HeloCmd(helo)) => {
-                self.set_helo(helo);
+                self.set_helo(conn.forwarded_helo.clone().unwrap_or(helo));
 
                 match self
                     .rule_engine
@@ -137,8 +173,8 @@ impl Transaction {
                 ProcessedEvent::Reply(SMTPReplyCode::Code502unimplemented)
             }
 
-            (_, Event::EhloCmd(helo)) => {
-                self.set_helo(helo);
+            (_, Event::EhloCmd(helo) | Event::LhloCmd(helo)) => {
+                self.set_helo(conn.forwarded_helo.clone().unwrap_or(helo));
 
                 match self
                     .rule_engine
@@ -148,17 +184,34 @@ impl Transaction {
                 {
                     Status::Info(packet) => Self::send_custom_code(&packet),
                     Status::Deny(packet) => Self::deny_with_custom_code(packet.as_ref()),
+                    _ => ProcessedEvent::ReplyChangeState(StateSMTP::Helo, Self::ehlo_reply(conn)),
+                }
+            }
+
+            (_, Event::XClientCmd(_) | Event::XForwardCmd(_)) if !conn.is_forwarder => {
+                ProcessedEvent::Reply(SMTPReplyCode::ForwardNotAllowed)
+            }
+
+            // the session starts again, the client must send EHLO.
+            (StateSMTP::Connect | StateSMTP::Helo, Event::XClientCmd(client)) => {
+                match self.xclient(conn, client) {
+                    Status::Info(packet) => ProcessedEvent::ReplyChangeState(
+                        StateSMTP::Connect,
+                        SMTPReplyCode::Custom(packet.to_string()),
+                    ),
+                    Status::Deny(packet) => Self::deny_with_custom_code(packet.as_ref()),
                     _ => ProcessedEvent::ReplyChangeState(
-                        StateSMTP::Helo,
-                        if conn.is_secured {
-                            SMTPReplyCode::Code250SecuredEsmtp
-                        } else {
-                            SMTPReplyCode::Code250PlainEsmtp
-                        },
+                        StateSMTP::Connect,
+                        SMTPReplyCode::Greetings,
                     ),
                 }
             }
 
+            (StateSMTP::Connect | StateSMTP::Helo, Event::XForwardCmd(client)) => {
+                self.xforward(client);
+                ProcessedEvent::Reply(SMTPReplyCode::Code250)
+            }
+
             (StateSMTP::Helo | StateSMTP::Connect, Event::StartTls)
                 if conn.config.server.tls.is_none() =>
             {
@@ -180,36 +233,45 @@ impl Transaction {
                 ProcessedEvent::ChangeState(StateSMTP::Authentication(mechanism, initial_response))
             }
 
-            (StateSMTP::Helo, Event::MailCmd(..))
-                if !conn.is_secured
-                    && conn
-                        .config
-                        .server
-                        .tls
-                        .as_ref()
-                        .map(|smtps| smtps.security_level)
-                        == Some(TlsSecurityLevel::Encrypt) =>
-            {
+            (StateSMTP::Helo, Event::MailCmd(..)) if !conn.is_secured && conn.tls_required => {
                 ProcessedEvent::Reply(SMTPReplyCode::Code530)
             }
 
             (StateSMTP::Helo, Event::MailCmd(..))
-                if !conn.is_authenticated
-                    && conn
-                        .config
-                        .server
-                        .smtp
-                        .auth
-                        .as_ref()
-                        .map_or(false, |auth| auth.must_be_authenticated) =>
+                if !conn.is_authenticated && conn.auth_required =>
             {
                 ProcessedEvent::Reply(SMTPReplyCode::AuthRequired)
             }
 
-            (StateSMTP::Helo, Event::MailCmd(mail_from, _body_bit_mime, _auth_mailbox)) => {
-                // TODO: store in envelop _body_bit_mime & _auth_mailbox
-                // TODO: handle : mail_from can be "<>""
-                self.set_mail_from(mail_from.unwrap(), conn);
+            (StateSMTP::Helo, Event::MailCmd(_, _, _, Some(size), _, _))
+                if size > conn.config.server.smtp.message_size_max =>
+            {
+                ProcessedEvent::Reply(SMTPReplyCode::Code552MessageSizeExceeded)
+            }
+
+            // non-ascii addresses require the SMTPUTF8 extension (rfc6531 section 3.5)
+            (StateSMTP::Helo, Event::MailCmd(Some(mail_from), _, _, _, _, false))
+                if mail_from.is_utf8() =>
+            {
+                ProcessedEvent::Reply(SMTPReplyCode::Code553NonAsciiAddress)
+            }
+
+            (StateSMTP::Helo, Event::MailCmd(..))
+                if !conn
+                    .rate_limiter
+                    .as_ref()
+                    .map_or(true, |limiter| limiter.message(conn.client_addr.ip())) =>
+            {
+                ProcessedEvent::Reply(SMTPReplyCode::MessageRateExceeded)
+            }
+
+            (
+                StateSMTP::Helo,
+                Event::MailCmd(mail_from, body_bit_mime, _auth_mailbox, _size, dsn, smtputf8),
+            ) => {
+                // TODO: store in envelop _auth_mailbox
+                self.set_mail_from(mail_from, dsn, smtputf8, conn);
+                self.body_type = body_bit_mime;
 
                 match self
                     .rule_engine
@@ -226,8 +288,24 @@ impl Transaction {
                 }
             }
 
-            (StateSMTP::MailFrom | StateSMTP::RcptTo, Event::RcptCmd(rcpt_to)) => {
-                self.set_rcpt_to(rcpt_to);
+            (StateSMTP::MailFrom | StateSMTP::RcptTo, Event::RcptCmd(rcpt_to, _))
+                if rcpt_to.is_utf8()
+                    && !self.rule_state.context().read().unwrap().envelop.smtputf8 =>
+            {
+                ProcessedEvent::Reply(SMTPReplyCode::Code553NonAsciiAddress)
+            }
+
+            (StateSMTP::MailFrom | StateSMTP::RcptTo, Event::RcptCmd(..))
+                if !conn
+                    .rate_limiter
+                    .as_ref()
+                    .map_or(true, |limiter| limiter.recipient(conn.client_addr.ip())) =>
+            {
+                ProcessedEvent::Reply(SMTPReplyCode::RcptRateExceeded)
+            }
+
+            (StateSMTP::MailFrom | StateSMTP::RcptTo, Event::RcptCmd(rcpt_to, dsn)) => {
+                self.set_rcpt_to(rcpt_to, dsn);
 
                 match self
                     .rule_engine
@@ -251,67 +329,343 @@ impl Transaction {
                 }
             }
 
+            // binary content must be sent with BDAT (rfc3030 section 3)
+            (StateSMTP::RcptTo, Event::DataCmd) if self.body_type == Some(MimeBodyType::Binary) => {
+                ProcessedEvent::Reply(SMTPReplyCode::BadSequence)
+            }
+
             (StateSMTP::RcptTo, Event::DataCmd) => {
-                self.rule_state.context().write().unwrap().body =
-                    Body::Raw(String::with_capacity(MAIL_CAPACITY));
-                ProcessedEvent::ReplyChangeState(StateSMTP::Data, SMTPReplyCode::Code354)
+                self.message_size = 0;
+                match self.create_spool(conn) {
+                    Ok(spool) => {
+                        self.spool = Some(spool);
+                        ProcessedEvent::ReplyChangeState(StateSMTP::Data, SMTPReplyCode::Code354)
+                    }
+                    Err(error) => {
+                        log::error!(
+                            target: log_channels::TRANSACTION,
+                            "failed to create the spool file: {error:?}"
+                        );
+                        ProcessedEvent::Reply(SMTPReplyCode::Code451)
+                    }
+                }
             }
 
             (StateSMTP::Data, Event::DataLine(line)) => {
-                let state = self.rule_state.context();
-                if let Body::Raw(body) = &mut state.write().unwrap().body {
-                    body.push_str(&line);
-                    body.push('\n');
+                // the line is counted with its "\r\n"
+                self.message_size += line.len() + 2;
+
+                // NOTE: the client cannot be interrupted during the DATA command,
+                //       the remaining lines are read but not stored.
+                if self.message_size <= conn.config.server.smtp.message_size_max {
+                    if let Some(Err(error)) =
+                        self.spool.as_mut().map(|spool| spool.write_line(&line))
+                    {
+                        log::error!(
+                            target: log_channels::TRANSACTION,
+                            "failed to write to the spool file: {error}"
+                        );
+                        self.spool = None;
+                    }
                 }
                 ProcessedEvent::Nothing
             }
 
-            (StateSMTP::Data, Event::DataEnd) => {
-                match self
-                    .rule_engine
-                    .read()
-                    .unwrap()
-                    .run_when(&mut self.rule_state, &StateSMTP::PreQ)
-                {
-                    Status::Info(packet) => return Self::send_custom_code(&packet),
-                    Status::Deny(packet) => return Self::deny_with_custom_code(packet.as_ref()),
-                    _ => {}
+            (StateSMTP::Data, Event::DataEnd)
+                if self.message_size > conn.config.server.smtp.message_size_max =>
+            {
+                log::warn!(
+                    target: log_channels::TRANSACTION,
+                    "message size exceeded: {} > {}",
+                    self.message_size,
+                    conn.config.server.smtp.message_size_max
+                );
+                let rcpt_count = self.rcpt_count();
+                self.reset();
+
+                ProcessedEvent::ReplyMessage(
+                    Some(StateSMTP::Helo),
+                    vec![SMTPReplyCode::Code552MessageSizeExceeded; rcpt_count],
+                )
+            }
+
+            (StateSMTP::Data, Event::DataEnd) => match self.spool.take().map(Spool::finish) {
+                Some(Ok(body)) => {
+                    self.rule_state.context().write().unwrap().body = body;
+                    self.complete(conn)
                 }
+                error => {
+                    if let Some(Err(error)) = error {
+                        log::error!(
+                            target: log_channels::TRANSACTION,
+                            "failed to write to the spool file: {error}"
+                        );
+                    }
+                    let rcpt_count = self.rcpt_count();
+                    self.reset();
 
-                let state = self.rule_state.context();
-                let mut ctx = state.write().unwrap();
-
-                // NOTE: the "skipped" field is updated by the rule engine internal state,
-                //       which does result in hard to read code, but it was the fastest way
-                //       to propagate the force accept to the server.
-                //       Alternatives:
-                //        - return ProcessedEvent::CompletedMimeSkipped
-                //        - set body to Body::ParsingFailed or Body::ParsingSkipped.
-                if let Some(metadata) = &mut ctx.metadata {
-                    metadata.skipped = self.rule_state.skipped().cloned();
+                    ProcessedEvent::ReplyMessage(
+                        Some(StateSMTP::Helo),
+                        vec![SMTPReplyCode::Code451; rcpt_count],
+                    )
                 }
+            },
+
+            // the chunk must be read even if the command is not valid in this state,
+            // see `Transaction::receive_chunk`
+            (_, Event::BdatCmd(size, last)) => ProcessedEvent::ReceiveChunk(size, last),
+
+            _ => ProcessedEvent::Reply(SMTPReplyCode::BadSequence),
+        }
+    }
+
+    fn complete<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        &mut self,
+        conn: &Connection<S>,
+    ) -> ProcessedEvent {
+        let spool_path = match &self.rule_state.context().read().unwrap().body {
+            Body::Spooled(path) | Body::Binary(path) => Some(path.clone()),
+            _ => None,
+        };
+
+        match self
+            .rule_engine
+            .read()
+            .unwrap()
+            .run_when(&mut self.rule_state, &StateSMTP::PreQ)
+        {
+            Status::Info(packet) => {
+                Self::discard_spool(spool_path);
+                return ProcessedEvent::ReplyMessage(
+                    None,
+                    vec![SMTPReplyCode::Custom(packet.to_string()); self.rcpt_count()],
+                );
+            }
+            Status::Deny(packet) => {
+                Self::discard_spool(spool_path);
+                return ProcessedEvent::ReplyMessage(
+                    Some(StateSMTP::Stop),
+                    vec![
+                        packet.map_or(SMTPReplyCode::Code554, |packet| {
+                            SMTPReplyCode::Custom(packet.to_string())
+                        });
+                        self.rcpt_count()
+                    ],
+                );
+            }
+            _ => {}
+        }
+
+        let state = self.rule_state.context();
+        let mut ctx = state.write().unwrap();
+
+        // the body could have been loaded and modified by the preq rules,
+        // it is written back to the spool file so it is not stored with the envelop.
+        if let Some(path) = spool_path {
+            if let Err(error) = ctx.body.spool(path) {
+                log::warn!(
+                    target: log_channels::TRANSACTION,
+                    "the body is kept in memory: {error:?}"
+                );
+            }
+        }
+
+        // NOTE: the "skipped" field is updated by the rule engine internal state,
+        //       which does result in hard to read code, but it was the fastest way
+        //       to propagate the force accept to the server.
+        //       Alternatives:
+        //        - return ProcessedEvent::CompletedMimeSkipped
+        //        - set body to Body::ParsingFailed or Body::ParsingSkipped.
+        if let Some(metadata) = &mut ctx.metadata {
+            metadata.skipped = self.rule_state.skipped().cloned();
+        }
+
+        let mut output = MailContext {
+            connection: ConnectionContext {
+                timestamp: std::time::SystemTime::now(),
+                credentials: conn.credentials.clone(),
+                is_authenticated: conn.is_authenticated,
+                is_secured: conn.is_secured,
+                server_name: conn.server_name.clone(),
+                query: None,
+                peer_credentials: conn.peer_credentials,
+                early_talker: conn.is_early_talker,
+                client_certificate: conn.client_certificate.clone(),
+            },
+            client_addr: ctx.client_addr,
+            envelop: Envelop::default(),
+            body: Body::Empty,
+            metadata: None,
+        };
+
+        std::mem::swap(&mut *ctx, &mut output);
+
+        ProcessedEvent::TransactionCompleted(Box::new(output))
+    }
+
+    /// read the chunk following a BDAT command, and append it to the message
+    async fn receive_chunk<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        &mut self,
+        conn: &mut Connection<S>,
+        size: usize,
+        last: bool,
+    ) -> anyhow::Result<ProcessedEvent> {
+        let timeout = get_timeout_for_state(&conn.config, &StateSMTP::Data);
+
+        match self.state {
+            StateSMTP::RcptTo => {
+                self.message_size = 0;
+                match self.create_spool(conn) {
+                    Ok(spool) => self.spool = Some(spool),
+                    Err(error) => {
+                        log::error!(
+                            target: log_channels::TRANSACTION,
+                            "failed to create the spool file: {error:?}"
+                        );
+                        conn.skip_chunk(size, timeout).await?;
+                        return Ok(ProcessedEvent::Reply(SMTPReplyCode::Code451));
+                    }
+                }
+            }
+            StateSMTP::Chunking => {}
+            _ => {
+                conn.skip_chunk(size, timeout).await?;
+                return Ok(ProcessedEvent::Reply(SMTPReplyCode::BadSequence));
+            }
+        }
+
+        // the size is given by the client, the sum can overflow
+        match self.message_size.checked_add(size) {
+            Some(message_size) if message_size <= conn.config.server.smtp.message_size_max => {
+                self.message_size = message_size;
+            }
+            _ => {
+                log::warn!(
+                    target: log_channels::TRANSACTION,
+                    "message size exceeded: {} + {} > {}",
+                    self.message_size,
+                    size,
+                    conn.config.server.smtp.message_size_max
+                );
+                conn.skip_chunk(size, timeout).await?;
+                let rcpt_count = if last { self.rcpt_count() } else { 1 };
+                self.reset();
+
+                return Ok(ProcessedEvent::ReplyMessage(
+                    Some(StateSMTP::Helo),
+                    vec![SMTPReplyCode::Code552MessageSizeExceeded; rcpt_count],
+                ));
+            }
+        }
+
+        let chunk = conn.read_chunk(size, timeout).await?;
+
+        let not_found = || std::io::Error::new(std::io::ErrorKind::Other, "spool file not found");
 
-                let mut output = MailContext {
-                    connection: ConnectionContext {
-                        timestamp: std::time::SystemTime::now(),
-                        credentials: None,
-                        is_authenticated: conn.is_authenticated,
-                        is_secured: conn.is_secured,
-                        server_name: conn.server_name.clone(),
-                    },
-                    client_addr: ctx.client_addr,
-                    envelop: Envelop::default(),
-                    body: Body::Empty,
-                    metadata: None,
+        let written = self
+            .spool
+            .as_mut()
+            .map_or_else(|| Err(not_found()), |spool| spool.write_chunk(&chunk));
+
+        if written.is_ok() && !last {
+            return Ok(ProcessedEvent::ReplyChangeState(
+                StateSMTP::Chunking,
+                SMTPReplyCode::Code250,
+            ));
+        }
+
+        match written.and_then(|()| {
+            self.spool
+                .take()
+                .map_or_else(|| Err(not_found()), Spool::finish)
+        }) {
+            Ok(body) => {
+                // same format as the body received with the DATA command
+                self.rule_state.context().write().unwrap().body = body;
+                Ok(self.complete(conn))
+            }
+            Err(error) => {
+                let code = if error.kind() == std::io::ErrorKind::InvalidData {
+                    log::warn!(
+                        target: log_channels::TRANSACTION,
+                        "message received with BDAT is not valid utf8: {}",
+                        error
+                    );
+                    SMTPReplyCode::Code554InvalidMessageContent
+                } else {
+                    log::error!(
+                        target: log_channels::TRANSACTION,
+                        "failed to write to the spool file: {}",
+                        error
+                    );
+                    SMTPReplyCode::Code451
                 };
+                let rcpt_count = if last { self.rcpt_count() } else { 1 };
+                self.reset();
 
-                std::mem::swap(&mut *ctx, &mut output);
+                Ok(ProcessedEvent::ReplyMessage(
+                    Some(StateSMTP::Helo),
+                    vec![code; rcpt_count],
+                ))
+            }
+        }
+    }
 
-                ProcessedEvent::TransactionCompleted(Box::new(output))
+    fn create_spool<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        &self,
+        conn: &Connection<S>,
+    ) -> anyhow::Result<Spool> {
+        let message_id = self
+            .rule_state
+            .context()
+            .read()
+            .unwrap()
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.message_id.clone())
+            .ok_or_else(|| anyhow::anyhow!("message metadata not found"))?;
+
+        Spool::create(
+            &conn.config.server.queues.dirpath,
+            &message_id,
+            self.body_type == Some(MimeBodyType::Binary),
+        )
+    }
+
+    /// remove the spool file of a message that will not be queued.
+    fn discard_spool(spool_path: Option<std::path::PathBuf>) {
+        if let Some(path) = spool_path {
+            if let Err(error) = std::fs::remove_file(&path) {
+                log::warn!(
+                    target: log_channels::TRANSACTION,
+                    "failed to remove the spool file '{}': {error}",
+                    path.display()
+                );
             }
+        }
+    }
 
-            _ => ProcessedEvent::Reply(SMTPReplyCode::BadSequence),
+    fn rcpt_count(&self) -> usize {
+        self.rule_state.context().read().unwrap().envelop.rcpt.len()
+    }
+
+    fn reset(&mut self) {
+        self.message_size = 0;
+        self.body_type = None;
+        self.spool = None;
+
+        let state = self.rule_state.context();
+        let mut ctx = state.write().unwrap();
+        if let Some((client_addr, helo)) = self.before_xforward.take() {
+            ctx.client_addr = client_addr;
+            ctx.envelop.helo = helo;
         }
+        ctx.body = Body::Empty;
+        ctx.metadata = None;
+        ctx.envelop.rcpt.clear();
+        ctx.envelop.mail_from = addr!("default@domain.com");
+        ctx.envelop.smtputf8 = false;
+        ctx.envelop.null_reverse_path = false;
     }
 
     fn set_connect<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
@@ -329,18 +683,28 @@ impl Transaction {
         let state = self.rule_state.context();
         let mut ctx = state.write().unwrap();
 
+        if let Some((client_addr, _)) = self.before_xforward.take() {
+            ctx.client_addr = client_addr;
+        }
         ctx.body = Body::Empty;
         ctx.metadata = None;
         ctx.envelop = Envelop {
             helo,
             mail_from: addr!("no@address.net"),
             rcpt: vec![],
+            dsn: DsnMail::default(),
+            smtputf8: false,
+            null_reverse_path: false,
         };
     }
 
+    /// `mail_from` is `None` for the null reverse-path `<>` (rfc5321 section 4.5.5),
+    /// the sender is then identified by the name given in HELO/EHLO.
     fn set_mail_from<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
         &mut self,
-        mail_from: Address,
+        mail_from: Option<Address>,
+        dsn: DsnMail,
+        smtputf8: bool,
         conn: &Connection<S>,
     ) {
         let now = std::time::SystemTime::now();
@@ -349,7 +713,14 @@ impl Transaction {
         let mut ctx = state.write().unwrap();
         ctx.body = Body::Empty;
         ctx.envelop.rcpt.clear();
-        ctx.envelop.mail_from = mail_from;
+        ctx.envelop.null_reverse_path = mail_from.is_none();
+        ctx.envelop.mail_from = match mail_from {
+            Some(mail_from) => mail_from,
+            None => Address::try_from(format!("mailer-daemon@{}", ctx.envelop.helo))
+                .unwrap_or_else(|_| addr!("mailer-daemon@localhost")),
+        };
+        ctx.envelop.dsn = dsn;
+        ctx.envelop.smtputf8 = smtputf8;
         ctx.metadata = Some(MessageMetadata {
             timestamp: now,
             // TODO: find a way to handle SystemTime failure.
@@ -377,14 +748,187 @@ impl Transaction {
         );
     }
 
-    fn set_rcpt_to(&mut self, rcpt_to: Address) {
+    fn set_rcpt_to(&mut self, rcpt_to: Address, dsn: DsnRcpt) {
         self.rule_state
             .context()
             .write()
             .unwrap()
             .envelop
             .rcpt
-            .push(vsmtp_common::rcpt::Rcpt::new(rcpt_to));
+            .push(vsmtp_common::rcpt::Rcpt {
+                dsn,
+                ..vsmtp_common::rcpt::Rcpt::new(rcpt_to)
+            });
+    }
+
+    /// VRFY and EXPN do not change the state of the transaction,
+    /// the answer is left to the rules, and defaults to 252 (rfc5321 section 3.5.3)
+    fn process_query(&mut self, query: String, stage: &StateSMTP) -> ProcessedEvent {
+        match self.run_query(query, stage) {
+            Status::Info(packet) => Self::send_custom_code(&packet),
+            Status::Deny(packet) => Self::deny_with_custom_code(packet.as_ref()),
+            _ => ProcessedEvent::Reply(SMTPReplyCode::Code252),
+        }
+    }
+
+    /// run the rules of `stage` with `query` available in `ctx().query`.
+    fn run_query(&mut self, query: String, stage: &StateSMTP) -> Status {
+        self.rule_state.context().write().unwrap().connection.query = Some(query);
+
+        let status = self
+            .rule_engine
+            .read()
+            .unwrap()
+            .run_when(&mut self.rule_state, stage);
+
+        self.rule_state.context().write().unwrap().connection.query = None;
+
+        status
+    }
+
+    /// the deferred messages of `node` are delivered only if the rules accept it,
+    /// the delivery process is asked to flush them without waiting for the next retry.
+    fn process_etrn<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        &mut self,
+        conn: &Connection<S>,
+        node: String,
+    ) -> ProcessedEvent {
+        match self.run_query(node.clone(), &StateSMTP::Etrn) {
+            Status::Info(packet) => Self::send_custom_code(&packet),
+            Status::Deny(packet) => Self::deny_with_custom_code(packet.as_ref()),
+            Status::Accept | Status::Faccept => match conn
+                .deferred_flush
+                .as_ref()
+                .map(|deferred_flush| deferred_flush.try_send(node.clone()))
+            {
+                Some(Ok(())) => {
+                    log::info!(
+                        target: log_channels::TRANSACTION,
+                        "delivery of the deferred messages of '{node}' requested by {}",
+                        conn.client_addr
+                    );
+                    ProcessedEvent::Reply(SMTPReplyCode::EtrnStarted)
+                }
+                Some(Err(error)) => {
+                    log::warn!(
+                        target: log_channels::TRANSACTION,
+                        "cannot request the delivery of the deferred messages of '{node}': {error}"
+                    );
+                    ProcessedEvent::Reply(SMTPReplyCode::EtrnUnavailable)
+                }
+                None => ProcessedEvent::Reply(SMTPReplyCode::EtrnUnavailable),
+            },
+            _ => ProcessedEvent::Reply(SMTPReplyCode::EtrnNotAllowed),
+        }
+    }
+
+    /// the trusted proxies are told they can send XCLIENT and XFORWARD.
+    fn ehlo_reply<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        conn: &Connection<S>,
+    ) -> SMTPReplyCode {
+        let code = if conn.is_secured {
+            SMTPReplyCode::Code250SecuredEsmtp
+        } else {
+            SMTPReplyCode::Code250PlainEsmtp
+        };
+
+        if !conn.is_forwarder {
+            return code;
+        }
+
+        SMTPReplyCode::Custom(format!(
+            "{}XCLIENT {}\r\nXFORWARD {}\r\n",
+            conn.config.server.smtp.codes[&code],
+            XCLIENT_ATTRIBUTES.join(" "),
+            XFORWARD_ATTRIBUTES.join(" ")
+        ))
+    }
+
+    /// replace the information of the client by the one given by a trusted proxy (XFORWARD)
+    /// for the next mail transaction only, the rules are not run again.
+    fn xforward(&mut self, client: ForwardedClient) {
+        let state = self.rule_state.context();
+        let mut ctx = state.write().unwrap();
+
+        if self.before_xforward.is_none() {
+            self.before_xforward = Some((ctx.client_addr, ctx.envelop.helo.clone()));
+        }
+        if let Some(addr) = client.addr {
+            ctx.client_addr.set_ip(addr);
+        }
+        if let Some(port) = client.port {
+            ctx.client_addr.set_port(port);
+        }
+        if let Some(helo) = client.helo {
+            ctx.envelop.helo = helo;
+        }
+
+        log::info!(
+            target: log_channels::TRANSACTION,
+            "transaction forwarded by a trusted proxy: addr={}, helo={:?}",
+            ctx.client_addr,
+            ctx.envelop.helo
+        );
+    }
+
+    /// replace the information of the client by the one given by a trusted proxy (XCLIENT)
+    /// for the rest of the session, then run the connect stage again as if the original
+    /// client was connected.
+    fn xclient<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        &mut self,
+        conn: &mut Connection<S>,
+        client: ForwardedClient,
+    ) -> Status {
+        if let Some(addr) = client.addr {
+            conn.client_addr.set_ip(addr);
+        }
+        if let Some(port) = client.port {
+            conn.client_addr.set_port(port);
+        }
+        if let Some(helo) = client.helo {
+            conn.forwarded_helo = Some(helo);
+        }
+        if let Some(login) = client.login {
+            conn.credentials = Some(AuthCredentials::Query { authid: login });
+            conn.is_authenticated = true;
+        }
+
+        log::info!(
+            target: log_channels::TRANSACTION,
+            "client forwarded by a trusted proxy: addr={}, helo={:?}",
+            conn.client_addr,
+            conn.forwarded_helo
+        );
+
+        // the result of the previous rules does not apply to the original client.
+        self.before_xforward = None;
+        self.rule_state = RuleState::with_connection(
+            &conn.config,
+            &*self.rule_engine.read().unwrap(),
+            Self::connection_context(conn),
+        );
+        self.set_connect(conn);
+
+        self.rule_engine
+            .read()
+            .unwrap()
+            .run_when(&mut self.rule_state, &StateSMTP::Connect)
+    }
+
+    fn connection_context<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        conn: &Connection<S>,
+    ) -> ConnectionContext {
+        ConnectionContext {
+            timestamp: conn.timestamp,
+            credentials: conn.credentials.clone(),
+            is_authenticated: conn.is_authenticated,
+            is_secured: conn.is_secured,
+            server_name: conn.server_name.clone(),
+            query: None,
+            peer_credentials: conn.peer_credentials,
+            early_talker: conn.is_early_talker,
+            client_certificate: conn.client_certificate.clone(),
+        }
     }
 
     fn send_custom_code(packet: &InfoPacket) -> ProcessedEvent {
@@ -417,13 +961,7 @@ impl Transaction {
             &*rule_engine
                 .read()
                 .map_err(|_| anyhow::anyhow!("failed to lock rule engine"))?,
-            ConnectionContext {
-                timestamp: conn.timestamp,
-                credentials: None,
-                is_authenticated: conn.is_authenticated,
-                is_secured: conn.is_secured,
-                server_name: conn.server_name.clone(),
-            },
+            Self::connection_context(conn),
         );
 
         let mut transaction = Self {
@@ -434,13 +972,18 @@ impl Transaction {
             },
             rule_state,
             rule_engine,
+            message_size: 0,
+            body_type: None,
+            spool: None,
+            before_xforward: None,
         };
 
+        // the address may have been given by a trusted proxy in a previous transaction.
+        transaction.set_connect(conn);
+
         if let Some(helo) = helo_domain.as_ref().cloned() {
             transaction.set_helo(helo);
         } else {
-            transaction.set_connect(conn);
-
             let status = transaction
                 .rule_engine
                 .read()
@@ -458,6 +1001,7 @@ impl Transaction {
                         None => SMTPReplyCode::Code554,
                     })
                     .await?;
+                    conn.flush().await?;
 
                     anyhow::bail!(
                         "connection at '{}' has been denied when connecting.",
@@ -471,7 +1015,7 @@ impl Transaction {
         let mut read_timeout = get_timeout_for_state(&conn.config, &transaction.state);
 
         loop {
-            match transaction.state {
+            let line = match transaction.state {
                 StateSMTP::NegotiationTLS => return Ok(TransactionResult::TlsUpgrade),
                 StateSMTP::Authentication(mechanism, initial_response) => {
                     return Ok(TransactionResult::Authentication(
@@ -488,28 +1032,53 @@ impl Transaction {
                     ));
                 }
                 StateSMTP::Stop => {
+                    conn.flush().await?;
                     conn.is_alive = false;
                     return Ok(TransactionResult::Nothing);
                 }
-                _ => match conn.read(read_timeout).await {
-                    Ok(Some(client_message)) => {
+                // a message being transferred is received entirely, even if the server is shutting down.
+                StateSMTP::Data | StateSMTP::Chunking => conn.read(read_timeout).await,
+                _ => conn.read_command(read_timeout).await,
+            };
+
+            match line {
+                Ok(Some(client_message)) => {
+                    let processed_event =
                         match transaction.parse_and_apply_and_get_reply(conn, &client_message) {
-                            ProcessedEvent::Nothing => {}
-                            ProcessedEvent::Reply(reply_to_send) => {
-                                conn.send_code(reply_to_send).await?;
+                            ProcessedEvent::ReceiveChunk(size, last) => {
+                                transaction.receive_chunk(conn, size, last).await?
                             }
-                            ProcessedEvent::ChangeState(new_state) => {
-                                log::info!(
-                                    target: log_channels::TRANSACTION,
-                                    "================ STATE: /{:?}/ => /{:?}/",
-                                    transaction.state,
-                                    new_state
-                                );
-                                transaction.state = new_state;
-                                read_timeout =
-                                    get_timeout_for_state(&conn.config, &transaction.state);
-                            }
-                            ProcessedEvent::ReplyChangeState(new_state, reply_to_send) => {
+                            otherwise => otherwise,
+                        };
+
+                    match processed_event {
+                        ProcessedEvent::Nothing => {}
+                        ProcessedEvent::Reply(reply_to_send) => {
+                            conn.send_code(reply_to_send).await?;
+                        }
+                        ProcessedEvent::ChangeState(new_state) => {
+                            log::info!(
+                                target: log_channels::TRANSACTION,
+                                "================ STATE: /{:?}/ => /{:?}/",
+                                transaction.state,
+                                new_state
+                            );
+                            transaction.state = new_state;
+                            read_timeout = get_timeout_for_state(&conn.config, &transaction.state);
+                        }
+                        ProcessedEvent::ReplyChangeState(new_state, reply_to_send) => {
+                            log::info!(
+                                target: log_channels::TRANSACTION,
+                                "================ STATE: /{:?}/ => /{:?}/",
+                                transaction.state,
+                                new_state
+                            );
+                            transaction.state = new_state;
+                            read_timeout = get_timeout_for_state(&conn.config, &transaction.state);
+                            conn.send_code(reply_to_send).await?;
+                        }
+                        ProcessedEvent::ReplyMessage(new_state, replies) => {
+                            if let Some(new_state) = new_state {
                                 log::info!(
                                     target: log_channels::TRANSACTION,
                                     "================ STATE: /{:?}/ => /{:?}/",
@@ -519,25 +1088,36 @@ impl Transaction {
                                 transaction.state = new_state;
                                 read_timeout =
                                     get_timeout_for_state(&conn.config, &transaction.state);
-                                conn.send_code(reply_to_send).await?;
-                            }
-                            ProcessedEvent::TransactionCompleted(mail) => {
-                                return Ok(TransactionResult::Mail(mail));
                             }
+                            conn.send_message_code(replies).await?;
+                        }
+                        ProcessedEvent::TransactionCompleted(mail) => {
+                            return Ok(TransactionResult::Mail(
+                                mail,
+                                transaction.before_xforward.map(|(_, helo)| helo),
+                            ));
+                        }
+                        ProcessedEvent::ReceiveChunk(..) => {
+                            unreachable!("chunk are read before processing the event")
                         }
                     }
-                    Ok(None) => {
-                        log::info!(target: log_channels::TRANSACTION, "eof");
-                        transaction.state = StateSMTP::Stop;
-                    }
-                    Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
-                        conn.send_code(SMTPReplyCode::Code451Timeout).await?;
-                        anyhow::bail!(e)
-                    }
-                    Err(e) => {
-                        anyhow::bail!(e)
-                    }
-                },
+                }
+                Ok(None) => {
+                    log::info!(target: log_channels::TRANSACTION, "eof");
+                    transaction.state = StateSMTP::Stop;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    conn.send_code(SMTPReplyCode::Code451Timeout).await?;
+                    anyhow::bail!(e)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    log::info!(target: log_channels::TRANSACTION, "{}", e);
+                    conn.send_code(SMTPReplyCode::ShuttingDown).await?;
+                    transaction.state = StateSMTP::Stop;
+                }
+                Err(e) => {
+                    anyhow::bail!(e)
+                }
             }
         }
     }
@@ -552,7 +1132,7 @@ fn get_timeout_for_state(
         StateSMTP::Helo => config.server.smtp.timeout_client.helo,
         StateSMTP::MailFrom => config.server.smtp.timeout_client.mail_from,
         StateSMTP::RcptTo => config.server.smtp.timeout_client.rcpt_to,
-        StateSMTP::Data => config.server.smtp.timeout_client.data,
+        StateSMTP::Data | StateSMTP::Chunking => config.server.smtp.timeout_client.data,
         _ => std::time::Duration::from_millis(TIMEOUT_DEFAULT),
     }
 }
@@ -0,0 +1,399 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{auth, log_channels, rate_limit::RateLimiter};
+use trust_dns_resolver::TokioAsyncResolver;
+use vsmtp_common::re::{
+    anyhow::{self, Context},
+    log, vsmtp_rsasl,
+};
+use vsmtp_config::{build_resolvers, get_rustls_config, re::rustls, Config, ConfigServerListener};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+
+/// The configuration, and everything built from it, used to serve a connection
+/// or to process a message.
+pub struct Snapshot {
+    /// configuration of the server
+    pub config: std::sync::Arc<Config>,
+    /// rules of `app.vsl.filepath`
+    pub rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+    /// tls settings of the server, if configured
+    pub tls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+    /// authentication backend, if configured
+    pub rsasl: Option<std::sync::Arc<tokio::sync::Mutex<auth::Backend>>>,
+    /// limits of the networks of clients, if configured
+    pub rate_limiter: Option<std::sync::Arc<RateLimiter>>,
+    /// resolvers of the server's domain and of the virtual domains, used by the delivery
+    pub resolvers: std::sync::Arc<std::collections::HashMap<String, TokioAsyncResolver>>,
+    /// rules of the listeners with their own entry point
+    listener_engines: std::collections::HashMap<
+        std::path::PathBuf,
+        std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+    >,
+}
+
+impl Snapshot {
+    /// Build the snapshot of `config`, `entry_points` are the vsl scripts of the listeners.
+    ///
+    /// # Errors
+    ///
+    /// * cannot initialize [rustls] config
+    /// * cannot initialize the [RuleEngine] of a listener
+    /// * cannot initialize the authentication backend
+    /// * cannot initialize the dns resolvers
+    pub fn new(
+        config: std::sync::Arc<Config>,
+        rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+        entry_points: &[std::path::PathBuf],
+    ) -> anyhow::Result<Self> {
+        // listeners sharing the same entry point share the same engine.
+        let mut listener_engines = std::collections::HashMap::new();
+        for vsl in entry_points {
+            if !listener_engines.contains_key(vsl) {
+                listener_engines.insert(
+                    vsl.clone(),
+                    std::sync::Arc::new(std::sync::RwLock::new(RuleEngine::new(
+                        &config,
+                        &Some(vsl.clone()),
+                    )?)),
+                );
+            }
+        }
+
+        Ok(Self {
+            tls_config: if let Some(smtps) = &config.server.tls {
+                Some(std::sync::Arc::new(get_rustls_config(
+                    smtps,
+                    &config.server.r#virtual,
+                )?))
+            } else {
+                None
+            },
+            rsasl: if config.server.smtp.auth.is_some() {
+                Some(std::sync::Arc::new(tokio::sync::Mutex::new({
+                    let mut rsasl =
+                        vsmtp_rsasl::SASL::new().map_err(|e| anyhow::anyhow!("{}", e))?;
+                    rsasl.install_callback::<auth::Callback>();
+                    rsasl.store(Box::new(config.clone()));
+                    rsasl
+                })))
+            } else {
+                None
+            },
+            rate_limiter: config
+                .server
+                .smtp
+                .rate_limit
+                .clone()
+                .map(|rate_limit| std::sync::Arc::new(RateLimiter::new(rate_limit))),
+            resolvers: std::sync::Arc::new(
+                build_resolvers(&config).context("could not initialize dns for delivery")?,
+            ),
+            config,
+            rule_engine,
+            listener_engines,
+        })
+    }
+
+    /// The rules of the connections accepted by `listener`.
+    #[must_use]
+    pub fn rule_engine_of(
+        &self,
+        listener: &ConfigServerListener,
+    ) -> std::sync::Arc<std::sync::RwLock<RuleEngine>> {
+        listener
+            .vsl
+            .as_ref()
+            .and_then(|vsl| self.listener_engines.get(vsl))
+            .unwrap_or(&self.rule_engine)
+            .clone()
+    }
+}
+
+/// Hold the current [`Snapshot`] of the server, and replace it on reload.
+///
+/// The connections and the messages keep the snapshot they started with,
+/// only the new ones use the reloaded configuration and rules.
+pub struct Reloader {
+    config_path: Option<std::path::PathBuf>,
+    entry_points: Vec<std::path::PathBuf>,
+    current: std::sync::RwLock<std::sync::Arc<Snapshot>>,
+}
+
+impl Reloader {
+    /// Create a reloader re-reading `config_path`, or reusing the current configuration if missing.
+    #[must_use]
+    pub fn new(
+        snapshot: Snapshot,
+        config_path: Option<std::path::PathBuf>,
+        entry_points: Vec<std::path::PathBuf>,
+    ) -> Self {
+        Self {
+            config_path,
+            entry_points,
+            current: std::sync::RwLock::new(std::sync::Arc::new(snapshot)),
+        }
+    }
+
+    /// The snapshot to use for a new connection or message.
+    ///
+    /// # Panics
+    ///
+    /// * the lock is poisoned
+    #[must_use]
+    pub fn current(&self) -> std::sync::Arc<Snapshot> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Parse the configuration and compile the rules again, then replace the current snapshot.
+    ///
+    /// The interfaces, system, queues and logs settings are kept from the current configuration,
+    /// they require a restart. The current snapshot is kept if anything fails.
+    ///
+    /// # Errors
+    ///
+    /// * cannot read or parse the configuration
+    /// * the rules do not compile
+    /// * see [`Snapshot::new`]
+    ///
+    /// # Panics
+    ///
+    /// * the lock is poisoned
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let current = self.current();
+
+        let config = match &self.config_path {
+            Some(path) => {
+                let mut config = std::fs::read_to_string(path)
+                    .with_context(|| format!("Cannot read file '{}'", path.display()))
+                    .and_then(|toml| Config::from_toml(&toml))
+                    .context("Cannot parse the configuration")?;
+
+                // those are used once, when the server starts, the current ones are kept.
+                if config.server.interfaces != current.config.server.interfaces
+                    || config.server.system != current.config.server.system
+                    || config.server.queues != current.config.server.queues
+                    || config.server.logs != current.config.server.logs
+                {
+                    log::warn!(
+                        target: log_channels::RUNTIME,
+                        "the changes of the interfaces, system, queues and logs settings require a restart, they are ignored"
+                    );
+                    config.server.interfaces = current.config.server.interfaces.clone();
+                    config.server.system = current.config.server.system.clone();
+                    config.server.queues = current.config.server.queues.clone();
+                    config.server.logs = current.config.server.logs.clone();
+                }
+                std::sync::Arc::new(config)
+            }
+            None => current.config.clone(),
+        };
+
+        let rule_engine = std::sync::Arc::new(std::sync::RwLock::new(
+            RuleEngine::new(&config, &config.app.vsl.filepath)
+                .context("Cannot compile the rules")?,
+        ));
+        let mut snapshot = Snapshot::new(config, rule_engine, &self.entry_points)?;
+
+        // the counters of the clients and the dns caches are kept if their settings did not change.
+        if snapshot.config.server.smtp.rate_limit == current.config.server.smtp.rate_limit {
+            snapshot.rate_limiter = current.rate_limiter.clone();
+        }
+        // the resolvers are indexed by the server's domain and the virtual domains.
+        if snapshot.config.server.domain == current.config.server.domain
+            && snapshot.config.server.dns == current.config.server.dns
+            && snapshot.config.server.r#virtual == current.config.server.r#virtual
+        {
+            snapshot.resolvers = current.resolvers.clone();
+        }
+
+        *self.current.write().unwrap() = std::sync::Arc::new(snapshot);
+        Ok(())
+    }
+
+    /// Reload each time the process receives SIGHUP.
+    ///
+    /// # Errors
+    ///
+    /// * cannot register the signal handler
+    pub async fn reload_on_sighup(self: std::sync::Arc<Self>) -> anyhow::Result<()> {
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .context("Cannot register the SIGHUP handler")?;
+
+        while hangup.recv().await.is_some() {
+            log::info!(target: log_channels::RUNTIME, "SIGHUP received, reloading");
+            match self.reload() {
+                Ok(()) => log::info!(target: log_channels::RUNTIME, "reloaded successfully"),
+                Err(error) => log::error!(
+                    target: log_channels::RUNTIME,
+                    "reload failed, the previous configuration and rules are kept: {error:?}"
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vsmtp_test::config;
+
+    fn reloader(config_path: Option<std::path::PathBuf>) -> Reloader {
+        let config = std::sync::Arc::new(config::local_test());
+        let rule_engine = std::sync::Arc::new(std::sync::RwLock::new(
+            RuleEngine::from_script(&config, "#{}").unwrap(),
+        ));
+        Reloader::new(
+            Snapshot::new(config, rule_engine, &[]).unwrap(),
+            config_path,
+            vec![],
+        )
+    }
+
+    #[test]
+    fn reload_swaps_the_state() {
+        let reloader = reloader(None);
+        let before = reloader.current();
+
+        reloader.reload().unwrap();
+
+        let after = reloader.current();
+        assert!(!std::sync::Arc::ptr_eq(&before, &after));
+        assert!(!std::sync::Arc::ptr_eq(
+            &before.rule_engine,
+            &after.rule_engine
+        ));
+        assert!(std::sync::Arc::ptr_eq(&before.resolvers, &after.resolvers));
+        assert_eq!(before.config, after.config);
+    }
+
+    #[test]
+    fn reload_rebuilds_the_changed_settings() {
+        let dir = std::path::PathBuf::from("./tmp/reload_rate_limit");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vsmtp.toml");
+        std::fs::write(
+            &path,
+            [
+                "version_requirement = \">=1.0.0\"\n",
+                "[server]\n",
+                "domain = \"reloaded.com\"\n",
+                "[server.system]\n",
+                "user = \"root\"\n",
+                "group = \"root\"\n",
+                "[server.smtp.rate_limit]\n",
+                "window = \"1m\"\n",
+            ]
+            .concat(),
+        )
+        .unwrap();
+
+        let reloader = reloader(Some(path));
+        let before = reloader.current();
+        assert!(before.rate_limiter.is_none());
+
+        reloader.reload().unwrap();
+        let after = reloader.current();
+        assert!(after.rate_limiter.is_some());
+        assert!(!std::sync::Arc::ptr_eq(&before.resolvers, &after.resolvers));
+        assert!(after.resolvers.contains_key("reloaded.com"));
+
+        // the counters of the clients are kept if the limits did not change.
+        reloader.reload().unwrap();
+        let again = reloader.current();
+        assert!(std::sync::Arc::ptr_eq(
+            after.rate_limiter.as_ref().unwrap(),
+            again.rate_limiter.as_ref().unwrap()
+        ));
+        assert!(std::sync::Arc::ptr_eq(&after.resolvers, &again.resolvers));
+    }
+
+    #[test]
+    fn reload_keeps_the_startup_settings() {
+        let dir = std::path::PathBuf::from("./tmp/reload_queues");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vsmtp.toml");
+        std::fs::write(
+            &path,
+            [
+                "version_requirement = \">=1.0.0\"\n",
+                "[server]\n",
+                "domain = \"reloaded.com\"\n",
+                "[server.system]\n",
+                "user = \"root\"\n",
+                "group = \"root\"\n",
+                "[server.queues]\n",
+                "dirpath = \"./tmp/reload_queues/spool\"\n",
+            ]
+            .concat(),
+        )
+        .unwrap();
+
+        let reloader = reloader(Some(path));
+        let before = reloader.current();
+
+        reloader.reload().unwrap();
+        let after = reloader.current();
+        assert_eq!(after.config.server.domain, "reloaded.com");
+        assert_eq!(after.config.server.queues, before.config.server.queues);
+        assert_ne!(
+            after.config.server.queues.dirpath,
+            std::path::PathBuf::from("./tmp/reload_queues/spool")
+        );
+        assert_eq!(after.config.server.system, before.config.server.system);
+    }
+
+    #[test]
+    fn failed_reload_keeps_the_state() {
+        let dir = std::path::PathBuf::from("./tmp/reload");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vsmtp.toml");
+        std::fs::write(&path, "this is not a configuration").unwrap();
+
+        let reloader = reloader(Some(path));
+        let before = reloader.current();
+
+        assert!(reloader.reload().is_err());
+        assert!(std::sync::Arc::ptr_eq(&before, &reloader.current()));
+    }
+
+    #[test]
+    fn invalid_rules_keep_the_state() {
+        let dir = std::path::PathBuf::from("./tmp/reload_vsl");
+        std::fs::create_dir_all(&dir).unwrap();
+        let vsl = dir.join("main.vsl");
+        std::fs::write(&vsl, "#{}").unwrap();
+
+        let mut config = config::local_test();
+        config.app.vsl.filepath = Some(vsl.clone());
+        let config = std::sync::Arc::new(config);
+        let rule_engine = std::sync::Arc::new(std::sync::RwLock::new(
+            RuleEngine::new(&config, &config.app.vsl.filepath).unwrap(),
+        ));
+        let reloader = Reloader::new(
+            Snapshot::new(config, rule_engine, &[]).unwrap(),
+            None,
+            vec![],
+        );
+        let before = reloader.current();
+
+        std::fs::write(&vsl, "#{ connect: [ rule \"broken\" || ").unwrap();
+
+        assert!(reloader.reload().is_err());
+        assert!(std::sync::Arc::ptr_eq(&before, &reloader.current()));
+    }
+}
@@ -17,7 +17,7 @@
 use crate::{
     log_channels,
     processes::{delivery, postq},
-    ProcessMessage, Server,
+    systemd, ProcessMessage, Reloader, Server, Snapshot, Socket,
 };
 use vsmtp_common::{
     queue::Queue,
@@ -26,7 +26,7 @@ use vsmtp_common::{
         log, strum,
     },
 };
-use vsmtp_config::Config;
+use vsmtp_config::{Config, ConfigServerListener};
 use vsmtp_rule_engine::rule_engine::RuleEngine;
 
 fn init_runtime<F: 'static>(
@@ -58,10 +58,10 @@ where
                     );
 
                     match timeout {
-                        Some(duration) => {
-                            tokio::time::timeout(duration, future).await.unwrap_err();
-                            anyhow::Ok(())
-                        }
+                        // the future may also end before, on shutdown.
+                        Some(duration) => tokio::time::timeout(duration, future)
+                            .await
+                            .unwrap_or(Ok(())),
                         None => future.await,
                     }
                 })
@@ -73,32 +73,56 @@ where
         .map_err(anyhow::Error::new)
 }
 
+/// Tell the service manager about the state of the server, if supervised.
+fn notify(state: &str) {
+    if let Err(error) = systemd::notify(state) {
+        log::warn!(
+            target: log_channels::RUNTIME,
+            "Cannot notify the service manager of '{state}': {error}"
+        );
+    }
+}
+
 /// Start the vSMTP server's runtime
 ///
+/// The configuration is read again from `config_path` when the server is reloaded (SIGHUP).
+///
 /// # Errors
 ///
 #[allow(clippy::module_name_repetitions)]
 pub fn start_runtime(
     config: Config,
-    sockets: (
-        std::net::TcpListener,
-        std::net::TcpListener,
-        std::net::TcpListener,
-    ),
+    config_path: Option<std::path::PathBuf>,
+    sockets: Vec<(ConfigServerListener, Socket)>,
     timeout: Option<std::time::Duration>,
 ) -> anyhow::Result<()> {
     <Queue as strum::IntoEnumIterator>::iter()
         .map(|q| vsmtp_common::queue_path!(create_if_missing => &config.server.queues.dirpath, q))
         .collect::<std::io::Result<Vec<_>>>()?;
 
+    // any local user can submit a message with `vsendmail`, but cannot list
+    // nor remove the messages of the others.
+    let pickup =
+        vsmtp_common::queue_path!(create_if_missing => &config.server.queues.dirpath, "pickup")?;
+    std::fs::set_permissions(
+        &pickup,
+        std::os::unix::fs::PermissionsExt::from_mode(0o1733),
+    )
+    .with_context(|| format!("cannot set the permissions of '{}'", pickup.display()))?;
+
     let mut error_handler = tokio::sync::mpsc::channel::<anyhow::Result<()>>(3);
 
-    let delivery_channel =
+    // the senders are moved to the tasks, the channels are closed once they are all dropped.
+    let (delivery_sender, delivery_receiver) =
         tokio::sync::mpsc::channel::<ProcessMessage>(config.server.queues.delivery.channel_size);
 
-    let working_channel =
+    let (working_sender, working_receiver) =
         tokio::sync::mpsc::channel::<ProcessMessage>(config.server.queues.working.channel_size);
 
+    // nodes given to the ETRN command, their deferred messages are delivered right away.
+    let (deferred_flush_sender, deferred_flush_receiver) =
+        tokio::sync::mpsc::channel::<String>(config.server.queues.delivery.channel_size);
+
     let rule_engine = std::sync::Arc::new(std::sync::RwLock::new(RuleEngine::new(
         &config,
         &config.app.vsl.filepath.clone(),
@@ -106,11 +130,18 @@ pub fn start_runtime(
 
     let config_arc = std::sync::Arc::new(config);
 
+    let entry_points = Server::entry_points(&sockets);
+    let reloader = std::sync::Arc::new(Reloader::new(
+        Snapshot::new(config_arc.clone(), rule_engine, &entry_points)?,
+        config_path,
+        entry_points,
+    ));
+
     let _tasks_delivery = init_runtime(
         error_handler.0.clone(),
         "vsmtp-delivery",
         config_arc.server.system.thread_pool.delivery,
-        delivery::start(config_arc.clone(), rule_engine.clone(), delivery_channel.1),
+        delivery::start(reloader.clone(), delivery_receiver, deferred_flush_receiver),
         timeout,
     )?;
 
@@ -118,12 +149,7 @@ pub fn start_runtime(
         error_handler.0.clone(),
         "vsmtp-processing",
         config_arc.server.system.thread_pool.processing,
-        postq::start(
-            config_arc.clone(),
-            rule_engine.clone(),
-            working_channel.1,
-            delivery_channel.0.clone(),
-        ),
+        postq::start(reloader.clone(), working_receiver, delivery_sender.clone()),
         timeout,
     )?;
 
@@ -132,23 +158,58 @@ pub fn start_runtime(
         "vsmtp-receiver",
         config_arc.server.system.thread_pool.receiver,
         async move {
-            Server::new(
-                config_arc.clone(),
-                sockets,
-                rule_engine.clone(),
-                working_channel.0.clone(),
-                delivery_channel.0.clone(),
-            )?
-            .listen_and_serve()
-            .await
+            let on_sighup = reloader.clone();
+            tokio::spawn(async move {
+                if let Err(error) = on_sighup.reload_on_sighup().await {
+                    log::error!(target: log_channels::RUNTIME, "{error:?}");
+                }
+            });
+
+            let (shutdown_sender, shutdown) = tokio::sync::watch::channel(false);
+            tokio::spawn(async move {
+                match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                    Ok(mut terminate) => {
+                        if terminate.recv().await.is_some() {
+                            log::warn!(target: log_channels::RUNTIME, "SIGTERM received, shutting down");
+                            notify("STOPPING=1");
+                            let _ = shutdown_sender.send(true);
+                        }
+                    }
+                    Err(error) => log::error!(
+                        target: log_channels::RUNTIME,
+                        "Cannot register the SIGTERM handler: {error}"
+                    ),
+                }
+            });
+
+            if let Some(interval) = systemd::watchdog_interval() {
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(interval);
+                    loop {
+                        interval.tick().await;
+                        notify("WATCHDOG=1");
+                    }
+                });
+            }
+
+            let server = Server::with_reloader(reloader, sockets, working_sender, delivery_sender)?
+                .with_shutdown(shutdown)
+                .with_deferred_flush(deferred_flush_sender);
+            notify("READY=1");
+            server.listen_and_serve().await
         },
         timeout,
-    );
+    )?;
 
-    error_handler
-        .1
-        .blocking_recv()
-        .ok_or_else(|| anyhow::anyhow!("Channel closed, but should not"))?
+    // on shutdown, the receiver ends first, then the processing and the delivery
+    // once their channel is closed and the messages they hold are handled.
+    for _ in 0..3 {
+        error_handler
+            .1
+            .blocking_recv()
+            .ok_or_else(|| anyhow::anyhow!("Channel closed, but should not"))??;
+    }
+    Ok(())
 
     // if the runtime panicked (receiver/processing/delivery)
     // .join() would return an error,
@@ -160,6 +221,7 @@ pub fn start_runtime(
 
 #[cfg(test)]
 mod tests {
+    use vsmtp_config::ListenerKind;
     use vsmtp_test::config;
 
     use super::*;
@@ -168,11 +230,20 @@ mod tests {
     fn basic() -> anyhow::Result<()> {
         start_runtime(
             config::local_test(),
-            (
-                std::net::TcpListener::bind("0.0.0.0:22001").unwrap(),
-                std::net::TcpListener::bind("0.0.0.0:22002").unwrap(),
-                std::net::TcpListener::bind("0.0.0.0:22003").unwrap(),
-            ),
+            None,
+            [
+                ("0.0.0.0:22001", ListenerKind::Plain),
+                ("0.0.0.0:22002", ListenerKind::Submission),
+                ("0.0.0.0:22003", ListenerKind::Tunneled),
+            ]
+            .into_iter()
+            .map(|(addr, kind)| {
+                (
+                    ConfigServerListener::new(vec![addr.parse().unwrap()], kind),
+                    Socket::Tcp(std::net::TcpListener::bind(addr).unwrap()),
+                )
+            })
+            .collect(),
             Some(std::time::Duration::from_millis(100)),
         )
     }
@@ -18,108 +18,201 @@ use crate::{
     auth,
     channel_message::ProcessMessage,
     log_channels,
-    receiver::{
-        handle_connection, {Connection, ConnectionKind},
-    },
+    rate_limit::RateLimiter,
+    receiver::{handle_connection, proxy_protocol::read_header, Connection},
+    reload::{Reloader, Snapshot},
 };
 use vsmtp_common::{
     code::SMTPReplyCode,
-    re::{anyhow, log, vsmtp_rsasl},
+    mail_context::PeerCredentials,
+    re::{anyhow, log},
 };
-use vsmtp_config::{get_rustls_config, re::rustls, Config};
+use vsmtp_config::{re::rustls, Config, ConfigServerListener, ListenerKind};
 use vsmtp_rule_engine::rule_engine::RuleEngine;
 
+/// a socket bound for a listener, see [`Server::new`]
+pub enum Socket {
+    /// TCP/IP socket
+    Tcp(std::net::TcpListener),
+    /// unix domain socket, for the local applications
+    Unix(std::os::unix::net::UnixListener),
+}
+
+enum AsyncSocket {
+    Tcp(tokio::net::TcpListener),
+    Unix(tokio::net::UnixListener),
+}
+
+/// a connection accepted on a [`Socket`]
+enum Accepted {
+    Tcp(tokio::net::TcpStream, std::net::SocketAddr),
+    Unix(tokio::net::UnixStream),
+}
+
+/// unix domain socket peers have no network address, the loopback address is used instead.
+const UNIX_PEER_ADDR: std::net::SocketAddr = std::net::SocketAddr::V4(std::net::SocketAddrV4::new(
+    std::net::Ipv4Addr::LOCALHOST,
+    0,
+));
+
+impl AsyncSocket {
+    fn from_std(socket: Socket) -> std::io::Result<Self> {
+        Ok(match socket {
+            Socket::Tcp(socket) => Self::Tcp(tokio::net::TcpListener::from_std(socket)?),
+            Socket::Unix(socket) => Self::Unix(tokio::net::UnixListener::from_std(socket)?),
+        })
+    }
+
+    async fn accept(&self) -> std::io::Result<Accepted> {
+        match self {
+            Self::Tcp(socket) => {
+                let (stream, client_addr) = socket.accept().await?;
+                Ok(Accepted::Tcp(stream, client_addr))
+            }
+            Self::Unix(socket) => {
+                let (stream, _) = socket.accept().await?;
+                Ok(Accepted::Unix(stream))
+            }
+        }
+    }
+}
+
+/// a bound socket, with the settings of the connections it accepts
+struct Listener {
+    socket: AsyncSocket,
+    settings: std::sync::Arc<ConfigServerListener>,
+}
+
 /// TCP/IP server
 pub struct Server {
-    listener: tokio::net::TcpListener,
-    listener_submission: tokio::net::TcpListener,
-    listener_submissions: tokio::net::TcpListener,
-    tls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
-    rsasl: Option<std::sync::Arc<tokio::sync::Mutex<auth::Backend>>>,
-    config: std::sync::Arc<Config>,
-    rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+    listeners: Vec<Listener>,
+    reloader: std::sync::Arc<Reloader>,
+    shutdown: Option<tokio::sync::watch::Receiver<bool>>,
+    deferred_flush: Option<tokio::sync::mpsc::Sender<String>>,
     working_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
     delivery_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
 }
 
 impl Server {
-    /// Create a server with the configuration provided, and the sockets already bound
+    /// Create a server with the configuration provided, and the sockets already bound,
+    /// each socket is associated to the listener it was bound for.
     ///
     /// # Errors
     ///
-    /// * `spool_dir` does not exist and failed to be created
-    /// * cannot convert sockets to [tokio::net::TcpListener]
-    /// * cannot initialize [rustls] config
+    /// * see [`Snapshot::new`]
+    /// * see [`Server::with_reloader`]
     pub fn new(
         config: std::sync::Arc<Config>,
-        sockets: (
-            std::net::TcpListener,
-            std::net::TcpListener,
-            std::net::TcpListener,
-        ),
+        sockets: Vec<(ConfigServerListener, Socket)>,
         rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
         working_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
         delivery_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
     ) -> anyhow::Result<Self> {
+        let entry_points = Self::entry_points(&sockets);
+        let snapshot = Snapshot::new(config, rule_engine, &entry_points)?;
+
+        Self::with_reloader(
+            std::sync::Arc::new(Reloader::new(snapshot, None, entry_points)),
+            sockets,
+            working_sender,
+            delivery_sender,
+        )
+    }
+
+    /// Create a server serving each new connection with the current snapshot of `reloader`.
+    ///
+    /// # Errors
+    ///
+    /// * `spool_dir` does not exist and failed to be created
+    /// * cannot convert sockets to [tokio::net::TcpListener]
+    pub fn with_reloader(
+        reloader: std::sync::Arc<Reloader>,
+        sockets: Vec<(ConfigServerListener, Socket)>,
+        working_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
+        delivery_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
+    ) -> anyhow::Result<Self> {
+        let config = reloader.current().config.clone();
+
         if !config.server.queues.dirpath.exists() {
             std::fs::DirBuilder::new()
                 .recursive(true)
                 .create(&config.server.queues.dirpath)?;
         }
 
-        if config.server.tls.is_none() {
+        if config.server.tls.is_none()
+            && sockets
+                .iter()
+                .any(|(listener, _)| listener.kind == ListenerKind::Tunneled)
+        {
             log::warn!(
                 "No TLS configuration provided, listening on submissions protocol (port 465) will cause issue"
             );
         }
 
         Ok(Self {
-            listener: tokio::net::TcpListener::from_std(sockets.0)?,
-            listener_submission: tokio::net::TcpListener::from_std(sockets.1)?,
-            listener_submissions: tokio::net::TcpListener::from_std(sockets.2)?,
-            tls_config: if let Some(smtps) = &config.server.tls {
-                Some(std::sync::Arc::new(get_rustls_config(
-                    smtps,
-                    &config.server.r#virtual,
-                )?))
-            } else {
-                None
-            },
-            rsasl: if config.server.smtp.auth.is_some() {
-                Some(std::sync::Arc::new(tokio::sync::Mutex::new({
-                    let mut rsasl =
-                        vsmtp_rsasl::SASL::new().map_err(|e| anyhow::anyhow!("{}", e))?;
-                    rsasl.install_callback::<auth::Callback>();
-                    rsasl.store(Box::new(config.clone()));
-                    rsasl
-                })))
-            } else {
-                None
-            },
-            config,
-            rule_engine,
+            listeners: sockets
+                .into_iter()
+                .map(|(settings, socket)| {
+                    Ok(Listener {
+                        socket: AsyncSocket::from_std(socket)?,
+                        settings: std::sync::Arc::new(settings),
+                    })
+                })
+                .collect::<std::io::Result<Vec<_>>>()?,
+            reloader,
+            shutdown: None,
+            deferred_flush: None,
             working_sender,
             delivery_sender,
         })
     }
 
-    /// Get the local address of the tcp listener
+    /// Stop the server when `shutdown` is set to `true`, see [`Server::listen_and_serve`].
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: tokio::sync::watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Send the nodes given to the ETRN command to `deferred_flush`,
+    /// the command is answered with an error if not set.
+    #[must_use]
+    pub fn with_deferred_flush(
+        mut self,
+        deferred_flush: tokio::sync::mpsc::Sender<String>,
+    ) -> Self {
+        self.deferred_flush = Some(deferred_flush);
+        self
+    }
+
+    /// The vsl entry points of the listeners of `sockets`, see [`Snapshot::new`].
+    #[must_use]
+    pub fn entry_points(sockets: &[(ConfigServerListener, Socket)]) -> Vec<std::path::PathBuf> {
+        sockets
+            .iter()
+            .filter_map(|(listener, _)| listener.vsl.clone())
+            .collect()
+    }
+
+    /// Get the local address of the tcp listeners
     pub fn addr(&self) -> Vec<std::net::SocketAddr> {
-        vec![
-            self.listener
-                .local_addr()
-                .expect("cannot retrieve local address"),
-            self.listener_submission
-                .local_addr()
-                .expect("cannot retrieve local address"),
-            self.listener_submissions
-                .local_addr()
-                .expect("cannot retrieve local address"),
-        ]
+        self.listeners
+            .iter()
+            .filter_map(|listener| match &listener.socket {
+                AsyncSocket::Tcp(socket) => {
+                    Some(socket.local_addr().expect("cannot retrieve local address"))
+                }
+                AsyncSocket::Unix(_) => None,
+            })
+            .collect()
     }
 
     /// Main loop of vSMTP's server
     ///
+    /// On shutdown (see [`Server::with_shutdown`]), the server stops accepting connections,
+    /// the sessions waiting for a command are closed with a 421 reply, and the messages
+    /// being received have `server.system.shutdown_grace_period` to complete.
+    ///
     /// # Errors
     ///
     /// * failed to initialize the [RuleEngine]
@@ -127,8 +220,7 @@ impl Server {
     /// # Panics
     ///
     /// * [tokio::spawn]
-    /// * [tokio::select]
-    pub async fn listen_and_serve(self) -> anyhow::Result<()> {
+    pub async fn listen_and_serve(mut self) -> anyhow::Result<()> {
         log::info!(
             target: log_channels::SERVER,
             "Listening on: {:?}",
@@ -136,65 +228,113 @@ impl Server {
         );
         let client_counter = std::sync::Arc::new(std::sync::atomic::AtomicI64::new(0));
 
-        loop {
-            let (mut stream, client_addr, kind) = tokio::select! {
-                Ok((stream, client_addr)) = self.listener.accept() => {
-                    (stream, client_addr, ConnectionKind::Opportunistic)
-                }
-                Ok((stream, client_addr)) = self.listener_submission.accept() => {
-                    (stream, client_addr, ConnectionKind::Submission)
-                }
-                Ok((stream, client_addr)) = self.listener_submissions.accept() => {
-                    (stream, client_addr, ConnectionKind::Tunneled)
+        // every listener accepts its connections in its own task,
+        // and forwards them to the main loop.
+        let (accepted_sender, mut accepted_receiver) = tokio::sync::mpsc::channel::<(
+            Accepted,
+            std::sync::Arc<ConfigServerListener>,
+        )>(self.listeners.len().max(1));
+
+        let mut accepting = Vec::with_capacity(self.listeners.len());
+        for listener in std::mem::take(&mut self.listeners) {
+            let accepted_sender = accepted_sender.clone();
+            accepting.push(tokio::spawn(async move {
+                loop {
+                    match listener.socket.accept().await {
+                        Ok(accepted) => {
+                            if accepted_sender
+                                .send((accepted, listener.settings.clone()))
+                                .await
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                        Err(error) => log::warn!(target: log_channels::SERVER, "{}", error),
+                    }
                 }
+            }));
+        }
+        drop(accepted_sender);
+
+        let mut shutdown = self.shutdown.clone();
+        loop {
+            let (accepted, listener) = tokio::select! {
+                accepted = accepted_receiver.recv() => match accepted {
+                    Some(accepted) => accepted,
+                    None => break,
+                },
+                () = Self::shutdown_requested(&mut shutdown) => break,
             };
-            stream.set_nodelay(true)?;
 
-            log::warn!(
-                target: log_channels::SERVER,
-                "Connection from: {:?}, {}",
-                kind,
-                client_addr
-            );
+            // the connection keeps this snapshot until its end, even if the server is reloaded.
+            let snapshot = self.reloader.current();
 
-            if self.config.server.client_count_max != -1
+            match &accepted {
+                Accepted::Tcp(_, client_addr) => log::warn!(
+                    target: log_channels::SERVER,
+                    "Connection from: {:?}, {}",
+                    listener.kind,
+                    client_addr
+                ),
+                Accepted::Unix(_) => log::warn!(
+                    target: log_channels::SERVER,
+                    "Connection from: {:?}, unix domain socket",
+                    listener.kind,
+                ),
+            }
+
+            if snapshot.config.server.client_count_max != -1
                 && client_counter.load(std::sync::atomic::Ordering::SeqCst)
-                    >= self.config.server.client_count_max
+                    >= snapshot.config.server.client_count_max
             {
-                if let Err(e) = tokio::io::AsyncWriteExt::write_all(
-                    &mut stream,
-                    self.config
-                        .server
-                        .smtp
-                        .codes
-                        .get(&SMTPReplyCode::ConnectionMaxReached)
-                        .unwrap()
-                        .as_bytes(),
-                )
-                .await
-                {
-                    log::warn!(target: log_channels::SERVER, "{}", e);
-                }
-
-                if let Err(e) = tokio::io::AsyncWriteExt::shutdown(&mut stream).await {
-                    log::warn!(target: log_channels::SERVER, "{}", e);
+                let code = SMTPReplyCode::ConnectionMaxReached;
+                match accepted {
+                    Accepted::Tcp(mut stream, _) => {
+                        Self::reject(&snapshot.config, &mut stream, code).await;
+                    }
+                    Accepted::Unix(mut stream) => {
+                        Self::reject(&snapshot.config, &mut stream, code).await;
+                    }
                 }
                 continue;
             }
 
             client_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
-            let session = Self::run_session(
-                stream,
-                client_addr,
-                kind,
-                self.config.clone(),
-                self.tls_config.clone(),
-                self.rsasl.clone(),
-                self.rule_engine.clone(),
-                self.working_sender.clone(),
-                self.delivery_sender.clone(),
-            );
+            let session: std::pin::Pin<
+                Box<dyn std::future::Future<Output = anyhow::Result<()>> + Send>,
+            > = match accepted {
+                Accepted::Tcp(stream, client_addr) => {
+                    stream.set_nodelay(true)?;
+                    Box::pin(Self::run_session(
+                        stream,
+                        client_addr,
+                        listener.clone(),
+                        snapshot.config.clone(),
+                        snapshot.tls_config.clone(),
+                        snapshot.rsasl.clone(),
+                        snapshot.rule_engine_of(&listener),
+                        snapshot.rate_limiter.clone(),
+                        self.shutdown.clone(),
+                        self.deferred_flush.clone(),
+                        self.working_sender.clone(),
+                        self.delivery_sender.clone(),
+                    ))
+                }
+                Accepted::Unix(stream) => Box::pin(Self::run_local_session(
+                    stream,
+                    listener.clone(),
+                    snapshot.config.clone(),
+                    snapshot.tls_config.clone(),
+                    snapshot.rsasl.clone(),
+                    snapshot.rule_engine_of(&listener),
+                    self.shutdown.clone(),
+                    self.deferred_flush.clone(),
+                    self.working_sender.clone(),
+                    self.delivery_sender.clone(),
+                )),
+            };
             let client_counter_copy = client_counter.clone();
             tokio::spawn(async move {
                 if let Err(e) = session.await {
@@ -204,31 +344,208 @@ impl Server {
                 client_counter_copy.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
             });
         }
+
+        if self.shutdown.is_some() {
+            for task in accepting {
+                task.abort();
+            }
+            Self::drain(&client_counter, &self.reloader.current().config).await;
+        }
+
+        Ok(())
+    }
+
+    /// Resolve when the server must shut down, never if it cannot be shut down.
+    async fn shutdown_requested(shutdown: &mut Option<tokio::sync::watch::Receiver<bool>>) {
+        match shutdown {
+            Some(receiver) => {
+                while !*receiver.borrow() {
+                    if receiver.changed().await.is_err() {
+                        std::future::pending::<()>().await;
+                    }
+                }
+            }
+            None => std::future::pending::<()>().await,
+        }
+    }
+
+    /// Wait for the sessions still running, at most `shutdown_grace_period`.
+    async fn drain(client_counter: &std::sync::atomic::AtomicI64, config: &Config) {
+        let grace_period = config.server.system.shutdown_grace_period;
+        log::warn!(
+            target: log_channels::SERVER,
+            "Shutting down, waiting at most {:?} for {} session(s)",
+            grace_period,
+            client_counter.load(std::sync::atomic::Ordering::SeqCst)
+        );
+
+        let drained = tokio::time::timeout(grace_period, async {
+            let mut interval = tokio::time::interval(std::time::Duration::from_millis(100));
+            while client_counter.load(std::sync::atomic::Ordering::SeqCst) != 0 {
+                interval.tick().await;
+            }
+        })
+        .await;
+
+        if drained.is_err() {
+            log::warn!(
+                target: log_channels::SERVER,
+                "Grace period elapsed, {} session(s) interrupted",
+                client_counter.load(std::sync::atomic::Ordering::SeqCst)
+            );
+        }
+    }
+
+    /// Send the reply `code` and close the connection
+    async fn reject<S: tokio::io::AsyncWrite + Unpin>(
+        config: &Config,
+        stream: &mut S,
+        code: SMTPReplyCode,
+    ) {
+        if let Err(e) = tokio::io::AsyncWriteExt::write_all(
+            stream,
+            config.server.smtp.codes.get(&code).unwrap().as_bytes(),
+        )
+        .await
+        {
+            log::warn!(target: log_channels::SERVER, "{}", e);
+        }
+
+        if let Err(e) = tokio::io::AsyncWriteExt::shutdown(stream).await {
+            log::warn!(target: log_channels::SERVER, "{}", e);
+        }
     }
 
     ///
     /// # Errors
     #[allow(clippy::too_many_arguments)]
-    pub async fn run_session(
-        stream: tokio::net::TcpStream,
+    pub async fn run_session<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        stream: S,
+        client_addr: std::net::SocketAddr,
+        listener: std::sync::Arc<ConfigServerListener>,
+        config: std::sync::Arc<Config>,
+        tls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+        rsasl: Option<std::sync::Arc<tokio::sync::Mutex<auth::Backend>>>,
+        rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+        rate_limiter: Option<std::sync::Arc<RateLimiter>>,
+        shutdown: Option<tokio::sync::watch::Receiver<bool>>,
+        deferred_flush: Option<tokio::sync::mpsc::Sender<String>>,
+        working_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
+        delivery_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
+    ) -> anyhow::Result<()> {
+        Self::serve(
+            stream,
+            client_addr,
+            None,
+            listener,
+            config,
+            tls_config,
+            rsasl,
+            rule_engine,
+            rate_limiter,
+            shutdown,
+            deferred_flush,
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    }
+
+    /// Run a session with a local process connected with a unix domain socket,
+    /// its credentials are read with `SO_PEERCRED`.
+    ///
+    /// # Errors
+    #[allow(clippy::too_many_arguments)]
+    pub async fn run_local_session(
+        stream: tokio::net::UnixStream,
+        listener: std::sync::Arc<ConfigServerListener>,
+        config: std::sync::Arc<Config>,
+        tls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
+        rsasl: Option<std::sync::Arc<tokio::sync::Mutex<auth::Backend>>>,
+        rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+        shutdown: Option<tokio::sync::watch::Receiver<bool>>,
+        deferred_flush: Option<tokio::sync::mpsc::Sender<String>>,
+        working_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
+        delivery_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
+    ) -> anyhow::Result<()> {
+        let credentials = stream.peer_cred()?;
+
+        Self::serve(
+            stream,
+            UNIX_PEER_ADDR,
+            Some(PeerCredentials {
+                uid: credentials.uid(),
+                gid: credentials.gid(),
+                pid: credentials.pid(),
+            }),
+            listener,
+            config,
+            tls_config,
+            rsasl,
+            rule_engine,
+            // the local processes are not limited.
+            None,
+            shutdown,
+            deferred_flush,
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn serve<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        mut stream: S,
         client_addr: std::net::SocketAddr,
-        kind: ConnectionKind,
+        peer_credentials: Option<PeerCredentials>,
+        listener: std::sync::Arc<ConfigServerListener>,
         config: std::sync::Arc<Config>,
         tls_config: Option<std::sync::Arc<rustls::ServerConfig>>,
         rsasl: Option<std::sync::Arc<tokio::sync::Mutex<auth::Backend>>>,
         rule_engine: std::sync::Arc<std::sync::RwLock<RuleEngine>>,
+        rate_limiter: Option<std::sync::Arc<RateLimiter>>,
+        shutdown: Option<tokio::sync::watch::Receiver<bool>>,
+        deferred_flush: Option<tokio::sync::mpsc::Sender<String>>,
         working_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
         delivery_sender: tokio::sync::mpsc::Sender<ProcessMessage>,
     ) -> anyhow::Result<()> {
         let begin = std::time::SystemTime::now();
+        let client_addr =
+            Self::proxied_client_addr(&mut stream, client_addr, &listener, &config).await?;
+
+        // the connection is counted until the end of the session.
+        let _connection = match &rate_limiter {
+            Some(limiter) => match limiter.connect(client_addr.ip()) {
+                Some(connection) => Some(connection),
+                None => {
+                    log::warn!(
+                        target: log_channels::SERVER,
+                        "Connection {} rejected, its network exceeded the connection rate",
+                        client_addr
+                    );
+                    Self::reject(&config, &mut stream, SMTPReplyCode::ConnectionRateExceeded).await;
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
         log::warn!(
             target: log_channels::SERVER,
             "Handling client: {}",
             client_addr
         );
 
+        let mut conn = Connection::new(listener.kind.into(), client_addr, config.clone(), stream);
+        // the requirements of the listener are added to the global ones.
+        conn.tls_required |= listener.tls_required;
+        conn.auth_required |= listener.auth_required;
+        conn.peer_credentials = peer_credentials;
+        conn.rate_limiter = rate_limiter;
+        conn.shutdown = shutdown;
+        conn.deferred_flush = deferred_flush;
+
         match handle_connection(
-            &mut Connection::new(kind, client_addr, config.clone(), stream),
+            &mut conn,
             tls_config,
             rsasl,
             rule_engine,
@@ -260,23 +577,61 @@ impl Server {
             }
         }
     }
+
+    /// If the listener is behind a proxy, read the PROXY header to get the address
+    /// of the real client, the header is only accepted from the trusted proxies.
+    async fn proxied_client_addr<S: tokio::io::AsyncRead + Send + Unpin>(
+        stream: &mut S,
+        peer_addr: std::net::SocketAddr,
+        listener: &ConfigServerListener,
+        config: &Config,
+    ) -> anyhow::Result<std::net::SocketAddr> {
+        let proxy = match &config.server.interfaces.proxy_protocol {
+            Some(proxy) if listener.proxy_protocol => proxy,
+            _ => return Ok(peer_addr),
+        };
+
+        anyhow::ensure!(
+            proxy
+                .trusted
+                .iter()
+                .any(|net| net.contains(&peer_addr.ip())),
+            "{peer_addr} is not a trusted proxy"
+        );
+
+        let client_addr = tokio::time::timeout(proxy.timeout, read_header(stream))
+            .await
+            .map_err(|_| anyhow::anyhow!("no PROXY header received from {peer_addr}"))??
+            .unwrap_or(peer_addr);
+
+        log::info!(
+            target: log_channels::SERVER,
+            "Connection from {} relayed by the proxy {}",
+            client_addr,
+            peer_addr
+        );
+
+        Ok(client_addr)
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use vsmtp_config::{ConfigServerListener, ListenerKind};
     use vsmtp_rule_engine::rule_engine::RuleEngine;
     use vsmtp_test::config;
 
-    use crate::{ProcessMessage, Server};
+    use crate::{ProcessMessage, Server, Socket};
 
     macro_rules! listen_with {
-        ($addr:expr, $addr_submission:expr, $addr_submissions:expr, $timeout:expr) => {{
+        ($listeners:expr, $timeout:expr) => {{
             let config = std::sync::Arc::new({
                 let mut config = config::local_test();
-                config.server.interfaces.addr = $addr;
-                config.server.interfaces.addr_submission = $addr_submission;
-                config.server.interfaces.addr_submissions = $addr_submissions;
+                config.server.interfaces.addr = vec![];
+                config.server.interfaces.addr_submission = vec![];
+                config.server.interfaces.addr_submissions = vec![];
+                config.server.interfaces.listeners = $listeners;
                 config
             });
 
@@ -290,13 +645,20 @@ mod tests {
 
             let s = Server::new(
                 config.clone(),
-                (
-                    std::net::TcpListener::bind(&config.server.interfaces.addr[..]).unwrap(),
-                    std::net::TcpListener::bind(&config.server.interfaces.addr_submission[..])
-                        .unwrap(),
-                    std::net::TcpListener::bind(&config.server.interfaces.addr_submissions[..])
-                        .unwrap(),
-                ),
+                config
+                    .server
+                    .interfaces
+                    .listeners()
+                    .into_iter()
+                    .flat_map(|listener| {
+                        listener.addr.clone().into_iter().map(move |addr| {
+                            (
+                                listener.clone(),
+                                Socket::Tcp(std::net::TcpListener::bind(addr).unwrap()),
+                            )
+                        })
+                    })
+                    .collect(),
                 std::sync::Arc::new(std::sync::RwLock::new(
                     RuleEngine::new(&config, &None).unwrap(),
                 )),
@@ -309,14 +671,13 @@ mod tests {
 
             assert_eq!(
                 s.addr(),
-                [
-                    config.server.interfaces.addr.clone(),
-                    config.server.interfaces.addr_submission.clone(),
-                    config.server.interfaces.addr_submissions.clone()
-                ]
-                .into_iter()
-                .flatten()
-                .collect::<Vec<_>>()
+                config
+                    .server
+                    .interfaces
+                    .listeners()
+                    .into_iter()
+                    .flat_map(|listener| listener.addr)
+                    .collect::<Vec<_>>()
             );
 
             tokio::time::timeout(
@@ -331,9 +692,50 @@ mod tests {
     #[tokio::test]
     async fn basic() {
         listen_with![
-            vec!["0.0.0.0:10026".parse().unwrap()],
-            vec!["0.0.0.0:10588".parse().unwrap()],
-            vec!["0.0.0.0:10466".parse().unwrap()],
+            vec![
+                ConfigServerListener::new(
+                    vec!["0.0.0.0:10026".parse().unwrap()],
+                    ListenerKind::Plain
+                ),
+                ConfigServerListener::new(
+                    vec!["0.0.0.0:10588".parse().unwrap()],
+                    ListenerKind::Submission
+                ),
+                ConfigServerListener::new(
+                    vec!["0.0.0.0:10466".parse().unwrap()],
+                    ListenerKind::Tunneled
+                ),
+            ],
+            10
+        ];
+    }
+
+    #[tokio::test]
+    async fn many_addresses() {
+        listen_with![
+            vec![
+                ConfigServerListener::new(
+                    vec![
+                        "127.0.0.1:10027".parse().unwrap(),
+                        "127.0.0.1:10028".parse().unwrap()
+                    ],
+                    ListenerKind::Plain
+                ),
+                ConfigServerListener {
+                    auth_required: true,
+                    ..ConfigServerListener::new(
+                        vec![
+                            "127.0.0.1:10589".parse().unwrap(),
+                            "127.0.0.1:10590".parse().unwrap()
+                        ],
+                        ListenerKind::Submission
+                    )
+                },
+                ConfigServerListener::new(
+                    vec!["127.0.0.1:10024".parse().unwrap()],
+                    ListenerKind::Lmtp
+                ),
+            ],
             10
         ];
     }
@@ -0,0 +1,330 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::Socket;
+use vsmtp_common::{
+    libc_abstraction::{set_cloexec, socket_family},
+    re::{
+        anyhow::{self, Context},
+        libc,
+    },
+};
+use vsmtp_config::{ConfigServerInterfaces, ConfigServerListener, ListenerKind};
+
+/// first file descriptor passed by the service manager
+const LISTEN_FDS_START: std::os::unix::io::RawFd = 3;
+
+/// The file descriptors passed by the service manager (socket activation), with their name.
+///
+/// Returns `None` if the process has not been started with sockets,
+/// the variables are removed from the environment so the child processes do not inherit them.
+///
+/// # Errors
+///
+/// * `LISTEN_FDS` or `LISTEN_PID` are invalid
+pub fn listen_fds() -> anyhow::Result<Option<Vec<(String, std::os::unix::io::RawFd)>>> {
+    let fds = parse_listen_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+        std::process::id(),
+    );
+
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+
+    fds
+}
+
+fn parse_listen_fds(
+    pid: Option<&str>,
+    fds: Option<&str>,
+    names: Option<&str>,
+    own_pid: u32,
+) -> anyhow::Result<Option<Vec<(String, std::os::unix::io::RawFd)>>> {
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(None),
+    };
+
+    // the sockets are passed to another process.
+    if pid.parse::<u32>().context("Invalid 'LISTEN_PID'")? != own_pid {
+        return Ok(None);
+    }
+
+    let count = fds
+        .parse::<std::os::unix::io::RawFd>()
+        .context("Invalid 'LISTEN_FDS'")?;
+    let mut names = names.map(|names| names.split(':'));
+
+    Ok(Some(
+        (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                (
+                    names
+                        .as_mut()
+                        .and_then(Iterator::next)
+                        .unwrap_or("unknown")
+                        .to_string(),
+                    fd,
+                )
+            })
+            .collect(),
+    ))
+}
+
+/// Take the ownership of the socket `fd` passed by the service manager, and find its listener.
+///
+/// The listener of `interfaces` bound to the same address or path is used,
+/// otherwise the listener is chosen by the name of the socket (`FileDescriptorName=` of the unit):
+/// `smtp`, `submission`, `submissions` or `lmtp`, with the default settings of the `addr*` fields.
+///
+/// # Errors
+///
+/// * `fd` is not a TCP/IP or unix domain socket
+/// * the name of the socket is unknown
+pub fn socket_of(
+    interfaces: &ConfigServerInterfaces,
+    name: &str,
+    fd: std::os::unix::io::RawFd,
+) -> anyhow::Result<(ConfigServerListener, Socket)> {
+    let family = socket_family(fd)?;
+    set_cloexec(fd)?;
+
+    let listeners = interfaces.listeners();
+    let (socket, listener) = match family {
+        libc::AF_INET | libc::AF_INET6 => {
+            let socket =
+                unsafe { <std::net::TcpListener as std::os::unix::io::FromRawFd>::from_raw_fd(fd) };
+            let addr = socket.local_addr()?;
+            let listener = listeners
+                .into_iter()
+                .find(|listener| listener.addr.contains(&addr));
+            (Socket::Tcp(socket), listener)
+        }
+        libc::AF_UNIX => {
+            let socket = unsafe {
+                <std::os::unix::net::UnixListener as std::os::unix::io::FromRawFd>::from_raw_fd(fd)
+            };
+            let addr = socket.local_addr()?;
+            let listener = listeners.into_iter().find(|listener| {
+                listener.unix.as_ref().map(|unix| unix.path.as_path()) == addr.as_pathname()
+            });
+            (Socket::Unix(socket), listener)
+        }
+        otherwise => anyhow::bail!("Socket '{name}' has an unsupported family: {otherwise}"),
+    };
+
+    match &socket {
+        Socket::Tcp(socket) => socket.set_nonblocking(true)?,
+        Socket::Unix(socket) => socket.set_nonblocking(true)?,
+    }
+
+    Ok((
+        listener.map_or_else(|| listener_by_name(interfaces, name), Ok)?,
+        socket,
+    ))
+}
+
+fn listener_by_name(
+    interfaces: &ConfigServerInterfaces,
+    name: &str,
+) -> anyhow::Result<ConfigServerListener> {
+    let proxy = interfaces.proxy_protocol.as_ref();
+    let (kind, proxy_protocol) = match name {
+        "smtp" => (ListenerKind::Plain, proxy.map_or(false, |p| p.addr)),
+        "submission" => (
+            ListenerKind::Submission,
+            proxy.map_or(false, |p| p.addr_submission),
+        ),
+        "submissions" => (
+            ListenerKind::Tunneled,
+            proxy.map_or(false, |p| p.addr_submissions),
+        ),
+        "lmtp" => (ListenerKind::Lmtp, proxy.map_or(false, |p| p.addr_lmtp)),
+        otherwise => anyhow::bail!(
+            "Socket '{otherwise}' matches no listener, expected a name among 'smtp', 'submission', 'submissions' and 'lmtp'"
+        ),
+    };
+
+    Ok(ConfigServerListener {
+        proxy_protocol,
+        ..ConfigServerListener::new(vec![], kind)
+    })
+}
+
+/// Send `state` to the service manager (`NOTIFY_SOCKET`), for example `READY=1`.
+///
+/// Returns `false` if the process is not supervised.
+///
+/// # Errors
+///
+/// * the notification socket cannot be reached
+pub fn notify(state: &str) -> std::io::Result<bool> {
+    match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => notify_to(std::path::Path::new(&path), state).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+fn notify_to(path: &std::path::Path, state: &str) -> std::io::Result<()> {
+    // abstract sockets (starting with '@') are used by the user instances of the service manager.
+    if path.to_string_lossy().starts_with('@') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "abstract notification sockets are not supported",
+        ));
+    }
+
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+/// The interval at which `WATCHDOG=1` must be sent, half of the delay expected by the service manager.
+#[must_use]
+pub fn watchdog_interval() -> Option<std::time::Duration> {
+    parse_watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn parse_watchdog(
+    usec: Option<&str>,
+    pid: Option<&str>,
+    own_pid: u32,
+) -> Option<std::time::Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+
+    match usec?.parse::<u64>().ok()? {
+        0 => None,
+        usec => Some(std::time::Duration::from_micros(usec / 2)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_fds_of_another_process() {
+        assert_eq!(parse_listen_fds(None, None, None, 42).unwrap(), None);
+        assert_eq!(
+            parse_listen_fds(Some("41"), Some("1"), None, 42).unwrap(),
+            None
+        );
+        assert!(parse_listen_fds(Some("foo"), Some("1"), None, 42).is_err());
+        assert!(parse_listen_fds(Some("42"), Some("foo"), None, 42).is_err());
+    }
+
+    #[test]
+    fn listen_fds_with_names() {
+        assert_eq!(
+            parse_listen_fds(Some("42"), Some("3"), Some("smtp:submission"), 42).unwrap(),
+            Some(vec![
+                ("smtp".to_string(), 3),
+                ("submission".to_string(), 4),
+                ("unknown".to_string(), 5)
+            ])
+        );
+    }
+
+    #[test]
+    fn socket_by_name() {
+        let interfaces = ConfigServerInterfaces {
+            addr: vec![],
+            addr_submission: vec![],
+            addr_submissions: vec![],
+            addr_lmtp: vec![],
+            listeners: vec![],
+            proxy_protocol: None,
+        };
+
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = std::os::unix::io::IntoRawFd::into_raw_fd(tcp);
+        let (listener, socket) = socket_of(&interfaces, "submissions", fd).unwrap();
+        assert_eq!(
+            listener,
+            ConfigServerListener::new(vec![], ListenerKind::Tunneled)
+        );
+        assert!(matches!(socket, Socket::Tcp(_)));
+
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = std::os::unix::io::IntoRawFd::into_raw_fd(tcp);
+        assert!(socket_of(&interfaces, "foo", fd).is_err());
+    }
+
+    #[test]
+    fn socket_by_address() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+
+        let interfaces = ConfigServerInterfaces {
+            addr: vec![],
+            addr_submission: vec![],
+            addr_submissions: vec![],
+            addr_lmtp: vec![],
+            listeners: vec![ConfigServerListener {
+                auth_required: true,
+                ..ConfigServerListener::new(vec![addr], ListenerKind::Submission)
+            }],
+            proxy_protocol: None,
+        };
+
+        let fd = std::os::unix::io::IntoRawFd::into_raw_fd(tcp);
+        let (listener, _) = socket_of(&interfaces, "unknown", fd).unwrap();
+        assert_eq!(listener, interfaces.listeners[0]);
+    }
+
+    #[test]
+    fn notify_fake_socket() {
+        let dir = std::path::PathBuf::from("./tmp/systemd");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let service_manager = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        notify_to(&path, "READY=1").unwrap();
+
+        let mut buffer = [0; 64];
+        let size = service_manager.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"READY=1");
+
+        assert!(notify_to(std::path::Path::new("@abstract"), "READY=1").is_err());
+    }
+
+    #[test]
+    fn watchdog() {
+        assert_eq!(parse_watchdog(None, None, 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+        assert_eq!(
+            parse_watchdog(Some("2000000"), None, 42),
+            Some(std::time::Duration::from_secs(1))
+        );
+        assert_eq!(
+            parse_watchdog(Some("2000000"), Some("42"), 42),
+            Some(std::time::Duration::from_secs(1))
+        );
+        assert_eq!(parse_watchdog(Some("2000000"), Some("41"), 42), None);
+    }
+}
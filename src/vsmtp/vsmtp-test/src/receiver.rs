@@ -83,8 +83,11 @@ impl OnMail for DefaultMailHandler {
         helo_domain: &mut Option<String>,
     ) -> anyhow::Result<()> {
         *helo_domain = Some(mail.envelop.helo.clone());
-        conn.send_code(vsmtp_common::code::SMTPReplyCode::Code250)
-            .await?;
+        conn.send_message_code(vec![
+            vsmtp_common::code::SMTPReplyCode::Code250;
+            mail.envelop.rcpt.len()
+        ])
+        .await?;
         Ok(())
     }
 }
@@ -100,6 +103,7 @@ impl OnMail for DefaultMailHandler {
 /// * argument provided are ill-formed
 pub async fn test_receiver_inner<M>(
     address: &str,
+    kind: ConnectionKind,
     mail_handler: &mut M,
     smtp_input: &[u8],
     expected_output: &[u8],
@@ -111,12 +115,7 @@ where
 {
     let mut written_data = Vec::new();
     let mut mock = Mock::new(smtp_input.to_vec(), &mut written_data);
-    let mut conn = Connection::new(
-        ConnectionKind::Opportunistic,
-        address.parse().unwrap(),
-        config.clone(),
-        &mut mock,
-    );
+    let mut conn = Connection::new(kind, address.parse().unwrap(), config.clone(), &mut mock);
 
     let rule_engine = std::sync::Arc::new(std::sync::RwLock::new(
         RuleEngine::new(&config, &config.app.vsl.filepath.clone())
@@ -125,9 +124,7 @@ where
     ));
 
     let result = handle_connection(&mut conn, None, rsasl, rule_engine, mail_handler).await;
-    tokio::io::AsyncWriteExt::flush(&mut conn.inner.inner)
-        .await
-        .unwrap();
+    conn.flush().await.unwrap();
 
     pretty_assertions::assert_eq!(
         std::str::from_utf8(expected_output),
@@ -167,6 +164,7 @@ macro_rules! test_receiver {
     (on_mail => $resolver:expr, with_config => $config:expr, $input:expr, $output:expr) => {
         $crate::receiver::test_receiver_inner(
             "127.0.0.1:0",
+            vsmtp_server::ConnectionKind::Opportunistic,
             $resolver,
             $input.as_bytes(),
             $output.as_bytes(),
@@ -175,6 +173,18 @@ macro_rules! test_receiver {
         )
         .await
     };
+    (with_kind => $kind:expr, with_config => $config:expr, $input:expr, $output:expr) => {
+        $crate::receiver::test_receiver_inner(
+            "127.0.0.1:0",
+            $kind,
+            &mut $crate::receiver::DefaultMailHandler {},
+            $input.as_bytes(),
+            $output.as_bytes(),
+            std::sync::Arc::new($config),
+            None,
+        )
+        .await
+    };
     (with_auth => $auth:expr, with_config => $config:expr, $input:expr, $output:expr) => {
         test_receiver! {
             with_auth => $auth,
@@ -187,6 +197,7 @@ macro_rules! test_receiver {
     (with_auth => $auth:expr, with_config => $config:expr, on_mail => $resolver:expr, $input:expr, $output:expr) => {
         $crate::receiver::test_receiver_inner(
             "127.0.0.1:0",
+            vsmtp_server::ConnectionKind::Opportunistic,
             $resolver,
             $input.as_bytes(),
             $output.as_bytes(),
@@ -19,11 +19,11 @@ use vsmtp_common::{
     auth::Mechanism,
     re::{anyhow, base64, strum, vsmtp_rsasl},
 };
-use vsmtp_config::Config;
+use vsmtp_config::{Config, ConfigServerListener, ListenerKind};
 use vsmtp_rule_engine::rule_engine::RuleEngine;
 use vsmtp_server::re::tokio;
 use vsmtp_server::Server;
-use vsmtp_server::{auth, ConnectionKind, ProcessMessage};
+use vsmtp_server::{auth, ProcessMessage};
 
 #[allow(clippy::too_many_lines)]
 async fn test_auth(
@@ -54,11 +54,14 @@ async fn test_auth(
         Server::run_session(
             client_stream,
             client_addr,
-            ConnectionKind::Opportunistic,
+            std::sync::Arc::new(ConfigServerListener::new(vec![], ListenerKind::Plain)),
             server_config,
             None,
             Some(rsasl),
             rule_engine,
+            None,
+            None,
+            None,
             working_sender,
             delivery_sender,
         )
@@ -158,6 +161,11 @@ async fn plain() {
             "250-testserver.com",
             "250-AUTH PLAIN LOGIN CRAM-MD5",
             "250-STARTTLS",
+            "250-PIPELINING",
+            "250-SIZE 20000000",
+            "250-CHUNKING",
+            "250-BINARYMIME",
+            "250-DSN",
             "250-8BITMIME",
             "250 SMTPUTF8",
             "235 2.7.0 Authentication succeeded",
@@ -186,6 +194,11 @@ async fn login() {
             "250-testserver.com",
             "250-AUTH PLAIN LOGIN CRAM-MD5",
             "250-STARTTLS",
+            "250-PIPELINING",
+            "250-SIZE 20000000",
+            "250-CHUNKING",
+            "250-BINARYMIME",
+            "250-DSN",
             "250-8BITMIME",
             "250 SMTPUTF8",
             "235 2.7.0 Authentication succeeded",
@@ -213,7 +226,10 @@ async fn all_supported_by_rsasl() {
     rsasl.store(Box::new(config.clone()));
 
     let rsasl = std::sync::Arc::new(tokio::sync::Mutex::new(rsasl));
-    for mechanism in <Mechanism as strum::IntoEnumIterator>::iter() {
+    // EXTERNAL is tested with the client certificates, see `tests::tls`.
+    for mechanism in <Mechanism as strum::IntoEnumIterator>::iter()
+        .filter(|mechanism| *mechanism != Mechanism::External)
+    {
         test_auth(
             config.clone(),
             &[
@@ -221,6 +237,11 @@ async fn all_supported_by_rsasl() {
                 "250-testserver.com",
                 "250-AUTH PLAIN LOGIN CRAM-MD5",
                 "250-STARTTLS",
+                "250-PIPELINING",
+                "250-SIZE 20000000",
+                "250-CHUNKING",
+                "250-BINARYMIME",
+                "250-DSN",
                 "250-8BITMIME",
                 "250 SMTPUTF8",
                 "235 2.7.0 Authentication succeeded",
@@ -46,6 +46,11 @@ async fn plain_in_clair_secured() {
             "250-testserver.com\r\n",
             "250-AUTH \r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "538 5.7.11 Encryption required for requested authentication mechanism\r\n",
@@ -100,6 +105,11 @@ async fn plain_in_clair_unsecured() {
             "250-testserver.com\r\n",
             "250-AUTH PLAIN LOGIN CRAM-MD5\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "235 2.7.0 Authentication succeeded\r\n",
@@ -159,6 +169,11 @@ async fn plain_in_clair_unsecured_utf8() {
             "250-testserver.com\r\n",
             "250-AUTH PLAIN LOGIN CRAM-MD5\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "235 2.7.0 Authentication succeeded\r\n",
@@ -197,6 +212,11 @@ async fn plain_in_clair_invalid_credentials() {
             "250-testserver.com\r\n",
             "250-AUTH PLAIN LOGIN CRAM-MD5\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "535 5.7.8 Authentication credentials invalid\r\n"
@@ -234,6 +254,11 @@ async fn plain_in_clair_unsecured_cancel() {
             "250-testserver.com\r\n",
             "250-AUTH PLAIN LOGIN CRAM-MD5\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "334 \r\n",
@@ -270,6 +295,11 @@ async fn plain_in_clair_unsecured_bad_base64() {
             "250-testserver.com\r\n",
             "250-AUTH PLAIN LOGIN CRAM-MD5\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "501 5.5.2 Invalid, not base64\r\n",
@@ -326,6 +356,11 @@ async fn plain_in_clair_unsecured_without_initial_response() {
             "250-testserver.com\r\n",
             "250-AUTH PLAIN LOGIN CRAM-MD5\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             // See https://datatracker.ietf.org/doc/html/rfc4422#section-5 2.a
@@ -363,6 +398,11 @@ async fn no_auth_with_authenticated_policy() {
             "250-testserver.com\r\n",
             "250-AUTH PLAIN LOGIN CRAM-MD5\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "530 5.7.0 Authentication required\r\n",
@@ -392,6 +432,11 @@ async fn client_must_not_start() {
             "250-testserver.com\r\n",
             "250-AUTH PLAIN LOGIN CRAM-MD5\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "501 5.7.0 Client must not start with this mechanism\r\n"
@@ -0,0 +1,308 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::test_receiver;
+use vsmtp_common::{
+    addr,
+    mail_context::{Body, MailContext},
+    re::anyhow,
+};
+use vsmtp_server::re::tokio;
+use vsmtp_server::Connection;
+use vsmtp_server::OnMail;
+
+// see https://datatracker.ietf.org/doc/html/rfc3030
+
+#[derive(Default)]
+struct StoreMails {
+    mails: Vec<Box<MailContext>>,
+}
+
+#[async_trait::async_trait]
+impl OnMail for StoreMails {
+    async fn on_mail<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+        &mut self,
+        conn: &mut Connection<S>,
+        mail: Box<MailContext>,
+        helo_domain: &mut Option<String>,
+    ) -> anyhow::Result<()> {
+        *helo_domain = Some(mail.envelop.helo.clone());
+        self.mails.push(mail);
+        conn.send_code(vsmtp_common::code::SMTPReplyCode::Code250)
+            .await?;
+        Ok(())
+    }
+}
+
+const CONTENT: &str = "from: john doe <john@doe>\r\n\r\nhello world\r\n";
+
+#[tokio::test]
+async fn same_as_data() {
+    let mut handler = StoreMails::default();
+
+    assert!(test_receiver! {
+        on_mail => &mut handler,
+        [
+            "EHLO foobar\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "DATA\r\n",
+            CONTENT,
+            ".\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            &format!("BDAT {} LAST\r\n", CONTENT.len()),
+            CONTENT,
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+
+    let (data, bdat) = match handler.mails.as_slice() {
+        [data, bdat] => (data, bdat),
+        otherwise => panic!("expected 2 mails, got {}", otherwise.len()),
+    };
+
+    assert_eq!(data.envelop, bdat.envelop);
+    assert_eq!(data.client_addr, bdat.client_addr);
+    assert!(matches!(data.body, Body::Spooled(_)));
+    assert_eq!(
+        data.body.to_raw().unwrap(),
+        "from: john doe <john@doe>\n\nhello world\n"
+    );
+    assert_eq!(data.body.to_raw().unwrap(), bdat.body.to_raw().unwrap());
+}
+
+#[tokio::test]
+async fn multiple_chunks() {
+    let mut handler = StoreMails::default();
+
+    assert!(test_receiver! {
+        on_mail => &mut handler,
+        [
+            "HELO foobar\r\n",
+            "MAIL FROM:<john@doe> BODY=8BITMIME\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "BDAT 10\r\n",
+            &CONTENT[..10],
+            &format!("BDAT {}\r\n", CONTENT.len() - 10),
+            &CONTENT[10..],
+            "bdat 0 last\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+
+    assert_eq!(handler.mails.len(), 1);
+    assert_eq!(handler.mails[0].envelop.rcpt, vec![addr!("aa@bb").into()]);
+    assert_eq!(
+        handler.mails[0].body.to_raw().unwrap(),
+        "from: john doe <john@doe>\n\nhello world\n"
+    );
+}
+
+#[tokio::test]
+async fn bad_sequence() {
+    assert!(test_receiver! {
+        [
+            "HELO foobar\r\n",
+            "BDAT 12 LAST\r\n",
+            "QUIT\r\nQUIT\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "BDAT 5\r\n",
+            "hello",
+            "DATA\r\n",
+            "RCPT TO:<cc@dd>\r\n",
+            "RSET\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "503 Bad sequence of commands\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "503 Bad sequence of commands\r\n",
+            "503 Bad sequence of commands\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn binarymime() {
+    let mut handler = StoreMails::default();
+    let content = b"content-type: application/octet-stream\r\n\r\n\xff\x00\n.\r\n";
+
+    assert!(crate::receiver::test_receiver_inner(
+        "127.0.0.1:0",
+        vsmtp_server::ConnectionKind::Opportunistic,
+        &mut handler,
+        &[
+            b"HELO foobar\r\n".as_slice(),
+            b"MAIL FROM:<john@doe> BODY=BINARYMIME\r\n",
+            b"RCPT TO:<aa@bb>\r\n",
+            format!("BDAT {} LAST\r\n", content.len()).as_bytes(),
+            content,
+            b"QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+        .as_bytes(),
+        std::sync::Arc::new(crate::config::local_test()),
+        None,
+    )
+    .await
+    .is_ok());
+
+    // the content is kept as received.
+    assert_eq!(handler.mails.len(), 1);
+    assert!(matches!(handler.mails[0].body, Body::Binary(_)));
+    assert_eq!(handler.mails[0].body.to_bytes().unwrap(), content);
+}
+
+#[tokio::test]
+async fn binarymime_with_data() {
+    assert!(test_receiver! {
+        [
+            "HELO foobar\r\n",
+            "MAIL FROM:<john@doe> BODY=BINARYMIME\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "DATA\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "503 Bad sequence of commands\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn size_exceeded() {
+    let mut config = crate::config::local_test();
+    config.server.smtp.message_size_max = 10;
+
+    assert!(test_receiver! {
+        with_config => config,
+        [
+            "HELO foobar\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "BDAT 6\r\n",
+            "hello ",
+            "BDAT 5 LAST\r\n",
+            "world",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn size_overflow() {
+    let mut config = crate::config::local_test();
+    config.server.smtp.message_size_max = usize::MAX;
+
+    // the chunk cannot be skipped entirely, the connection is closed at the end of the input
+    assert!(test_receiver! {
+        with_config => config,
+        [
+            "HELO foobar\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "BDAT 6\r\n",
+            "hello ",
+            &format!("BDAT {} LAST\r\n", usize::MAX),
+            "world",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+        ]
+        .concat()
+    }
+    .is_err());
+}
@@ -17,6 +17,7 @@
 use crate::{config, test_receiver};
 use vsmtp_common::{
     addr,
+    dsn::{DsnMail, DsnRcpt, NotifyOn, OriginalRecipient, ReturnContent},
     mail::{BodyType, Mail},
     mail_context::{Body, MailContext},
     re::anyhow,
@@ -250,6 +251,11 @@ async fn max_rcpt_reached() {
             "220 testserver.com Service ready\r\n",
             "250-testserver.com\r\n",
             "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
             "250-8BITMIME\r\n",
             "250 SMTPUTF8\r\n",
             "250 Ok\r\n",
@@ -265,6 +271,72 @@ async fn max_rcpt_reached() {
     .is_ok());
 }
 
+#[tokio::test]
+async fn mail_from_size_exceeded() {
+    let mut config = config::local_test();
+    config.server.smtp.message_size_max = 1000;
+
+    assert!(test_receiver! {
+        with_config => config,
+        [
+            "HELO client.com\r\n",
+            "MAIL FROM:<foo@bar.com> SIZE=1001\r\n",
+            "MAIL FROM:<foo@bar.com> SIZE=1000\r\n",
+        ].concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+            "250 Ok\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn data_size_exceeded() {
+    let mut config = config::local_test();
+    config.server.smtp.message_size_max = 100;
+
+    assert!(test_receiver! {
+        with_config => config,
+        [
+            "HELO client.com\r\n",
+            "MAIL FROM:<foo@bar.com>\r\n",
+            "RCPT TO:<bar@foo.com>\r\n",
+            "DATA\r\n",
+            "a".repeat(60).as_str(),
+            "\r\n",
+            "b".repeat(60).as_str(),
+            "\r\n",
+            ".\r\n",
+            "RCPT TO:<bar@foo.com>\r\n",
+            "MAIL FROM:<foo@bar.com>\r\n",
+            "RCPT TO:<bar@foo.com>\r\n",
+            "DATA\r\n",
+            "a".repeat(60).as_str(),
+            "\r\n",
+            ".\r\n",
+        ].concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "552 5.3.4 Message size exceeds fixed maximum message size\r\n",
+            "503 Bad sequence of commands\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
 #[tokio::test]
 async fn test_receiver_13() {
     struct T {
@@ -510,3 +582,149 @@ async fn test_receiver_9() {
                 .unwrap()
     );
 }
+
+#[tokio::test]
+async fn dsn_parameters() {
+    struct T;
+
+    #[async_trait::async_trait]
+    impl OnMail for T {
+        async fn on_mail<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+            &mut self,
+            conn: &mut Connection<S>,
+            mail: Box<MailContext>,
+            _: &mut Option<String>,
+        ) -> anyhow::Result<()> {
+            assert_eq!(
+                mail.envelop.dsn,
+                DsnMail {
+                    ret: Some(ReturnContent::Headers),
+                    envid: Some("QQ314159".to_string())
+                }
+            );
+            assert_eq!(
+                mail.envelop.rcpt[0].dsn,
+                DsnRcpt {
+                    notify: Some(NotifyOn {
+                        success: true,
+                        failure: true,
+                        delay: false
+                    }),
+                    orcpt: Some(OriginalRecipient {
+                        addr_type: "rfc822".to_string(),
+                        mailbox: "aa@bb".to_string()
+                    })
+                }
+            );
+            assert_eq!(mail.envelop.rcpt[1].dsn, DsnRcpt::default());
+            conn.send_code(vsmtp_common::code::SMTPReplyCode::Code250)
+                .await?;
+
+            Ok(())
+        }
+    }
+
+    assert!(test_receiver! {
+        on_mail => &mut T,
+        [
+            "EHLO foobar\r\n",
+            "MAIL FROM:<john@doe> RET=HDRS ENVID=QQ314159\r\n",
+            "RCPT TO:<aa@bb> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;aa@bb\r\n",
+            "RCPT TO:<cc@dd>\r\n",
+            "RCPT TO:<ee@ff> NOTIFY=NEVER,SUCCESS\r\n",
+            "DATA\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "501 Syntax error in parameters or arguments\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn null_reverse_path() {
+    struct T {
+        count: u32,
+    }
+
+    #[async_trait::async_trait]
+    impl OnMail for T {
+        async fn on_mail<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+            &mut self,
+            conn: &mut Connection<S>,
+            mail: Box<MailContext>,
+            _: &mut Option<String>,
+        ) -> anyhow::Result<()> {
+            self.count += 1;
+            if self.count == 1 {
+                assert!(mail.envelop.null_reverse_path);
+                assert_eq!(mail.envelop.reverse_path(), None);
+                assert_eq!(mail.envelop.mail_from.full(), "mailer-daemon@foobar");
+            } else {
+                assert!(!mail.envelop.null_reverse_path);
+                assert_eq!(mail.envelop.mail_from.full(), "john@doe");
+            }
+            assert_eq!(mail.envelop.rcpt, vec![addr!("aa@bb").into()]);
+            conn.send_code(vsmtp_common::code::SMTPReplyCode::Code250)
+                .await?;
+
+            Ok(())
+        }
+    }
+
+    let mut handler = T { count: 0 };
+
+    assert!(test_receiver! {
+        on_mail => &mut handler,
+        [
+            "HELO foobar\r\n",
+            "MAIL FROM:<>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "DATA\r\n",
+            ".\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "DATA\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+
+    assert_eq!(handler.count, 2);
+}
@@ -0,0 +1,141 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{config, test_receiver};
+use vsmtp_config::{ConfigServerListener, ListenerKind};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_server::re::tokio;
+use vsmtp_server::{ProcessMessage, Server};
+
+fn with_etrn_enabled() -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.app.vsl.filepath = Some("./src/tests/etrn/main.vsl".into());
+    config.server.smtp.enable_etrn = true;
+    config
+}
+
+#[tokio::test]
+async fn disabled_by_default() {
+    assert!(test_receiver! {
+        ["HELO foo\r\n", "ETRN example.com\r\n", "QUIT\r\n"].concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "502 Command not implemented\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn no_delivery_process() {
+    assert!(test_receiver! {
+        with_config => with_etrn_enabled(),
+        ["HELO foo\r\n", "ETRN example.com\r\n", "QUIT\r\n"].concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "458 Unable to queue messages for node\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn flush_requested() {
+    let config = std::sync::Arc::new(with_etrn_enabled());
+    let socket_server = tokio::net::TcpListener::bind("127.0.0.1:20150")
+        .await
+        .unwrap();
+
+    let (working_sender, _working_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (delivery_sender, _delivery_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (deferred_flush, mut deferred_flush_receiver) = tokio::sync::mpsc::channel::<String>(10);
+
+    let server = tokio::spawn(async move {
+        let (client_stream, client_addr) = socket_server.accept().await.unwrap();
+
+        Server::run_session(
+            client_stream,
+            client_addr,
+            std::sync::Arc::new(ConfigServerListener::new(vec![], ListenerKind::Plain)),
+            config.clone(),
+            None,
+            None,
+            std::sync::Arc::new(std::sync::RwLock::new(
+                RuleEngine::new(&config, &config.app.vsl.filepath).unwrap(),
+            )),
+            None,
+            None,
+            Some(deferred_flush),
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    });
+
+    let mut client = tokio::net::TcpStream::connect("127.0.0.1:20150")
+        .await
+        .unwrap();
+    tokio::io::AsyncWriteExt::write_all(
+        &mut client,
+        [
+            "ETRN example.com\r\n",
+            "HELO foo\r\n",
+            "ETRN example.com\r\n",
+            "ETRN @example.com\r\n",
+            "ETRN example.org\r\n",
+            "QUIT\r\n",
+        ]
+        .concat()
+        .as_bytes(),
+    )
+    .await
+    .unwrap();
+
+    let mut output = String::new();
+    let _ = tokio::io::AsyncReadExt::read_to_string(&mut client, &mut output).await;
+
+    assert!(server.await.unwrap().is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        [
+            "220 testserver.com Service ready\r\n",
+            "503 Bad sequence of commands\r\n",
+            "250 Ok\r\n",
+            "250 Queuing started\r\n",
+            "250 Queuing started\r\n",
+            "459 Node not allowed\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    );
+
+    // only the nodes accepted by the rules are sent to the delivery process.
+    assert_eq!(
+        deferred_flush_receiver.recv().await,
+        Some("example.com".to_string())
+    );
+    assert_eq!(
+        deferred_flush_receiver.recv().await,
+        Some("@example.com".to_string())
+    );
+    assert_eq!(deferred_flush_receiver.recv().await, None);
+}
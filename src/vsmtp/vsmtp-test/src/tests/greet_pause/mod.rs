@@ -0,0 +1,121 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use vsmtp_common::re::anyhow;
+use vsmtp_config::{Config, ConfigServerListener, ListenerKind};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_server::re::tokio;
+use vsmtp_server::{ProcessMessage, Server};
+
+fn greet_pause_config() -> Config {
+    let mut config = config::local_test();
+    config.server.smtp.greet_pause = Some(std::time::Duration::from_millis(500));
+    config.app.vsl.filepath = Some("./src/tests/greet_pause/main.vsl".into());
+    config
+}
+
+async fn start_session(
+    port: u16,
+) -> (
+    tokio::task::JoinHandle<anyhow::Result<()>>,
+    tokio::net::TcpStream,
+) {
+    let config = std::sync::Arc::new(greet_pause_config());
+    let socket_server = tokio::net::TcpListener::bind(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+
+    let (working_sender, _working_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (delivery_sender, _delivery_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+
+    let server = tokio::spawn(async move {
+        let (client_stream, client_addr) = socket_server.accept().await.unwrap();
+
+        Server::run_session(
+            client_stream,
+            client_addr,
+            std::sync::Arc::new(ConfigServerListener::new(vec![], ListenerKind::Plain)),
+            config.clone(),
+            None,
+            None,
+            std::sync::Arc::new(std::sync::RwLock::new(
+                RuleEngine::new(&config, &config.app.vsl.filepath).unwrap(),
+            )),
+            None,
+            None,
+            None,
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    });
+
+    let client = tokio::net::TcpStream::connect(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+
+    (server, client)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn early_talker_denied() {
+    let (server, mut client) = start_session(20130).await;
+
+    tokio::io::AsyncWriteExt::write_all(&mut client, b"HELO foo\r\n")
+        .await
+        .unwrap();
+
+    let mut output = String::new();
+    let _ = tokio::io::AsyncReadExt::read_to_string(&mut client, &mut output).await;
+
+    assert!(server.await.unwrap().is_err());
+    pretty_assertions::assert_eq!(
+        output,
+        [
+            "220 testserver.com Service ready\r\n",
+            "554 permanent problems with the remote server\r\n",
+        ]
+        .concat()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn patient_client_accepted() {
+    let (server, client) = start_session(20131).await;
+    let mut client = tokio::io::BufReader::new(client);
+
+    let begin = std::time::Instant::now();
+    let mut greetings = String::new();
+    tokio::io::AsyncBufReadExt::read_line(&mut client, &mut greetings)
+        .await
+        .unwrap();
+    assert!(begin.elapsed() >= std::time::Duration::from_millis(500));
+    pretty_assertions::assert_eq!(greetings, "220 testserver.com Service ready\r\n");
+
+    tokio::io::AsyncWriteExt::write_all(&mut client, b"HELO foo\r\nQUIT\r\n")
+        .await
+        .unwrap();
+
+    let mut output = String::new();
+    let _ = tokio::io::AsyncReadExt::read_to_string(&mut client, &mut output).await;
+
+    assert!(server.await.unwrap().is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        ["250 Ok\r\n", "221 Service closing transmission channel\r\n",].concat()
+    );
+}
@@ -0,0 +1,198 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use vsmtp_common::re::anyhow;
+use vsmtp_config::{ConfigServerListener, ListenerKind};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_server::re::tokio;
+use vsmtp_server::{ProcessMessage, Server};
+
+async fn run_session(
+    listener: ConfigServerListener,
+    port: u16,
+    input: &'static [u8],
+) -> (anyhow::Result<()>, String) {
+    let config = std::sync::Arc::new(config::local_test());
+    let socket_server = tokio::net::TcpListener::bind(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+
+    let (working_sender, _working_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (delivery_sender, _delivery_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+
+    let server = tokio::spawn(async move {
+        let (client_stream, client_addr) = socket_server.accept().await.unwrap();
+
+        Server::run_session(
+            client_stream,
+            client_addr,
+            std::sync::Arc::new(listener.clone()),
+            config.clone(),
+            None,
+            None,
+            std::sync::Arc::new(std::sync::RwLock::new(
+                RuleEngine::new(&config, &listener.vsl).unwrap(),
+            )),
+            None,
+            None,
+            None,
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    });
+
+    let mut client = tokio::net::TcpStream::connect(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+    tokio::io::AsyncWriteExt::write_all(&mut client, input)
+        .await
+        .unwrap();
+
+    let mut output = String::new();
+    let _ = tokio::io::AsyncReadExt::read_to_string(&mut client, &mut output).await;
+
+    (server.await.unwrap(), output)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn vsl_entry_point() {
+    let (server, output) = run_session(
+        ConfigServerListener {
+            vsl: Some("./src/tests/listeners/main.vsl".into()),
+            ..ConfigServerListener::new(vec![], ListenerKind::Plain)
+        },
+        20110,
+        b"HELO foo\r\nQUIT\r\n",
+    )
+    .await;
+
+    assert!(server.is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 hello from the listener rules\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn auth_required() {
+    let (server, output) = run_session(
+        ConfigServerListener {
+            auth_required: true,
+            ..ConfigServerListener::new(vec![], ListenerKind::Submission)
+        },
+        20111,
+        b"HELO foo\r\nMAIL FROM:<john@doe>\r\nQUIT\r\n",
+    )
+    .await;
+
+    assert!(server.is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "530 5.7.0 Authentication required\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn tls_required() {
+    let (server, output) = run_session(
+        ConfigServerListener {
+            tls_required: true,
+            ..ConfigServerListener::new(vec![], ListenerKind::Submission)
+        },
+        20112,
+        b"HELO foo\r\nMAIL FROM:<john@doe>\r\nQUIT\r\n",
+    )
+    .await;
+
+    assert!(server.is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "530 Must issue a STARTTLS command first\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn unix_domain_socket() {
+    let config = std::sync::Arc::new(config::local_test());
+    let listener = ConfigServerListener {
+        vsl: Some("./src/tests/listeners/local.vsl".into()),
+        ..ConfigServerListener::new(vec![], ListenerKind::Submission)
+    };
+
+    let (working_sender, _working_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (delivery_sender, _delivery_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+
+    let (server_stream, mut client) = tokio::net::UnixStream::pair().unwrap();
+
+    let server = tokio::spawn(async move {
+        Server::run_local_session(
+            server_stream,
+            std::sync::Arc::new(listener.clone()),
+            config.clone(),
+            None,
+            None,
+            std::sync::Arc::new(std::sync::RwLock::new(
+                RuleEngine::new(&config, &listener.vsl).unwrap(),
+            )),
+            None,
+            None,
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    });
+
+    tokio::io::AsyncWriteExt::write_all(&mut client, b"HELO foo\r\nQUIT\r\n")
+        .await
+        .unwrap();
+
+    let mut output = String::new();
+    let _ = tokio::io::AsyncReadExt::read_to_string(&mut client, &mut output).await;
+
+    assert!(server.await.unwrap().is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        [
+            "220 testserver.com Service ready\r\n",
+            &format!(
+                "250 hello local process {}:{}\r\n",
+                unsafe { vsmtp_common::re::libc::getuid() },
+                unsafe { vsmtp_common::re::libc::getgid() }
+            ),
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    );
+}
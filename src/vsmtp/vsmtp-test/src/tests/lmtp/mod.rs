@@ -0,0 +1,229 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{config, test_receiver};
+use vsmtp_server::re::tokio;
+use vsmtp_server::ConnectionKind;
+
+// see https://datatracker.ietf.org/doc/html/rfc2033
+
+#[tokio::test]
+async fn one_reply_per_recipient() {
+    assert!(test_receiver! {
+        with_kind => ConnectionKind::Lmtp,
+        with_config => config::local_test(),
+        [
+            "LHLO foobar\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RCPT TO:<cc@dd>\r\n",
+            "RCPT TO:<ee@ff>\r\n",
+            "DATA\r\n",
+            "from: john doe <john@doe>\r\n",
+            "\r\n",
+            "mail content wow\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn one_reply_per_recipient_with_bdat() {
+    assert!(test_receiver! {
+        with_kind => ConnectionKind::Lmtp,
+        with_config => config::local_test(),
+        [
+            "LHLO foobar\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RCPT TO:<cc@dd>\r\n",
+            "BDAT 18 LAST\r\n",
+            "mail content wow\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn denied_by_preq() {
+    let mut config = config::local_test();
+    config.app.vsl.filepath = Some("./src/tests/lmtp/main.vsl".into());
+
+    assert!(test_receiver! {
+        with_kind => ConnectionKind::Lmtp,
+        with_config => config,
+        [
+            "LHLO foobar\r\n",
+            "MAIL FROM:<john@spam.com>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RCPT TO:<cc@dd>\r\n",
+            "DATA\r\n",
+            "buy now\r\n",
+            ".\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "554 5.7.1 rejected as spam\r\n",
+            "554 5.7.1 rejected as spam\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn replies_are_not_counted_as_errors() {
+    let mut config = config::local_test();
+    config.app.vsl.filepath = Some("./src/tests/lmtp/main.vsl".into());
+    config.server.smtp.error.hard_count = 2;
+
+    assert!(test_receiver! {
+        with_kind => ConnectionKind::Lmtp,
+        with_config => config,
+        [
+            "LHLO foobar\r\n",
+            "MAIL FROM:<john@spam.com>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RCPT TO:<cc@dd>\r\n",
+            "RCPT TO:<ee@ff>\r\n",
+            "DATA\r\n",
+            "buy now\r\n",
+            ".\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "554 5.7.1 rejected as spam\r\n",
+            "554 5.7.1 rejected as spam\r\n",
+            "554 5.7.1 rejected as spam\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn helo_and_ehlo_are_refused() {
+    assert!(test_receiver! {
+        with_kind => ConnectionKind::Lmtp,
+        with_config => config::local_test(),
+        ["HELO foobar\r\n", "EHLO foobar\r\n", "LHLO foobar\r\n"].concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "500 Syntax error command unrecognized\r\n",
+            "500 Syntax error command unrecognized\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn lhlo_is_refused_in_smtp() {
+    assert!(test_receiver! {
+        ["LHLO foobar\r\n", "HELO foobar\r\n"].concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "500 Syntax error command unrecognized\r\n",
+            "250 Ok\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
@@ -15,9 +15,20 @@
  *
 */
 mod auth;
+mod chunking;
 mod clair;
+mod etrn;
 mod examples;
+mod greet_pause;
+mod listeners;
+mod lmtp;
+mod outbound_tls;
+mod pipelining;
+mod proxy_protocol;
+mod rate_limit;
 mod rset;
 mod rules;
+mod shutdown;
 mod tls;
 mod utf8;
+mod xclient;
@@ -0,0 +1,335 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::tests::tls::get_tls_config;
+use trust_dns_resolver::proto::rr::rdata::tlsa::{CertUsage, Matching, Selector, TLSA};
+use vsmtp_common::{addr, re::anyhow};
+use vsmtp_config::{get_rustls_config, re::rustls, ConfigServerListener, ListenerKind};
+use vsmtp_delivery::{
+    dane, fingerprint, tls_rpt,
+    transport::{starttls, Content},
+};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_server::{re::tokio, ProcessMessage, Server};
+
+/// sha256 of the public key of `src/template/certs/certificate.crt`.
+const TEST_SERVER_SPKI_SHA256: &str =
+    "fae03a4995f695dcce166882b542617d3bf0a7845eb63079517917544e3ccc92";
+
+/// sha256 fingerprint of `src/template/certs/certificate.crt`.
+const TEST_SERVER_SHA256: &str = "dbeb64fe9e0c839e2b754b152be398cb96ab767617a985a5db4b1c478038b043";
+
+fn tlsa_record(usage: u8, selector: u8, matching: u8, data: &str) -> TLSA {
+    TLSA::new(
+        CertUsage::from(usage),
+        Selector::from(selector),
+        Matching::from(matching),
+        (0..data.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&data[i..i + 2], 16).unwrap())
+            .collect(),
+    )
+}
+
+const CONTENT: Content<'static> =
+    Content::Text("From: foo@client.com\r\nSubject: dane\r\n\r\n.hidden line\r\n");
+
+/// deliver a message to a local server, its certificate is verified by `tls_config`.
+async fn send_to_local_server(
+    tls_config: rustls::ClientConfig,
+    port: u16,
+    from: vsmtp_common::Address,
+    content: Content<'_>,
+) -> anyhow::Result<()> {
+    let config = std::sync::Arc::new(get_tls_config());
+    let socket_server = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
+        .await
+        .unwrap();
+
+    let (working_sender, _working_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (delivery_sender, _delivery_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+
+    let server = tokio::spawn(async move {
+        let (client_stream, client_addr) = socket_server.accept().await.unwrap();
+
+        Server::run_session(
+            client_stream,
+            client_addr,
+            std::sync::Arc::new(ConfigServerListener::new(vec![], ListenerKind::Plain)),
+            config.clone(),
+            Some(std::sync::Arc::new(
+                get_rustls_config(
+                    config.server.tls.as_ref().unwrap(),
+                    &config.server.r#virtual,
+                )
+                .unwrap(),
+            )),
+            None,
+            std::sync::Arc::new(std::sync::RwLock::new(
+                RuleEngine::new(&config, &config.app.vsl.filepath.clone()).unwrap(),
+            )),
+            None,
+            None,
+            None,
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    });
+
+    let sent = starttls::send(
+        "localhost",
+        port,
+        "client.com",
+        &starttls::Tls::Required(starttls::TlsParameters::new(
+            std::sync::Arc::new(tls_config),
+            "localhost",
+        )),
+        &starttls::Envelope {
+            smtputf8: !from.has_ascii_local_part(),
+            from: Some(from),
+            to: vec![addr!("bar@testserver.com")],
+        },
+        content,
+    )
+    .await;
+
+    // the server is left waiting for the handshake if the certificate has been rejected.
+    server.abort();
+    sent
+}
+
+fn dane(records: Vec<TLSA>) -> rustls::ClientConfig {
+    dane::get_client_config(dane::DaneVerifier::new(records))
+}
+
+fn pinned(sha256: &str) -> rustls::ClientConfig {
+    fingerprint::get_client_config(
+        fingerprint::FingerprintVerifier::new(&[sha256.to_string()]).unwrap(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn dane_ee() {
+    send_to_local_server(
+        dane(vec![tlsa_record(3, 1, 1, TEST_SERVER_SPKI_SHA256)]),
+        20170,
+        addr!("foo@client.com"),
+        CONTENT,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn dane_mismatch() {
+    let error = send_to_local_server(
+        dane(vec![tlsa_record(3, 1, 1, &"00".repeat(32))]),
+        20171,
+        addr!("foo@client.com"),
+        CONTENT,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        tls_rpt::get_result_type(&error),
+        Some(tls_rpt::ResultType::TlsaInvalid)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn fingerprint_pinned() {
+    send_to_local_server(
+        pinned(TEST_SERVER_SHA256),
+        20172,
+        addr!("foo@client.com"),
+        CONTENT,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn fingerprint_mismatch() {
+    assert!(send_to_local_server(
+        pinned(&"00".repeat(32)),
+        20173,
+        addr!("foo@client.com"),
+        CONTENT,
+    )
+    .await
+    .is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn smtputf8() {
+    send_to_local_server(
+        pinned(TEST_SERVER_SHA256),
+        20174,
+        addr!("用户@client.com"),
+        CONTENT,
+    )
+    .await
+    .unwrap();
+}
+
+/// send a message in plain text to a fake server replying `ehlo` to EHLO.
+async fn send_to_fake_server(
+    port: u16,
+    ehlo: &'static [u8],
+    envelop: starttls::Envelope,
+    content: Content<'_>,
+) -> anyhow::Result<()> {
+    let socket_server = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
+        .await
+        .unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = socket_server.accept().await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut stream, b"220 fake\r\n")
+            .await
+            .unwrap();
+        let mut stream = tokio::io::BufReader::new(stream);
+        let mut command = String::new();
+        tokio::io::AsyncBufReadExt::read_line(&mut stream, &mut command)
+            .await
+            .unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut stream, ehlo)
+            .await
+            .unwrap();
+        // keep the connection open until the client closes it.
+        let mut rest = String::new();
+        let _ = tokio::io::AsyncBufReadExt::read_line(&mut stream, &mut rest).await;
+    });
+
+    let sent = starttls::send(
+        "localhost",
+        port,
+        "client.com",
+        &starttls::Tls::None,
+        &envelop,
+        content,
+    )
+    .await;
+
+    server.abort();
+    sent
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn smtputf8_not_supported() {
+    let error = send_to_fake_server(
+        20175,
+        b"250-fake\r\n250 8BITMIME\r\n",
+        starttls::Envelope {
+            from: Some(addr!("用户@client.com")),
+            to: vec![addr!("bar@testserver.com")],
+            smtputf8: true,
+        },
+        Content::Text("From: foo@client.com\r\n\r\nhello\r\n"),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        error
+            .downcast_ref::<starttls::ExtensionNotSupported>()
+            .unwrap()
+            .extension,
+        "SMTPUTF8"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn eight_bit_mime_not_supported() {
+    let error = send_to_fake_server(
+        20176,
+        b"250-fake\r\n250 PIPELINING\r\n",
+        starttls::Envelope {
+            from: Some(addr!("foo@client.com")),
+            to: vec![addr!("bar@testserver.com")],
+            smtputf8: false,
+        },
+        Content::Text("From: foo@client.com\r\n\r\nhéllo\r\n"),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        error
+            .downcast_ref::<starttls::ExtensionNotSupported>()
+            .unwrap()
+            .extension,
+        "8BITMIME"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn reply_line_too_long() {
+    let ehlo = format!("250-fake\r\n250 {}\r\n", "A".repeat(1000));
+
+    assert!(send_to_fake_server(
+        20177,
+        Box::leak(ehlo.into_bytes().into_boxed_slice()),
+        starttls::Envelope {
+            from: Some(addr!("foo@client.com")),
+            to: vec![addr!("bar@testserver.com")],
+            smtputf8: false,
+        },
+        Content::Text("From: foo@client.com\r\n\r\nhello\r\n"),
+    )
+    .await
+    .unwrap_err()
+    .to_string()
+    .contains("reply line longer than"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn binarymime() {
+    send_to_local_server(
+        pinned(TEST_SERVER_SHA256),
+        20178,
+        addr!("foo@client.com"),
+        Content::Binary(b"content-type: application/octet-stream\r\n\r\n\xff\x00\n.\r\n"),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn chunking_not_supported() {
+    let error = send_to_fake_server(
+        20179,
+        b"250-fake\r\n250 8BITMIME\r\n",
+        starttls::Envelope {
+            from: Some(addr!("foo@client.com")),
+            to: vec![addr!("bar@testserver.com")],
+            smtputf8: false,
+        },
+        Content::Binary(b"content-type: application/octet-stream\r\n\r\n\xff\x00\r\n"),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(
+        error
+            .downcast_ref::<starttls::ExtensionNotSupported>()
+            .unwrap()
+            .extension,
+        "CHUNKING"
+    );
+}
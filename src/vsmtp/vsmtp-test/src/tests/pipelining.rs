@@ -0,0 +1,329 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{config, test_receiver};
+use vsmtp_common::{addr, mail_context::MailContext, re::anyhow};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_server::re::tokio;
+use vsmtp_server::{handle_connection, Connection, ConnectionKind, OnMail};
+
+// see https://datatracker.ietf.org/doc/html/rfc2920
+
+#[tokio::test]
+async fn full_transaction_in_one_write() {
+    struct T;
+
+    #[async_trait::async_trait]
+    impl OnMail for T {
+        async fn on_mail<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+            &mut self,
+            conn: &mut Connection<S>,
+            mail: Box<MailContext>,
+            helo_domain: &mut Option<String>,
+        ) -> anyhow::Result<()> {
+            assert_eq!(mail.envelop.helo, "foobar");
+            assert_eq!(mail.envelop.mail_from.full(), "john@doe");
+            assert_eq!(
+                mail.envelop.rcpt,
+                vec![addr!("aa@bb").into(), addr!("cc@dd").into()]
+            );
+            *helo_domain = Some(mail.envelop.helo.clone());
+            conn.send_code(vsmtp_common::code::SMTPReplyCode::Code250)
+                .await?;
+
+            Ok(())
+        }
+    }
+
+    assert!(test_receiver! {
+        on_mail => &mut T,
+        [
+            "EHLO foobar\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "RCPT TO:<cc@dd>\r\n",
+            "DATA\r\n",
+            "from: john doe <john@doe>\r\n",
+            "\r\n",
+            "hello world\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn two_transactions_in_one_write() {
+    assert!(test_receiver! {
+        [
+            "EHLO foobar\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "DATA\r\n",
+            ".\r\n",
+            "MAIL FROM:<jane@doe>\r\n",
+            "RCPT TO:<cc@dd>\r\n",
+            "RSET\r\n",
+            "MAIL FROM:<jane@doe>\r\n",
+            "RCPT TO:<ee@ff>\r\n",
+            "DATA\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn error_in_group() {
+    assert!(test_receiver! {
+        [
+            "EHLO foobar\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<aa@bb>\r\n",
+            "DATA\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "503 Bad sequence of commands\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+/// a stream recording each write made by the server.
+struct Recorder {
+    input: std::io::Cursor<Vec<u8>>,
+    writes: Vec<Vec<u8>>,
+}
+
+impl tokio::io::AsyncRead for Recorder {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.input).poll_read(cx, buf)
+    }
+}
+
+impl tokio::io::AsyncWrite for Recorder {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        self.writes.push(buf.to_vec());
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn replies_are_grouped() {
+    let config = std::sync::Arc::new(config::local_test());
+    let rule_engine = std::sync::Arc::new(std::sync::RwLock::new(
+        RuleEngine::new(&config, &config.app.vsl.filepath.clone()).unwrap(),
+    ));
+
+    let mut recorder = Recorder {
+        input: std::io::Cursor::new(
+            [
+                "EHLO foobar\r\n",
+                "MAIL FROM:<john@doe>\r\n",
+                "RCPT TO:<aa@bb>\r\n",
+                "RCPT TO:<cc@dd>\r\n",
+                "DATA\r\n",
+            ]
+            .concat()
+            .into_bytes(),
+        ),
+        writes: vec![],
+    };
+
+    let mut conn = Connection::new(
+        ConnectionKind::Opportunistic,
+        "127.0.0.1:0".parse().unwrap(),
+        config.clone(),
+        &mut recorder,
+    );
+
+    handle_connection(
+        &mut conn,
+        None,
+        None,
+        rule_engine,
+        &mut crate::receiver::DefaultMailHandler {},
+    )
+    .await
+    .unwrap();
+
+    pretty_assertions::assert_eq!(
+        recorder
+            .writes
+            .iter()
+            .map(|w| std::str::from_utf8(w).unwrap())
+            .collect::<Vec<_>>(),
+        vec![
+            "220 testserver.com Service ready\r\n".to_string(),
+            [
+                "250-testserver.com\r\n",
+                "250-STARTTLS\r\n",
+                "250-PIPELINING\r\n",
+                "250-SIZE 20000000\r\n",
+                "250-CHUNKING\r\n",
+                "250-BINARYMIME\r\n",
+                "250-DSN\r\n",
+                "250-8BITMIME\r\n",
+                "250 SMTPUTF8\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            ]
+            .concat()
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pending_replies_are_sent_on_error() {
+    let config = std::sync::Arc::new(config::local_test());
+    let rule_engine = std::sync::Arc::new(std::sync::RwLock::new(
+        RuleEngine::new(&config, &config.app.vsl.filepath.clone()).unwrap(),
+    ));
+
+    let mut recorder = Recorder {
+        input: std::io::Cursor::new(
+            [
+                b"HELO foobar\r\n".as_slice(),
+                b"MAIL FROM:<john@doe>\r\n",
+                // not utf8, the connection is closed.
+                b"\xff\r\n",
+            ]
+            .concat(),
+        ),
+        writes: vec![],
+    };
+
+    let mut conn = Connection::new(
+        ConnectionKind::Opportunistic,
+        "127.0.0.1:0".parse().unwrap(),
+        config.clone(),
+        &mut recorder,
+    );
+
+    assert!(handle_connection(
+        &mut conn,
+        None,
+        None,
+        rule_engine,
+        &mut crate::receiver::DefaultMailHandler {},
+    )
+    .await
+    .is_err());
+
+    pretty_assertions::assert_eq!(
+        recorder
+            .writes
+            .iter()
+            .map(|w| std::str::from_utf8(w).unwrap())
+            .collect::<Vec<_>>(),
+        vec![
+            "220 testserver.com Service ready\r\n".to_string(),
+            ["250 Ok\r\n", "250 Ok\r\n"].concat()
+        ]
+    );
+}
@@ -0,0 +1,142 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use vsmtp_common::re::anyhow;
+use vsmtp_config::{re::ipnet, Config, ConfigServerInterfacesProxy};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_server::re::tokio;
+use vsmtp_server::{ProcessMessage, Server};
+
+fn behind_proxy(trusted: &str) -> Config {
+    let mut config = config::local_test();
+    config.app.vsl.filepath = Some("./src/tests/proxy_protocol/main.vsl".into());
+    config.server.interfaces.proxy_protocol = Some(ConfigServerInterfacesProxy {
+        addr: true,
+        addr_submission: false,
+        addr_submissions: false,
+        addr_lmtp: false,
+        trusted: vec![trusted.parse::<ipnet::IpNet>().unwrap()],
+        timeout: std::time::Duration::from_secs(1),
+    });
+    config
+}
+
+async fn run_session(
+    config: Config,
+    port: u16,
+    input: &'static [u8],
+) -> (anyhow::Result<()>, String) {
+    let config = std::sync::Arc::new(config);
+    let socket_server = tokio::net::TcpListener::bind(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+
+    let (working_sender, _working_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (delivery_sender, _delivery_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+
+    let server = tokio::spawn(async move {
+        let (client_stream, client_addr) = socket_server.accept().await.unwrap();
+
+        Server::run_session(
+            client_stream,
+            client_addr,
+            // the `addr` listener, expecting a PROXY header.
+            std::sync::Arc::new(config.server.interfaces.listeners()[0].clone()),
+            config.clone(),
+            None,
+            None,
+            std::sync::Arc::new(std::sync::RwLock::new(
+                RuleEngine::new(&config, &config.app.vsl.filepath.clone()).unwrap(),
+            )),
+            None,
+            None,
+            None,
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    });
+
+    let mut client = tokio::net::TcpStream::connect(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+    tokio::io::AsyncWriteExt::write_all(&mut client, input)
+        .await
+        .unwrap();
+
+    // the connection may be reset if the server closed it without reading the input
+    let mut output = String::new();
+    let _ = tokio::io::AsyncReadExt::read_to_string(&mut client, &mut output).await;
+
+    (server.await.unwrap(), output)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn client_addr_from_v1_header() {
+    let (server, output) = run_session(
+        behind_proxy("127.0.0.0/8"),
+        20100,
+        b"PROXY TCP4 192.0.2.1 127.0.0.1 4242 25\r\nHELO foo\r\nQUIT\r\n",
+    )
+    .await;
+
+    assert!(server.is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 hello 192.0.2.1:4242\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn client_addr_from_v2_header() {
+    const INPUT: &[u8] = &[
+        b'\r', b'\n', b'\r', b'\n', 0, b'\r', b'\n', b'Q', b'U', b'I', b'T', b'\n', 0x21, 0x11, 0,
+        12, 192, 0, 2, 1, 127, 0, 0, 1, 0x10, 0x92, 0, 25, b'H', b'E', b'L', b'O', b' ', b'f',
+        b'o', b'o', b'\r', b'\n', b'Q', b'U', b'I', b'T', b'\r', b'\n',
+    ];
+
+    let (server, output) = run_session(behind_proxy("127.0.0.0/8"), 20101, INPUT).await;
+
+    assert!(server.is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 hello 192.0.2.1:4242\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn untrusted_proxy() {
+    let (server, output) = run_session(
+        behind_proxy("10.0.0.0/8"),
+        20102,
+        b"PROXY TCP4 192.0.2.1 127.0.0.1 4242 25\r\nHELO foo\r\nQUIT\r\n",
+    )
+    .await;
+
+    assert!(server.is_err());
+    assert_eq!(output, "");
+}
@@ -0,0 +1,165 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use vsmtp_common::re::anyhow;
+use vsmtp_config::{
+    ConfigServerListener, ConfigServerSMTPRateLimit, ConfigServerSMTPRateLimitNetwork, ListenerKind,
+};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_server::re::tokio;
+use vsmtp_server::{ProcessMessage, RateLimiter, Server};
+
+/// run one session per input, all sharing the same rate limiter.
+async fn run_sessions(
+    rate_limit: ConfigServerSMTPRateLimit,
+    port: u16,
+    inputs: &[&'static [u8]],
+) -> Vec<(anyhow::Result<()>, String)> {
+    let config = std::sync::Arc::new(config::local_test());
+    let rate_limiter = std::sync::Arc::new(RateLimiter::new(rate_limit));
+    let rule_engine = std::sync::Arc::new(std::sync::RwLock::new(
+        RuleEngine::new(&config, &None).unwrap(),
+    ));
+    let socket_server = tokio::net::TcpListener::bind(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+
+    let mut outputs = vec![];
+    for input in inputs {
+        let (working_sender, _working_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+        let (delivery_sender, _delivery_receiver) =
+            tokio::sync::mpsc::channel::<ProcessMessage>(10);
+
+        let mut client = tokio::net::TcpStream::connect(format!("127.0.0.1:{port}"))
+            .await
+            .unwrap();
+        let (client_stream, client_addr) = socket_server.accept().await.unwrap();
+
+        let server = tokio::spawn(Server::run_session(
+            client_stream,
+            client_addr,
+            std::sync::Arc::new(ConfigServerListener::new(vec![], ListenerKind::Plain)),
+            config.clone(),
+            None,
+            None,
+            rule_engine.clone(),
+            Some(rate_limiter.clone()),
+            None,
+            None,
+            working_sender,
+            delivery_sender,
+        ));
+
+        tokio::io::AsyncWriteExt::write_all(&mut client, input)
+            .await
+            .unwrap();
+
+        let mut output = String::new();
+        let _ = tokio::io::AsyncReadExt::read_to_string(&mut client, &mut output).await;
+
+        outputs.push((server.await.unwrap(), output));
+    }
+
+    outputs
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn connections() {
+    let outputs = run_sessions(
+        ConfigServerSMTPRateLimit {
+            ipv4: vec![ConfigServerSMTPRateLimitNetwork {
+                prefix: 32,
+                concurrent_connections: None,
+                connections: Some(1),
+                messages: None,
+                recipients: None,
+            }],
+            ..ConfigServerSMTPRateLimit::default()
+        },
+        20120,
+        &[b"QUIT\r\n", b"QUIT\r\n"],
+    )
+    .await;
+
+    assert!(outputs.iter().all(|(server, _)| server.is_ok()));
+    pretty_assertions::assert_eq!(
+        outputs
+            .into_iter()
+            .map(|(_, output)| output)
+            .collect::<Vec<_>>(),
+        vec![
+            [
+                "220 testserver.com Service ready\r\n",
+                "221 Service closing transmission channel\r\n",
+            ]
+            .concat(),
+            "421 4.7.0 Too many connections from your network, closing.\r\n".to_string(),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn messages_and_recipients() {
+    let outputs = run_sessions(
+        ConfigServerSMTPRateLimit {
+            ipv4: vec![ConfigServerSMTPRateLimitNetwork {
+                prefix: 32,
+                concurrent_connections: None,
+                connections: None,
+                messages: Some(2),
+                recipients: Some(2),
+            }],
+            ..ConfigServerSMTPRateLimit::default()
+        },
+        20121,
+        &[
+            b"HELO foo\r\nMAIL FROM:<john@doe>\r\nRCPT TO:<aa@bb>\r\nRCPT TO:<aa2@bb>\r\nRCPT TO:<aa3@bb>\r\nQUIT\r\n",
+            b"HELO foo\r\nMAIL FROM:<john@doe>\r\nRCPT TO:<aa@bb>\r\nRSET\r\nMAIL FROM:<john@doe>\r\nQUIT\r\n",
+        ],
+    )
+    .await;
+
+    assert!(outputs.iter().all(|(server, _)| server.is_ok()));
+    pretty_assertions::assert_eq!(
+        outputs
+            .into_iter()
+            .map(|(_, output)| output)
+            .collect::<Vec<_>>(),
+        vec![
+            [
+                "220 testserver.com Service ready\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "451 4.7.1 Too many recipients from your network, try again later\r\n",
+                "221 Service closing transmission channel\r\n",
+            ]
+            .concat(),
+            [
+                "220 testserver.com Service ready\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "451 4.7.1 Too many recipients from your network, try again later\r\n",
+                "250 Ok\r\n",
+                "451 4.7.1 Too many messages from your network, try again later\r\n",
+                "221 Service closing transmission channel\r\n",
+            ]
+            .concat(),
+        ]
+    );
+}
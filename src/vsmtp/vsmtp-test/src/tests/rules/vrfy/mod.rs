@@ -0,0 +1,83 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{config, test_receiver};
+use vsmtp_server::re::tokio;
+
+fn with_queries_enabled() -> vsmtp_config::Config {
+    let mut config = config::local_test();
+    config.app.vsl.filepath = Some("./src/tests/rules/vrfy/main.vsl".into());
+    config.server.smtp.enable_vrfy = true;
+    config.server.smtp.enable_expn = true;
+    config
+}
+
+#[tokio::test]
+async fn disabled_by_default() {
+    assert!(test_receiver! {
+        ["HELO foo\r\n", "VRFY john\r\n", "EXPN staff\r\n", "QUIT\r\n"].concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "502 Command not implemented\r\n",
+            "502 Command not implemented\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn answered_by_rules() {
+    assert!(test_receiver! {
+        with_config => with_queries_enabled(),
+        [
+            "HELO foo\r\n",
+            "VRFY john\r\n",
+            "VRFY jenny\r\n",
+            "EXPN staff\r\n",
+            "MAIL FROM:<a@satan.org>\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 John Doe <john@testserver.com>\r\n",
+            "550 5.1.1 user unknown\r\n",
+            "250-John Doe <john@testserver.com>\r\n250 Jenny Doe <jenny@testserver.com>\r\n",
+            "250 Ok\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn cannot_verify() {
+    assert!(test_receiver! {
+        with_config => with_queries_enabled(),
+        ["HELO foo\r\n", "VRFY bob\r\n", "EXPN everyone\r\n"].concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "252 Cannot VRFY user, but will accept message and attempt delivery\r\n",
+            "252 Cannot VRFY user, but will accept message and attempt delivery\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
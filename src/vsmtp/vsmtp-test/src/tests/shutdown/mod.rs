@@ -0,0 +1,84 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::config;
+use vsmtp_config::{ConfigServerListener, ListenerKind};
+use vsmtp_rule_engine::rule_engine::RuleEngine;
+use vsmtp_server::re::tokio;
+use vsmtp_server::{ProcessMessage, Server};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn idle_client_disconnected() {
+    let config = std::sync::Arc::new(config::local_test());
+    let socket_server = tokio::net::TcpListener::bind("127.0.0.1:20140")
+        .await
+        .unwrap();
+
+    let (working_sender, _working_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (delivery_sender, _delivery_receiver) = tokio::sync::mpsc::channel::<ProcessMessage>(10);
+    let (shutdown_sender, shutdown) = tokio::sync::watch::channel(false);
+
+    let server = tokio::spawn(async move {
+        let (client_stream, client_addr) = socket_server.accept().await.unwrap();
+
+        Server::run_session(
+            client_stream,
+            client_addr,
+            std::sync::Arc::new(ConfigServerListener::new(vec![], ListenerKind::Plain)),
+            config.clone(),
+            None,
+            None,
+            std::sync::Arc::new(std::sync::RwLock::new(
+                RuleEngine::from_script(&config, "#{}").unwrap(),
+            )),
+            None,
+            Some(shutdown),
+            None,
+            working_sender,
+            delivery_sender,
+        )
+        .await
+    });
+
+    let client = tokio::net::TcpStream::connect("127.0.0.1:20140")
+        .await
+        .unwrap();
+    let mut client = tokio::io::BufReader::new(client);
+
+    tokio::io::AsyncWriteExt::write_all(&mut client, b"HELO foo\r\n")
+        .await
+        .unwrap();
+
+    for expected in ["220 testserver.com Service ready\r\n", "250 Ok\r\n"] {
+        let mut line = String::new();
+        tokio::io::AsyncBufReadExt::read_line(&mut client, &mut line)
+            .await
+            .unwrap();
+        pretty_assertions::assert_eq!(line, expected);
+    }
+
+    // the client is waiting, the server closes the connection.
+    shutdown_sender.send(true).unwrap();
+
+    let mut output = String::new();
+    let _ = tokio::io::AsyncReadExt::read_to_string(&mut client, &mut output).await;
+
+    assert!(server.await.unwrap().is_ok());
+    pretty_assertions::assert_eq!(
+        output,
+        "421 4.3.2 Service shutting down, closing transmission channel\r\n"
+    );
+}
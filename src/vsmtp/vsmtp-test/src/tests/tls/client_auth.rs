@@ -0,0 +1,237 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use super::{TEST_SERVER_CERT, TEST_SERVER_KEY};
+use crate::tests::tls::test_tls_tunneled_with_client_cert;
+use vsmtp_common::{auth::Mechanism, re::vsmtp_rsasl};
+use vsmtp_config::{
+    get_rustls_config,
+    re::{rustls, rustls_pemfile},
+    Config, ConfigServerTlsClientAuth,
+};
+use vsmtp_server::{auth, re::tokio};
+
+const TEST_CLIENT_CA: &str = "src/template/certs/client/ca.crt";
+const TEST_CLIENT_CERT: &str = "src/template/certs/client/client.crt";
+const TEST_CLIENT_KEY: &str = "src/template/certs/client/client.key";
+
+fn get_client_auth_config(required: bool) -> Config {
+    let mut config = Config::builder()
+        .with_version_str("<1.0.0")
+        .unwrap()
+        .with_server_name("testserver.com")
+        .with_user_group_and_default_system("root", "root")
+        .unwrap()
+        .with_ipv4_localhost()
+        .with_default_logs_settings()
+        .with_spool_dir_and_default_queues("./tmp/delivery")
+        .with_safe_tls_config(TEST_SERVER_CERT, TEST_SERVER_KEY)
+        .unwrap()
+        .with_default_smtp_options()
+        .with_default_smtp_error_handler()
+        .with_default_smtp_codes()
+        .with_auth(false, false, vec![Mechanism::External], -1)
+        .with_default_app()
+        .with_vsl("./src/vsl/client_cert.vsl")
+        .with_default_app_logs()
+        .with_system_dns()
+        .without_virtual_entries()
+        .validate()
+        .unwrap();
+
+    config.server.tls.as_mut().unwrap().client_auth = Some(ConfigServerTlsClientAuth {
+        ca_file: read_certs(TEST_CLIENT_CA),
+        required,
+    });
+    config
+}
+
+fn read_certs(path: &str) -> Vec<rustls::Certificate> {
+    rustls_pemfile::certs(&mut std::io::BufReader::new(
+        std::fs::File::open(path).unwrap(),
+    ))
+    .unwrap()
+    .into_iter()
+    .map(rustls::Certificate)
+    .collect::<Vec<_>>()
+}
+
+fn get_client_cert() -> (Vec<rustls::Certificate>, rustls::PrivateKey) {
+    let key = rustls_pemfile::pkcs8_private_keys(&mut std::io::BufReader::new(
+        std::fs::File::open(TEST_CLIENT_KEY).unwrap(),
+    ))
+    .unwrap()
+    .remove(0);
+
+    (read_certs(TEST_CLIENT_CERT), rustls::PrivateKey(key))
+}
+
+fn get_tls(config: &Config) -> Option<std::sync::Arc<rustls::ServerConfig>> {
+    Some(std::sync::Arc::new(
+        get_rustls_config(
+            config.server.tls.as_ref().unwrap(),
+            &config.server.r#virtual,
+        )
+        .unwrap(),
+    ))
+}
+
+fn get_auth(config: &Config) -> Option<std::sync::Arc<tokio::sync::Mutex<auth::Backend>>> {
+    Some({
+        let mut rsasl = vsmtp_rsasl::SASL::new().unwrap();
+        rsasl.install_callback::<auth::Callback>();
+        rsasl.store(Box::new(std::sync::Arc::new(config.clone())));
+        std::sync::Arc::new(tokio::sync::Mutex::new(rsasl))
+    })
+}
+
+const EHLO_REPLY: [&str; 10] = [
+    "250-testserver.com",
+    "250-AUTH EXTERNAL",
+    "250-PIPELINING",
+    "250-SIZE 20000000",
+    "250-CHUNKING",
+    "250-BINARYMIME",
+    "250-DSN",
+    "250-8BITMIME",
+    "250 SMTPUTF8",
+    "235 2.7.0 Authentication succeeded",
+];
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn external() {
+    let (client, server) = test_tls_tunneled_with_client_cert(
+        "testserver.com",
+        std::sync::Arc::new(get_client_auth_config(false)),
+        [
+            "EHLO client.com\r\n",
+            "AUTH EXTERNAL =\r\n",
+            "MAIL FROM:<foo@bar>\r\n",
+            "RCPT TO:<bar@foo>\r\n",
+            "QUIT\r\n",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>(),
+        std::iter::once("220 testserver.com Service ready")
+            .chain(EHLO_REPLY)
+            .chain([
+                "250 Ok",
+                "250 Ok",
+                "221 Service closing transmission channel",
+            ])
+            .map(str::to_string)
+            .collect::<Vec<_>>(),
+        20160,
+        get_tls,
+        get_auth,
+        |_| (),
+        Some(get_client_cert()),
+    )
+    .await
+    .unwrap();
+
+    client.unwrap();
+    server.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn external_as_another_identity() {
+    let (client, server) = test_tls_tunneled_with_client_cert(
+        "testserver.com",
+        std::sync::Arc::new(get_client_auth_config(false)),
+        [
+            "EHLO client.com\r\n",
+            &format!(
+                "AUTH EXTERNAL {}\r\n",
+                vsmtp_common::re::base64::encode("postmaster.example.com")
+            ),
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>(),
+        std::iter::once("220 testserver.com Service ready")
+            .chain(EHLO_REPLY.into_iter().take(9))
+            .chain(["535 5.7.8 Authentication credentials invalid"])
+            .map(str::to_string)
+            .collect::<Vec<_>>(),
+        20161,
+        get_tls,
+        get_auth,
+        |_| (),
+        Some(get_client_cert()),
+    )
+    .await
+    .unwrap();
+
+    client.unwrap();
+    assert!(server.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn anonymous_client() {
+    let (client, server) = test_tls_tunneled_with_client_cert(
+        "testserver.com",
+        std::sync::Arc::new(get_client_auth_config(false)),
+        [
+            "EHLO client.com\r\n",
+            "MAIL FROM:<foo@bar>\r\n",
+            "RCPT TO:<bar@foo>\r\n",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>(),
+        std::iter::once("220 testserver.com Service ready")
+            .chain(EHLO_REPLY.into_iter().take(9))
+            .chain(["250 Ok", "554 permanent problems with the remote server"])
+            .map(str::to_string)
+            .collect::<Vec<_>>(),
+        20162,
+        get_tls,
+        get_auth,
+        |_| (),
+        None,
+    )
+    .await
+    .unwrap();
+
+    client.unwrap();
+    server.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn certificate_required() {
+    let mut config = get_client_auth_config(true);
+    // with TLS1.2 the client is told about the failure during the handshake.
+    config.server.tls.as_mut().unwrap().protocol_version = vec![rustls::ProtocolVersion::TLSv1_2];
+
+    let (client, server) = test_tls_tunneled_with_client_cert(
+        "testserver.com",
+        std::sync::Arc::new(config),
+        vec!["QUIT\r\n".to_string()],
+        vec![],
+        20163,
+        get_tls,
+        get_auth,
+        |_| (),
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert!(client.is_err());
+    assert!(server.is_err());
+}
@@ -15,6 +15,7 @@
  *
 */
 mod cipher_suite;
+mod client_auth;
 mod starttls;
 mod tunneled;
 mod tunneled_with_auth;
@@ -26,12 +27,12 @@ use vsmtp_common::re::anyhow;
 use vsmtp_config::{
     get_rustls_config,
     re::{rustls, rustls_pemfile},
-    Config,
+    Config, ConfigServerListener, ListenerKind,
 };
 use vsmtp_rule_engine::rule_engine::RuleEngine;
 use vsmtp_server::auth;
 use vsmtp_server::re::tokio;
-use vsmtp_server::{ConnectionKind, ProcessMessage, Server};
+use vsmtp_server::{ProcessMessage, Server};
 
 pub fn get_tls_config() -> Config {
     Config::builder()
@@ -82,7 +83,7 @@ async fn test_starttls(
         Server::run_session(
             client_stream,
             client_addr,
-            ConnectionKind::Opportunistic,
+            std::sync::Arc::new(ConfigServerListener::new(vec![], ListenerKind::Plain)),
             server_config.clone(),
             if with_valid_config {
                 Some(std::sync::Arc::new(
@@ -103,6 +104,9 @@ async fn test_starttls(
                 )
                 .unwrap(),
             )),
+            None,
+            None,
+            None,
             working_sender,
             delivery_sender,
         )
@@ -201,7 +205,6 @@ async fn test_starttls(
     Ok((client.unwrap(), server.unwrap()))
 }
 
-// using sockets on 2 thread to make the handshake concurrently
 #[allow(clippy::too_many_arguments)]
 async fn test_tls_tunneled(
     server_name: &'static str,
@@ -212,6 +215,33 @@ async fn test_tls_tunneled(
     get_tls_config: fn(&Config) -> Option<std::sync::Arc<rustls::ServerConfig>>,
     get_auth_config: fn(&Config) -> Option<std::sync::Arc<tokio::sync::Mutex<auth::Backend>>>,
     after_handshake: impl Fn(&tokio_rustls::client::TlsStream<tokio::net::TcpStream>) + 'static + Send,
+) -> anyhow::Result<(anyhow::Result<()>, anyhow::Result<()>)> {
+    test_tls_tunneled_with_client_cert(
+        server_name,
+        server_config,
+        smtp_input,
+        expected_output,
+        port,
+        get_tls_config,
+        get_auth_config,
+        after_handshake,
+        None,
+    )
+    .await
+}
+
+// using sockets on 2 thread to make the handshake concurrently
+#[allow(clippy::too_many_arguments)]
+async fn test_tls_tunneled_with_client_cert(
+    server_name: &'static str,
+    server_config: std::sync::Arc<Config>,
+    smtp_input: Vec<String>,
+    expected_output: Vec<String>,
+    port: u32,
+    get_tls_config: fn(&Config) -> Option<std::sync::Arc<rustls::ServerConfig>>,
+    get_auth_config: fn(&Config) -> Option<std::sync::Arc<tokio::sync::Mutex<auth::Backend>>>,
+    after_handshake: impl Fn(&tokio_rustls::client::TlsStream<tokio::net::TcpStream>) + 'static + Send,
+    client_cert: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
 ) -> anyhow::Result<(anyhow::Result<()>, anyhow::Result<()>)> {
     let socket_server = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
         .await
@@ -226,13 +256,16 @@ async fn test_tls_tunneled(
         Server::run_session(
             client_stream,
             client_addr,
-            ConnectionKind::Tunneled,
+            std::sync::Arc::new(ConfigServerListener::new(vec![], ListenerKind::Tunneled)),
             server_config.clone(),
             get_tls_config(&server_config),
             get_auth_config(&server_config),
             std::sync::Arc::new(std::sync::RwLock::new(
                 RuleEngine::new(&server_config, &server_config.app.vsl.filepath.clone()).unwrap(),
             )),
+            None,
+            None,
+            None,
             working_sender,
             delivery_sender,
         )
@@ -252,15 +285,17 @@ async fn test_tls_tunneled(
         root_store.add(&i).unwrap();
     }
 
-    let client_config = std::sync::Arc::new(
-        rustls::ClientConfig::builder()
-            .with_safe_default_cipher_suites()
-            .with_safe_default_kx_groups()
-            .with_safe_default_protocol_versions()
-            .unwrap()
-            .with_root_certificates(root_store)
-            .with_no_client_auth(),
-    );
+    let client_config = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(root_store);
+
+    let client_config = std::sync::Arc::new(match client_cert {
+        Some((cert_chain, key_der)) => client_config.with_single_cert(cert_chain, key_der)?,
+        None => client_config.with_no_client_auth(),
+    });
 
     let connector = tokio_rustls::TlsConnector::from(client_config.clone());
 
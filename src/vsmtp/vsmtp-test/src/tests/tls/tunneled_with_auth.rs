@@ -70,6 +70,11 @@ async fn simple() {
             "220 testserver.com Service ready",
             "250-testserver.com",
             "250-AUTH PLAIN LOGIN CRAM-MD5",
+            "250-PIPELINING",
+            "250-SIZE 20000000",
+            "250-CHUNKING",
+            "250-BINARYMIME",
+            "250-DSN",
             "250-8BITMIME",
             "250 SMTPUTF8",
             "334 ",
@@ -132,3 +132,87 @@ async fn test_receiver_utf8_ar() {
 async fn test_receiver_utf8_ko() {
     assert!(test_lang!("mail/ko.txt").is_ok());
 }
+
+#[tokio::test]
+async fn non_ascii_address_without_smtputf8() {
+    assert!(test_receiver! {
+        [
+            "EHLO foobar\r\n",
+            "MAIL FROM:<用户@例子.广告>\r\n",
+            "MAIL FROM:<john@doe>\r\n",
+            "RCPT TO:<用户@例子.广告>\r\n",
+            "RCPT TO:<aa@bücher.example>\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "553 5.6.7 Non-ASCII addresses not permitted for that sender/recipient\r\n",
+            "250 Ok\r\n",
+            "553 5.6.7 Non-ASCII addresses not permitted for that sender/recipient\r\n",
+            "553 5.6.7 Non-ASCII addresses not permitted for that sender/recipient\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn non_ascii_address_with_smtputf8() {
+    struct T;
+
+    #[async_trait::async_trait]
+    impl OnMail for T {
+        async fn on_mail<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+            &mut self,
+            conn: &mut Connection<S>,
+            mail: Box<MailContext>,
+            _: &mut Option<String>,
+        ) -> anyhow::Result<()> {
+            assert!(mail.envelop.smtputf8);
+            assert_eq!(mail.envelop.mail_from, addr!("用户@例子.广告"));
+            assert_eq!(mail.envelop.rcpt, vec![addr!("aa@bücher.example").into()]);
+
+            conn.send_code(vsmtp_common::code::SMTPReplyCode::Code250)
+                .await?;
+            Ok(())
+        }
+    }
+
+    assert!(test_receiver! {
+        on_mail => &mut T,
+        [
+            "HELO foobar\r\n",
+            "MAIL FROM:<用户@例子.广告> SMTPUTF8\r\n",
+            "RCPT TO:<aa@bücher.example>\r\n",
+            "DATA\r\n",
+            "from: 用户 <用户@例子.广告>\r\n",
+            "\r\n",
+            "你好\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "250 Ok\r\n",
+            "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+            "250 Ok\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
@@ -0,0 +1,245 @@
+/*
+ * vSMTP mail transfer agent
+ * Copyright (C) 2022 viridIT SAS
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see https://www.gnu.org/licenses/.
+ *
+*/
+use crate::{config, test_receiver};
+use vsmtp_common::{
+    mail_context::{AuthCredentials, MailContext},
+    re::anyhow,
+};
+use vsmtp_config::Config;
+use vsmtp_server::{re::tokio, Connection, OnMail};
+
+fn forwarder_config() -> Config {
+    let mut config = config::local_test();
+    config.server.smtp.trusted_forwarders = vec!["127.0.0.0/8".parse().unwrap()];
+    config.app.vsl.filepath = Some("./src/tests/xclient/main.vsl".into());
+    config
+}
+
+const EHLO_FORWARDER: [&str; 11] = [
+    "250-testserver.com\r\n",
+    "250-STARTTLS\r\n",
+    "250-PIPELINING\r\n",
+    "250-SIZE 20000000\r\n",
+    "250-CHUNKING\r\n",
+    "250-BINARYMIME\r\n",
+    "250-DSN\r\n",
+    "250-8BITMIME\r\n",
+    "250-SMTPUTF8\r\n",
+    "250-XCLIENT NAME ADDR PORT PROTO HELO LOGIN\r\n",
+    "250 XFORWARD NAME ADDR PORT PROTO HELO IDENT SOURCE\r\n",
+];
+
+#[tokio::test]
+async fn untrusted_client() {
+    assert!(test_receiver! {
+        [
+            "EHLO foo\r\n",
+            "XCLIENT ADDR=192.0.2.1\r\n",
+            "XFORWARD ADDR=192.0.2.1\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            "220 testserver.com Service ready\r\n",
+            "250-testserver.com\r\n",
+            "250-STARTTLS\r\n",
+            "250-PIPELINING\r\n",
+            "250-SIZE 20000000\r\n",
+            "250-CHUNKING\r\n",
+            "250-BINARYMIME\r\n",
+            "250-DSN\r\n",
+            "250-8BITMIME\r\n",
+            "250 SMTPUTF8\r\n",
+            "550 5.7.0 Error: insufficient authorization\r\n",
+            "550 5.7.0 Error: insufficient authorization\r\n",
+            "221 Service closing transmission channel\r\n",
+        ]
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn xclient() {
+    struct T;
+
+    #[async_trait::async_trait]
+    impl OnMail for T {
+        async fn on_mail<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+            &mut self,
+            conn: &mut Connection<S>,
+            mail: Box<MailContext>,
+            _: &mut Option<String>,
+        ) -> anyhow::Result<()> {
+            assert_eq!(mail.client_addr, "192.0.2.1:4242".parse().unwrap());
+            // the helo of the proxy's client replaces the one of the EHLO command.
+            assert_eq!(mail.envelop.helo, "mx.example.com");
+            assert_eq!(
+                mail.connection.credentials,
+                Some(AuthCredentials::Query {
+                    authid: "john".to_string()
+                })
+            );
+            assert!(mail.connection.is_authenticated);
+
+            conn.send_code(vsmtp_common::code::SMTPReplyCode::Code250)
+                .await?;
+            Ok(())
+        }
+    }
+
+    assert!(test_receiver! {
+        on_mail => &mut T,
+        with_config => forwarder_config(),
+        [
+            "EHLO proxy.example.com\r\n",
+            "XCLIENT ADDR=192.0.2.1 PORT=4242 HELO=mx.example.com LOGIN=john NAME=[UNAVAILABLE]\r\n",
+            "EHLO proxy.example.com\r\n",
+            "MAIL FROM:<a@b>\r\n",
+            "RCPT TO:<b@c>\r\n",
+            "DATA\r\n",
+            "from: a b <a@b>\r\n",
+            "\r\n",
+            "mail content\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            &["220 testserver.com Service ready\r\n"][..],
+            &EHLO_FORWARDER,
+            &["220 testserver.com Service ready\r\n"],
+            &EHLO_FORWARDER,
+            &[
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+                "250 Ok\r\n",
+                "221 Service closing transmission channel\r\n",
+            ],
+        ]
+        .concat()
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn xclient_denied_by_the_rules() {
+    assert!(test_receiver! {
+        with_config => forwarder_config(),
+        [
+            "EHLO proxy.example.com\r\n",
+            "XCLIENT ADDR=192.0.2.66\r\n",
+            "EHLO mx.example.com\r\n",
+        ]
+        .concat(),
+        [
+            &["220 testserver.com Service ready\r\n"][..],
+            &EHLO_FORWARDER,
+            &["554 permanent problems with the remote server\r\n"],
+        ]
+        .concat()
+        .concat()
+    }
+    .is_ok());
+}
+
+#[tokio::test]
+async fn xforward() {
+    struct T(usize);
+
+    #[async_trait::async_trait]
+    impl OnMail for T {
+        async fn on_mail<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin>(
+            &mut self,
+            conn: &mut Connection<S>,
+            mail: Box<MailContext>,
+            helo_domain: &mut Option<String>,
+        ) -> anyhow::Result<()> {
+            if self.0 == 0 {
+                assert_eq!(
+                    mail.client_addr.ip(),
+                    "192.0.2.1".parse::<std::net::IpAddr>().unwrap()
+                );
+                assert_eq!(mail.envelop.helo, "mx.example.com");
+            } else {
+                // the attributes only applied to the previous transaction.
+                assert!(mail.client_addr.ip().is_loopback());
+                assert_eq!(mail.envelop.helo, "proxy.example.com");
+            }
+            assert_eq!(mail.connection.credentials, None);
+            self.0 += 1;
+
+            *helo_domain = Some(mail.envelop.helo.clone());
+            conn.send_code(vsmtp_common::code::SMTPReplyCode::Code250)
+                .await?;
+            Ok(())
+        }
+    }
+
+    assert!(test_receiver! {
+        on_mail => &mut T(0),
+        with_config => forwarder_config(),
+        [
+            "EHLO proxy.example.com\r\n",
+            "XFORWARD ADDR=192.0.2.3\r\n",
+            "RSET\r\n",
+            "XFORWARD ADDR=192.0.2.1 HELO=mx.example.com PROTO=ESMTP SOURCE=REMOTE\r\n",
+            "MAIL FROM:<a@b>\r\n",
+            "XFORWARD ADDR=192.0.2.2\r\n",
+            "RCPT TO:<b@c>\r\n",
+            "DATA\r\n",
+            "from: a b <a@b>\r\n",
+            "\r\n",
+            "mail content\r\n",
+            ".\r\n",
+            "MAIL FROM:<a@b>\r\n",
+            "RCPT TO:<b@c>\r\n",
+            "DATA\r\n",
+            "from: a b <a@b>\r\n",
+            "\r\n",
+            "mail content\r\n",
+            ".\r\n",
+            "QUIT\r\n",
+        ]
+        .concat(),
+        [
+            &["220 testserver.com Service ready\r\n"][..],
+            &EHLO_FORWARDER,
+            &[
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                // not during a mail transaction
+                "503 Bad sequence of commands\r\n",
+                "250 Ok\r\n",
+                "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "250 Ok\r\n",
+                "354 Start mail input; end with <CRLF>.<CRLF>\r\n",
+                "250 Ok\r\n",
+                "221 Service closing transmission channel\r\n",
+            ],
+        ]
+        .concat()
+        .concat()
+    }
+    .is_ok());
+}